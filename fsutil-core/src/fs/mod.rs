@@ -4,12 +4,14 @@
 
 mod errors;
 mod file;
+mod setstat;
 pub mod stream;
 mod sync;
 mod welcome;
 
 pub use self::errors::{RemoteError, RemoteErrorType, RemoteResult};
 pub use self::file::{File, FileType, Metadata, UnixPex, UnixPexClass};
+pub use self::setstat::{MetadataField, SetStatReport};
 pub use self::stream::{ReadStream, WriteStream};
 pub use self::sync::RemoteFileSystem;
 pub use self::welcome::Welcome;
@@ -0,0 +1,115 @@
+//! ## Setstat
+//!
+//! outcome of setting file attributes, for protocols which can't set all of them
+
+use std::fmt;
+
+use super::Metadata;
+
+/// An attribute of [`Metadata`] which can be set with `setstat`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum MetadataField {
+    Accessed,
+    Modified,
+    Mode,
+    Uid,
+    Gid,
+}
+
+impl MetadataField {
+    /// Attributes set in `metadata`
+    pub fn requested(metadata: &Metadata) -> Vec<Self> {
+        [
+            (metadata.accessed.is_some(), Self::Accessed),
+            (metadata.modified.is_some(), Self::Modified),
+            (metadata.mode.is_some(), Self::Mode),
+            (metadata.uid.is_some(), Self::Uid),
+            (metadata.gid.is_some(), Self::Gid),
+        ]
+        .into_iter()
+        .filter_map(|(requested, field)| requested.then_some(field))
+        .collect()
+    }
+}
+
+impl fmt::Display for MetadataField {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Self::Accessed => "accessed",
+            Self::Modified => "modified",
+            Self::Mode => "mode",
+            Self::Uid => "uid",
+            Self::Gid => "gid",
+        };
+        write!(f, "{name}")
+    }
+}
+
+/// Outcome of setting the attributes in [`Metadata`] on a file
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SetStatReport {
+    /// Attributes which have been set
+    pub applied: Vec<MetadataField>,
+    /// Attributes which the server can't set
+    pub unsupported: Vec<MetadataField>,
+}
+
+impl SetStatReport {
+    /// Build the report of setting `metadata`, where only the `applied` attributes have been set
+    pub fn new(metadata: &Metadata, applied: Vec<MetadataField>) -> Self {
+        let unsupported = MetadataField::requested(metadata)
+            .into_iter()
+            .filter(|field| !applied.contains(field))
+            .collect();
+        Self {
+            applied,
+            unsupported,
+        }
+    }
+
+    /// Returns whether all the requested attributes have been set
+    pub fn is_complete(&self) -> bool {
+        self.unsupported.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod test {
+
+    use std::time::SystemTime;
+
+    use pretty_assertions::assert_eq;
+
+    use super::*;
+    use crate::fs::UnixPex;
+
+    #[test]
+    fn should_tell_requested_fields() {
+        assert!(MetadataField::requested(&Metadata::default()).is_empty());
+        let metadata = Metadata::default()
+            .mode(UnixPex::from(0o644))
+            .modified(SystemTime::UNIX_EPOCH)
+            .uid(1000);
+        assert_eq!(
+            MetadataField::requested(&metadata),
+            vec![
+                MetadataField::Modified,
+                MetadataField::Mode,
+                MetadataField::Uid
+            ]
+        );
+        assert_eq!(MetadataField::Modified.to_string(), "modified");
+        assert!(SetStatReport::default().is_complete());
+    }
+
+    #[test]
+    fn should_build_setstat_report() {
+        let metadata = Metadata::default()
+            .mode(UnixPex::from(0o644))
+            .modified(SystemTime::UNIX_EPOCH);
+        let report = SetStatReport::new(&metadata, vec![MetadataField::Mode]);
+        assert_eq!(report.applied, vec![MetadataField::Mode]);
+        assert_eq!(report.unsupported, vec![MetadataField::Modified]);
+        assert!(!report.is_complete());
+    }
+}
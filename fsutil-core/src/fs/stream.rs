@@ -4,6 +4,9 @@
 
 use std::io::{Error as IoError, ErrorKind as IoErrorKind, Read, Seek, Write};
 
+/// Size of the chunks read by [`LineEndingReader`]
+const LINE_ENDING_CHUNK_SIZE: usize = 8192;
+
 // -- read stream
 
 /// A trait which combines [`Read`] and [`Seek`] together
@@ -159,11 +162,207 @@ impl Seek for StreamWriter {
     }
 }
 
+// -- line endings
+
+/// Line ending of text data
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum LineEnding {
+    /// `\n`, as on Unix
+    Lf,
+    /// `\r\n`, as on Windows and in FTP ASCII transfers
+    CrLf,
+}
+
+impl LineEnding {
+    /// Line ending of the platform
+    pub fn native() -> Self {
+        if cfg!(windows) {
+            Self::CrLf
+        } else {
+            Self::Lf
+        }
+    }
+}
+
+/// Converts `\n` and `\r\n` line endings to the target [`LineEnding`]; lone `\r` are left as they are
+#[derive(Debug, Clone, Copy)]
+struct LineEndingConverter {
+    target: LineEnding,
+    /// Whether the last byte was a `\r`; when converting to `\n`, it has been held back
+    after_cr: bool,
+}
+
+impl LineEndingConverter {
+    fn new(target: LineEnding) -> Self {
+        Self {
+            target,
+            after_cr: false,
+        }
+    }
+
+    /// Convert `input` into `output`
+    fn convert(&mut self, input: &[u8], output: &mut Vec<u8>) {
+        for &byte in input {
+            match (self.target, byte) {
+                // the `\r` held back wasn't followed by `\n`
+                (LineEnding::Lf, b'\r') if self.after_cr => output.push(b'\r'),
+                (LineEnding::Lf, b'\r') => {}
+                (LineEnding::Lf, b'\n') => output.push(b'\n'),
+                (LineEnding::Lf, byte) => {
+                    if self.after_cr {
+                        output.push(b'\r');
+                    }
+                    output.push(byte);
+                }
+                (LineEnding::CrLf, b'\n') if self.after_cr => output.push(b'\n'),
+                (LineEnding::CrLf, b'\n') => output.extend_from_slice(b"\r\n"),
+                (LineEnding::CrLf, byte) => output.push(byte),
+            }
+            self.after_cr = byte == b'\r';
+        }
+    }
+
+    /// Write the data held back at the end of the stream into `output`
+    fn finish(&mut self, output: &mut Vec<u8>) {
+        if self.target == LineEnding::Lf && self.after_cr {
+            output.push(b'\r');
+        }
+        self.after_cr = false;
+    }
+}
+
+/// A reader which converts the line endings of the data read from the inner reader to a [`LineEnding`].
+///
+/// It can wrap the stream returned by [`crate::RemoteFileSystem::open`] to transfer text files
+/// between systems with different line endings
+pub struct LineEndingReader<R: Read> {
+    inner: R,
+    converter: LineEndingConverter,
+    /// Converted data not read yet, from `pos` on
+    buffer: Vec<u8>,
+    pos: usize,
+    eof: bool,
+}
+
+impl<R: Read> LineEndingReader<R> {
+    /// Instantiates a new `LineEndingReader` converting the data of `inner` to `target`
+    pub fn new(inner: R, target: LineEnding) -> Self {
+        Self {
+            inner,
+            converter: LineEndingConverter::new(target),
+            buffer: Vec::new(),
+            pos: 0,
+            eof: false,
+        }
+    }
+
+    /// Get back the inner reader; the data converted but not read yet is lost
+    pub fn into_inner(self) -> R {
+        self.inner
+    }
+}
+
+impl<R: Read> Read for LineEndingReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        while self.pos == self.buffer.len() {
+            if self.eof {
+                return Ok(0);
+            }
+            self.buffer.clear();
+            self.pos = 0;
+            let mut chunk = [0; LINE_ENDING_CHUNK_SIZE];
+            match self.inner.read(&mut chunk)? {
+                0 => {
+                    self.eof = true;
+                    self.converter.finish(&mut self.buffer);
+                }
+                read => self.converter.convert(&chunk[..read], &mut self.buffer),
+            }
+        }
+        let len = buf.len().min(self.buffer.len() - self.pos);
+        buf[..len].copy_from_slice(&self.buffer[self.pos..self.pos + len]);
+        self.pos += len;
+        Ok(len)
+    }
+}
+
+/// A writer which converts the line endings of the data written to the inner writer to a [`LineEnding`].
+///
+/// It can wrap the stream returned by [`crate::RemoteFileSystem::create`] to transfer text files
+/// between systems with different line endings.
+/// A trailing `\r` is held back until it's known whether a `\n` follows: it's written by [`Self::finish`],
+/// or when the writer is dropped
+pub struct LineEndingWriter<W: Write> {
+    /// Always `Some`, but when finished
+    inner: Option<W>,
+    converter: LineEndingConverter,
+    buffer: Vec<u8>,
+}
+
+impl<W: Write> LineEndingWriter<W> {
+    /// Instantiates a new `LineEndingWriter` converting the data written to `inner` to `target`
+    pub fn new(inner: W, target: LineEnding) -> Self {
+        Self {
+            inner: Some(inner),
+            converter: LineEndingConverter::new(target),
+            buffer: Vec::new(),
+        }
+    }
+
+    /// Write the data held back and get back the inner writer
+    pub fn finish(mut self) -> std::io::Result<W> {
+        self.write_held_back()?;
+        Ok(self.inner.take().expect("inner writer taken before finish"))
+    }
+
+    fn write_held_back(&mut self) -> std::io::Result<()> {
+        self.buffer.clear();
+        self.converter.finish(&mut self.buffer);
+        match self.inner.as_mut() {
+            Some(inner) if !self.buffer.is_empty() => inner.write_all(&self.buffer),
+            _ => Ok(()),
+        }
+    }
+}
+
+impl<W: Write> Write for LineEndingWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let inner = self
+            .inner
+            .as_mut()
+            .expect("inner writer taken before finish");
+        self.buffer.clear();
+        self.converter.convert(buf, &mut self.buffer);
+        inner.write_all(&self.buffer)?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        match self.inner.as_mut() {
+            Some(inner) => inner.flush(),
+            None => Ok(()),
+        }
+    }
+}
+
+impl<W: Write> Drop for LineEndingWriter<W> {
+    fn drop(&mut self) {
+        if let Err(err) = self.write_held_back() {
+            error!("Failed to write the end of the converted stream: {}", err);
+        }
+    }
+}
+
 #[cfg(test)]
 mod test {
 
     use std::fs::File;
+    use std::io::Cursor;
 
+    use pretty_assertions::assert_eq;
     use tempfile::NamedTempFile;
 
     use super::*;
@@ -206,4 +405,75 @@ mod test {
         let s = WriteStream::from(file);
         assert_eq!(s.seekable(), true);
     }
+
+    /// Reader yielding one byte at a time, to split the data at every position
+    struct ByteReader(Cursor<Vec<u8>>);
+
+    impl Read for ByteReader {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            let len = buf.len().min(1);
+            self.0.read(&mut buf[..len])
+        }
+    }
+
+    fn read_converted(data: &[u8], target: LineEnding) -> Vec<u8> {
+        let mut converted = Vec::new();
+        LineEndingReader::new(ByteReader(Cursor::new(data.to_vec())), target)
+            .read_to_end(&mut converted)
+            .unwrap();
+        converted
+    }
+
+    fn write_converted(data: &[u8], target: LineEnding) -> Vec<u8> {
+        let mut writer = LineEndingWriter::new(Vec::new(), target);
+        for byte in data {
+            writer.write_all(&[*byte]).unwrap();
+        }
+        writer.finish().unwrap()
+    }
+
+    #[test]
+    fn should_convert_line_endings_when_reading() {
+        assert_eq!(
+            read_converted(b"a\r\nb\nc\rd\r\r\n\r", LineEnding::Lf),
+            b"a\nb\nc\rd\r\n\r".to_vec()
+        );
+        assert_eq!(
+            read_converted(b"a\r\nb\nc\rd\n\n", LineEnding::CrLf),
+            b"a\r\nb\r\nc\rd\r\n\r\n".to_vec()
+        );
+        let mut reader = LineEndingReader::new(Cursor::new(b"a\r\nb".to_vec()), LineEnding::Lf);
+        let mut converted = String::new();
+        reader.read_to_string(&mut converted).unwrap();
+        assert_eq!(converted.as_str(), "a\nb");
+        assert_eq!(reader.into_inner().position(), 4);
+    }
+
+    #[test]
+    fn should_convert_line_endings_when_writing() {
+        assert_eq!(
+            write_converted(b"a\r\nb\nc\rd\r\r\n\r", LineEnding::Lf),
+            b"a\nb\nc\rd\r\n\r".to_vec()
+        );
+        assert_eq!(
+            write_converted(b"a\r\nb\nc\rd\n", LineEnding::CrLf),
+            b"a\r\nb\r\nc\rd\r\n".to_vec()
+        );
+        // the held back `\r` is written on drop
+        let mut output = Vec::new();
+        {
+            let mut writer = LineEndingWriter::new(&mut output, LineEnding::Lf);
+            writer.write_all(b"a\r").unwrap();
+            writer.flush().unwrap();
+        }
+        assert_eq!(output, b"a\r".to_vec());
+    }
+
+    #[test]
+    fn should_get_native_line_ending() {
+        #[cfg(windows)]
+        assert_eq!(LineEnding::native(), LineEnding::CrLf);
+        #[cfg(not(windows))]
+        assert_eq!(LineEnding::native(), LineEnding::Lf);
+    }
 }
@@ -8,6 +8,10 @@
 pub struct Welcome {
     /// Welcome message / banner
     pub banner: Option<String>,
+    /// Type of the remote system, if reported by the server
+    pub system: Option<String>,
+    /// Protocol features advertised by the server
+    pub features: Vec<String>,
 }
 
 impl Welcome {
@@ -16,6 +20,18 @@ impl Welcome {
         self.banner = banner;
         self
     }
+
+    /// Set remote system type
+    pub fn system(mut self, system: Option<String>) -> Self {
+        self.system = system;
+        self
+    }
+
+    /// Set features advertised by the server
+    pub fn features(mut self, features: Vec<String>) -> Self {
+        self.features = features;
+        self
+    }
 }
 
 #[cfg(test)]
@@ -28,7 +44,14 @@ mod test {
     fn should_create_welcome_type() {
         let welcome = Welcome::default();
         assert!(welcome.banner.is_none());
-        let welcome = Welcome::default().banner(Some("Hello, world!".to_string()));
+        assert!(welcome.system.is_none());
+        assert!(welcome.features.is_empty());
+        let welcome = Welcome::default()
+            .banner(Some("Hello, world!".to_string()))
+            .system(Some("UNIX Type: L8".to_string()))
+            .features(vec!["UTF8".to_string()]);
         assert_eq!(welcome.banner.as_deref().unwrap(), "Hello, world!");
+        assert_eq!(welcome.system.as_deref().unwrap(), "UNIX Type: L8");
+        assert_eq!(welcome.features, vec!["UTF8".to_string()]);
     }
 }
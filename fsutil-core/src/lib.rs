@@ -4,8 +4,10 @@ extern crate tracing;
 
 // -- export
 pub use fs::{File, RemoteError, RemoteErrorType, RemoteFileSystem, RemoteResult};
+pub use proxy::Proxy;
 // -- modules
 pub mod fs;
+pub mod proxy;
 
 // -- utils
 pub(crate) mod utils;
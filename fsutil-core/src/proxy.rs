@@ -0,0 +1,583 @@
+//! ## Proxy
+//!
+//! TCP connections through a SOCKS5 (RFC 1928) or an HTTP `CONNECT` proxy, shared by the backends
+
+use std::fmt;
+use std::io::{self, Read, Write};
+use std::net::{IpAddr, Ipv6Addr, TcpStream, ToSocketAddrs};
+use std::time::Duration;
+
+const SOCKS5_VERSION: u8 = 0x05;
+const SOCKS5_NO_AUTH: u8 = 0x00;
+const SOCKS5_USER_PASS: u8 = 0x02;
+const SOCKS5_NO_ACCEPTABLE_METHOD: u8 = 0xff;
+/// Version of the username/password authentication (RFC 1929)
+const SOCKS5_USER_PASS_VERSION: u8 = 0x01;
+const SOCKS5_CONNECT: u8 = 0x01;
+const SOCKS5_IPV4: u8 = 0x01;
+const SOCKS5_DOMAIN: u8 = 0x03;
+const SOCKS5_IPV6: u8 = 0x04;
+
+/// Maximum size of the reply of an HTTP proxy to `CONNECT`
+const HTTP_MAX_REPLY: usize = 16 * 1024;
+
+/// Protocol spoken with a proxy
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ProxyKind {
+    /// SOCKS5; host names are resolved by the proxy
+    Socks5,
+    /// HTTP proxy supporting the `CONNECT` method
+    Http,
+}
+
+/// A proxy to open TCP connections through.
+///
+/// Only the host name is sent to the proxy, which resolves it: servers can be reached by names only the proxy knows
+#[derive(Clone, PartialEq, Eq)]
+pub struct Proxy {
+    kind: ProxyKind,
+    /// Address of the proxy, as `host:port`
+    address: String,
+    /// Username and password to authenticate with
+    credentials: Option<(String, String)>,
+}
+
+impl Proxy {
+    /// SOCKS5 proxy at `address` (`host:port`)
+    pub fn socks5<S: AsRef<str>>(address: S) -> Self {
+        Self::new(ProxyKind::Socks5, address)
+    }
+
+    /// HTTP proxy at `address` (`host:port`), opening connections with `CONNECT`
+    pub fn http<S: AsRef<str>>(address: S) -> Self {
+        Self::new(ProxyKind::Http, address)
+    }
+
+    fn new<S: AsRef<str>>(kind: ProxyKind, address: S) -> Self {
+        Self {
+            kind,
+            address: address.as_ref().to_string(),
+            credentials: None,
+        }
+    }
+
+    /// Authenticate with `username` and `password`: SOCKS5 username/password authentication (RFC 1929),
+    /// or HTTP basic authentication
+    pub fn credentials<S: AsRef<str>>(mut self, username: S, password: S) -> Self {
+        self.credentials = Some((username.as_ref().to_string(), password.as_ref().to_string()));
+        self
+    }
+
+    /// Get the protocol of the proxy
+    pub fn kind(&self) -> ProxyKind {
+        self.kind
+    }
+
+    /// Get the address of the proxy
+    pub fn address(&self) -> &str {
+        self.address.as_str()
+    }
+
+    /// Open a connection to `host`:`port` through the proxy.
+    ///
+    /// `timeout` bounds connecting to the proxy and each read and write of the handshake;
+    /// the returned stream has no timeout set
+    pub fn connect(
+        &self,
+        host: &str,
+        port: u16,
+        timeout: Option<Duration>,
+    ) -> io::Result<TcpStream> {
+        debug!(
+            "Connecting to {}:{} through {:?} proxy {}",
+            host, port, self.kind, self.address
+        );
+        let mut stream = connect_tcp(self.address.as_str(), timeout)?;
+        stream.set_read_timeout(timeout)?;
+        stream.set_write_timeout(timeout)?;
+        // IPv6 addresses may be bracketed
+        let host = host.trim_start_matches('[').trim_end_matches(']');
+        match self.kind {
+            ProxyKind::Socks5 => self.socks5_connect(&mut stream, host, port)?,
+            ProxyKind::Http => self.http_connect(&mut stream, host, port)?,
+        }
+        stream.set_read_timeout(None)?;
+        stream.set_write_timeout(None)?;
+        trace!("Connected to {}:{} through the proxy", host, port);
+        Ok(stream)
+    }
+
+    fn socks5_connect(&self, stream: &mut TcpStream, host: &str, port: u16) -> io::Result<()> {
+        // greeting
+        let methods: &[u8] = match self.credentials {
+            Some(_) => &[SOCKS5_NO_AUTH, SOCKS5_USER_PASS],
+            None => &[SOCKS5_NO_AUTH],
+        };
+        let mut greeting = vec![SOCKS5_VERSION, methods.len() as u8];
+        greeting.extend_from_slice(methods);
+        stream.write_all(&greeting)?;
+        let mut reply = [0; 2];
+        stream.read_exact(&mut reply)?;
+        if reply[0] != SOCKS5_VERSION {
+            return Err(proxy_error("not a SOCKS5 proxy"));
+        }
+        match (reply[1], self.credentials.as_ref()) {
+            (SOCKS5_NO_AUTH, _) => {}
+            (SOCKS5_USER_PASS, Some((username, password))) => {
+                socks5_authenticate(stream, username, password)?
+            }
+            (SOCKS5_NO_ACCEPTABLE_METHOD, _) => {
+                return Err(io::Error::new(
+                    io::ErrorKind::PermissionDenied,
+                    "no authentication method accepted by the SOCKS5 proxy",
+                ))
+            }
+            (method, _) => {
+                return Err(proxy_error(format!(
+                    "unexpected SOCKS5 authentication method {method}"
+                )))
+            }
+        }
+        // request
+        let mut request = vec![SOCKS5_VERSION, SOCKS5_CONNECT, 0x00];
+        match host.parse::<IpAddr>() {
+            Ok(IpAddr::V4(ip)) => {
+                request.push(SOCKS5_IPV4);
+                request.extend_from_slice(&ip.octets());
+            }
+            Ok(IpAddr::V6(ip)) => {
+                request.push(SOCKS5_IPV6);
+                request.extend_from_slice(&ip.octets());
+            }
+            Err(_) => {
+                let len = u8::try_from(host.len()).map_err(|_| {
+                    io::Error::new(io::ErrorKind::InvalidInput, "host name too long for SOCKS5")
+                })?;
+                request.push(SOCKS5_DOMAIN);
+                request.push(len);
+                request.extend_from_slice(host.as_bytes());
+            }
+        }
+        request.extend_from_slice(&port.to_be_bytes());
+        stream.write_all(&request)?;
+        // reply: version, status, reserved, then the bound address
+        let mut reply = [0; 4];
+        stream.read_exact(&mut reply)?;
+        if reply[1] != 0x00 {
+            return Err(socks5_error(reply[1]));
+        }
+        let addr_len = match reply[3] {
+            SOCKS5_IPV4 => 4,
+            SOCKS5_IPV6 => 16,
+            SOCKS5_DOMAIN => {
+                let mut len = [0; 1];
+                stream.read_exact(&mut len)?;
+                usize::from(len[0])
+            }
+            atyp => {
+                return Err(proxy_error(format!(
+                    "unexpected SOCKS5 address type {atyp}"
+                )))
+            }
+        };
+        let mut bound = vec![0; addr_len + 2];
+        stream.read_exact(&mut bound)
+    }
+
+    fn http_connect(&self, stream: &mut TcpStream, host: &str, port: u16) -> io::Result<()> {
+        let target = match host.parse::<Ipv6Addr>() {
+            Ok(_) => format!("[{host}]:{port}"),
+            Err(_) => format!("{host}:{port}"),
+        };
+        let mut request = format!("CONNECT {target} HTTP/1.1\r\nHost: {target}\r\n");
+        if let Some((username, password)) = self.credentials.as_ref() {
+            request.push_str(&format!(
+                "Proxy-Authorization: Basic {}\r\n",
+                base64(format!("{username}:{password}").as_bytes())
+            ));
+        }
+        request.push_str("\r\n");
+        stream.write_all(request.as_bytes())?;
+        // read the reply byte by byte, so that nothing sent by the server is consumed
+        let mut reply = Vec::new();
+        let mut byte = [0; 1];
+        while !reply.ends_with(b"\r\n\r\n") {
+            if reply.len() >= HTTP_MAX_REPLY {
+                return Err(proxy_error("HTTP proxy reply too long"));
+            }
+            stream.read_exact(&mut byte)?;
+            reply.push(byte[0]);
+        }
+        let reply = String::from_utf8_lossy(&reply);
+        let status_line = reply.lines().next().unwrap_or_default();
+        match status_line.split_whitespace().nth(1).map(str::parse::<u16>) {
+            Some(Ok(code)) if (200..300).contains(&code) => Ok(()),
+            Some(Ok(407)) => Err(io::Error::new(
+                io::ErrorKind::PermissionDenied,
+                format!("HTTP proxy authentication failed: {status_line}"),
+            )),
+            _ => Err(proxy_error(format!(
+                "HTTP proxy refused to connect: {status_line}"
+            ))),
+        }
+    }
+}
+
+impl fmt::Debug for Proxy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Proxy")
+            .field("kind", &self.kind)
+            .field("address", &self.address)
+            .field(
+                "username",
+                &self.credentials.as_ref().map(|(username, _)| username),
+            )
+            .finish()
+    }
+}
+
+/// Connect to `address`, trying each of the addresses it resolves to
+fn connect_tcp(address: &str, timeout: Option<Duration>) -> io::Result<TcpStream> {
+    let mut result = Err(io::Error::new(
+        io::ErrorKind::NotFound,
+        format!("no address found for {address}"),
+    ));
+    for addr in address.to_socket_addrs()? {
+        result = match timeout {
+            Some(timeout) => TcpStream::connect_timeout(&addr, timeout),
+            None => TcpStream::connect(addr),
+        };
+        if result.is_ok() {
+            break;
+        }
+    }
+    result
+}
+
+/// Username/password authentication (RFC 1929)
+fn socks5_authenticate(stream: &mut TcpStream, username: &str, password: &str) -> io::Result<()> {
+    let too_long = || io::Error::new(io::ErrorKind::InvalidInput, "SOCKS5 credentials too long");
+    let mut request = vec![SOCKS5_USER_PASS_VERSION];
+    request.push(u8::try_from(username.len()).map_err(|_| too_long())?);
+    request.extend_from_slice(username.as_bytes());
+    request.push(u8::try_from(password.len()).map_err(|_| too_long())?);
+    request.extend_from_slice(password.as_bytes());
+    stream.write_all(&request)?;
+    let mut reply = [0; 2];
+    stream.read_exact(&mut reply)?;
+    match reply[1] {
+        0x00 => Ok(()),
+        _ => Err(io::Error::new(
+            io::ErrorKind::PermissionDenied,
+            "SOCKS5 proxy authentication failed",
+        )),
+    }
+}
+
+/// Error for a SOCKS5 reply status
+fn socks5_error(status: u8) -> io::Error {
+    let (kind, msg) = match status {
+        0x01 => (io::ErrorKind::Other, "general SOCKS server failure"),
+        0x02 => (
+            io::ErrorKind::PermissionDenied,
+            "connection not allowed by ruleset",
+        ),
+        0x03 => (io::ErrorKind::Other, "network unreachable"),
+        0x04 => (io::ErrorKind::Other, "host unreachable"),
+        0x05 => (io::ErrorKind::ConnectionRefused, "connection refused"),
+        0x06 => (io::ErrorKind::TimedOut, "TTL expired"),
+        0x07 => (io::ErrorKind::Unsupported, "command not supported"),
+        0x08 => (io::ErrorKind::Unsupported, "address type not supported"),
+        _ => (io::ErrorKind::Other, "unknown error"),
+    };
+    io::Error::new(kind, format!("SOCKS5 proxy: {msg}"))
+}
+
+fn proxy_error<S: Into<String>>(msg: S) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg.into())
+}
+
+/// Encode `data` in base64, with padding
+fn base64(data: &[u8]) -> String {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut encoded = String::with_capacity(data.len().div_ceil(3) * 4);
+    for chunk in data.chunks(3) {
+        let bytes = [
+            chunk[0],
+            chunk.get(1).copied().unwrap_or_default(),
+            chunk.get(2).copied().unwrap_or_default(),
+        ];
+        let n = u32::from(bytes[0]) << 16 | u32::from(bytes[1]) << 8 | u32::from(bytes[2]);
+        for i in 0..4 {
+            match i <= chunk.len() {
+                true => encoded.push(char::from(ALPHABET[(n >> (18 - 6 * i) & 0x3f) as usize])),
+                false => encoded.push('='),
+            }
+        }
+    }
+    encoded
+}
+
+#[cfg(test)]
+mod test {
+
+    use std::net::{Shutdown, TcpListener};
+    use std::thread;
+
+    use pretty_assertions::assert_eq;
+
+    use super::*;
+
+    /// Start a server echoing the data of one connection; returns its port
+    fn echo_server() -> u16 {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let _ = io::copy(&mut stream.try_clone().unwrap(), &mut &stream);
+        });
+        port
+    }
+
+    /// Start an in-process proxy serving one connection, which requires `credentials` if set;
+    /// returns its address
+    fn proxy_stand_in(
+        kind: ProxyKind,
+        credentials: Option<(&'static str, &'static str)>,
+    ) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        thread::spawn(move || {
+            let (mut client, _) = listener.accept().unwrap();
+            let target = match kind {
+                ProxyKind::Socks5 => socks5_stand_in(&mut client, credentials),
+                ProxyKind::Http => http_stand_in(&mut client, credentials),
+            };
+            if let Some(target) = target {
+                relay(client, target);
+            }
+        });
+        address
+    }
+
+    fn socks5_stand_in(
+        client: &mut TcpStream,
+        credentials: Option<(&str, &str)>,
+    ) -> Option<TcpStream> {
+        let mut header = [0; 2];
+        client.read_exact(&mut header).unwrap();
+        let mut methods = vec![0; usize::from(header[1])];
+        client.read_exact(&mut methods).unwrap();
+        let method = match credentials {
+            Some(_) => SOCKS5_USER_PASS,
+            None => SOCKS5_NO_AUTH,
+        };
+        if !methods.contains(&method) {
+            client
+                .write_all(&[SOCKS5_VERSION, SOCKS5_NO_ACCEPTABLE_METHOD])
+                .unwrap();
+            return None;
+        }
+        client.write_all(&[SOCKS5_VERSION, method]).unwrap();
+        if let Some((username, password)) = credentials {
+            let mut version = [0; 1];
+            client.read_exact(&mut version).unwrap();
+            let ok = read_field(client) == username && read_field(client) == password;
+            client
+                .write_all(&[SOCKS5_USER_PASS_VERSION, u8::from(!ok)])
+                .unwrap();
+            if !ok {
+                return None;
+            }
+        }
+        let mut request = [0; 4];
+        client.read_exact(&mut request).unwrap();
+        let host = match request[3] {
+            SOCKS5_IPV4 => {
+                let mut ip = [0; 4];
+                client.read_exact(&mut ip).unwrap();
+                IpAddr::from(ip).to_string()
+            }
+            SOCKS5_IPV6 => {
+                let mut ip = [0; 16];
+                client.read_exact(&mut ip).unwrap();
+                IpAddr::from(ip).to_string()
+            }
+            _ => {
+                let mut len = [0; 1];
+                client.read_exact(&mut len).unwrap();
+                let mut name = vec![0; usize::from(len[0])];
+                client.read_exact(&mut name).unwrap();
+                String::from_utf8(name).unwrap()
+            }
+        };
+        let mut port = [0; 2];
+        client.read_exact(&mut port).unwrap();
+        match TcpStream::connect((host.as_str(), u16::from_be_bytes(port))) {
+            Ok(target) => {
+                client
+                    .write_all(&[SOCKS5_VERSION, 0x00, 0x00, SOCKS5_DOMAIN, 4])
+                    .unwrap();
+                client.write_all(b"test\x00\x00").unwrap();
+                Some(target)
+            }
+            Err(_) => {
+                client
+                    .write_all(&[SOCKS5_VERSION, 0x05, 0x00, SOCKS5_IPV4, 0, 0, 0, 0, 0, 0])
+                    .unwrap();
+                None
+            }
+        }
+    }
+
+    /// Read a field prefixed with its length
+    fn read_field(client: &mut TcpStream) -> String {
+        let mut len = [0; 1];
+        client.read_exact(&mut len).unwrap();
+        let mut field = vec![0; usize::from(len[0])];
+        client.read_exact(&mut field).unwrap();
+        String::from_utf8(field).unwrap()
+    }
+
+    fn http_stand_in(
+        client: &mut TcpStream,
+        credentials: Option<(&str, &str)>,
+    ) -> Option<TcpStream> {
+        let mut request = Vec::new();
+        let mut byte = [0; 1];
+        while !request.ends_with(b"\r\n\r\n") {
+            client.read_exact(&mut byte).unwrap();
+            request.push(byte[0]);
+        }
+        let request = String::from_utf8(request).unwrap();
+        let target = request
+            .strip_prefix("CONNECT ")
+            .and_then(|x| x.split_whitespace().next())
+            .unwrap()
+            .to_string();
+        if let Some((username, password)) = credentials {
+            let expected = format!(
+                "Proxy-Authorization: Basic {}\r\n",
+                base64(format!("{username}:{password}").as_bytes())
+            );
+            if !request.contains(&expected) {
+                client
+                    .write_all(b"HTTP/1.1 407 Proxy Authentication Required\r\n\r\n")
+                    .unwrap();
+                return None;
+            }
+        }
+        let target = TcpStream::connect(target).unwrap();
+        client
+            .write_all(b"HTTP/1.1 200 Connection established\r\n\r\n")
+            .unwrap();
+        Some(target)
+    }
+
+    /// Relay data between `client` and `target` until both are closed
+    fn relay(client: TcpStream, target: TcpStream) {
+        let (mut client_r, mut target_w) =
+            (client.try_clone().unwrap(), target.try_clone().unwrap());
+        let upstream = thread::spawn(move || {
+            let _ = io::copy(&mut client_r, &mut target_w);
+            let _ = target_w.shutdown(Shutdown::Write);
+        });
+        let _ = io::copy(&mut &target, &mut &client);
+        let _ = client.shutdown(Shutdown::Write);
+        let _ = upstream.join();
+    }
+
+    fn assert_echo(mut stream: TcpStream) {
+        stream.write_all(b"hello").unwrap();
+        let mut buffer = [0; 5];
+        stream.read_exact(&mut buffer).unwrap();
+        assert_eq!(&buffer, b"hello");
+    }
+
+    #[test]
+    fn should_connect_through_socks5_proxy() {
+        let port = echo_server();
+        let proxy = Proxy::socks5(proxy_stand_in(ProxyKind::Socks5, None));
+        assert_echo(proxy.connect("127.0.0.1", port, None).unwrap());
+        // host name resolved by the proxy
+        let port = echo_server();
+        let proxy = Proxy::socks5(proxy_stand_in(ProxyKind::Socks5, None));
+        assert_echo(
+            proxy
+                .connect("localhost", port, Some(Duration::from_secs(5)))
+                .unwrap(),
+        );
+    }
+
+    #[test]
+    fn should_authenticate_to_socks5_proxy() {
+        let port = echo_server();
+        let proxy = Proxy::socks5(proxy_stand_in(ProxyKind::Socks5, Some(("omar", "secret"))))
+            .credentials("omar", "secret");
+        assert_echo(proxy.connect("127.0.0.1", port, None).unwrap());
+        let proxy = Proxy::socks5(proxy_stand_in(ProxyKind::Socks5, Some(("omar", "secret"))))
+            .credentials("omar", "wrong");
+        assert_eq!(
+            proxy.connect("127.0.0.1", port, None).unwrap_err().kind(),
+            io::ErrorKind::PermissionDenied
+        );
+        // credentials required
+        let proxy = Proxy::socks5(proxy_stand_in(ProxyKind::Socks5, Some(("omar", "secret"))));
+        assert_eq!(
+            proxy.connect("127.0.0.1", port, None).unwrap_err().kind(),
+            io::ErrorKind::PermissionDenied
+        );
+    }
+
+    #[test]
+    fn should_report_socks5_connection_failure() {
+        // nothing listens on the port of a dropped listener
+        let port = TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port();
+        let proxy = Proxy::socks5(proxy_stand_in(ProxyKind::Socks5, None));
+        assert_eq!(
+            proxy.connect("127.0.0.1", port, None).unwrap_err().kind(),
+            io::ErrorKind::ConnectionRefused
+        );
+    }
+
+    #[test]
+    fn should_connect_through_http_proxy() {
+        let port = echo_server();
+        let proxy = Proxy::http(proxy_stand_in(ProxyKind::Http, None));
+        assert_echo(proxy.connect("127.0.0.1", port, None).unwrap());
+        let port = echo_server();
+        let proxy = Proxy::http(proxy_stand_in(ProxyKind::Http, Some(("omar", "secret"))))
+            .credentials("omar", "secret");
+        assert_echo(proxy.connect("localhost", port, None).unwrap());
+        let proxy = Proxy::http(proxy_stand_in(ProxyKind::Http, Some(("omar", "secret"))))
+            .credentials("omar", "wrong");
+        assert_eq!(
+            proxy.connect("127.0.0.1", port, None).unwrap_err().kind(),
+            io::ErrorKind::PermissionDenied
+        );
+    }
+
+    #[test]
+    fn should_build_proxy() {
+        let proxy = Proxy::socks5("proxy.example.com:1080").credentials("omar", "secret");
+        assert_eq!(proxy.kind(), ProxyKind::Socks5);
+        assert_eq!(proxy.address(), "proxy.example.com:1080");
+        let debug = format!("{proxy:?}");
+        assert!(debug.contains("omar"));
+        assert!(!debug.contains("secret"));
+        assert_eq!(Proxy::http("127.0.0.1:3128").kind(), ProxyKind::Http);
+    }
+
+    #[test]
+    fn should_encode_base64() {
+        assert_eq!(base64(b"").as_str(), "");
+        assert_eq!(base64(b"f").as_str(), "Zg==");
+        assert_eq!(base64(b"fo").as_str(), "Zm8=");
+        assert_eq!(base64(b"foo").as_str(), "Zm9v");
+        assert_eq!(base64(b"omar:secret").as_str(), "b21hcjpzZWNyZXQ=");
+    }
+}
@@ -0,0 +1,132 @@
+//! ## Active
+//!
+//! helpers for active mode data connections, where the client listens and the server connects to it
+//! after `PORT` (RFC 959) or `EPRT` (RFC 2428)
+
+use std::io;
+use std::net::{IpAddr, SocketAddr, SocketAddrV4, TcpListener, TcpStream};
+use std::ops::RangeInclusive;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::fxp;
+
+/// Bind a listener on `ip`, on the first free port in `ports` or on any port if `ports` is `None`
+pub fn bind(ip: IpAddr, ports: Option<&RangeInclusive<u16>>) -> io::Result<TcpListener> {
+    let Some(ports) = ports else {
+        return TcpListener::bind((ip, 0));
+    };
+    for port in ports.clone() {
+        match TcpListener::bind((ip, port)) {
+            Ok(listener) => return Ok(listener),
+            Err(e) if e.kind() == io::ErrorKind::AddrInUse => continue,
+            Err(e) => return Err(e),
+        }
+    }
+    Err(io::Error::new(
+        io::ErrorKind::AddrInUse,
+        format!("no free port between {} and {}", ports.start(), ports.end()),
+    ))
+}
+
+/// Make the command to make the server connect to `addr`: `PORT` for IPv4 addresses, `EPRT` for IPv6 addresses
+pub fn port_command(addr: SocketAddr) -> String {
+    match addr {
+        SocketAddr::V4(addr) => fxp::port_command(addr),
+        SocketAddr::V6(addr) => match addr.ip().to_ipv4_mapped() {
+            Some(ip) => fxp::port_command(SocketAddrV4::new(ip, addr.port())),
+            None => format!("EPRT |2|{}|{}|", addr.ip(), addr.port()),
+        },
+    }
+}
+
+/// Wait up to `timeout` for the server to connect to `listener`
+pub fn accept(listener: &TcpListener, timeout: Duration) -> io::Result<TcpStream> {
+    listener.set_nonblocking(true)?;
+    let start = Instant::now();
+    loop {
+        match listener.accept() {
+            Ok((stream, addr)) => {
+                trace!("Server connected from {}", addr);
+                stream.set_nonblocking(false)?;
+                return Ok(stream);
+            }
+            Err(e) if e.kind() == io::ErrorKind::WouldBlock => {
+                if start.elapsed() >= timeout {
+                    return Err(io::Error::new(
+                        io::ErrorKind::TimedOut,
+                        "server didn't open the data connection",
+                    ));
+                }
+                thread::sleep(Duration::from_millis(20));
+            }
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+
+    use std::net::{Ipv4Addr, Ipv6Addr};
+
+    use pretty_assertions::assert_eq;
+
+    use super::*;
+
+    #[test]
+    fn should_make_port_command() {
+        assert_eq!(
+            port_command(SocketAddr::from((Ipv4Addr::new(203, 0, 113, 7), 50000))).as_str(),
+            "PORT 203,0,113,7,195,80"
+        );
+        assert_eq!(
+            port_command(SocketAddr::from((
+                Ipv6Addr::new(0x2001, 0xdb8, 0, 0, 0, 0, 0, 1),
+                50000
+            )))
+            .as_str(),
+            "EPRT |2|2001:db8::1|50000|"
+        );
+        assert_eq!(
+            port_command(SocketAddr::from((
+                Ipv4Addr::new(10, 0, 0, 1).to_ipv6_mapped(),
+                21
+            )))
+            .as_str(),
+            "PORT 10,0,0,1,0,21"
+        );
+    }
+
+    #[test]
+    fn should_bind_in_port_range() {
+        let ip = IpAddr::from(Ipv4Addr::LOCALHOST);
+        let first = bind(ip, None).unwrap();
+        let port = first.local_addr().unwrap().port();
+        // the first port is taken, so the next one must be picked
+        let range = port..=port.saturating_add(16);
+        let second = bind(ip, Some(&range)).unwrap();
+        let second_port = second.local_addr().unwrap().port();
+        assert!(range.contains(&second_port));
+        assert_ne!(second_port, port);
+        assert_eq!(
+            bind(ip, Some(&(port..=port))).unwrap_err().kind(),
+            io::ErrorKind::AddrInUse
+        );
+    }
+
+    #[test]
+    fn should_accept_data_connection() {
+        let listener = bind(IpAddr::from(Ipv4Addr::LOCALHOST), None).unwrap();
+        let addr = listener.local_addr().unwrap();
+        let client = TcpStream::connect(addr).unwrap();
+        let stream = accept(&listener, Duration::from_secs(5)).unwrap();
+        assert_eq!(stream.peer_addr().unwrap(), client.local_addr().unwrap());
+        assert_eq!(
+            accept(&listener, Duration::from_millis(50))
+                .unwrap_err()
+                .kind(),
+            io::ErrorKind::TimedOut
+        );
+    }
+}
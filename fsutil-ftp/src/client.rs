@@ -2,19 +2,33 @@
 //!
 //! ftp client for fsutil
 
+use crate::data::DataChannel;
+use crate::encoding::{Charset, PathCodec};
+use crate::exec;
+use crate::features::FtpFeatures;
+use crate::fxp;
+use crate::keepalive::{self, Keepalive};
+use crate::list::{AutoParser, ListParser};
+use crate::mlsx;
+use crate::reader::{self, FtpReader, SharedControl};
+use crate::setstat::{self, MetadataField, SetStatReport};
+#[cfg(any(feature = "native-tls", feature = "rustls"))]
+use crate::tls::FtpTls;
+use crate::transfer::TransferMode;
 use crate::utils::path as path_utils;
 
+use fsutil_core::fs::stream::{LineEnding, LineEndingReader, LineEndingWriter, ReadAndSeek};
 use fsutil_core::fs::{
     FileType, Metadata, ReadStream, RemoteError, RemoteErrorType, RemoteFileSystem, RemoteResult,
-    UnixPex, UnixPexClass, Welcome, WriteStream,
+    UnixPex, Welcome, WriteStream,
 };
-use fsutil_core::File;
-use std::io::{Read, Write};
+use fsutil_core::{File, Proxy};
+use std::io::{self, Read, Write};
+use std::net::{IpAddr, Ipv6Addr, TcpStream, ToSocketAddrs};
+use std::ops::RangeInclusive;
 use std::path::{Path, PathBuf};
-#[cfg(feature = "native-tls")]
-use suppaftp::native_tls::TlsConnector as NativeTlsConnector;
-#[cfg(feature = "rustls")]
-use suppaftp::rustls::ClientConfig;
+use std::sync::Arc;
+use std::time::Duration;
 #[cfg(not(any(feature = "native-tls", feature = "rustls")))]
 pub use suppaftp::FtpStream;
 #[cfg(feature = "native-tls")]
@@ -26,15 +40,27 @@ use suppaftp::RustlsConnector as TlsConnector;
 #[cfg(feature = "rustls")]
 pub use suppaftp::RustlsFtpStream as FtpStream;
 use suppaftp::{
-    list::{File as FtpFile, PosixPexQuery},
-    types::{FileType as SuppaFtpFileType, Mode, Response},
+    types::{FileType as SuppaFtpFileType, FormatControl, Mode, Response},
     FtpError, Status,
 };
 
+/// Callback receiving a listing line which can't be parsed and the reason
+type UnparsableLineCallback = Arc<dyn Fn(&str, &str) + Send + Sync>;
+
 /// Ftp file system client
 pub struct FtpFileSystem {
     /// Client
     stream: Option<FtpStream>,
+    /// Control connection lent to the read stream of the transfer in progress
+    lent: SharedControl,
+    /// Keepalive state of the control connection
+    keepalive: Option<Keepalive>,
+    /// Working directory set with `change_dir`, restored on reconnect
+    wrkdir: Option<PathBuf>,
+    /// How data connections are opened; set on connect
+    channel: Option<DataChannel>,
+    /// Whether the session transfer type is ASCII; reset to binary on connect
+    ascii: bool,
     // -- options
     hostname: String,
     port: u16,
@@ -43,15 +69,41 @@ pub struct FtpFileSystem {
     password: Option<String>,
     /// Client mode; default: `Mode::Passive`
     mode: Mode,
+    /// Address advertised to the server in active mode; default: the local address
+    active_external_address: Option<IpAddr>,
+    /// Local ports to listen on in active mode; default: any
+    active_ports: Option<RangeInclusive<u16>>,
+    /// Server feature profile; negotiated on connect
+    features: Option<FtpFeatures>,
+    /// Set times of the files uploaded with `create_file`; default: `false`
+    preserve_times: bool,
+    /// Interval between `NOOP` keepalive messages; default: disabled
+    keepalive_interval: Option<Duration>,
+    /// Reconnect and login again when the control connection is found dead; default: `false`
+    reconnect: bool,
+    /// Timeout for connecting to the server; default: none
+    connection_timeout: Option<Duration>,
+    /// Proxy for the control connection and passive data connections; default: none
+    proxy: Option<Proxy>,
+    /// Timeout for reading and writing on the control connection; default: none
+    read_timeout: Option<Duration>,
+    /// Timeout for connecting, reading and writing on data connections; default: none
+    data_timeout: Option<Duration>,
+    /// Negotiate UTF-8 paths with `OPTS UTF8 ON` if the server supports it; default: `true`
+    utf8: bool,
+    /// Charset of paths when UTF-8 isn't negotiated; default: none, paths are decoded as UTF-8
+    charset: Option<Arc<dyn Charset>>,
+    /// Transfer type of files; default: `TransferMode::Binary`
+    transfer_mode: TransferMode,
+    /// Line ending of the files read in ASCII; default: the platform one
+    line_ending: LineEnding,
+    /// Parser of `LIST` listings; default: chosen from the system type of the server
+    list_parser: Option<Arc<dyn ListParser>>,
+    /// Called with the `LIST` lines which can't be parsed and the reason; default: none, they're only logged
+    on_unparsable_line: Option<UnparsableLineCallback>,
     #[cfg(any(feature = "native-tls", feature = "rustls"))]
-    /// use FTPS; default: `false`
-    secure: bool,
-    #[cfg(feature = "native-tls")]
-    /// Accept invalid certificates when building TLS connector. (Applies only if `secure`). Default: `false`
-    accept_invalid_certs: bool,
-    #[cfg(feature = "native-tls")]
-    /// Accept invalid hostnames when building TLS connector. (Applies only if `secure`). Default: `false`
-    accept_invalid_hostnames: bool,
+    /// TLS configuration, to use FTPS; default: `None`
+    tls: Option<FtpTls>,
 }
 
 impl FtpFileSystem {
@@ -59,17 +111,34 @@ impl FtpFileSystem {
     pub fn new<S: AsRef<str>>(hostname: S, port: u16) -> Self {
         Self {
             stream: None,
+            lent: SharedControl::default(),
+            keepalive: None,
+            wrkdir: None,
+            channel: None,
+            ascii: false,
             hostname: hostname.as_ref().to_string(),
             port,
             username: String::from("anonymous"),
             password: None,
             mode: Mode::Passive,
+            active_external_address: None,
+            active_ports: None,
+            features: None,
+            preserve_times: false,
+            keepalive_interval: None,
+            reconnect: false,
+            connection_timeout: None,
+            proxy: None,
+            read_timeout: None,
+            data_timeout: None,
+            utf8: true,
+            charset: None,
+            transfer_mode: TransferMode::default(),
+            line_ending: LineEnding::native(),
+            list_parser: None,
+            on_unparsable_line: None,
             #[cfg(any(feature = "native-tls", feature = "rustls"))]
-            secure: false,
-            #[cfg(feature = "native-tls")]
-            accept_invalid_certs: false,
-            #[cfg(feature = "native-tls")]
-            accept_invalid_hostnames: false,
+            tls: None,
         }
     }
 
@@ -93,91 +162,882 @@ impl FtpFileSystem {
         self
     }
 
-    /// Set passive mode for client
+    /// Set passive mode for client.
+    /// `EPSV` is used instead of `PASV` if the server is reached over IPv6
     pub fn passive_mode(mut self) -> Self {
         self.mode = Mode::Passive;
         self
     }
 
-    #[cfg(feature = "native-tls")]
-    /// enable FTPS and configure options
-    pub fn secure(mut self, accept_invalid_certs: bool, accept_invalid_hostnames: bool) -> Self {
-        self.secure = true;
-        self.accept_invalid_certs = accept_invalid_certs;
-        self.accept_invalid_hostnames = accept_invalid_hostnames;
+    /// Set extended passive mode (`EPSV`) for client
+    pub fn extended_passive_mode(mut self) -> Self {
+        self.mode = Mode::ExtendedPassive;
+        self
+    }
+
+    /// Set the address advertised to the server with `PORT` or `EPRT` in active mode,
+    /// e.g. the public address of a NAT. By default, the local address of the control connection is advertised
+    pub fn active_external_address(mut self, address: IpAddr) -> Self {
+        self.active_external_address = Some(address);
+        self
+    }
+
+    /// Set the local ports to listen on for data connections in active mode, e.g. the ports open on a firewall
+    pub fn active_port_range(mut self, ports: RangeInclusive<u16>) -> Self {
+        self.active_ports = Some(ports);
+        self
+    }
+
+    /// Set the modification time of the files uploaded with `create_file` to the one in their metadata,
+    /// if the server supports it (see [`FtpFileSystem::setstat_with_report`])
+    pub fn preserve_times(mut self, preserve: bool) -> Self {
+        self.preserve_times = preserve;
+        self
+    }
+
+    /// Send a `NOOP` to the server every `interval`, to keep it from closing the control connection for inactivity.
+    /// Keepalive messages are sent before each operation when due, and by [`FtpFileSystem::keepalive_send`]
+    pub fn keepalive_interval(mut self, interval: Duration) -> Self {
+        self.keepalive_interval = Some(interval);
+        self
+    }
+
+    /// Reconnect and login again when the control connection is found dead before an operation,
+    /// restoring the working directory set with `change_dir`
+    pub fn reconnect(mut self, reconnect: bool) -> Self {
+        self.reconnect = reconnect;
+        self
+    }
+
+    /// Set the timeout for connecting to the server
+    pub fn connection_timeout(mut self, timeout: Duration) -> Self {
+        self.connection_timeout = Some(timeout);
+        self
+    }
+
+    /// Set the timeout for reading and writing on the control connection
+    pub fn read_timeout(mut self, timeout: Duration) -> Self {
+        self.read_timeout = Some(timeout);
+        self
+    }
+
+    /// Set the timeout for connecting, reading and writing on data connections
+    pub fn data_timeout(mut self, timeout: Duration) -> Self {
+        self.data_timeout = Some(timeout);
+        self
+    }
+
+    /// Negotiate UTF-8 paths with `OPTS UTF8 ON` when the server advertises `UTF8`.
+    /// Disable it for servers which advertise UTF-8 but store names in a legacy charset
+    pub fn utf8(mut self, utf8: bool) -> Self {
+        self.utf8 = utf8;
+        self
+    }
+
+    /// Set the charset used to encode paths in commands and to decode replies and listings,
+    /// when UTF-8 isn't negotiated (e.g. [`crate::Latin1`]).
+    ///
+    /// Names which can't be decoded are escaped as described in [`crate::encoding`], so that they can be
+    /// passed back to the client. Escaped names can't be sent on a secure control connection
+    pub fn charset(mut self, charset: impl Charset + 'static) -> Self {
+        self.charset = Some(Arc::new(charset));
+        self
+    }
+
+    /// Connect through `proxy`, for the control connection and the data connections in passive mode.
+    ///
+    /// In active mode the server connects to the client, which requires an external address it can reach
+    /// (see [`Self::active_external_address`]). Implicit FTPS isn't supported through a proxy
+    pub fn proxy(mut self, proxy: Proxy) -> Self {
+        self.proxy = Some(proxy);
         self
     }
 
+    /// Set the transfer type of files: in ASCII, the server converts line endings and so does the client,
+    /// from `\r\n` to the line ending set with [`Self::line_ending`] when reading,
+    /// and from `\n` or `\r\n` to `\r\n` when writing
+    pub fn transfer_mode(mut self, mode: TransferMode) -> Self {
+        self.transfer_mode = mode;
+        self
+    }
+
+    /// Set the line ending of the files read in ASCII; default: the platform one
+    pub fn line_ending(mut self, line_ending: LineEnding) -> Self {
+        self.line_ending = line_ending;
+        self
+    }
+
+    /// Set the parser of `LIST` listings, used when the server doesn't support `MLSD`.
+    /// By default, the built-in parsers are tried in turn, starting with the one matching the system type of
+    /// the server (see [`crate::list::AutoParser`])
+    pub fn list_parser(mut self, parser: impl ListParser + 'static) -> Self {
+        self.list_parser = Some(Arc::new(parser));
+        self
+    }
+
+    /// Set a callback receiving the `LIST` lines which can't be parsed, with the reason.
+    /// Such lines are skipped, and logged in any case
+    pub fn on_unparsable_line(
+        mut self,
+        callback: impl Fn(&str, &str) + Send + Sync + 'static,
+    ) -> Self {
+        self.on_unparsable_line = Some(Arc::new(callback));
+        self
+    }
+
+    #[cfg(feature = "native-tls")]
+    /// enable FTPS and configure options; shorthand for [`Self::tls`] with the default [`FtpTls`]
+    pub fn secure(self, accept_invalid_certs: bool, accept_invalid_hostnames: bool) -> Self {
+        self.tls(
+            FtpTls::default()
+                .accept_invalid_certs(accept_invalid_certs)
+                .accept_invalid_hostnames(accept_invalid_hostnames),
+        )
+    }
+
     #[cfg(feature = "rustls")]
-    pub fn secure(mut self) -> Self {
-        self.secure = true;
+    /// enable FTPS; shorthand for [`Self::tls`] with the default [`FtpTls`]
+    pub fn secure(self) -> Self {
+        self.tls(FtpTls::default())
+    }
+
+    #[cfg(any(feature = "native-tls", feature = "rustls"))]
+    /// enable FTPS with the TLS configuration `tls`.
+    /// With implicit FTPS, the port should usually be [`crate::tls::IMPLICIT_PORT`]
+    pub fn tls(mut self, tls: FtpTls) -> Self {
+        self.tls = Some(tls);
         self
     }
 
+    // -- setters
+
+    /// Change the transfer type of the next transfers, e.g. to transfer a single file in ASCII
+    pub fn set_transfer_mode(&mut self, mode: TransferMode) {
+        self.transfer_mode = mode;
+    }
+
     // -- as_ref
 
+    /// Get the server feature profile, negotiated on connect; `None` if not connected
+    pub fn features(&self) -> Option<&FtpFeatures> {
+        self.features.as_ref()
+    }
+
     /// Get reference to inner stream
     pub fn stream(&mut self) -> Option<&mut FtpStream> {
+        self.reclaim_stream();
         self.stream.as_mut()
     }
 
+    /// Send a `NOOP` to the server, if one is due.
+    ///
+    /// Keepalive messages are also sent before each operation, but callers which leave the client idle
+    /// for long should call this method periodically; nothing is sent while a file is being read.
+    /// Returns the time before the next keepalive message is due, or `None` if keepalive is disabled
+    pub fn keepalive_send(&mut self) -> RemoteResult<Option<Duration>> {
+        self.reclaim_stream();
+        let (Some(stream), Some(keepalive)) = (self.stream.as_mut(), self.keepalive.as_mut())
+        else {
+            return Err(RemoteError::new(RemoteErrorType::NotConnected));
+        };
+        match keepalive.due_in() {
+            Some(due_in) if due_in.is_zero() && !reader::lock(&self.lent).pending => {
+                trace!("Sending keepalive");
+                stream.noop().map_err(|e| {
+                    error!("Failed to send keepalive: {}", e);
+                    RemoteError::new_ex(RemoteErrorType::ConnectionError, e)
+                })?;
+                keepalive.touch();
+                Ok(keepalive.interval())
+            }
+            due_in => Ok(due_in),
+        }
+    }
+
+    /// Set the attributes in `metadata` on file at `path`, as far as the server supports it:
+    /// `modified` through `MFMT` or `SITE UTIME`, `accessed` through `SITE UTIME` and `mode` through `SITE CHMOD`.
+    ///
+    /// Attributes the server can't set, such as `uid` and `gid`, don't fail the call,
+    /// but are reported in the returned [`SetStatReport`]
+    pub fn setstat_with_report(
+        &mut self,
+        path: &Path,
+        metadata: &Metadata,
+    ) -> RemoteResult<SetStatReport> {
+        debug!("Setting attributes for {}", path.display());
+        self.check_connection()?;
+        let path = Self::resolve(path);
+        let features = self.features.clone().unwrap_or_default();
+        // servers often don't advertise their SITE commands, so try them if none is advertised
+        let site_allowed = |cmd: &str| features.site.is_empty() || features.supports_site(cmd);
+        let mut applied = Vec::new();
+        if let Some(modified) = metadata.modified {
+            let mut cmds: Vec<(String, bool)> = Vec::new();
+            if features.mfmt {
+                cmds.push((setstat::mfmt(path.as_path(), modified), false));
+            }
+            if site_allowed("UTIME") {
+                let accessed = metadata.accessed.unwrap_or(modified);
+                cmds.push((
+                    setstat::site_utime(path.as_path(), accessed, modified),
+                    metadata.accessed.is_some(),
+                ));
+                cmds.push((setstat::site_utime_short(path.as_path(), modified), false));
+            }
+            let accepted = self.perform_setstat_cmds(cmds.iter().map(|(cmd, _)| cmd.as_str()))?;
+            if let Some(sets_accessed) = accepted.map(|i| cmds[i].1) {
+                applied.push(MetadataField::Modified);
+                if sets_accessed {
+                    applied.push(MetadataField::Accessed);
+                }
+            }
+        } else if let Some(accessed) = metadata.accessed {
+            // SITE UTIME sets both times; without the modification time, keep the current one
+            let modified = self.stat(path.as_path())?.metadata.modified;
+            if let Some(modified) = modified.filter(|_| site_allowed("UTIME")) {
+                let cmd = setstat::site_utime(path.as_path(), accessed, modified);
+                if self.perform_setstat_cmds([cmd.as_str()])?.is_some() {
+                    applied.push(MetadataField::Accessed);
+                }
+            }
+        }
+        if let Some(mode) = metadata.mode {
+            if site_allowed("CHMOD") {
+                let cmd = setstat::site_chmod(path.as_path(), mode);
+                if self.perform_setstat_cmds([cmd.as_str()])?.is_some() {
+                    applied.push(MetadataField::Mode);
+                }
+            }
+        }
+        let report = SetStatReport::new(metadata, applied);
+        if !report.is_complete() {
+            warn!(
+                "Server can't set {} for {}",
+                report
+                    .unsupported
+                    .iter()
+                    .map(|x| x.to_string())
+                    .collect::<Vec<String>>()
+                    .join(", "),
+                path.display()
+            );
+        }
+        Ok(report)
+    }
+
+    /// Copy file or directory `src` to `dest` on the server `dest_fs` is connected to, which may be another server.
+    ///
+    /// Files are transferred server to server with FXP when both servers allow it;
+    /// otherwise they're streamed through the client. Directories are copied recursively.
+    /// Returns the amount of bytes copied
+    pub fn copy_to(
+        &mut self,
+        src: &Path,
+        dest_fs: &mut FtpFileSystem,
+        dest: &Path,
+    ) -> RemoteResult<u64> {
+        debug!("Copying {} to {}", src.display(), dest.display());
+        self.check_connection()?;
+        dest_fs.check_connection()?;
+        let src = Self::resolve(src);
+        let dest = Self::resolve(dest);
+        let file = self.stat(src.as_path())?;
+        self.copy_entry(&file, dest_fs, dest.as_path(), true)
+    }
+
+    /// Open file at `path` for read, starting at `offset` with `REST`.
+    ///
+    /// The returned stream can seek: forward seeks within a small distance discard the data in between,
+    /// any other seek restarts the transfer at the new offset. To do so, the control connection is lent
+    /// to the stream until it is finalized with `on_read`; calling any other method takes it back,
+    /// after which the stream can't seek anymore.
+    ///
+    /// Files transferred in ASCII are read with their line endings converted, through a stream which can't seek.
+    ///
+    /// Returns [`RemoteErrorType::UnsupportedFeature`] if the server refuses `REST`,
+    /// or if `offset` isn't 0 and the file is transferred in ASCII
+    pub fn open_at(&mut self, path: &Path, offset: u64) -> RemoteResult<ReadStream> {
+        debug!("Opening {} for read at {}", path.display(), offset);
+        self.check_connection()?;
+        let path = Self::resolve(path);
+        let ascii = self.select_transfer_type(path.as_path(), offset)?;
+        let path = path.to_string_lossy().to_string();
+        let stream = self.stream.as_mut().unwrap();
+        if offset > 0 {
+            stream.resume_transfer(offset as usize).map_err(|e| {
+                error!("Failed to restart transfer at {}: {}", offset, e);
+                RemoteError::new_ex(RemoteErrorType::UnsupportedFeature, e)
+            })?;
+        }
+        let channel = self.channel.clone().unwrap();
+        let data = channel.retr(stream, path.as_str()).map_err(|e| {
+            error!("Failed to open file: {}", e);
+            RemoteError::new_ex(RemoteErrorType::ProtocolError, e)
+        })?;
+        let mut control = reader::lock(&self.lent);
+        control.stream = self.stream.take();
+        control.pending = true;
+        let reader = FtpReader::new(self.lent.clone(), channel, data, path, offset);
+        match ascii {
+            true => Ok(ReadStream::from(
+                Box::new(LineEndingReader::new(reader, self.line_ending)) as Box<dyn Read + Send>,
+            )),
+            false => Ok(ReadStream::from(Box::new(reader) as Box<dyn ReadAndSeek>)),
+        }
+    }
+
+    /// Open file at `path` to resume an upload at `offset` with `REST` and `STOR`:
+    /// the data written to the stream replaces the file content from `offset` on.
+    /// The stream must be finalized with `on_written`.
+    ///
+    /// Returns [`RemoteErrorType::UnsupportedFeature`] if the server doesn't advertise `REST STREAM`,
+    /// or if `offset` isn't 0 and the file is transferred in ASCII
+    pub fn resume_upload(&mut self, path: &Path, offset: u64) -> RemoteResult<WriteStream> {
+        debug!("Resuming upload of {} at {}", path.display(), offset);
+        self.check_connection()?;
+        if !self.features.as_ref().is_some_and(|x| x.rest_stream) {
+            return Err(RemoteError::new_ex(
+                RemoteErrorType::UnsupportedFeature,
+                "server doesn't support REST STREAM",
+            ));
+        }
+        let path = Self::resolve(path);
+        let ascii = self.select_transfer_type(path.as_path(), offset)?;
+        let stream = self.stream.as_mut().unwrap();
+        stream.resume_transfer(offset as usize).map_err(|e| {
+            error!("Failed to restart transfer at {}: {}", offset, e);
+            RemoteError::new_ex(RemoteErrorType::UnsupportedFeature, e)
+        })?;
+        let channel = self.channel.as_ref().unwrap();
+        channel
+            .stor(stream, path.as_path().to_string_lossy().as_ref())
+            .map(|x| Self::text_writer(x, ascii))
+            .map_err(|e| {
+                error!("Failed to open file: {}", e);
+                RemoteError::new_ex(RemoteErrorType::ProtocolError, e)
+            })
+    }
+
     // -- private
 
-    /// Parse all lines of LIST command output and instantiates a vector of `File` from it.
-    /// This function also converts from `suppaftp::list::File` to `File`
-    fn parse_list_lines(&mut self, path: &Path, lines: Vec<String>) -> Vec<File> {
-        // Iter and collect
+    /// Take back the control connection lent to a read stream, if any
+    fn reclaim_stream(&mut self) {
+        if self.stream.is_none() {
+            self.stream = reader::lock(&self.lent).stream.take();
+        }
+    }
+
+    /// Switch the session to the transfer type of `path`, if it isn't already; returns whether it's ASCII.
+    ///
+    /// Transfers in ASCII can't start at an `offset`, since offsets on the server don't match converted data
+    fn select_transfer_type(&mut self, path: &Path, offset: u64) -> RemoteResult<bool> {
+        let ascii = self.transfer_mode.is_ascii(path);
+        if ascii && offset > 0 {
+            return Err(RemoteError::new_ex(
+                RemoteErrorType::UnsupportedFeature,
+                "transfers in ASCII can't start at an offset",
+            ));
+        }
+        if ascii != self.ascii {
+            let file_type = match ascii {
+                true => SuppaFtpFileType::Ascii(FormatControl::Default),
+                false => SuppaFtpFileType::Binary,
+            };
+            trace!("Setting transfer type to {}", file_type);
+            self.stream
+                .as_mut()
+                .unwrap()
+                .transfer_type(file_type)
+                .map_err(|e| {
+                    error!("Failed to set transfer type: {}", e);
+                    RemoteError::new_ex(RemoteErrorType::ProtocolError, e)
+                })?;
+            self.ascii = ascii;
+        }
+        Ok(ascii)
+    }
+
+    /// Make the write stream of a data connection, converting line endings to `\r\n` in ASCII
+    fn text_writer(data: Box<dyn Write + Send>, ascii: bool) -> WriteStream {
+        match ascii {
+            true => {
+                WriteStream::from(Box::new(LineEndingWriter::new(data, LineEnding::CrLf))
+                    as Box<dyn Write + Send>)
+            }
+            false => WriteStream::from(data),
+        }
+    }
+
+    /// Make a new client with the same options, not connected
+    fn twin(&self) -> Self {
+        Self {
+            stream: None,
+            lent: SharedControl::default(),
+            keepalive: None,
+            wrkdir: None,
+            channel: None,
+            ascii: false,
+            hostname: self.hostname.clone(),
+            port: self.port,
+            username: self.username.clone(),
+            password: self.password.clone(),
+            mode: self.mode,
+            active_external_address: self.active_external_address,
+            active_ports: self.active_ports.clone(),
+            features: None,
+            preserve_times: self.preserve_times,
+            keepalive_interval: self.keepalive_interval,
+            reconnect: self.reconnect,
+            connection_timeout: self.connection_timeout,
+            proxy: self.proxy.clone(),
+            read_timeout: self.read_timeout,
+            data_timeout: self.data_timeout,
+            utf8: self.utf8,
+            charset: self.charset.clone(),
+            transfer_mode: self.transfer_mode.clone(),
+            line_ending: self.line_ending,
+            list_parser: self.list_parser.clone(),
+            on_unparsable_line: self.on_unparsable_line.clone(),
+            #[cfg(any(feature = "native-tls", feature = "rustls"))]
+            tls: self.tls.clone(),
+        }
+    }
+
+    /// Copy `file` to `dest` on `dest_fs`; directories are copied recursively
+    fn copy_entry(
+        &mut self,
+        file: &File,
+        dest_fs: &mut FtpFileSystem,
+        dest: &Path,
+        fxp: bool,
+    ) -> RemoteResult<u64> {
+        if file.is_file() {
+            return self.copy_file(file, dest_fs, dest, fxp);
+        }
+        if !file.is_dir() {
+            warn!(
+                "Skipping {}: symlinks can't be copied",
+                file.path().display()
+            );
+            return Ok(0);
+        }
+        trace!("Copying directory {}", file.path().display());
+        match dest_fs.create_dir(dest, file.metadata().mode.unwrap_or(UnixPex::from(0o755))) {
+            Ok(())
+            | Err(RemoteError {
+                kind: RemoteErrorType::DirectoryAlreadyExists,
+                ..
+            }) => {}
+            Err(err) => return Err(err),
+        }
+        let mut bytes: u64 = 0;
+        for entry in self.list_dir(file.path())? {
+            let name = entry.name();
+            if name == "." || name == ".." {
+                continue;
+            }
+            bytes += self.copy_entry(&entry, dest_fs, dest.join(name).as_path(), fxp)?;
+        }
+        Ok(bytes)
+    }
+
+    /// Copy regular `file` to `dest` on `dest_fs`, checking that the whole file has been copied,
+    /// unless it's transferred in ASCII, whose size changes with line endings
+    fn copy_file(
+        &mut self,
+        file: &File,
+        dest_fs: &mut FtpFileSystem,
+        dest: &Path,
+        fxp: bool,
+    ) -> RemoteResult<u64> {
+        trace!("Copying file {}", file.path().display());
+        let size = file.metadata().size;
+        let ascii =
+            self.transfer_mode.is_ascii(file.path()) || dest_fs.transfer_mode.is_ascii(dest);
+        let mut copied: Option<u64> = None;
+        if fxp && self.fxp_allowed() && dest_fs.fxp_allowed() {
+            match self.fxp_file(file.path(), dest_fs, dest) {
+                Ok(()) => copied = Some(dest_fs.stat(dest)?.metadata().size),
+                Err(err) if err.kind == RemoteErrorType::UnsupportedFeature => {
+                    warn!(
+                        "FXP not allowed; streaming file through the client: {}",
+                        err
+                    );
+                }
+                Err(err) => return Err(err),
+            }
+        }
+        let copied = match copied {
+            Some(copied) => copied,
+            None => self.stream_file(file.path(), dest_fs, dest)?,
+        };
+        if !ascii && copied != size {
+            error!("Copied {} bytes out of {}", copied, size);
+            return Err(RemoteError::new_ex(
+                RemoteErrorType::ProtocolError,
+                format!("copied {copied} bytes out of {size}"),
+            ));
+        }
+        if dest_fs.preserve_times && file.metadata().modified.is_some() {
+            let times = Metadata {
+                modified: file.metadata().modified,
+                ..Default::default()
+            };
+            dest_fs.setstat_with_report(dest, &times)?;
+        }
+        Ok(copied)
+    }
+
+    /// Stream file at `src` to `dest` on `dest_fs`, reading and writing at once on the two data connections
+    fn stream_file(
+        &mut self,
+        src: &Path,
+        dest_fs: &mut FtpFileSystem,
+        dest: &Path,
+    ) -> RemoteResult<u64> {
+        let mut reader = self.open(src)?;
+        let mut writer = match dest_fs.create(dest, &Metadata::default()) {
+            Ok(writer) => writer,
+            Err(err) => {
+                let _ = self.on_read(reader);
+                return Err(err);
+            }
+        };
+        let copied = io::copy(&mut reader, &mut writer);
+        // finalize both transfers, whatever the result of the copy
+        let written = dest_fs.on_written(writer);
+        let read = self.on_read(reader);
+        let copied = copied.map_err(|e| {
+            error!("Failed to copy file: {}", e);
+            RemoteError::new_ex(RemoteErrorType::ProtocolError, e)
+        })?;
+        written?;
+        read?;
+        Ok(copied)
+    }
+
+    /// Returns whether this client can take part in a FXP transfer; FXP isn't supported over TLS
+    fn fxp_allowed(&self) -> bool {
+        !self.is_secure()
+    }
+
+    /// Returns whether the control connection is secured with TLS
+    fn is_secure(&self) -> bool {
+        #[cfg(any(feature = "native-tls", feature = "rustls"))]
+        if self.tls.is_some() {
+            return true;
+        }
+        false
+    }
+
+    /// Send `cmd` on the control connection, with its paths encoded, and read the reply
+    fn command(&mut self, cmd: &str, expected: &[Status]) -> Result<Response, FtpError> {
+        let stream = self.stream.as_mut().unwrap();
+        let codec = &self.channel.as_ref().unwrap().codec;
+        codec.command(stream, cmd, expected)
+    }
+
+    /// Decode the text of `response`
+    fn decode(&self, response: &Response) -> String {
+        self.channel.as_ref().unwrap().codec.decode(&response.body)
+    }
+
+    /// Transfer file at `src` to `dest` on `dest_fs` server to server:
+    /// the destination listens with `PASV` and the source connects to it after `PORT`.
+    ///
+    /// Returns [`RemoteErrorType::UnsupportedFeature`] if any of the servers refuses the transfer,
+    /// or if the two files aren't transferred with the same type
+    fn fxp_file(
+        &mut self,
+        src: &Path,
+        dest_fs: &mut FtpFileSystem,
+        dest: &Path,
+    ) -> RemoteResult<()> {
+        let unsupported = |e: FtpError| RemoteError::new_ex(RemoteErrorType::UnsupportedFeature, e);
+        if self.transfer_mode.is_ascii(src) != dest_fs.transfer_mode.is_ascii(dest) {
+            return Err(RemoteError::new_ex(
+                RemoteErrorType::UnsupportedFeature,
+                "source and destination are transferred with different types",
+            ));
+        }
+        self.select_transfer_type(src, 0)?;
+        dest_fs.select_transfer_type(dest, 0)?;
+        let dest_stream = dest_fs.stream.as_mut().unwrap();
+        let reply = dest_stream
+            .custom_command("PASV", &[Status::PassiveMode])
+            .map_err(unsupported)?;
+        let addr = fxp::parse_pasv_reply(String::from_utf8_lossy(&reply.body).as_ref())
+            .ok_or_else(|| {
+                RemoteError::new_ex(RemoteErrorType::UnsupportedFeature, "bad PASV reply")
+            })?;
+        trace!("Destination listening at {}", addr);
+        let src_stream = self.stream.as_mut().unwrap();
+        src_stream
+            .custom_command(fxp::port_command(addr), &[Status::CommandOk])
+            .map_err(unsupported)?;
+        let dest_codec = &dest_fs.channel.as_ref().unwrap().codec;
+        dest_codec
+            .command(
+                dest_stream,
+                &format!("STOR {}", dest.to_string_lossy()),
+                &[Status::AboutToSend, Status::AlreadyOpen],
+            )
+            .map_err(|e| {
+                error!("Failed to open destination file: {}", e);
+                RemoteError::new_ex(RemoteErrorType::FileCreateDenied, e)
+            })?;
+        let src_codec = &self.channel.as_ref().unwrap().codec;
+        if let Err(e) = src_codec.command(
+            src_stream,
+            &format!("RETR {}", src.to_string_lossy()),
+            &[Status::AboutToSend, Status::AlreadyOpen],
+        ) {
+            Self::abort_pending_store(dest_stream);
+            return Err(unsupported(e));
+        }
+        // wait for both transfers to complete
+        let read = src_stream.finalize_retr_stream(io::empty());
+        let written = dest_stream.finalize_put_stream(io::sink());
+        read.and(written).map_err(|e| {
+            error!("FXP transfer failed: {}", e);
+            RemoteError::new_ex(RemoteErrorType::ProtocolError, e)
+        })
+    }
+
+    /// Abort a `STOR` which is waiting for its data connection
+    fn abort_pending_store(stream: &mut FtpStream) {
+        match stream.custom_command(
+            "ABOR",
+            &[
+                Status::TransferAborted,
+                Status::ClosingDataConnection,
+                Status::DataConnectionOpen,
+            ],
+        ) {
+            // the aborted transfer is replied first, then the abort
+            Ok(Response {
+                status: Status::TransferAborted,
+                ..
+            }) => {
+                let _ = stream.finalize_retr_stream(io::empty());
+            }
+            Ok(_) => {}
+            Err(e) => warn!("Failed to abort transfer: {}", e),
+        }
+    }
+
+    /// Send `cmds` in order, until the server accepts one.
+    /// Returns the index of the accepted command, or `None` if the server doesn't implement any of them
+    fn perform_setstat_cmds<'a>(
+        &mut self,
+        cmds: impl IntoIterator<Item = &'a str>,
+    ) -> RemoteResult<Option<usize>> {
+        for (i, cmd) in cmds.into_iter().enumerate() {
+            match self.command(cmd, &[Status::CommandOk, Status::File]) {
+                Ok(_) => return Ok(Some(i)),
+                Err(FtpError::UnexpectedResponse(Response {
+                    status:
+                        Status::BadCommand
+                        | Status::BadArguments
+                        | Status::NotImplemented
+                        | Status::NotImplementedParameter,
+                    ..
+                })) => {
+                    debug!("Server doesn't implement \"{}\"", cmd);
+                }
+                Err(FtpError::UnexpectedResponse(Response {
+                    status: Status::FileUnavailable,
+                    ..
+                })) => {
+                    error!("Failed to set attributes: no such file or directory");
+                    return Err(RemoteError::new(RemoteErrorType::NoSuchFileOrDirectory));
+                }
+                Err(e) => {
+                    error!("Failed to set attributes: {}", e);
+                    return Err(RemoteError::new_ex(RemoteErrorType::StatFailed, e));
+                }
+            }
+        }
+        Ok(None)
+    }
+
+    /// Negotiate the server feature profile with `SYST` and `FEAT`.
+    /// If the server supports `MLST`, enable the facts we're interested in with `OPTS MLST`
+    fn negotiate_features(stream: &mut FtpStream) -> FtpFeatures {
+        let system = match stream.custom_command("SYST", &[Status::Name]) {
+            Ok(response) => response.as_string().ok().map(|x| {
+                x.trim_start_matches("215")
+                    .trim_start_matches([' ', '-'])
+                    .to_string()
+            }),
+            Err(e) => {
+                debug!("SYST failed: {}", e);
+                None
+            }
+        };
+        let features = match stream.feat() {
+            Ok(features) => FtpFeatures::new(&features, system),
+            Err(e) => {
+                debug!("FEAT failed; assuming no extension is supported: {}", e);
+                FtpFeatures::new(&Default::default(), system)
+            }
+        };
+        debug!("Server features: {:?}", features);
+        match features.mlst.as_deref() {
+            Some(facts) => {
+                let wanted: Vec<&str> = mlsx::WANTED_FACTS
+                    .iter()
+                    .copied()
+                    .filter(|wanted| facts.iter().any(|(fact, _)| fact == wanted))
+                    .collect();
+                if wanted.iter().any(|wanted| {
+                    facts
+                        .iter()
+                        .any(|(fact, enabled)| fact == wanted && !enabled)
+                }) {
+                    let value = format!("{};", wanted.join(";"));
+                    trace!("Enabling MLST facts {}", value);
+                    if let Err(e) = stream.opts("MLST", Some(value)) {
+                        warn!("Failed to enable MLST facts: {}", e);
+                    }
+                }
+            }
+            None => debug!("Server doesn't support MLST; LIST will be used"),
+        }
+        features
+    }
+
+    /// Ask the server to use UTF-8 paths with `OPTS UTF8 ON`; returns whether it agreed
+    fn enable_utf8(stream: &mut FtpStream) -> bool {
+        // 202 means UTF-8 is always on
+        match stream.custom_command(
+            "OPTS UTF8 ON",
+            &[Status::CommandOk, Status::CommandNotImplemented],
+        ) {
+            Ok(_) => {
+                debug!("UTF-8 paths enabled");
+                true
+            }
+            Err(e) => {
+                warn!("Failed to enable UTF-8 paths: {}", e);
+                false
+            }
+        }
+    }
+
+    /// Returns whether `MLSD` and `MLST` can be used
+    fn supports_mlst(&self) -> bool {
+        self.features.as_ref().is_some_and(|x| x.mlst.is_some())
+    }
+
+    /// Parse all lines of MLSD command output and instantiates a vector of `File` from it.
+    /// Entries for the directory itself and its parent are skipped
+    fn parse_mlsd_lines(path: &Path, lines: Vec<String>) -> Vec<File> {
         lines
             .into_iter()
-            .flat_map(FtpFile::try_from)
-            .map(|f| {
-                let mut abs_path: PathBuf = path.to_path_buf();
-                abs_path.push(f.name());
-                let file_type = if f.is_symlink() {
-                    FileType::Symlink
-                } else if f.is_directory() {
-                    FileType::Directory
-                } else {
-                    FileType::File
-                };
-                let metadata = Metadata {
-                    accessed: None,
-                    created: None,
-                    file_type,
-                    gid: f.gid(),
-                    mode: Some(Self::query_unix_pex(&f)),
-                    modified: Some(f.modified()),
-                    size: f.size() as u64,
-                    symlink: f.symlink().map(|x| path_utils::absolutize(path, x)),
-                    uid: None,
-                };
+            .filter_map(|line| match mlsx::parse_line(line.as_str()) {
+                Ok(entry) => Some(entry),
+                Err(e) => {
+                    warn!("Skipping bad MLSD line: {}", e);
+                    None
+                }
+            })
+            .filter(|entry| !entry.special)
+            .map(|mut entry| {
+                entry.metadata.symlink = entry
+                    .metadata
+                    .symlink
+                    .map(|x| path_utils::absolutize(path, x.as_path()));
                 File {
-                    path: abs_path,
-                    metadata,
+                    path: path.join(entry.name),
+                    metadata: entry.metadata,
                 }
             })
             .collect()
     }
 
-    /// Returns unix pex from ftp file pex
-    fn query_unix_pex(f: &FtpFile) -> UnixPex {
-        UnixPex::new(
-            UnixPexClass::new(
-                f.can_read(PosixPexQuery::Owner),
-                f.can_write(PosixPexQuery::Owner),
-                f.can_execute(PosixPexQuery::Owner),
-            ),
-            UnixPexClass::new(
-                f.can_read(PosixPexQuery::Group),
-                f.can_write(PosixPexQuery::Group),
-                f.can_execute(PosixPexQuery::Group),
-            ),
-            UnixPexClass::new(
-                f.can_read(PosixPexQuery::Others),
-                f.can_write(PosixPexQuery::Others),
-                f.can_execute(PosixPexQuery::Others),
-            ),
-        )
+    /// Stat file at absolute `path` with `MLST`
+    fn stat_mlst(&mut self, path: &Path) -> RemoteResult<File> {
+        let line = match self.command(
+            &format!("MLST {}", path.display()),
+            &[Status::RequestedFileActionOk],
+        ) {
+            // the fact line follows the first line of the reply
+            Ok(response) => match self.decode(&response).lines().nth(1) {
+                Some(line) if !line.trim().is_empty() => line.trim().to_string(),
+                _ => {
+                    error!("Failed to stat file: bad MLST reply");
+                    return Err(RemoteError::new_ex(
+                        RemoteErrorType::ProtocolError,
+                        "bad MLST reply",
+                    ));
+                }
+            },
+            Err(FtpError::UnexpectedResponse(Response {
+                status: Status::FileUnavailable,
+                ..
+            })) => {
+                error!("Could not find file; no such file or directory");
+                return Err(RemoteError::new(RemoteErrorType::NoSuchFileOrDirectory));
+            }
+            Err(e) => {
+                error!("Failed to stat file: {}", e);
+                return Err(RemoteError::new_ex(RemoteErrorType::ProtocolError, e));
+            }
+        };
+        let mut entry = mlsx::parse_line(line.as_str()).map_err(|e| {
+            error!("Failed to parse MLST output: {}", e);
+            RemoteError::new_ex(RemoteErrorType::ProtocolError, e)
+        })?;
+        if let Some(parent) = path.parent() {
+            entry.metadata.symlink = entry
+                .metadata
+                .symlink
+                .map(|x| path_utils::absolutize(parent, x.as_path()));
+        }
+        Ok(File {
+            path: path.to_path_buf(),
+            metadata: entry.metadata,
+        })
+    }
+
+    /// Parse the lines of a `LIST` listing of `path` with the configured parser,
+    /// or with the one matching the system type of the server.
+    /// Lines which can't be parsed are reported to the `on_unparsable_line` callback and skipped
+    fn parse_list_lines(&self, path: &Path, lines: Vec<String>) -> Vec<File> {
+        let auto;
+        let parser: &dyn ListParser = match self.list_parser.as_deref() {
+            Some(parser) => parser,
+            None => {
+                auto = AutoParser::for_system(
+                    self.features.as_ref().and_then(|x| x.system.as_deref()),
+                );
+                &auto
+            }
+        };
+        lines
+            .into_iter()
+            .filter_map(|line| match parser.parse_line(line.as_str()) {
+                Ok(entry) => entry,
+                Err(e) => {
+                    warn!("Skipping bad LIST line: {}", e);
+                    if let Some(callback) = self.on_unparsable_line.as_ref() {
+                        callback(line.as_str(), e.as_str());
+                    }
+                    None
+                }
+            })
+            .map(|mut entry| {
+                entry.metadata.symlink = entry
+                    .metadata
+                    .symlink
+                    .map(|x| path_utils::absolutize(path, x.as_path()));
+                File {
+                    path: path.join(entry.name),
+                    metadata: entry.metadata,
+                }
+            })
+            .collect()
     }
 
     /// Fix provided path; on Windows fixes the backslashes, converting them to slashes
@@ -192,73 +1052,258 @@ impl FtpFileSystem {
         p.to_path_buf()
     }
 
+    /// Check connection status before an operation.
+    ///
+    /// If the control connection has been idle for long, it's probed with `NOOP`, which also serves as keepalive;
+    /// a dead connection is reestablished if `reconnect` is enabled, otherwise it's dropped
     fn check_connection(&mut self) -> RemoteResult<()> {
-        if self.is_connected() {
-            Ok(())
+        self.reclaim_stream();
+        let Some(keepalive) = self.keepalive.as_ref() else {
+            return Err(RemoteError::new(RemoteErrorType::NotConnected));
+        };
+        if keepalive.should_probe() && !reader::lock(&self.lent).pending && !self.probe() {
+            if !self.reconnect {
+                self.drop_connection();
+                return Err(RemoteError::new_ex(
+                    RemoteErrorType::ConnectionError,
+                    "connection closed by the server",
+                ));
+            }
+            self.reestablish()?;
+        }
+        if let Some(keepalive) = self.keepalive.as_mut() {
+            keepalive.touch();
+        }
+        Ok(())
+    }
+
+    /// Check the client is connected before finalizing a transfer, without probing the control connection:
+    /// the server doesn't answer to `NOOP` while the data connection is open, and the reply to the transfer
+    /// would be taken for the answer to the probe
+    fn ensure_connected(&mut self) -> RemoteResult<()> {
+        self.reclaim_stream();
+        match (self.stream.as_ref(), self.keepalive.as_mut()) {
+            (Some(_), Some(keepalive)) => {
+                keepalive.touch();
+                Ok(())
+            }
+            _ => Err(RemoteError::new(RemoteErrorType::NotConnected)),
+        }
+    }
+
+    /// Send a `NOOP` to the server, bounded by the read timeout. Returns whether the server answered
+    fn probe(&mut self) -> bool {
+        let Some(stream) = self.stream.as_mut() else {
+            return false;
+        };
+        trace!("Probing control connection");
+        let timeout = stream.get_ref().read_timeout().ok().flatten();
+        let _ = stream
+            .get_ref()
+            .set_read_timeout(Some(self.read_timeout.unwrap_or(keepalive::PROBE_TIMEOUT)));
+        let alive = stream.noop().is_ok();
+        let _ = stream.get_ref().set_read_timeout(timeout);
+        if alive {
+            if let Some(keepalive) = self.keepalive.as_mut() {
+                keepalive.touch();
+            }
         } else {
-            Err(RemoteError::new(RemoteErrorType::NotConnected))
+            warn!("Server didn't answer to NOOP; control connection is dead");
         }
+        alive
     }
 
-    #[cfg(feature = "native-tls")]
-    fn setup_tls_connector(&self) -> RemoteResult<TlsConnector> {
-        NativeTlsConnector::builder()
-            .danger_accept_invalid_certs(self.accept_invalid_certs)
-            .danger_accept_invalid_hostnames(self.accept_invalid_hostnames)
-            .build()
+    /// Drop a dead control connection
+    fn drop_connection(&mut self) {
+        self.stream = None;
+        self.features = None;
+        self.keepalive = None;
+        self.channel = None;
+    }
+
+    /// Connect and login again after the control connection died, restoring the working directory
+    fn reestablish(&mut self) -> RemoteResult<()> {
+        warn!("Reconnecting to {}:{}", self.hostname, self.port);
+        let wrkdir = self.wrkdir.take();
+        self.drop_connection();
+        self.connect()?;
+        if let Some(wrkdir) = wrkdir {
+            debug!("Restoring working directory {}", wrkdir.display());
+            self.command(
+                &format!("CWD {}", wrkdir.display()),
+                &[Status::RequestedFileActionOk],
+            )
             .map_err(|e| {
-                error!("Failed to setup TLS stream: {}", e);
-                RemoteError::new_ex(RemoteErrorType::SslError, e)
-            })
-            .map(|x| x.into())
+                error!("Failed to restore working directory: {}", e);
+                RemoteError::new_ex(RemoteErrorType::NoSuchFileOrDirectory, e)
+            })?;
+            self.wrkdir = Some(wrkdir);
+        }
+        Ok(())
     }
 
-    #[cfg(feature = "rustls")]
-    fn setup_tls_connector(&self) -> RemoteResult<TlsConnector> {
-        let mut root_store = suppaftp::rustls::RootCertStore::empty();
-        root_store.extend(webpki_roots::TLS_SERVER_ROOTS.iter().map(|ta| {
-            rustls_pki_types::TrustAnchor {
-                subject: ta.subject.clone(),
-                subject_public_key_info: ta.subject_public_key_info.clone(),
-                name_constraints: ta.name_constraints.clone(),
+    /// Connect the control connection, applying the configured timeouts
+    fn open_control(&self) -> RemoteResult<FtpStream> {
+        let address = format!("{}:{}", self.hostname, self.port);
+        let socket = match (self.proxy.as_ref(), self.connection_timeout) {
+            (Some(proxy), timeout) => proxy.connect(self.hostname.as_str(), self.port, timeout),
+            (None, None) => TcpStream::connect(address.as_str()),
+            (None, Some(timeout)) => {
+                let addresses: Vec<_> = address
+                    .to_socket_addrs()
+                    .map_err(|e| {
+                        error!("Failed to resolve {}: {}", address, e);
+                        RemoteError::new_ex(RemoteErrorType::BadAddress, e)
+                    })?
+                    .collect();
+                let mut result = Err(io::Error::new(
+                    io::ErrorKind::NotFound,
+                    format!("no address found for {address}"),
+                ));
+                for addr in addresses {
+                    result = TcpStream::connect_timeout(&addr, timeout);
+                    if result.is_ok() {
+                        break;
+                    }
+                }
+                result
             }
-        }));
-        Ok(std::sync::Arc::new(
-            ClientConfig::builder()
-                .with_root_certificates(root_store)
-                .with_no_client_auth(),
-        )
-        .into())
+        }
+        .and_then(|socket| {
+            socket.set_read_timeout(self.read_timeout)?;
+            socket.set_write_timeout(self.read_timeout)?;
+            Ok(socket)
+        })
+        .map_err(|e| {
+            error!("Failed to connect to remote server: {}", e);
+            RemoteError::new_ex(RemoteErrorType::ConnectionError, e)
+        })?;
+        FtpStream::connect_with_stream(socket).map_err(|e| {
+            error!("Failed to connect to remote server: {}", e);
+            RemoteError::new_ex(RemoteErrorType::ConnectionError, e)
+        })
     }
-}
 
-impl RemoteFileSystem for FtpFileSystem {
-    fn connect(&mut self) -> RemoteResult<Welcome> {
-        info!("Connecting to {}:{}", self.hostname, self.port);
-        let mut stream =
-            FtpStream::connect(format!("{}:{}", self.hostname, self.port)).map_err(|e| {
-                error!("Failed to connect to remote server: {}", e);
-                RemoteError::new_ex(RemoteErrorType::ConnectionError, e)
+    /// Mode for data connections: passive mode switches to `EPSV` over IPv6, since `PASV` only supports IPv4.
+    /// Through a proxy, the peer of the control connection is the proxy: only IPv6 host addresses are told
+    fn data_mode(&self, stream: &FtpStream) -> Mode {
+        let ipv6 = match self.proxy {
+            Some(_) => self
+                .hostname
+                .trim_start_matches('[')
+                .trim_end_matches(']')
+                .parse::<Ipv6Addr>()
+                .is_ok(),
+            None => stream.get_ref().peer_addr().is_ok_and(|x| x.is_ipv6()),
+        };
+        match self.mode {
+            Mode::Passive if ipv6 => Mode::ExtendedPassive,
+            mode => mode,
+        }
+    }
+
+    /// Connect the control connection and secure it with `tls`, either right away (implicit FTPS)
+    /// or with `AUTH TLS`; then set the protection of data connections.
+    ///
+    /// Returns the TLS configuration and domain to secure the data connections with, unless they are clear
+    #[cfg(any(feature = "native-tls", feature = "rustls"))]
+    fn open_secure_control(
+        &self,
+        tls: &FtpTls,
+    ) -> RemoteResult<(FtpStream, Option<(crate::tls::TlsConfig, String)>)> {
+        debug!("Setting up TLS stream...");
+        trace!("TLS options: {:?}", tls);
+        let config = tls.build()?;
+        let connector = TlsConnector::from(config.clone());
+        let mut stream = if tls.implicit {
+            if self.proxy.is_some() {
+                return Err(RemoteError::new_ex(
+                    RemoteErrorType::UnsupportedFeature,
+                    "implicit FTPS isn't supported through a proxy",
+                ));
+            }
+            // the connection timeout can't be applied, since suppaftp connects the socket itself
+            let stream = FtpStream::connect_secure_implicit(
+                (self.hostname.as_str(), self.port),
+                connector,
+                self.hostname.as_str(),
+            )
+            .map_err(|e| {
+                error!(
+                    "Failed to connect to remote server with implicit TLS: {}",
+                    e
+                );
+                RemoteError::new_ex(RemoteErrorType::SslError, e)
             })?;
-        // If secure, connect TLS
-        #[cfg(any(feature = "native-tls", feature = "rustls"))]
-        if self.secure {
-            debug!("Setting up TLS stream...");
-            #[cfg(feature = "native-tls")]
-            trace!("Accept invalid certs: {}", self.accept_invalid_certs);
-            #[cfg(feature = "native-tls")]
-            trace!(
-                "Accept invalid hostnames: {}",
-                self.accept_invalid_hostnames
-            );
-            stream = stream
-                .into_secure(self.setup_tls_connector()?, self.hostname.as_str())
+            let socket = stream.get_ref();
+            socket
+                .set_read_timeout(self.read_timeout)
+                .and_then(|_| socket.set_write_timeout(self.read_timeout))
+                .map_err(|e| RemoteError::new_ex(RemoteErrorType::ConnectionError, e))?;
+            stream
+        } else {
+            self.open_control()?
+                .into_secure(connector, self.hostname.as_str())
                 .map_err(|e| {
                     error!("Failed to negotiate TLS with server: {}", e);
                     RemoteError::new_ex(RemoteErrorType::SslError, e)
+                })?
+        };
+        debug!("TLS handshake OK!");
+        let protection = match tls.clear_data_channel {
+            true => "PROT C",
+            false => "PROT P",
+        };
+        let commands: &[&str] = match tls.implicit {
+            true => &["PBSZ 0", protection],
+            // `into_secure` already sent `PBSZ 0` and `PROT P`
+            false if tls.clear_data_channel => &[protection],
+            false => &[],
+        };
+        for cmd in commands {
+            stream
+                .custom_command(*cmd, &[Status::CommandOk])
+                .map_err(|e| {
+                    error!("Failed to set data connections protection: {}", e);
+                    RemoteError::new_ex(RemoteErrorType::ProtocolError, e)
                 })?;
-            debug!("TLS handshake OK!");
         }
+        let data_tls = match tls.clear_data_channel {
+            true => None,
+            false => Some((config, self.hostname.clone())),
+        };
+        Ok((stream, data_tls))
+    }
+}
+
+/// Parse the path in a `PWD` reply, such as `257 "/home/"" quoted" is the current directory`,
+/// where quotes in the path are doubled
+fn parse_pwd_reply(reply: &str) -> Option<String> {
+    let start = reply.find('"')? + 1;
+    let end = reply.rfind('"').filter(|end| *end >= start)?;
+    Some(reply[start..end].replace("\"\"", "\""))
+}
+
+impl RemoteFileSystem for FtpFileSystem {
+    fn connect(&mut self) -> RemoteResult<Welcome> {
+        info!("Connecting to {}:{}", self.hostname, self.port);
+        if self.proxy.is_some()
+            && self.mode == Mode::Active
+            && self.active_external_address.is_none()
+        {
+            return Err(RemoteError::new_ex(
+                RemoteErrorType::UnsupportedFeature,
+                "active mode through a proxy requires an external address",
+            ));
+        }
+        #[cfg(not(any(feature = "native-tls", feature = "rustls")))]
+        let mut stream = self.open_control()?;
+        // If secure, connect TLS
+        #[cfg(any(feature = "native-tls", feature = "rustls"))]
+        let (mut stream, tls) = match self.tls.as_ref() {
+            Some(tls) => self.open_secure_control(tls)?,
+            None => (self.open_control()?, None),
+        };
         // Login
         debug!("Signin in as {}", self.username);
         stream
@@ -277,50 +1322,95 @@ impl RemoteFileSystem for FtpFileSystem {
                 error!("Failed to set transfer type to Binary: {}", e);
                 RemoteError::new_ex(RemoteErrorType::ProtocolError, e)
             })?;
+        let features = Self::negotiate_features(&mut stream);
+        let utf8 = self.utf8 && features.utf8 && Self::enable_utf8(&mut stream);
+        let channel = DataChannel {
+            mode: self.data_mode(&stream),
+            external_address: self.active_external_address,
+            ports: self.active_ports.clone(),
+            timeout: self.data_timeout,
+            proxy: self.proxy.clone().map(|x| (x, self.hostname.clone())),
+            #[cfg(any(feature = "native-tls", feature = "rustls"))]
+            tls,
+            codec: PathCodec::new(self.charset.clone(), utf8, !self.is_secure()),
+        };
+        trace!("Data connections in {:?} mode", channel.mode);
         info!("Connection established!");
-        let welcome = Welcome::default().banner(stream.get_welcome_msg().map(|x| x.to_string()));
+        let welcome = Welcome::default()
+            .banner(stream.get_welcome_msg().map(|x| x.to_string()))
+            .system(features.system.clone())
+            .features(features.lines());
+        self.features = Some(features);
         self.stream = Some(stream);
+        self.keepalive = Some(Keepalive::new(self.keepalive_interval));
+        self.wrkdir = None;
+        self.channel = Some(channel);
+        self.ascii = false;
         Ok(welcome)
     }
 
     fn disconnect(&mut self) -> RemoteResult<()> {
         info!("Disconnecting from FTP server...");
-        self.check_connection()?;
-        let stream = self.stream.as_mut().unwrap();
+        self.reclaim_stream();
+        let stream = self
+            .stream
+            .as_mut()
+            .ok_or_else(|| RemoteError::new(RemoteErrorType::NotConnected))?;
         stream.quit().map_err(|e| {
             error!("Failed to disconnect from remote: {}", e);
             RemoteError::new_ex(RemoteErrorType::ConnectionError, e)
         })?;
-        self.stream = None;
+        self.drop_connection();
+        self.wrkdir = None;
         Ok(())
     }
 
     fn is_connected(&mut self) -> bool {
-        self.stream.is_some()
+        self.reclaim_stream();
+        if self.stream.is_none() {
+            return false;
+        }
+        // the server can't answer while a file is being read
+        if reader::lock(&self.lent).pending {
+            return true;
+        }
+        let alive = self.probe();
+        if !alive {
+            self.drop_connection();
+        }
+        alive
     }
 
     fn pwd(&mut self) -> RemoteResult<PathBuf> {
         debug!("Getting working directory...");
         self.check_connection()?;
-        let stream = self.stream.as_mut().unwrap();
-        stream.pwd().map(PathBuf::from).map_err(|e| {
+        let response = self.command("PWD", &[Status::PathCreated]).map_err(|e| {
             error!("Pwd failed: {}", e);
             RemoteError::new_ex(RemoteErrorType::ProtocolError, e)
-        })
+        })?;
+        parse_pwd_reply(&self.decode(&response))
+            .map(PathBuf::from)
+            .ok_or_else(|| {
+                error!("Pwd failed: bad reply");
+                RemoteError::new_ex(RemoteErrorType::ProtocolError, "bad PWD reply")
+            })
     }
 
     fn change_dir(&mut self, dir: &Path) -> RemoteResult<PathBuf> {
         debug!("Changing working directory to {}", dir.display());
         self.check_connection()?;
         let dir: PathBuf = Self::resolve(dir);
-        let stream = self.stream.as_mut().unwrap();
-        stream
-            .cwd(dir.as_path().to_string_lossy())
-            .map(|_| dir)
-            .map_err(|e| {
-                error!("Failed to change directory: {}", e);
-                RemoteError::new_ex(RemoteErrorType::NoSuchFileOrDirectory, e)
-            })
+        self.command(
+            &format!("CWD {}", dir.display()),
+            &[Status::RequestedFileActionOk],
+        )
+        .map_err(|e| {
+            error!("Failed to change directory: {}", e);
+            RemoteError::new_ex(RemoteErrorType::NoSuchFileOrDirectory, e)
+        })?;
+        // relative paths are restored on reconnect as the server resolves them now
+        self.wrkdir = Some(self.pwd().unwrap_or(dir.clone()));
+        Ok(dir)
     }
 
     fn list_dir(&mut self, path: &Path) -> RemoteResult<Vec<File>> {
@@ -328,8 +1418,18 @@ impl RemoteFileSystem for FtpFileSystem {
         self.check_connection()?;
         let path: PathBuf = Self::resolve(path);
         let stream = self.stream.as_mut().unwrap();
-        stream
-            .list(Some(&path.as_path().to_string_lossy()))
+        let channel = self.channel.as_ref().unwrap();
+        if self.features.as_ref().is_some_and(|x| x.mlst.is_some()) {
+            return channel
+                .mlsd(stream, path.as_path().to_string_lossy().as_ref())
+                .map(|lines| Self::parse_mlsd_lines(path.as_path(), lines))
+                .map_err(|e| {
+                    error!("Failed to list directory: {}", e);
+                    RemoteError::new_ex(RemoteErrorType::ProtocolError, e)
+                });
+        }
+        channel
+            .list(stream, path.as_path().to_string_lossy().as_ref())
             .map(|files| self.parse_list_lines(path.as_path(), files))
             .map_err(|e| {
                 error!("Failed to list directory: {}", e);
@@ -355,6 +1455,9 @@ impl RemoteFileSystem for FtpFileSystem {
                 });
             }
         };
+        if self.supports_mlst() {
+            return self.stat_mlst(path.as_path());
+        }
         trace!("Listing entries for stat path file: {}", parent.display());
         let entries = self.list_dir(parent)?;
         // Get target
@@ -368,8 +1471,8 @@ impl RemoteFileSystem for FtpFileSystem {
         }
     }
 
-    fn setstat(&mut self, _path: &Path, _metadata: Metadata) -> RemoteResult<()> {
-        Err(RemoteError::new(RemoteErrorType::UnsupportedFeature))
+    fn setstat(&mut self, path: &Path, metadata: Metadata) -> RemoteResult<()> {
+        self.setstat_with_report(path, &metadata).map(|_| ())
     }
 
     fn exists(&mut self, path: &Path) -> RemoteResult<bool> {
@@ -388,8 +1491,12 @@ impl RemoteFileSystem for FtpFileSystem {
         debug!("Removing file {}", path.display());
         self.check_connection()?;
         let path = Self::resolve(path);
-        let stream = self.stream.as_mut().unwrap();
-        stream.rm(path.as_path().to_string_lossy()).map_err(|e| {
+        self.command(
+            &format!("DELE {}", path.display()),
+            &[Status::RequestedFileActionOk],
+        )
+        .map(|_| ())
+        .map_err(|e| {
             error!("Failed to remove file {}", e);
             RemoteError::new_ex(RemoteErrorType::ProtocolError, e)
         })
@@ -399,8 +1506,12 @@ impl RemoteFileSystem for FtpFileSystem {
         debug!("Removing file {}", path.display());
         self.check_connection()?;
         let path = Self::resolve(path);
-        let stream = self.stream.as_mut().unwrap();
-        stream.rmdir(path.as_path().to_string_lossy()).map_err(|e| {
+        self.command(
+            &format!("RMD {}", path.display()),
+            &[Status::RequestedFileActionOk],
+        )
+        .map(|_| ())
+        .map_err(|e| {
             error!("Failed to remove directory {}", e);
             RemoteError::new_ex(RemoteErrorType::ProtocolError, e)
         })
@@ -410,8 +1521,7 @@ impl RemoteFileSystem for FtpFileSystem {
         debug!("Trying to create directory {}", path.display());
         self.check_connection()?;
         let path = Self::resolve(path);
-        let stream = self.stream.as_mut().unwrap();
-        match stream.mkdir(path.as_path().to_string_lossy()) {
+        match self.command(&format!("MKD {}", path.display()), &[Status::PathCreated]) {
             Ok(_) => Ok(()),
             Err(FtpError::UnexpectedResponse(Response {
                 status: Status::FileUnavailable,
@@ -431,8 +1541,30 @@ impl RemoteFileSystem for FtpFileSystem {
         Err(RemoteError::new(RemoteErrorType::UnsupportedFeature))
     }
 
-    fn copy(&mut self, _src: &Path, _dest: &Path) -> RemoteResult<()> {
-        Err(RemoteError::new(RemoteErrorType::UnsupportedFeature))
+    /// Copy `src` to `dest`; directories are copied recursively.
+    ///
+    /// FTP can't run two transfers on the same session, so the file data is streamed through a second session
+    fn copy(&mut self, src: &Path, dest: &Path) -> RemoteResult<()> {
+        debug!("Copying {} to {}", src.display(), dest.display());
+        self.check_connection()?;
+        let wrkdir = self.pwd()?;
+        let src = path_utils::absolutize(wrkdir.as_path(), Self::resolve(src).as_path());
+        let dest = path_utils::absolutize(wrkdir.as_path(), Self::resolve(dest).as_path());
+        if dest.starts_with(src.as_path()) {
+            error!("Can't copy {} into itself", src.display());
+            return Err(RemoteError::new_ex(
+                RemoteErrorType::BadAddress,
+                "can't copy a file into itself",
+            ));
+        }
+        let file = self.stat(src.as_path())?;
+        let mut twin = self.twin();
+        twin.connect()?;
+        let result = self.copy_entry(&file, &mut twin, dest.as_path(), false);
+        if let Err(err) = twin.disconnect() {
+            warn!("Failed to disconnect copy session: {}", err);
+        }
+        result.map(|_| ())
     }
 
     fn mov(&mut self, src: &Path, dest: &Path) -> RemoteResult<()> {
@@ -440,46 +1572,94 @@ impl RemoteFileSystem for FtpFileSystem {
         self.check_connection()?;
         let src = Self::resolve(src);
         let dest = Self::resolve(dest);
-        let stream = self.stream.as_mut().unwrap();
-        stream
-            .rename(
-                &src.as_path().to_string_lossy(),
-                &dest.as_path().to_string_lossy(),
+        self.command(
+            &format!("RNFR {}", src.display()),
+            &[Status::RequestFilePending],
+        )
+        .and_then(|_| {
+            self.command(
+                &format!("RNTO {}", dest.display()),
+                &[Status::RequestedFileActionOk],
             )
-            .map_err(|e| {
-                error!("Failed to rename file: {}", e);
-                RemoteError::new_ex(RemoteErrorType::ProtocolError, e)
-            })
+        })
+        .map(|_| ())
+        .map_err(|e| {
+            error!("Failed to rename file: {}", e);
+            RemoteError::new_ex(RemoteErrorType::ProtocolError, e)
+        })
     }
 
-    fn exec(&mut self, _cmd: &str) -> RemoteResult<(u32, String)> {
-        Err(RemoteError::new(RemoteErrorType::UnsupportedFeature))
+    /// Send `cmd` to the server: FTP commands (e.g. `NOOP`, `SITE IDLE 60`) are sent as they are,
+    /// any other command is sent as a `SITE` command (e.g. `CHMOD 644 a.txt`).
+    ///
+    /// Returns the reply code and the reply text.
+    /// Commands which would corrupt the control channel or the client state, such as `PASV`, `RETR`, `QUIT`
+    /// or `CWD` (use `change_dir` instead), are refused
+    fn exec(&mut self, cmd: &str) -> RemoteResult<(u32, String)> {
+        self.check_connection()?;
+        let cmd = exec::prepare_command(cmd)?;
+        debug!("Sending command \"{}\"", cmd);
+        let response = match self.command(cmd.as_str(), &[Status::CommandOk]) {
+            Ok(response) | Err(FtpError::UnexpectedResponse(response)) => response,
+            Err(e) => {
+                error!("Failed to send command: {}", e);
+                return Err(RemoteError::new_ex(RemoteErrorType::ProtocolError, e));
+            }
+        };
+        let (code, text) = exec::parse_reply(self.decode(&response).as_str())?;
+        trace!("Command replied with code {}", code);
+        Ok((code, text))
     }
 
     fn append(&mut self, path: &Path, _metadata: &Metadata) -> RemoteResult<WriteStream> {
         debug!("Opening {} for append", path.display());
         self.check_connection()?;
         let path = Self::resolve(path);
+        let ascii = self.select_transfer_type(path.as_path(), 0)?;
         let stream = self.stream.as_mut().unwrap();
-        stream
-            .append_with_stream(path.as_path().to_string_lossy())
-            .map(|x| Box::new(x) as Box<dyn Write + Send>)
-            .map(WriteStream::from)
+        let channel = self.channel.as_ref().unwrap();
+        channel
+            .appe(stream, path.as_path().to_string_lossy().as_ref())
+            .map(|x| Self::text_writer(x, ascii))
             .map_err(|e| {
                 error!("Failed to open file: {}", e);
                 RemoteError::new_ex(RemoteErrorType::ProtocolError, e)
             })
     }
 
+    fn create_file(
+        &mut self,
+        path: &Path,
+        metadata: &Metadata,
+        mut reader: Box<dyn Read + Send>,
+    ) -> RemoteResult<u64> {
+        let mut stream = self.create(path, metadata)?;
+        trace!("Opened remote file");
+        let sz = io::copy(&mut reader, &mut stream)
+            .map_err(|e| RemoteError::new_ex(RemoteErrorType::ProtocolError, e.to_string()))?;
+        self.on_written(stream)?;
+        trace!("Written {} bytes to destination", sz);
+        if self.preserve_times && (metadata.modified.is_some() || metadata.accessed.is_some()) {
+            let times = Metadata {
+                accessed: metadata.accessed,
+                modified: metadata.modified,
+                ..Default::default()
+            };
+            self.setstat_with_report(path, &times)?;
+        }
+        Ok(sz)
+    }
+
     fn create(&mut self, path: &Path, _metadata: &Metadata) -> RemoteResult<WriteStream> {
         debug!("Opening {} for write", path.display());
         self.check_connection()?;
         let path = Self::resolve(path);
+        let ascii = self.select_transfer_type(path.as_path(), 0)?;
         let stream = self.stream.as_mut().unwrap();
-        stream
-            .put_with_stream(path.as_path().to_string_lossy())
-            .map(|x| Box::new(x) as Box<dyn Write + Send>)
-            .map(WriteStream::from)
+        let channel = self.channel.as_ref().unwrap();
+        channel
+            .stor(stream, path.as_path().to_string_lossy().as_ref())
+            .map(|x| Self::text_writer(x, ascii))
             .map_err(|e| {
                 error!("Failed to open file: {}", e);
                 RemoteError::new_ex(RemoteErrorType::ProtocolError, e)
@@ -487,23 +1667,19 @@ impl RemoteFileSystem for FtpFileSystem {
     }
 
     fn open(&mut self, path: &Path) -> RemoteResult<ReadStream> {
-        debug!("Opening {} for read", path.display());
-        self.check_connection()?;
-        let path = Self::resolve(path);
-        let stream = self.stream.as_mut().unwrap();
-        stream
-            .retr_as_stream(path.as_path().to_string_lossy())
-            .map(|x| Box::new(x) as Box<dyn Read + Send>)
-            .map(ReadStream::from)
-            .map_err(|e| {
-                error!("Failed to open file: {}", e);
-                RemoteError::new_ex(RemoteErrorType::ProtocolError, e)
-            })
+        self.open_at(path, 0)
     }
 
     fn on_read(&mut self, readable: ReadStream) -> RemoteResult<()> {
         debug!("Finalizing read stream");
-        self.check_connection()?;
+        self.reclaim_stream();
+        let pending = std::mem::take(&mut reader::lock(&self.lent).pending);
+        self.ensure_connected()?;
+        if !pending {
+            // the transfer failed to restart, so the server has nothing left to reply
+            trace!("No transfer to finalize");
+            return Ok(());
+        }
         let stream = self.stream.as_mut().unwrap();
         stream.finalize_retr_stream(readable).map_err(|e| {
             error!("Failed to finalize read stream: {}", e);
@@ -513,7 +1689,7 @@ impl RemoteFileSystem for FtpFileSystem {
 
     fn on_written(&mut self, writable: WriteStream) -> RemoteResult<()> {
         debug!("Finalizing write stream");
-        self.check_connection()?;
+        self.ensure_connected()?;
         let stream = self.stream.as_mut().unwrap();
         stream.finalize_put_stream(writable).map_err(|e| {
             error!("Failed to finalize write stream: {}", e);
@@ -531,7 +1707,7 @@ mod test {
     #[cfg(feature = "with-containers")]
     use serial_test::serial;
     #[cfg(feature = "with-containers")]
-    use std::io::Cursor;
+    use std::io::{Cursor, Write};
 
     #[test]
     fn should_initialize_ftp_filesystem() {
@@ -542,12 +1718,9 @@ mod test {
         assert_eq!(client.username.as_str(), "anonymous");
         assert!(client.password.is_none());
         assert_eq!(client.mode, Mode::Passive);
+        assert!(client.features().is_none());
         #[cfg(any(feature = "native-tls", feature = "rustls"))]
-        assert_eq!(client.secure, false);
-        #[cfg(feature = "native-tls")]
-        assert_eq!(client.accept_invalid_certs, false);
-        #[cfg(feature = "native-tls")]
-        assert_eq!(client.accept_invalid_hostnames, false);
+        assert!(client.tls.is_none());
     }
 
     #[test]
@@ -556,13 +1729,78 @@ mod test {
             .username("test")
             .password("omar")
             .passive_mode()
-            .active_mode();
+            .extended_passive_mode()
+            .active_mode()
+            .active_external_address(IpAddr::from([203, 0, 113, 7]))
+            .active_port_range(50000..=50100)
+            .preserve_times(true)
+            .keepalive_interval(Duration::from_secs(60))
+            .reconnect(true)
+            .connection_timeout(Duration::from_secs(10))
+            .read_timeout(Duration::from_secs(30))
+            .data_timeout(Duration::from_secs(20))
+            .utf8(false)
+            .charset(crate::Latin1)
+            .transfer_mode(TransferMode::auto())
+            .line_ending(LineEnding::CrLf)
+            .proxy(Proxy::socks5("127.0.0.1:1080"));
         assert!(client.stream.is_none());
         assert_eq!(client.hostname.as_str(), "127.0.0.1");
         assert_eq!(client.port, 21);
         assert_eq!(client.username.as_str(), "test");
         assert_eq!(client.password.as_deref().unwrap(), "omar");
         assert_eq!(client.mode, Mode::Active);
+        assert_eq!(
+            client.active_external_address,
+            Some(IpAddr::from([203, 0, 113, 7]))
+        );
+        assert_eq!(client.active_ports, Some(50000..=50100));
+        assert!(client.preserve_times);
+        assert_eq!(client.keepalive_interval, Some(Duration::from_secs(60)));
+        assert!(client.reconnect);
+        assert_eq!(client.connection_timeout, Some(Duration::from_secs(10)));
+        assert_eq!(client.read_timeout, Some(Duration::from_secs(30)));
+        assert_eq!(client.data_timeout, Some(Duration::from_secs(20)));
+        assert_eq!(client.transfer_mode, TransferMode::auto());
+        assert_eq!(client.line_ending, LineEnding::CrLf);
+        assert_eq!(client.proxy, Some(Proxy::socks5("127.0.0.1:1080")));
+        // twin has the same options
+        let mut twin = client.twin();
+        assert!(twin.reconnect);
+        assert_eq!(twin.data_timeout, Some(Duration::from_secs(20)));
+        assert!(!twin.utf8);
+        assert!(twin.charset.is_some());
+        assert_eq!(twin.transfer_mode, TransferMode::auto());
+        assert_eq!(twin.line_ending, LineEnding::CrLf);
+        twin.set_transfer_mode(TransferMode::Ascii);
+        assert_eq!(twin.transfer_mode, TransferMode::Ascii);
+        assert!(twin.proxy.is_some());
+    }
+
+    #[test]
+    fn should_not_connect_in_active_mode_through_proxy() {
+        let mut client = FtpFileSystem::new("127.0.0.1", 21)
+            .active_mode()
+            .proxy(Proxy::socks5("127.0.0.1:1080"));
+        assert_eq!(
+            client.connect().err().unwrap().kind,
+            RemoteErrorType::UnsupportedFeature
+        );
+    }
+
+    #[test]
+    fn should_parse_pwd_reply() {
+        assert_eq!(
+            parse_pwd_reply("257 \"/home/test\" is the current directory\r\n").as_deref(),
+            Some("/home/test")
+        );
+        assert_eq!(
+            parse_pwd_reply("257 \"/say \"\"hi\"\"\" is current").as_deref(),
+            Some("/say \"hi\"")
+        );
+        assert_eq!(parse_pwd_reply("257 \"\"").as_deref(), Some(""));
+        assert!(parse_pwd_reply("257 /home/test").is_none());
+        assert!(parse_pwd_reply("257 \"/home/test").is_none());
     }
 
     #[test]
@@ -588,11 +1826,85 @@ mod test {
         assert_eq!(client.username.as_str(), "test");
         assert_eq!(client.password.as_deref().unwrap(), "omar");
         assert_eq!(client.mode, Mode::Active);
-        assert_eq!(client.secure, true);
-        #[cfg(feature = "native-tls")]
-        assert_eq!(client.accept_invalid_certs, true);
         #[cfg(feature = "native-tls")]
-        assert_eq!(client.accept_invalid_hostnames, true);
+        assert_eq!(
+            client.tls,
+            Some(
+                FtpTls::default()
+                    .accept_invalid_certs(true)
+                    .accept_invalid_hostnames(true)
+            )
+        );
+        #[cfg(feature = "rustls")]
+        assert_eq!(client.tls, Some(FtpTls::default()));
+        let client = client.tls(
+            FtpTls::default()
+                .implicit(true)
+                .session_reuse(true)
+                .clear_data_channel(true),
+        );
+        assert_eq!(client.twin().tls, client.tls);
+        assert!(!client.fxp_allowed());
+    }
+
+    #[test]
+    fn should_parse_mlsd_lines() {
+        let files = FtpFileSystem::parse_mlsd_lines(
+            Path::new("/home/test"),
+            vec![
+                "type=cdir;perm=el; .".to_string(),
+                "type=pdir;perm=el; ..".to_string(),
+                "type=file;size=8192;modify=20210101120000;unix.mode=0640; a b.txt".to_string(),
+                "type=OS.unix=slink:../docs;size=7; docs".to_string(),
+                "this is not a mlsd line".to_string(),
+            ],
+        );
+        assert_eq!(files.len(), 2);
+        assert_eq!(files[0].path(), Path::new("/home/test/a b.txt"));
+        assert_eq!(files[0].metadata().size, 8192);
+        assert_eq!(files[0].metadata().mode, Some(UnixPex::from(0o640)));
+        assert_eq!(files[1].path(), Path::new("/home/test/docs"));
+        assert!(files[1].metadata().file_type.is_symlink());
+        assert_eq!(
+            files[1].metadata().symlink.as_deref(),
+            Some(Path::new("/home/test/../docs"))
+        );
+    }
+
+    #[test]
+    fn should_parse_list_lines() {
+        let unparsable = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let lines = unparsable.clone();
+        let mut client = FtpFileSystem::new("127.0.0.1", 21)
+            .on_unparsable_line(move |line, _| lines.lock().unwrap().push(line.to_string()));
+        let files = client.parse_list_lines(
+            Path::new("/home/test"),
+            vec![
+                "total 8".to_string(),
+                "lrwxrwxrwx 1 0 0 7 Nov 05 2018 docs -> ../docs".to_string(),
+                "10-19-20  03:19PM <DIR> pub".to_string(),
+                "this is not a list line".to_string(),
+            ],
+        );
+        assert_eq!(files.len(), 2);
+        assert_eq!(
+            files[0].metadata().symlink.as_deref(),
+            Some(Path::new("/home/test/../docs"))
+        );
+        assert_eq!(files[1].path(), Path::new("/home/test/pub"));
+        assert!(files[1].is_dir());
+        assert_eq!(
+            unparsable.lock().unwrap().as_slice(),
+            &["this is not a list line".to_string()]
+        );
+        // custom parser
+        client = client.list_parser(crate::list::DosParser);
+        let files = client.parse_list_lines(
+            Path::new("/"),
+            vec!["-rw-r--r-- 1 0 0 8192 Nov 05 2018 omar.txt".to_string()],
+        );
+        assert!(files.is_empty());
+        assert_eq!(unparsable.lock().unwrap().len(), 2);
     }
 
     #[test]
@@ -602,9 +1914,37 @@ mod test {
         let mut client = FtpFileSystem::new("test.rebex.net", 21)
             .username("demo")
             .password("password")
-            .secure(false, false)
+            .secure(false, false)
+            .passive_mode();
+        assert!(client.connect().is_ok());
+        assert!(client.disconnect().is_ok());
+    }
+
+    #[test]
+    #[ignore]
+    #[cfg(feature = "native-tls")]
+    fn should_connect_with_implicit_ftps() {
+        let mut client = FtpFileSystem::new("test.rebex.net", crate::tls::IMPLICIT_PORT)
+            .username("demo")
+            .password("password")
+            .tls(FtpTls::default().implicit(true))
+            .passive_mode();
+        assert!(client.connect().is_ok());
+        assert!(client.list_dir(Path::new("/")).is_ok());
+        assert!(client.disconnect().is_ok());
+    }
+
+    #[test]
+    #[ignore]
+    #[cfg(feature = "native-tls")]
+    fn should_connect_with_ftps_and_clear_data_channel() {
+        let mut client = FtpFileSystem::new("test.rebex.net", 21)
+            .username("demo")
+            .password("password")
+            .tls(FtpTls::default().clear_data_channel(true))
             .passive_mode();
         assert!(client.connect().is_ok());
+        assert!(client.list_dir(Path::new("/")).is_ok());
         assert!(client.disconnect().is_ok());
     }
 
@@ -641,6 +1981,23 @@ mod test {
         finalize_client(client);
     }
 
+    #[test]
+    #[cfg(feature = "with-containers")]
+    #[serial]
+    fn should_negotiate_features() {
+        crate::mock::logger();
+        let mut client = FtpFileSystem::new("127.0.0.1", 10021)
+            .username("test")
+            .password("test");
+        let welcome = client.connect().unwrap();
+        let features = client.features().unwrap();
+        assert!(features.system.is_some());
+        assert_eq!(welcome.system, features.system);
+        assert_eq!(welcome.features, features.lines());
+        assert!(client.disconnect().is_ok());
+        assert!(client.features().is_none());
+    }
+
     #[test]
     #[cfg(feature = "with-containers")]
     #[serial]
@@ -682,6 +2039,94 @@ mod test {
         finalize_client(client);
     }
 
+    #[test]
+    #[cfg(feature = "with-containers")]
+    #[serial]
+    fn should_copy_file() {
+        crate::mock::logger();
+        let mut client = setup_client();
+        let reader = Cursor::new(b"test data\n".to_vec());
+        assert!(client
+            .create_file(Path::new("a.txt"), &Metadata::default(), Box::new(reader))
+            .is_ok());
+        assert!(client.copy(Path::new("a.txt"), Path::new("b.txt")).is_ok());
+        assert_eq!(client.stat(Path::new("b.txt")).unwrap().metadata().size, 10);
+        // source is still there
+        assert_eq!(client.stat(Path::new("a.txt")).unwrap().metadata().size, 10);
+        finalize_client(client);
+    }
+
+    #[test]
+    #[cfg(feature = "with-containers")]
+    #[serial]
+    fn should_copy_directory() {
+        crate::mock::logger();
+        let mut client = setup_client();
+        assert!(client
+            .create_dir(Path::new("src"), UnixPex::from(0o755))
+            .is_ok());
+        assert!(client
+            .create_dir(Path::new("src/sub"), UnixPex::from(0o755))
+            .is_ok());
+        let reader = Cursor::new(b"test data\n".to_vec());
+        assert!(client
+            .create_file(
+                Path::new("src/sub/a.txt"),
+                &Metadata::default(),
+                Box::new(reader)
+            )
+            .is_ok());
+        assert!(client.copy(Path::new("src"), Path::new("dest")).is_ok());
+        assert_eq!(
+            client
+                .stat(Path::new("dest/sub/a.txt"))
+                .unwrap()
+                .metadata()
+                .size,
+            10
+        );
+        // can't copy into itself
+        assert!(client
+            .copy(Path::new("src"), Path::new("src/sub/copy"))
+            .is_err());
+        finalize_client(client);
+    }
+
+    #[test]
+    #[cfg(feature = "with-containers")]
+    #[serial]
+    fn should_copy_file_to_another_client() {
+        crate::mock::logger();
+        let mut client = setup_client();
+        let reader = Cursor::new(b"test data\n".to_vec());
+        assert!(client
+            .create_file(Path::new("a.txt"), &Metadata::default(), Box::new(reader))
+            .is_ok());
+        let wrkdir = client.pwd().unwrap();
+        let mut other = client.twin();
+        assert!(other.connect().is_ok());
+        assert_eq!(
+            client
+                .copy_to(
+                    Path::new("a.txt"),
+                    &mut other,
+                    wrkdir.join("b.txt").as_path()
+                )
+                .unwrap(),
+            10
+        );
+        assert_eq!(
+            other
+                .stat(wrkdir.join("b.txt").as_path())
+                .unwrap()
+                .metadata()
+                .size,
+            10
+        );
+        assert!(other.disconnect().is_ok());
+        finalize_client(client);
+    }
+
     #[test]
     #[cfg(feature = "with-containers")]
     #[serial]
@@ -764,6 +2209,25 @@ mod test {
         finalize_client(client);
     }
 
+    #[test]
+    #[cfg(feature = "with-containers")]
+    #[serial]
+    fn should_handle_non_ascii_names() {
+        crate::mock::logger();
+        let mut client = setup_client();
+        let p = Path::new("café 日本.txt");
+        let reader = Cursor::new("test data\n".as_bytes());
+        assert!(client
+            .create_file(p, &Metadata::default(), Box::new(reader))
+            .is_ok());
+        let wrkdir = client.pwd().unwrap();
+        let files = client.list_dir(wrkdir.as_path()).unwrap();
+        assert!(files.iter().any(|x| x.name().as_str() == "café 日本.txt"));
+        assert!(client.mov(p, Path::new("ünïcode.txt")).is_ok());
+        assert!(client.remove_file(Path::new("ünïcode.txt")).is_ok());
+        finalize_client(client);
+    }
+
     #[test]
     #[cfg(feature = "with-containers")]
     #[serial]
@@ -780,14 +2244,29 @@ mod test {
         finalize_client(client);
     }
 
+    #[test]
+    #[cfg(feature = "with-containers")]
+    #[serial]
+    fn should_exec_command() {
+        crate::mock::logger();
+        let mut client = setup_client();
+        assert_eq!(client.exec("NOOP").unwrap().0, 200);
+        // unknown site command
+        let (code, _) = client.exec("echo 5").unwrap();
+        assert!((500..600).contains(&code));
+        // control channel is still usable
+        assert!(client.pwd().is_ok());
+        finalize_client(client);
+    }
+
     #[test]
     #[cfg(feature = "with-containers")]
     #[serial]
     fn should_not_exec_command() {
         crate::mock::logger();
         let mut client = setup_client();
-        // Create file
-        assert!(client.exec("echo 5").is_err());
+        assert!(client.exec("PASV").is_err());
+        assert!(client.exec("QUIT").is_err());
         finalize_client(client);
     }
 
@@ -909,6 +2388,243 @@ mod test {
         finalize_client(client);
     }
 
+    #[test]
+    #[cfg(feature = "with-containers")]
+    #[serial]
+    fn should_open_file_at_offset() {
+        crate::mock::logger();
+        let mut client = setup_client();
+        let p = Path::new("a.txt");
+        let reader = Cursor::new("Hello, world!".as_bytes());
+        assert!(client
+            .create_file(p, &Metadata::default(), Box::new(reader))
+            .is_ok());
+        let mut stream = client.open_at(p, 7).ok().unwrap();
+        assert!(stream.seekable());
+        let mut data = String::new();
+        assert!(stream.read_to_string(&mut data).is_ok());
+        assert_eq!(data.as_str(), "world!");
+        assert!(client.on_read(stream).is_ok());
+        // client is usable after the transfer
+        assert_eq!(client.stat(p).ok().unwrap().metadata().size, 13);
+        finalize_client(client);
+    }
+
+    #[test]
+    #[cfg(feature = "with-containers")]
+    #[serial]
+    fn should_transfer_files_in_ascii() {
+        crate::mock::logger();
+        let mut client = setup_client();
+        client.line_ending = LineEnding::Lf;
+        client.set_transfer_mode(TransferMode::auto());
+        let p = Path::new("a.txt");
+        let reader = Cursor::new("Hello\r\nworld\n".as_bytes());
+        assert!(client
+            .create_file(p, &Metadata::default(), Box::new(reader))
+            .is_ok());
+        let mut stream = client.open(p).ok().unwrap();
+        assert!(!stream.seekable());
+        let mut data = String::new();
+        assert!(stream.read_to_string(&mut data).is_ok());
+        assert_eq!(data.as_str(), "Hello\nworld\n");
+        assert!(client.on_read(stream).is_ok());
+        assert_eq!(
+            client.open_at(p, 2).err().unwrap().kind,
+            RemoteErrorType::UnsupportedFeature
+        );
+        // other files are still transferred in binary
+        let p = Path::new("b.bin");
+        let reader = Cursor::new("Hello\r\n".as_bytes());
+        assert!(client
+            .create_file(p, &Metadata::default(), Box::new(reader))
+            .is_ok());
+        assert_eq!(client.stat(p).ok().unwrap().metadata().size, 7);
+        finalize_client(client);
+    }
+
+    #[test]
+    #[cfg(feature = "with-containers")]
+    #[serial]
+    fn should_seek_read_stream() {
+        use std::io::{Seek, SeekFrom};
+
+        crate::mock::logger();
+        let mut client = setup_client();
+        let p = Path::new("a.txt");
+        let reader = Cursor::new("Hello, world!".as_bytes());
+        assert!(client
+            .create_file(p, &Metadata::default(), Box::new(reader))
+            .is_ok());
+        let mut stream = client.open(p).ok().unwrap();
+        let mut buf = [0; 5];
+        // skip forward
+        assert_eq!(stream.seek(SeekFrom::Current(7)).unwrap(), 7);
+        assert!(stream.read_exact(&mut buf).is_ok());
+        assert_eq!(&buf, b"world");
+        // restart backward
+        assert_eq!(stream.seek(SeekFrom::Start(0)).unwrap(), 0);
+        assert!(stream.read_exact(&mut buf).is_ok());
+        assert_eq!(&buf, b"Hello");
+        assert!(stream.seek(SeekFrom::End(0)).is_err());
+        assert!(client.on_read(stream).is_ok());
+        assert!(client.exists(p).ok().unwrap());
+        finalize_client(client);
+    }
+
+    #[test]
+    #[cfg(feature = "with-containers")]
+    #[serial]
+    fn should_resume_upload() {
+        crate::mock::logger();
+        let mut client = setup_client();
+        let p = Path::new("a.txt");
+        let reader = Cursor::new("Hello, wor".as_bytes());
+        assert!(client
+            .create_file(p, &Metadata::default(), Box::new(reader))
+            .is_ok());
+        if !client.features().unwrap().rest_stream {
+            assert_eq!(
+                client.resume_upload(p, 10).err().unwrap().kind,
+                RemoteErrorType::UnsupportedFeature
+            );
+            finalize_client(client);
+            return;
+        }
+        let mut stream = client.resume_upload(p, 7).ok().unwrap();
+        assert!(stream.write_all(b"world!").is_ok());
+        assert!(client.on_written(stream).is_ok());
+        let mut stream = client.open(p).ok().unwrap();
+        let mut data = String::new();
+        assert!(stream.read_to_string(&mut data).is_ok());
+        assert_eq!(data.as_str(), "Hello, world!");
+        assert!(client.on_read(stream).is_ok());
+        finalize_client(client);
+    }
+
+    #[test]
+    #[cfg(feature = "with-containers")]
+    #[serial]
+    fn should_reconnect_and_restore_working_directory() {
+        crate::mock::logger();
+        let mut client = FtpFileSystem::new("127.0.0.1", 10021)
+            .username("test")
+            .password("test")
+            // probe before each operation
+            .keepalive_interval(Duration::ZERO)
+            .reconnect(true)
+            .connection_timeout(Duration::from_secs(10))
+            .read_timeout(Duration::from_secs(10))
+            .data_timeout(Duration::from_secs(10));
+        assert!(client.connect().is_ok());
+        let tempdir = PathBuf::from(generate_tempdir());
+        assert!(client
+            .create_dir(tempdir.as_path(), UnixPex::from(0o775))
+            .is_ok());
+        assert!(client.change_dir(tempdir.as_path()).is_ok());
+        let wrkdir = client.pwd().ok().unwrap();
+        assert_eq!(client.keepalive_send().ok().unwrap(), Some(Duration::ZERO));
+        // kill the control connection
+        assert!(client
+            .stream()
+            .unwrap()
+            .get_ref()
+            .shutdown(std::net::Shutdown::Both)
+            .is_ok());
+        assert!(client.list_dir(Path::new(".")).is_ok());
+        assert_eq!(client.pwd().ok().unwrap(), wrkdir);
+        finalize_client(client);
+    }
+
+    #[test]
+    #[cfg(feature = "with-containers")]
+    #[serial]
+    fn should_finalize_transfers_outlasting_keepalive_interval() {
+        crate::mock::logger();
+        let mut client = FtpFileSystem::new("127.0.0.1", 10021)
+            .username("test")
+            .password("test")
+            .keepalive_interval(Duration::from_secs(1));
+        assert!(client.connect().is_ok());
+        let tempdir = PathBuf::from(generate_tempdir());
+        assert!(client
+            .create_dir(tempdir.as_path(), UnixPex::from(0o775))
+            .is_ok());
+        assert!(client.change_dir(tempdir.as_path()).is_ok());
+        let p = Path::new("a.txt");
+        // upload
+        let mut writable = client.create(p, &Metadata::default()).ok().unwrap();
+        assert!(writable.write_all(b"Hello, ").is_ok());
+        std::thread::sleep(Duration::from_secs(2));
+        assert!(writable.write_all(b"world!").is_ok());
+        assert!(client.on_written(writable).is_ok());
+        // download
+        let mut readable = client.open(p).ok().unwrap();
+        let mut buffer = [0u8; 7];
+        assert!(readable.read_exact(&mut buffer).is_ok());
+        std::thread::sleep(Duration::from_secs(2));
+        let mut rest = String::new();
+        assert!(readable.read_to_string(&mut rest).is_ok());
+        assert_eq!(rest.as_str(), "world!");
+        assert!(client.on_read(readable).is_ok());
+        // the control connection is still usable
+        assert_eq!(client.stat(p).ok().unwrap().metadata().size, 13);
+        finalize_client(client);
+    }
+
+    #[test]
+    #[cfg(feature = "with-containers")]
+    #[serial]
+    fn should_transfer_files_in_active_mode() {
+        crate::mock::logger();
+        let client = FtpFileSystem::new("127.0.0.1", 10021)
+            .username("test")
+            .password("test")
+            .active_mode()
+            .active_external_address(IpAddr::from([127, 0, 0, 1]))
+            .active_port_range(40000..=40100)
+            .data_timeout(Duration::from_secs(10));
+        transfer_files(client);
+    }
+
+    #[test]
+    #[cfg(feature = "with-containers")]
+    #[serial]
+    fn should_transfer_files_in_extended_passive_mode() {
+        crate::mock::logger();
+        let client = FtpFileSystem::new("127.0.0.1", 10021)
+            .username("test")
+            .password("test")
+            .extended_passive_mode();
+        transfer_files(client);
+    }
+
+    #[test]
+    #[cfg(feature = "with-containers")]
+    #[serial]
+    fn should_tell_connection_is_dead() {
+        crate::mock::logger();
+        let mut client = setup_client();
+        let wrkdir = client.pwd().ok().unwrap();
+        assert!(client.is_connected());
+        assert!(client.keepalive_send().ok().unwrap().is_none());
+        assert!(client
+            .stream()
+            .unwrap()
+            .get_ref()
+            .shutdown(std::net::Shutdown::Both)
+            .is_ok());
+        assert!(!client.is_connected());
+        assert_eq!(
+            client.pwd().err().unwrap().kind,
+            RemoteErrorType::NotConnected
+        );
+        // cleanup
+        assert!(client.connect().is_ok());
+        assert!(client.remove_dir_all(wrkdir.as_path()).is_ok());
+        assert!(client.disconnect().is_ok());
+    }
+
     #[test]
     #[cfg(feature = "with-containers")]
     #[serial]
@@ -1029,6 +2745,62 @@ mod test {
         finalize_client(client);
     }
 
+    #[test]
+    #[cfg(feature = "with-containers")]
+    #[serial]
+    fn should_setstat_file() {
+        use std::time::{Duration, SystemTime};
+
+        crate::mock::logger();
+        let mut client = setup_client();
+        let p = Path::new("a.sh");
+        let file_data = "echo 5\n";
+        let reader = Cursor::new(file_data.as_bytes());
+        assert!(client
+            .create_file(p, &Metadata::default(), Box::new(reader))
+            .is_ok());
+        let modified = SystemTime::UNIX_EPOCH + Duration::from_secs(1612164210);
+        let report = client
+            .setstat_with_report(
+                p,
+                &Metadata::default()
+                    .modified(modified)
+                    .mode(UnixPex::from(0o755))
+                    .uid(1),
+            )
+            .unwrap();
+        assert!(report.unsupported.contains(&MetadataField::Uid));
+        let metadata = client.stat(p).unwrap().metadata;
+        if report.applied.contains(&MetadataField::Modified) {
+            assert_eq!(metadata.modified, Some(modified));
+        }
+        if report.applied.contains(&MetadataField::Mode) {
+            assert_eq!(metadata.mode, Some(UnixPex::from(0o755)));
+        }
+        finalize_client(client);
+    }
+
+    #[test]
+    #[cfg(feature = "with-containers")]
+    #[serial]
+    fn should_preserve_times_on_create_file() {
+        use std::time::{Duration, SystemTime};
+
+        crate::mock::logger();
+        let mut client = setup_client();
+        client.preserve_times = true;
+        let p = Path::new("a.txt");
+        let modified = SystemTime::UNIX_EPOCH + Duration::from_secs(1612164210);
+        let reader = Cursor::new(b"test data\n".to_vec());
+        assert!(client
+            .create_file(p, &Metadata::default().modified(modified), Box::new(reader))
+            .is_ok());
+        if client.features().unwrap().mfmt {
+            assert_eq!(client.stat(p).unwrap().metadata.modified, Some(modified));
+        }
+        finalize_client(client);
+    }
+
     #[test]
     #[cfg(feature = "with-containers")]
     #[serial]
@@ -1193,6 +2965,38 @@ mod test {
         client
     }
 
+    /// Upload, list, read and remove a file with `client`, which is not connected yet
+    #[cfg(feature = "with-containers")]
+    fn transfer_files(mut client: FtpFileSystem) {
+        assert!(client.connect().is_ok());
+        let tempdir = PathBuf::from(generate_tempdir());
+        assert!(client
+            .create_dir(tempdir.as_path(), UnixPex::from(0o775))
+            .is_ok());
+        assert!(client.change_dir(tempdir.as_path()).is_ok());
+        let p = Path::new("a.txt");
+        let reader = Cursor::new("Hello, world!".as_bytes());
+        assert_eq!(
+            client
+                .create_file(p, &Metadata::default(), Box::new(reader))
+                .ok()
+                .unwrap(),
+            13
+        );
+        let mut stream = client.append(p, &Metadata::default()).ok().unwrap();
+        assert!(stream.write_all(b"\n").is_ok());
+        assert!(client.on_written(stream).is_ok());
+        let files = client.list_dir(Path::new(".")).ok().unwrap();
+        assert_eq!(files.len(), 1);
+        assert_eq!(files[0].metadata().size, 14);
+        let mut stream = client.open_at(p, 7).ok().unwrap();
+        let mut data = String::new();
+        assert!(stream.read_to_string(&mut data).is_ok());
+        assert_eq!(data.as_str(), "world!\n");
+        assert!(client.on_read(stream).is_ok());
+        finalize_client(client);
+    }
+
     #[cfg(feature = "with-containers")]
     fn finalize_client(mut client: FtpFileSystem) {
         // Get working directory
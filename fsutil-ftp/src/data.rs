@@ -0,0 +1,301 @@
+//! ## Data
+//!
+//! data connections for transfers and listings, opened by the client itself rather than by suppaftp,
+//! to control the listener and the advertised address in active mode and how connections are secured
+
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::{IpAddr, SocketAddr, TcpStream};
+use std::ops::RangeInclusive;
+use std::time::Duration;
+
+use fsutil_core::Proxy;
+#[cfg(feature = "native-tls")]
+use suppaftp::native_tls::TlsStream as NativeTlsStream;
+#[cfg(feature = "rustls")]
+use suppaftp::rustls::{pki_types::ServerName, ClientConnection, StreamOwned};
+use suppaftp::types::Mode;
+use suppaftp::{FtpError, FtpResult, Status};
+
+use crate::client::FtpStream;
+use crate::encoding::PathCodec;
+#[cfg(any(feature = "native-tls", feature = "rustls"))]
+use crate::tls::TlsConfig;
+use crate::{active, fxp};
+
+/// Time the server is given to open an active data connection, unless a data timeout is set
+const ACCEPT_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// How data connections are opened on a control connection
+#[derive(Clone)]
+pub struct DataChannel {
+    /// `Active`, `Passive` (`PASV`) or `ExtendedPassive` (`EPSV`)
+    pub mode: Mode,
+    /// Address advertised to the server in active mode, instead of the local address
+    pub external_address: Option<IpAddr>,
+    /// Local ports to listen on in active mode
+    pub ports: Option<RangeInclusive<u16>>,
+    /// Timeout for opening, reading and writing data connections
+    pub timeout: Option<Duration>,
+    /// Proxy to open passive data connections through, and host of the server
+    pub proxy: Option<(Proxy, String)>,
+    /// TLS configuration and domain to secure data connections with, if they are protected (`PROT P`)
+    #[cfg(any(feature = "native-tls", feature = "rustls"))]
+    pub tls: Option<(TlsConfig, String)>,
+    /// Encoding of the paths in commands and listings
+    pub codec: PathCodec,
+}
+
+impl DataChannel {
+    /// Start the download of `path`
+    pub fn retr(&self, stream: &mut FtpStream, path: &str) -> FtpResult<Box<dyn Read + Send>> {
+        Ok(Box::new(self.open(stream, format!("RETR {path}"))?))
+    }
+
+    /// Start the upload of `path`
+    pub fn stor(&self, stream: &mut FtpStream, path: &str) -> FtpResult<Box<dyn Write + Send>> {
+        Ok(Box::new(self.open(stream, format!("STOR {path}"))?))
+    }
+
+    /// Start appending to `path`
+    pub fn appe(&self, stream: &mut FtpStream, path: &str) -> FtpResult<Box<dyn Write + Send>> {
+        Ok(Box::new(self.open(stream, format!("APPE {path}"))?))
+    }
+
+    /// List `path` with `LIST`
+    pub fn list(&self, stream: &mut FtpStream, path: &str) -> FtpResult<Vec<String>> {
+        self.lines(stream, format!("LIST {path}"))
+    }
+
+    /// List `path` with `MLSD`
+    pub fn mlsd(&self, stream: &mut FtpStream, path: &str) -> FtpResult<Vec<String>> {
+        self.lines(stream, format!("MLSD {path}"))
+    }
+
+    /// Run listing command `cmd` on a data connection and read its lines, decoded with the codec.
+    /// Empty lines are skipped
+    fn lines(&self, stream: &mut FtpStream, cmd: String) -> FtpResult<Vec<String>> {
+        let mut reader = BufReader::new(self.open(stream, cmd)?);
+        let lines = reader
+            .by_ref()
+            .split(b'\n')
+            .map(|line| {
+                line.map(|mut line| {
+                    if line.last() == Some(&b'\r') {
+                        line.pop();
+                    }
+                    self.codec.decode(&line)
+                })
+            })
+            .filter(|line| !line.as_ref().is_ok_and(|x| x.is_empty()))
+            .collect::<Result<Vec<String>, _>>()
+            .map_err(FtpError::ConnectionError);
+        stream.finalize_retr_stream(reader)?;
+        lines
+    }
+
+    /// Open a data connection for `cmd`, secured if the data connections are protected
+    fn open(&self, stream: &mut FtpStream, cmd: String) -> FtpResult<DataStream> {
+        let data = match self.mode {
+            Mode::Active => self.open_active(stream, cmd)?,
+            _ => self.open_passive(stream, cmd)?,
+        };
+        data.set_read_timeout(self.timeout)
+            .and_then(|_| data.set_write_timeout(self.timeout))
+            .map_err(FtpError::ConnectionError)?;
+        self.secure(data)
+    }
+
+    /// Listen for the data connection, advertise it with `PORT` or `EPRT`, send `cmd` and wait for the server
+    /// to connect
+    fn open_active(&self, stream: &mut FtpStream, cmd: String) -> FtpResult<TcpStream> {
+        let local = stream
+            .get_ref()
+            .local_addr()
+            .map_err(FtpError::ConnectionError)?;
+        let listener =
+            active::bind(local.ip(), self.ports.as_ref()).map_err(FtpError::ConnectionError)?;
+        let mut addr = listener.local_addr().map_err(FtpError::ConnectionError)?;
+        if let Some(ip) = self.external_address {
+            addr.set_ip(ip);
+        }
+        debug!(
+            "Active mode, listening on {} (advertised as {})",
+            listener.local_addr().map_err(FtpError::ConnectionError)?,
+            addr
+        );
+        stream.custom_command(active::port_command(addr), &[Status::CommandOk])?;
+        self.codec
+            .command(stream, &cmd, &[Status::AboutToSend, Status::AlreadyOpen])?;
+        active::accept(&listener, self.timeout.unwrap_or(ACCEPT_TIMEOUT))
+            .map_err(FtpError::ConnectionError)
+    }
+
+    /// Ask the server where to connect with `PASV` or `EPSV`, connect and send `cmd`
+    fn open_passive(&self, stream: &mut FtpStream, cmd: String) -> FtpResult<TcpStream> {
+        let addr = match self.mode {
+            Mode::ExtendedPassive => Self::epsv(stream)?,
+            _ => Self::pasv(stream)?,
+        };
+        trace!("Connecting to data address {}", addr);
+        let data = match (self.proxy.as_ref(), self.timeout) {
+            // the address of `EPSV` is the one of the control connection, that is of the proxy
+            (Some((proxy, host)), timeout) if self.mode == Mode::ExtendedPassive => {
+                proxy.connect(host, addr.port(), timeout)
+            }
+            (Some((proxy, _)), timeout) => {
+                proxy.connect(addr.ip().to_string().as_str(), addr.port(), timeout)
+            }
+            (None, Some(timeout)) => TcpStream::connect_timeout(&addr, timeout),
+            (None, None) => TcpStream::connect(addr),
+        }
+        .map_err(FtpError::ConnectionError)?;
+        self.codec
+            .command(stream, &cmd, &[Status::AboutToSend, Status::AlreadyOpen])?;
+        Ok(data)
+    }
+
+    fn pasv(stream: &mut FtpStream) -> FtpResult<SocketAddr> {
+        let response = stream.custom_command("PASV", &[Status::PassiveMode])?;
+        match fxp::parse_pasv_reply(&String::from_utf8_lossy(&response.body)) {
+            Some(addr) => Ok(SocketAddr::V4(addr)),
+            None => Err(FtpError::UnexpectedResponse(response)),
+        }
+    }
+
+    /// `EPSV` only gives the port: the address is the one of the control connection
+    fn epsv(stream: &mut FtpStream) -> FtpResult<SocketAddr> {
+        let response = stream.custom_command("EPSV", &[Status::ExtendedPassiveMode])?;
+        let Some(port) = parse_epsv_reply(&String::from_utf8_lossy(&response.body)) else {
+            return Err(FtpError::UnexpectedResponse(response));
+        };
+        let mut addr = stream
+            .get_ref()
+            .peer_addr()
+            .map_err(FtpError::ConnectionError)?;
+        addr.set_port(port);
+        Ok(addr)
+    }
+
+    #[cfg(not(any(feature = "native-tls", feature = "rustls")))]
+    fn secure(&self, data: TcpStream) -> FtpResult<DataStream> {
+        Ok(DataStream::Tcp(data))
+    }
+
+    #[cfg(feature = "native-tls")]
+    fn secure(&self, data: TcpStream) -> FtpResult<DataStream> {
+        let Some((connector, domain)) = self.tls.as_ref() else {
+            return Ok(DataStream::Tcp(data));
+        };
+        connector
+            .connect(domain, data)
+            .map(|x| DataStream::Tls(Box::new(x)))
+            .map_err(|e| FtpError::SecureError(e.to_string()))
+    }
+
+    #[cfg(feature = "rustls")]
+    fn secure(&self, data: TcpStream) -> FtpResult<DataStream> {
+        let Some((config, domain)) = self.tls.as_ref() else {
+            return Ok(DataStream::Tcp(data));
+        };
+        let name = ServerName::try_from(domain.clone())
+            .map_err(|e| FtpError::SecureError(e.to_string()))?;
+        let conn = ClientConnection::new(config.clone(), name)
+            .map_err(|e| FtpError::SecureError(e.to_string()))?;
+        let mut tls = StreamOwned::new(conn, data);
+        // handshake now, as the server expects it even if nothing is transferred
+        while tls.conn.is_handshaking() {
+            tls.conn
+                .complete_io(&mut tls.sock)
+                .map_err(|e| FtpError::SecureError(e.to_string()))?;
+        }
+        Ok(DataStream::Tls(Box::new(tls)))
+    }
+}
+
+/// A data connection
+enum DataStream {
+    Tcp(TcpStream),
+    #[cfg(feature = "native-tls")]
+    Tls(Box<NativeTlsStream<TcpStream>>),
+    #[cfg(feature = "rustls")]
+    Tls(Box<StreamOwned<ClientConnection, TcpStream>>),
+}
+
+impl Read for DataStream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            Self::Tcp(stream) => stream.read(buf),
+            #[cfg(any(feature = "native-tls", feature = "rustls"))]
+            Self::Tls(stream) => stream.read(buf),
+        }
+    }
+}
+
+impl Write for DataStream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Self::Tcp(stream) => stream.write(buf),
+            #[cfg(any(feature = "native-tls", feature = "rustls"))]
+            Self::Tls(stream) => stream.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Self::Tcp(stream) => stream.flush(),
+            #[cfg(any(feature = "native-tls", feature = "rustls"))]
+            Self::Tls(stream) => stream.flush(),
+        }
+    }
+}
+
+impl Drop for DataStream {
+    /// Close the TLS session, since some servers consider the transfer failed otherwise
+    fn drop(&mut self) {
+        match self {
+            Self::Tcp(_) => {}
+            #[cfg(feature = "native-tls")]
+            Self::Tls(stream) => {
+                let _ = stream.shutdown();
+            }
+            #[cfg(feature = "rustls")]
+            Self::Tls(stream) => {
+                stream.conn.send_close_notify();
+                let _ = stream.conn.complete_io(&mut stream.sock);
+            }
+        }
+    }
+}
+
+/// Parse the port in the reply to `EPSV`, such as `229 Entering Extended Passive Mode (|||6446|)`
+fn parse_epsv_reply(reply: &str) -> Option<u16> {
+    let start = reply.find('(')?;
+    let end = reply[start..].find(')')? + start;
+    let inner = &reply[start + 1..end];
+    let delimiter = inner.chars().next()?;
+    let fields: Vec<&str> = inner.split(delimiter).collect();
+    match fields.as_slice() {
+        ["", "", "", port, ""] => port.parse().ok(),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod test {
+
+    use pretty_assertions::assert_eq;
+
+    use super::*;
+
+    #[test]
+    fn should_parse_epsv_reply() {
+        assert_eq!(
+            parse_epsv_reply("229 Entering Extended Passive Mode (|||6446|)"),
+            Some(6446)
+        );
+        assert_eq!(parse_epsv_reply("229 Extended Passive (!!!21!)"), Some(21));
+        assert_eq!(parse_epsv_reply("229 Entering Extended Passive Mode"), None);
+        assert_eq!(parse_epsv_reply("229 (|||port|)"), None);
+        assert_eq!(parse_epsv_reply("229 (|2|::1|21|)"), None);
+    }
+}
@@ -0,0 +1,301 @@
+//! ## Encoding
+//!
+//! encoding of paths in commands, replies and listings: UTF-8 once negotiated with `OPTS UTF8 ON` (RFC 2640),
+//! otherwise an optional fallback charset for legacy servers.
+//!
+//! Bytes which can't be decoded are escaped into the private use characters `U+F780` to `U+F7FF`
+//! (byte `b` becomes `U+F700 + b`), so that such paths can still be passed back to the client
+
+use std::io::{self, Read, Write};
+use std::sync::Arc;
+
+use suppaftp::types::Response;
+use suppaftp::{FtpError, FtpResult, Status};
+
+use crate::client::FtpStream;
+
+/// Offset of the characters undecodable bytes are escaped into
+const ESCAPE_OFFSET: u32 = 0xF700;
+
+/// Charset of file names on servers which don't use UTF-8, such as Latin-1 or Shift-JIS
+pub trait Charset: Send + Sync {
+    /// Decode `bytes`; returns `None` if they aren't valid in this charset
+    fn decode(&self, bytes: &[u8]) -> Option<String>;
+
+    /// Encode `text`; returns `None` if it has characters which can't be represented in this charset
+    fn encode(&self, text: &str) -> Option<Vec<u8>>;
+}
+
+/// ISO-8859-1 charset
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Latin1;
+
+impl Charset for Latin1 {
+    fn decode(&self, bytes: &[u8]) -> Option<String> {
+        Some(bytes.iter().copied().map(char::from).collect())
+    }
+
+    fn encode(&self, text: &str) -> Option<Vec<u8>> {
+        text.chars().map(|x| u8::try_from(x).ok()).collect()
+    }
+}
+
+/// How paths are encoded on a control connection
+#[derive(Clone, Default)]
+pub(crate) struct PathCodec {
+    /// Charset to use when UTF-8 hasn't been negotiated
+    charset: Option<Arc<dyn Charset>>,
+    /// Whether commands which aren't valid UTF-8 can be written straight to the socket of the control
+    /// connection, which is only possible if the connection isn't secure
+    raw_commands: bool,
+}
+
+impl PathCodec {
+    /// Make the codec of a session: `charset` is ignored if the server agreed to use UTF-8
+    pub fn new(charset: Option<Arc<dyn Charset>>, utf8: bool, raw_commands: bool) -> Self {
+        Self {
+            charset: charset.filter(|_| !utf8),
+            raw_commands,
+        }
+    }
+
+    /// Decode `bytes` received from the server, escaping the bytes which can't be decoded
+    pub fn decode(&self, bytes: &[u8]) -> String {
+        match self.charset.as_ref() {
+            None => escape_utf8(bytes),
+            Some(charset) => charset
+                .decode(bytes)
+                .unwrap_or_else(|| escape_non_ascii(bytes)),
+        }
+    }
+
+    /// Encode `text` to send it to the server, restoring escaped bytes.
+    /// Characters the charset can't represent are sent as UTF-8
+    pub fn encode(&self, text: &str) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(text.len());
+        let mut run = String::new();
+        for ch in text.chars() {
+            match unescape(ch) {
+                Some(byte) => {
+                    self.encode_run(&mut bytes, &run);
+                    run.clear();
+                    bytes.push(byte);
+                }
+                None => run.push(ch),
+            }
+        }
+        self.encode_run(&mut bytes, &run);
+        bytes
+    }
+
+    fn encode_run(&self, bytes: &mut Vec<u8>, run: &str) {
+        match self.charset.as_ref().and_then(|x| x.encode(run)) {
+            Some(encoded) => bytes.extend(encoded),
+            None => bytes.extend_from_slice(run.as_bytes()),
+        }
+    }
+
+    /// Send `cmd`, with its paths encoded, and read the reply
+    pub fn command(
+        &self,
+        stream: &mut FtpStream,
+        cmd: &str,
+        expected: &[Status],
+    ) -> FtpResult<Response> {
+        match String::from_utf8(self.encode(cmd)) {
+            Ok(cmd) => stream.custom_command(cmd, expected),
+            Err(e) if self.raw_commands => raw_command(stream, e.into_bytes(), expected),
+            Err(_) => Err(FtpError::ConnectionError(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "paths which aren't UTF-8 can't be sent on a secure control connection",
+            ))),
+        }
+    }
+}
+
+/// Write `cmd` straight to the socket of the plain control connection and read the reply.
+///
+/// suppaftp has nothing buffered from the socket between commands, so the reply can be read from it too
+fn raw_command(stream: &FtpStream, mut cmd: Vec<u8>, expected: &[Status]) -> FtpResult<Response> {
+    debug!("Sending raw command: {}", String::from_utf8_lossy(&cmd));
+    cmd.extend_from_slice(b"\r\n");
+    let mut socket = stream.get_ref();
+    socket.write_all(&cmd).map_err(FtpError::ConnectionError)?;
+    let response = read_reply(socket)?;
+    match expected.contains(&response.status) {
+        true => Ok(response),
+        false => Err(FtpError::UnexpectedResponse(response)),
+    }
+}
+
+/// Read a reply byte by byte, so that nothing past its last line is consumed
+fn read_reply(mut reader: impl Read) -> FtpResult<Response> {
+    let mut body = Vec::new();
+    let mut code: Option<[u8; 3]> = None;
+    let mut line_start = 0;
+    let mut byte = [0; 1];
+    loop {
+        reader
+            .read_exact(&mut byte)
+            .map_err(FtpError::ConnectionError)?;
+        body.push(byte[0]);
+        if byte[0] != b'\n' {
+            continue;
+        }
+        let line = &body[line_start..];
+        line_start = body.len();
+        let code = match code {
+            Some(code) => code,
+            None => {
+                let first: [u8; 3] = line
+                    .get(..3)
+                    .and_then(|x| x.try_into().ok())
+                    .filter(|x: &[u8; 3]| x.iter().all(u8::is_ascii_digit))
+                    .ok_or(FtpError::BadResponse)?;
+                *code.insert(first)
+            }
+        };
+        if line.starts_with(&code) && line.get(3) == Some(&b' ') {
+            let code = u32::from(code[0] - b'0') * 100
+                + u32::from(code[1] - b'0') * 10
+                + u32::from(code[2] - b'0');
+            return Ok(Response::new(Status::from(code), body));
+        }
+    }
+}
+
+/// Decode `bytes` as UTF-8, escaping the invalid bytes
+fn escape_utf8(bytes: &[u8]) -> String {
+    let mut text = String::with_capacity(bytes.len());
+    let mut rest = bytes;
+    loop {
+        match std::str::from_utf8(rest) {
+            Ok(valid) => {
+                text.push_str(valid);
+                return text;
+            }
+            Err(e) => {
+                let (valid, invalid) = rest.split_at(e.valid_up_to());
+                text.push_str(std::str::from_utf8(valid).unwrap_or_default());
+                let len = e.error_len().unwrap_or(invalid.len());
+                text.extend(invalid[..len].iter().copied().map(escape));
+                rest = &invalid[len..];
+            }
+        }
+    }
+}
+
+/// Keep ASCII bytes and escape all the other ones
+fn escape_non_ascii(bytes: &[u8]) -> String {
+    bytes
+        .iter()
+        .copied()
+        .map(|x| match x.is_ascii() {
+            true => char::from(x),
+            false => escape(x),
+        })
+        .collect()
+}
+
+fn escape(byte: u8) -> char {
+    char::from_u32(ESCAPE_OFFSET + u32::from(byte)).unwrap_or(char::REPLACEMENT_CHARACTER)
+}
+
+fn unescape(ch: char) -> Option<u8> {
+    u32::from(ch)
+        .checked_sub(ESCAPE_OFFSET)
+        .and_then(|x| u8::try_from(x).ok())
+        .filter(|x| !x.is_ascii())
+}
+
+#[cfg(test)]
+mod test {
+
+    use pretty_assertions::assert_eq;
+
+    use super::*;
+
+    /// Charset which only knows `é` as `0x82` (as in code page 437)
+    struct Cp437;
+
+    impl Charset for Cp437 {
+        fn decode(&self, bytes: &[u8]) -> Option<String> {
+            bytes
+                .iter()
+                .map(|x| match *x {
+                    0x82 => Some('é'),
+                    x if x.is_ascii() => Some(char::from(x)),
+                    _ => None,
+                })
+                .collect()
+        }
+
+        fn encode(&self, text: &str) -> Option<Vec<u8>> {
+            text.chars()
+                .map(|x| match x {
+                    'é' => Some(0x82),
+                    x if x.is_ascii() => Some(x as u8),
+                    _ => None,
+                })
+                .collect()
+        }
+    }
+
+    #[test]
+    fn should_encode_latin1() {
+        assert_eq!(Latin1.decode(b"caf\xe9").unwrap().as_str(), "café");
+        assert_eq!(Latin1.encode("café").unwrap(), b"caf\xe9".to_vec());
+        assert!(Latin1.encode("日本").is_none());
+    }
+
+    #[test]
+    fn should_decode_and_encode_utf8_losslessly() {
+        let codec = PathCodec::default();
+        assert_eq!(codec.decode("/tmp/café".as_bytes()).as_str(), "/tmp/café");
+        let decoded = codec.decode(b"/tmp/caf\xe9.txt");
+        assert_eq!(decoded.as_str(), "/tmp/caf\u{f7e9}.txt");
+        assert_eq!(codec.encode(&decoded), b"/tmp/caf\xe9.txt".to_vec());
+        assert_eq!(codec.encode("/tmp/café"), "/tmp/café".as_bytes().to_vec());
+        // truncated sequence at the end
+        assert_eq!(codec.decode(b"a\xe6\x97").as_str(), "a\u{f7e6}\u{f797}");
+    }
+
+    #[test]
+    fn should_use_charset_unless_utf8() {
+        let codec = PathCodec::new(Some(Arc::new(Cp437)), false, true);
+        assert_eq!(codec.decode(b"caf\x82").as_str(), "café");
+        assert_eq!(codec.encode("DELE café"), b"DELE caf\x82".to_vec());
+        // undecodable names are escaped and restored
+        let decoded = codec.decode(b"caf\x82\xff");
+        assert_eq!(decoded.as_str(), "caf\u{f782}\u{f7ff}");
+        assert_eq!(codec.encode(&decoded), b"caf\x82\xff".to_vec());
+        // characters out of the charset are sent as UTF-8
+        assert_eq!(codec.encode("日"), "日".as_bytes().to_vec());
+        let codec = PathCodec::new(Some(Arc::new(Cp437)), true, true);
+        assert_eq!(codec.encode("café"), "café".as_bytes().to_vec());
+    }
+
+    #[test]
+    fn should_read_reply() {
+        let mut reader = io::Cursor::new(b"250 ok\r\n200 next\r\n".to_vec());
+        let response = read_reply(&mut reader).unwrap();
+        assert_eq!(response.status, Status::RequestedFileActionOk);
+        assert_eq!(response.body, b"250 ok\r\n".to_vec());
+        // nothing past the reply has been consumed
+        assert_eq!(reader.position(), 8);
+        let response = read_reply(io::Cursor::new(
+            b"257-caf\xe9\r\n 257 inner\r\n257 done\r\n".to_vec(),
+        ))
+        .unwrap();
+        assert_eq!(response.status, Status::PathCreated);
+        assert_eq!(response.body.len(), 32);
+        assert!(matches!(
+            read_reply(io::Cursor::new(b"oops\r\n".to_vec())),
+            Err(FtpError::BadResponse)
+        ));
+        assert!(matches!(
+            read_reply(io::Cursor::new(b"250-partial\r\n".to_vec())),
+            Err(FtpError::ConnectionError(_))
+        ));
+    }
+}
@@ -0,0 +1,146 @@
+//! ## Exec
+//!
+//! raw and `SITE` commands sent through `exec`
+
+use fsutil_core::fs::{RemoteError, RemoteErrorType, RemoteResult};
+
+/// FTP commands which are sent as they are; any other command is sent as a `SITE` command
+const FTP_COMMANDS: &[&str] = &[
+    "ABOR", "ACCT", "ALLO", "APPE", "AUTH", "CCC", "CDUP", "CWD", "DELE", "EPRT", "EPSV", "FEAT",
+    "HASH", "HELP", "LANG", "LIST", "LPRT", "LPSV", "MDTM", "MFCT", "MFF", "MFMT", "MKD", "MLSD",
+    "MLST", "MODE", "NLST", "NOOP", "OPTS", "PASS", "PASV", "PBSZ", "PORT", "PROT", "PWD", "QUIT",
+    "REIN", "REST", "RETR", "RMD", "RNFR", "RNTO", "SITE", "SIZE", "SMNT", "STAT", "STOR", "STOU",
+    "STRU", "SYST", "TYPE", "USER", "XCUP", "XCWD", "XMKD", "XPWD", "XRMD",
+];
+
+/// Commands which would corrupt the state of the control channel or of the client:
+/// data connection setup and transfers, session and security changes, transfer parameters and options,
+/// directory changes (the client tracks the working directory) and renames, which span two commands
+const FORBIDDEN_COMMANDS: &[&str] = &[
+    "ABOR", "ACCT", "APPE", "AUTH", "CCC", "CDUP", "CWD", "EPRT", "EPSV", "LIST", "LPRT", "LPSV",
+    "MLSD", "MODE", "NLST", "OPTS", "PASS", "PASV", "PBSZ", "PORT", "PROT", "QUIT", "REIN", "REST",
+    "RETR", "RNFR", "SMNT", "STOR", "STOU", "STRU", "TYPE", "USER", "XCUP", "XCWD",
+];
+
+/// Make the command to send for `cmd`: FTP commands are sent as they are,
+/// anything else as a `SITE` command (e.g. `CHMOD 644 a.txt` becomes `SITE CHMOD 644 a.txt`).
+///
+/// Commands which would corrupt the control channel state are refused
+pub fn prepare_command(cmd: &str) -> RemoteResult<String> {
+    let cmd = cmd.trim();
+    if cmd.contains(['\r', '\n']) {
+        return Err(RemoteError::new_ex(
+            RemoteErrorType::BadAddress,
+            "command can't contain line breaks",
+        ));
+    }
+    let verb = cmd.split(' ').next().unwrap_or_default().to_uppercase();
+    if verb.is_empty() {
+        return Err(RemoteError::new_ex(
+            RemoteErrorType::BadAddress,
+            "empty command",
+        ));
+    }
+    if FORBIDDEN_COMMANDS.contains(&verb.as_str()) {
+        return Err(RemoteError::new_ex(
+            RemoteErrorType::UnsupportedFeature,
+            format!("{verb} can't be sent through exec"),
+        ));
+    }
+    if FTP_COMMANDS.contains(&verb.as_str()) {
+        Ok(cmd.to_string())
+    } else {
+        Ok(format!("SITE {cmd}"))
+    }
+}
+
+/// Parse a reply into its code and text, without the code prefix on each line
+pub fn parse_reply(reply: &str) -> RemoteResult<(u32, String)> {
+    let code = reply
+        .get(0..3)
+        .and_then(|x| x.parse::<u32>().ok())
+        .ok_or_else(|| {
+            RemoteError::new_ex(
+                RemoteErrorType::ProtocolError,
+                format!("bad reply: {reply}"),
+            )
+        })?;
+    let prefix = &reply[0..3];
+    let text = reply
+        .lines()
+        .map(|line| match line.strip_prefix(prefix) {
+            Some("") => "",
+            Some(rest) if rest.starts_with([' ', '-']) => &rest[1..],
+            _ => line,
+        })
+        .collect::<Vec<&str>>()
+        .join("\n");
+    Ok((code, text))
+}
+
+#[cfg(test)]
+mod test {
+
+    use pretty_assertions::assert_eq;
+
+    use super::*;
+
+    #[test]
+    fn should_prepare_command() {
+        assert_eq!(
+            prepare_command("CHMOD 644 a.txt").unwrap().as_str(),
+            "SITE CHMOD 644 a.txt"
+        );
+        assert_eq!(
+            prepare_command(" site idle 60 ").unwrap().as_str(),
+            "site idle 60"
+        );
+        assert_eq!(prepare_command("NOOP").unwrap().as_str(), "NOOP");
+        assert_eq!(prepare_command("stat /tmp").unwrap().as_str(), "stat /tmp");
+    }
+
+    #[test]
+    fn should_not_prepare_forbidden_command() {
+        for cmd in [
+            "PASV",
+            "quit",
+            "TYPE A",
+            "RETR a.txt",
+            "USER root",
+            "CWD /tmp",
+            "cdup",
+            "XCWD /tmp",
+            "XCUP",
+            "RNFR a.txt",
+            "OPTS UTF8 ON",
+        ] {
+            assert_eq!(
+                prepare_command(cmd).unwrap_err().kind,
+                RemoteErrorType::UnsupportedFeature
+            );
+        }
+        assert!(prepare_command("").is_err());
+        assert!(prepare_command("CHMOD 644 a.txt\r\nQUIT").is_err());
+    }
+
+    #[test]
+    fn should_parse_reply() {
+        assert_eq!(
+            parse_reply("200 SITE CHMOD command successful\r\n").unwrap(),
+            (200, "SITE CHMOD command successful".to_string())
+        );
+        assert_eq!(
+            parse_reply("214-The following SITE commands are recognized\r\n CHMOD\r\n UTIME\r\n214 Direct comments to root\r\n").unwrap(),
+            (
+                214,
+                "The following SITE commands are recognized\n CHMOD\n UTIME\nDirect comments to root"
+                    .to_string()
+            )
+        );
+        assert_eq!(
+            parse_reply("550 a.txt: No such file or directory").unwrap(),
+            (550, "a.txt: No such file or directory".to_string())
+        );
+        assert!(parse_reply("hello").is_err());
+    }
+}
@@ -0,0 +1,168 @@
+//! ## Features
+//!
+//! server feature profile, as negotiated with `FEAT` (RFC 2389) and `SYST`
+
+use std::collections::BTreeMap;
+
+use suppaftp::types::Features;
+
+use crate::mlsx;
+
+/// Features advertised by the FTP server with `FEAT` and its system type, as replied to `SYST`.
+///
+/// The profile is negotiated after login and is used by [`crate::FtpFileSystem`] to pick the best command
+/// for each operation
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct FtpFeatures {
+    /// System type (e.g. `UNIX Type: L8`); `None` if `SYST` failed
+    pub system: Option<String>,
+    /// Facts supported by `MLST` and `MLSD`, with whether each one is enabled; `None` if unsupported
+    pub mlst: Option<Vec<(String, bool)>>,
+    /// `UTF8`: paths can be sent and received as UTF-8
+    pub utf8: bool,
+    /// `REST STREAM`: transfers can be restarted at an offset
+    pub rest_stream: bool,
+    /// `MDTM`: modification time of files can be queried
+    pub mdtm: bool,
+    /// `MFMT`: modification time of files can be set
+    pub mfmt: bool,
+    /// `SIZE`: size of files can be queried
+    pub size: bool,
+    /// Algorithms supported by `HASH` (e.g. `SHA-256`); empty if unsupported
+    pub hash: Vec<String>,
+    /// `EPSV`: extended passive mode
+    pub epsv: bool,
+    /// `AUTH TLS`: explicit FTPS
+    pub auth_tls: bool,
+    /// Commands supported by `SITE`, uppercase (e.g. `CHMOD`, `UTIME`)
+    pub site: Vec<String>,
+    /// All the advertised features, with the uppercase label as key
+    pub raw: BTreeMap<String, Option<String>>,
+}
+
+impl FtpFeatures {
+    /// Make the profile out of the `FEAT` reply and the `SYST` reply
+    pub fn new(features: &Features, system: Option<String>) -> Self {
+        let raw: BTreeMap<String, Option<String>> = features
+            .iter()
+            .map(|(label, value)| (label.to_uppercase(), value.clone()))
+            .collect();
+        let value = |label: &str| raw.get(label).map(|x| x.as_deref().unwrap_or_default());
+        let tokens = |label: &str| -> Vec<String> {
+            value(label)
+                .map(|x| {
+                    x.split([';', ',', ' '])
+                        .map(|x| x.trim().trim_end_matches('*').to_uppercase())
+                        .filter(|x| !x.is_empty())
+                        .collect()
+                })
+                .unwrap_or_default()
+        };
+        Self {
+            system,
+            mlst: value("MLST").map(mlsx::parse_mlst_feature),
+            utf8: raw.contains_key("UTF8"),
+            rest_stream: tokens("REST").iter().any(|x| x == "STREAM"),
+            mdtm: raw.contains_key("MDTM"),
+            mfmt: raw.contains_key("MFMT"),
+            size: raw.contains_key("SIZE"),
+            hash: tokens("HASH"),
+            epsv: raw.contains_key("EPSV"),
+            auth_tls: tokens("AUTH").iter().any(|x| x == "TLS"),
+            site: tokens("SITE"),
+            raw: raw.clone(),
+        }
+    }
+
+    /// Returns whether the server advertised feature `label` (case insensitive)
+    pub fn supports(&self, label: &str) -> bool {
+        self.raw.contains_key(label.to_uppercase().as_str())
+    }
+
+    /// Returns whether the server advertised `SITE` command `cmd` (case insensitive)
+    pub fn supports_site(&self, cmd: &str) -> bool {
+        self.site.iter().any(|x| x.eq_ignore_ascii_case(cmd))
+    }
+
+    /// Advertised features as `LABEL [value]` lines
+    pub fn lines(&self) -> Vec<String> {
+        self.raw
+            .iter()
+            .map(|(label, value)| match value {
+                Some(value) => format!("{label} {value}"),
+                None => label.clone(),
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod test {
+
+    use pretty_assertions::assert_eq;
+
+    use super::*;
+
+    fn features(lines: &[(&str, Option<&str>)]) -> Features {
+        lines
+            .iter()
+            .map(|(label, value)| (label.to_string(), value.map(|x| x.to_string())))
+            .collect()
+    }
+
+    #[test]
+    fn should_make_feature_profile() {
+        let profile = FtpFeatures::new(
+            &features(&[
+                ("MLST", Some("type*;size*;modify*;UNIX.mode;")),
+                ("UTF8", None),
+                ("REST", Some("STREAM")),
+                ("MDTM", None),
+                ("MFMT", None),
+                ("SIZE", None),
+                ("HASH", Some("SHA-256*;SHA-1;MD5")),
+                ("EPSV", None),
+                ("AUTH", Some("TLS")),
+                ("SITE", Some("CHMOD;UTIME")),
+            ]),
+            Some("UNIX Type: L8".to_string()),
+        );
+        assert_eq!(profile.system.as_deref(), Some("UNIX Type: L8"));
+        assert_eq!(
+            profile.mlst,
+            Some(vec![
+                ("type".to_string(), true),
+                ("size".to_string(), true),
+                ("modify".to_string(), true),
+                ("unix.mode".to_string(), false),
+            ])
+        );
+        assert!(profile.utf8);
+        assert!(profile.rest_stream);
+        assert!(profile.mdtm);
+        assert!(profile.mfmt);
+        assert!(profile.size);
+        assert_eq!(profile.hash, vec!["SHA-256", "SHA-1", "MD5"]);
+        assert!(profile.epsv);
+        assert!(profile.auth_tls);
+        assert_eq!(profile.site, vec!["CHMOD", "UTIME"]);
+        assert!(profile.supports("utf8"));
+        assert!(profile.supports_site("chmod"));
+        assert!(!profile.supports_site("EXEC"));
+        assert_eq!(profile.lines().first().unwrap().as_str(), "AUTH TLS");
+    }
+
+    #[test]
+    fn should_make_empty_feature_profile() {
+        let profile = FtpFeatures::new(&features(&[("mdtm", None), ("REST", None)]), None);
+        assert!(profile.system.is_none());
+        assert!(profile.mlst.is_none());
+        assert!(profile.mdtm);
+        assert!(!profile.rest_stream);
+        assert!(!profile.auth_tls);
+        assert!(profile.hash.is_empty());
+        assert!(profile.site.is_empty());
+        assert_eq!(profile.lines(), vec!["MDTM", "REST"]);
+        assert_eq!(FtpFeatures::default().lines(), Vec::<String>::new());
+    }
+}
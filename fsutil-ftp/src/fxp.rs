@@ -0,0 +1,73 @@
+//! ## Fxp
+//!
+//! helpers for server to server transfers (FXP), where the destination listens with `PASV`
+//! and the source connects to it after `PORT`
+
+use std::net::{Ipv4Addr, SocketAddrV4};
+
+/// Parse the address in a `PASV` reply, such as `227 Entering Passive Mode (192,168,1,2,195,80).`;
+/// some servers omit the parentheses
+pub fn parse_pasv_reply(reply: &str) -> Option<SocketAddrV4> {
+    let text = reply.get(4..)?;
+    let start = text.find(|x: char| x.is_ascii_digit())?;
+    let end = text[start..]
+        .find(|x: char| !(x.is_ascii_digit() || x == ',' || x == ' '))
+        .map_or(text.len(), |x| start + x);
+    let numbers: Vec<u8> = text[start..end]
+        .split(',')
+        .map(|x| x.trim().parse::<u8>())
+        .collect::<Result<_, _>>()
+        .ok()?;
+    match numbers.as_slice() {
+        [h1, h2, h3, h4, p1, p2] => Some(SocketAddrV4::new(
+            Ipv4Addr::new(*h1, *h2, *h3, *h4),
+            (u16::from(*p1) << 8) | u16::from(*p2),
+        )),
+        _ => None,
+    }
+}
+
+/// Make the `PORT` command to make the source connect to `addr`
+pub fn port_command(addr: SocketAddrV4) -> String {
+    let [h1, h2, h3, h4] = addr.ip().octets();
+    format!(
+        "PORT {h1},{h2},{h3},{h4},{},{}",
+        addr.port() >> 8,
+        addr.port() & 0xff
+    )
+}
+
+#[cfg(test)]
+mod test {
+
+    use pretty_assertions::assert_eq;
+
+    use super::*;
+
+    #[test]
+    fn should_parse_pasv_reply() {
+        assert_eq!(
+            parse_pasv_reply("227 Entering Passive Mode (192,168,1,2,195,80).\r\n"),
+            Some(SocketAddrV4::new(Ipv4Addr::new(192, 168, 1, 2), 50000))
+        );
+        assert_eq!(
+            parse_pasv_reply("227 Entering Passive Mode (10, 0, 0, 1, 0, 21)"),
+            Some(SocketAddrV4::new(Ipv4Addr::new(10, 0, 0, 1), 21))
+        );
+        assert_eq!(
+            parse_pasv_reply("227 Entering Passive Mode 10,0,0,1,4,1"),
+            Some(SocketAddrV4::new(Ipv4Addr::new(10, 0, 0, 1), 1025))
+        );
+        assert!(parse_pasv_reply("227 Entering Passive Mode").is_none());
+        assert!(parse_pasv_reply("227 (10,0,0,1,21)").is_none());
+        assert!(parse_pasv_reply("227 (10,0,0,300,0,21)").is_none());
+    }
+
+    #[test]
+    fn should_make_port_command() {
+        assert_eq!(
+            port_command(SocketAddrV4::new(Ipv4Addr::new(192, 168, 1, 2), 50000)).as_str(),
+            "PORT 192,168,1,2,195,80"
+        );
+    }
+}
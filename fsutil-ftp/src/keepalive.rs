@@ -0,0 +1,109 @@
+//! ## Keepalive
+//!
+//! keepalive and idle tracking for the FTP control connection
+
+use std::time::{Duration, Instant};
+
+/// After being idle for this long, the control connection is probed with `NOOP` before being used,
+/// since servers usually close idle connections after a few minutes
+pub const IDLE_PROBE: Duration = Duration::from_secs(30);
+
+/// Time the server is given to answer a liveness probe, unless a read timeout is set
+pub const PROBE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Keepalive state of a connected client
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Keepalive {
+    /// Interval between keepalive messages; `None` if keepalive is disabled
+    interval: Option<Duration>,
+    /// Last time a command has been sent on the control connection
+    last_activity: Instant,
+}
+
+impl Keepalive {
+    pub fn new(interval: Option<Duration>) -> Self {
+        Self {
+            interval,
+            last_activity: Instant::now(),
+        }
+    }
+
+    /// Interval between keepalive messages; `None` if keepalive is disabled
+    pub fn interval(&self) -> Option<Duration> {
+        self.interval
+    }
+
+    /// Record activity on the control connection
+    pub fn touch(&mut self) {
+        self.last_activity = Instant::now();
+    }
+
+    /// Returns the time before the next keepalive message is due (zero if it's due),
+    /// or `None` if keepalive is disabled
+    pub fn due_in(&self) -> Option<Duration> {
+        self.due_in_at(Instant::now())
+    }
+
+    /// Returns whether the connection has been idle long enough to be probed before being used
+    pub fn should_probe(&self) -> bool {
+        self.should_probe_at(Instant::now())
+    }
+
+    fn due_in_at(&self, now: Instant) -> Option<Duration> {
+        let idle = now.saturating_duration_since(self.last_activity);
+        self.interval.map(|interval| interval.saturating_sub(idle))
+    }
+
+    fn should_probe_at(&self, now: Instant) -> bool {
+        let idle = now.saturating_duration_since(self.last_activity);
+        let threshold = self
+            .interval
+            .map(|interval| interval.min(IDLE_PROBE))
+            .unwrap_or(IDLE_PROBE);
+        idle >= threshold
+    }
+}
+
+#[cfg(test)]
+mod test {
+
+    use pretty_assertions::assert_eq;
+
+    use super::*;
+
+    #[test]
+    fn should_tell_when_keepalive_is_due() {
+        let keepalive = Keepalive::new(Some(Duration::from_secs(60)));
+        let now = keepalive.last_activity;
+        assert_eq!(
+            keepalive.due_in_at(now + Duration::from_secs(20)),
+            Some(Duration::from_secs(40))
+        );
+        assert_eq!(
+            keepalive.due_in_at(now + Duration::from_secs(90)),
+            Some(Duration::ZERO)
+        );
+        assert_eq!(keepalive.interval(), Some(Duration::from_secs(60)));
+        let keepalive = Keepalive::new(None);
+        assert!(keepalive
+            .due_in_at(keepalive.last_activity + Duration::from_secs(90))
+            .is_none());
+    }
+
+    #[test]
+    fn should_tell_when_to_probe() {
+        let keepalive = Keepalive::new(None);
+        let now = keepalive.last_activity;
+        assert!(!keepalive.should_probe_at(now + Duration::from_secs(10)));
+        assert!(keepalive.should_probe_at(now + IDLE_PROBE));
+        let keepalive = Keepalive::new(Some(Duration::from_secs(10)));
+        let now = keepalive.last_activity;
+        assert!(!keepalive.should_probe_at(now + Duration::from_secs(5)));
+        assert!(keepalive.should_probe_at(now + Duration::from_secs(10)));
+        let mut keepalive = Keepalive::new(Some(Duration::from_secs(300)));
+        let now = keepalive.last_activity;
+        assert!(keepalive.should_probe_at(now + IDLE_PROBE));
+        keepalive.touch();
+        assert!(!keepalive.should_probe());
+    }
+}
@@ -4,7 +4,35 @@ extern crate tracing;
 
 pub mod client;
 pub use client::FtpFileSystem;
+pub mod encoding;
+pub use encoding::{Charset, Latin1};
+pub mod features;
+pub use features::FtpFeatures;
+pub mod list;
+pub use list::ListParser;
+pub mod setstat;
+pub use setstat::{MetadataField, SetStatReport};
+#[cfg(any(feature = "native-tls", feature = "rustls"))]
+pub mod tls;
+#[cfg(any(feature = "native-tls", feature = "rustls"))]
+pub use tls::FtpTls;
+pub mod transfer;
+pub use transfer::TransferMode;
 
+// -- active
+pub(crate) mod active;
+// -- data
+pub(crate) mod data;
+// -- exec
+pub(crate) mod exec;
+// -- fxp
+pub(crate) mod fxp;
+// -- keepalive
+pub(crate) mod keepalive;
+// -- mlsx
+pub(crate) mod mlsx;
+// -- reader
+pub(crate) mod reader;
 // -- utils
 pub(crate) mod utils;
 // -- mock
@@ -0,0 +1,632 @@
+//! ## List
+//!
+//! parsers for the listings returned by `LIST`, whose format depends on the server:
+//! Unix `ls -l`, DOS/IIS, VMS and OS/400
+
+use std::path::PathBuf;
+use std::time::SystemTime;
+
+use chrono::{Datelike, NaiveDate, NaiveDateTime, NaiveTime, Utc};
+use fsutil_core::fs::{FileType, Metadata, UnixPex, UnixPexClass};
+use suppaftp::list::{File as FtpFile, PosixPexQuery};
+
+/// Size of the blocks VMS sizes are counted in
+const VMS_BLOCK_SIZE: u64 = 512;
+
+const MONTHS: [&str; 12] = [
+    "jan", "feb", "mar", "apr", "may", "jun", "jul", "aug", "sep", "oct", "nov", "dec",
+];
+
+/// An entry of a `LIST` listing
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ListEntry {
+    /// Name of the entry, as sent by the server
+    pub name: String,
+    /// Metadata of the entry; the symlink target, if any, is as sent by the server
+    pub metadata: Metadata,
+}
+
+/// Parser of the lines of a `LIST` listing
+pub trait ListParser: Send + Sync {
+    /// Parse a line of a listing. Returns `Ok(None)` for lines which aren't entries, such as `total 8`,
+    /// and an error telling why the line can't be parsed otherwise
+    fn parse_line(&self, line: &str) -> Result<Option<ListEntry>, String>;
+}
+
+/// Parser for Unix `ls -l` listings, also accepting lines without the link count or the group,
+/// as sent by some embedded devices
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct UnixParser;
+
+impl ListParser for UnixParser {
+    fn parse_line(&self, line: &str) -> Result<Option<ListEntry>, String> {
+        let line = line.trim_end_matches(['\r', '\n']);
+        if line.trim().is_empty() || starts_with_ignore_case(line.trim_start(), "total ") {
+            return Ok(None);
+        }
+        match FtpFile::from_posix_line(line) {
+            Ok(file) => Ok(Some(ftp_file_to_entry(&file))),
+            Err(_) => parse_unix_line(line).map(Some),
+        }
+    }
+}
+
+/// Parser for DOS listings, as sent by IIS: `10-19-20  03:19PM  <DIR>  pub`.
+/// Times may be on 12 or 24 hours, years on 2 or 4 digits and sizes may have thousands separators
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct DosParser;
+
+impl ListParser for DosParser {
+    fn parse_line(&self, line: &str) -> Result<Option<ListEntry>, String> {
+        let line = line.trim_end_matches(['\r', '\n']);
+        if line.trim().is_empty() {
+            return Ok(None);
+        }
+        let tokens = tokens(line);
+        let [(_, date), (_, time), rest @ ..] = tokens.as_slice() else {
+            return Err(format!("not a DOS line: {line}"));
+        };
+        let date = parse_dos_date(date).ok_or_else(|| format!("bad date: {date}"))?;
+        // AM/PM may be apart from the time
+        let (time, rest) = match rest {
+            [(_, meridiem), rest @ ..]
+                if meridiem.eq_ignore_ascii_case("AM") || meridiem.eq_ignore_ascii_case("PM") =>
+            {
+                (format!("{time}{meridiem}"), rest)
+            }
+            rest => (time.to_string(), rest),
+        };
+        let time = parse_dos_time(&time).ok_or_else(|| format!("bad time: {time}"))?;
+        let [(_, kind), (name_at, _), ..] = rest else {
+            return Err(format!("missing file name: {line}"));
+        };
+        let mut metadata = Metadata {
+            modified: Some(to_system_time(date.and_time(time))),
+            ..Default::default()
+        };
+        if kind.eq_ignore_ascii_case("<DIR>") {
+            metadata.file_type = FileType::Directory;
+        } else {
+            metadata.file_type = FileType::File;
+            metadata.size = kind
+                .replace([',', '.'], "")
+                .parse::<u64>()
+                .map_err(|_| format!("bad size: {kind}"))?;
+        }
+        Ok(Some(ListEntry {
+            name: line[*name_at..].to_string(),
+            metadata,
+        }))
+    }
+}
+
+/// Parser for OpenVMS listings: `FILE.TXT;1  2/16  12-JAN-2020 10:15:30  [GROUP,OWNER]  (RWED,RWED,RE,)`.
+///
+/// Versions are stripped from names, and so is the `.DIR` extension of directories.
+/// Names too long to fit on the line of their entry, sent on a line of their own, aren't supported
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct VmsParser;
+
+impl ListParser for VmsParser {
+    fn parse_line(&self, line: &str) -> Result<Option<ListEntry>, String> {
+        let line = line.trim_end_matches(['\r', '\n']);
+        let trimmed = line.trim();
+        if trimmed.is_empty()
+            || starts_with_ignore_case(trimmed, "Directory ")
+            || starts_with_ignore_case(trimmed, "Total of ")
+            || starts_with_ignore_case(trimmed, "Grand total of ")
+        {
+            return Ok(None);
+        }
+        let tokens = tokens(line);
+        let [(_, name), (_, size), (_, date), (_, time), rest @ ..] = tokens.as_slice() else {
+            return Err(format!("not a VMS line: {line}"));
+        };
+        let (name, _version) = name
+            .split_once(';')
+            .ok_or_else(|| format!("missing file version: {name}"))?;
+        let used = size.split('/').next().unwrap_or_default();
+        let blocks = used
+            .parse::<u64>()
+            .map_err(|_| format!("bad size: {size}"))?;
+        let date =
+            NaiveDate::parse_from_str(date, "%d-%b-%Y").map_err(|_| format!("bad date: {date}"))?;
+        let time = time.split('.').next().unwrap_or_default();
+        let time = NaiveTime::parse_from_str(time, "%H:%M:%S")
+            .or_else(|_| NaiveTime::parse_from_str(time, "%H:%M"))
+            .map_err(|_| format!("bad time: {time}"))?;
+        // NOTE: the name may contain multi-byte chars, so it must be split at a char boundary
+        let (name, file_type) = match name.split_at_checked(name.len().saturating_sub(4)) {
+            Some((stem, ext)) if !stem.is_empty() && ext.eq_ignore_ascii_case(".DIR") => {
+                (stem, FileType::Directory)
+            }
+            _ => (name, FileType::File),
+        };
+        let metadata = Metadata {
+            file_type,
+            size: match file_type {
+                FileType::Directory => 0,
+                _ => blocks * VMS_BLOCK_SIZE,
+            },
+            modified: Some(to_system_time(date.and_time(time))),
+            mode: rest
+                .iter()
+                .find(|(_, x)| x.starts_with('(') && x.ends_with(')'))
+                .and_then(|(_, x)| parse_vms_protection(x)),
+            ..Default::default()
+        };
+        Ok(Some(ListEntry {
+            name: name.to_string(),
+            metadata,
+        }))
+    }
+}
+
+/// Parser for OS/400 (IBM i) listings: `QSYS  77824 02/23/00 15:09:55 *DIR  QOpenSys/`.
+///
+/// Objects containing other ones (`*DIR`, `*LIB`, `*FLR` and `*FILE`) are directories;
+/// members (`*MEM`) have no size nor time
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct As400Parser;
+
+impl ListParser for As400Parser {
+    fn parse_line(&self, line: &str) -> Result<Option<ListEntry>, String> {
+        let line = line.trim_end_matches(['\r', '\n']);
+        if line.trim().is_empty() {
+            return Ok(None);
+        }
+        let tokens = tokens(line);
+        let mut metadata = Metadata::default();
+        let (kind, name_at) = match tokens.as_slice() {
+            [_, (_, kind), (name_at, _), ..] if kind.starts_with('*') => (*kind, *name_at),
+            [_, (_, size), (_, date), (_, time), (_, kind), (name_at, _), ..]
+                if kind.starts_with('*') =>
+            {
+                metadata.size = size
+                    .parse::<u64>()
+                    .map_err(|_| format!("bad size: {size}"))?;
+                let date = NaiveDate::parse_from_str(date, "%m/%d/%y")
+                    .or_else(|_| NaiveDate::parse_from_str(date, "%d.%m.%y"))
+                    .map_err(|_| format!("bad date: {date}"))?;
+                let time = NaiveTime::parse_from_str(time, "%H:%M:%S")
+                    .map_err(|_| format!("bad time: {time}"))?;
+                metadata.modified = Some(to_system_time(date.and_time(time)));
+                (*kind, *name_at)
+            }
+            _ => return Err(format!("not an OS/400 line: {line}")),
+        };
+        metadata.file_type = match kind.to_uppercase().as_str() {
+            "*DIR" | "*LIB" | "*FLR" | "*FILE" => FileType::Directory,
+            _ => FileType::File,
+        };
+        if metadata.file_type.is_dir() {
+            metadata.size = 0;
+        }
+        let name = line[name_at..].trim_end().trim_end_matches('/');
+        if name.is_empty() {
+            return Err(format!("missing file name: {line}"));
+        }
+        Ok(Some(ListEntry {
+            name: name.to_string(),
+            metadata,
+        }))
+    }
+}
+
+/// Parser trying the built-in parsers in turn, starting with the one matching the system type of the server
+pub struct AutoParser {
+    parsers: Vec<Box<dyn ListParser>>,
+}
+
+impl AutoParser {
+    /// Make the parser for the server with system type `system`, as replied to `SYST` (e.g. `Windows_NT`)
+    pub fn for_system(system: Option<&str>) -> Self {
+        let system = system.unwrap_or_default().to_uppercase();
+        let mut parsers: Vec<Box<dyn ListParser>> = vec![
+            Box::new(UnixParser),
+            Box::new(DosParser),
+            Box::new(VmsParser),
+            Box::new(As400Parser),
+        ];
+        let preferred = if system.contains("WINDOWS") || system.contains("MSDOS") {
+            1
+        } else if system.contains("VMS") {
+            2
+        } else if system.contains("OS/400") {
+            3
+        } else {
+            0
+        };
+        parsers.swap(0, preferred);
+        Self { parsers }
+    }
+}
+
+impl Default for AutoParser {
+    fn default() -> Self {
+        Self::for_system(None)
+    }
+}
+
+impl ListParser for AutoParser {
+    /// Returns the result of the first parser which accepts the line, or the error of the preferred parser
+    fn parse_line(&self, line: &str) -> Result<Option<ListEntry>, String> {
+        let mut error = None;
+        for parser in self.parsers.iter() {
+            match parser.parse_line(line) {
+                Ok(entry) => return Ok(entry),
+                Err(e) => {
+                    error.get_or_insert(e);
+                }
+            }
+        }
+        Err(error.unwrap_or_else(|| format!("no parser for line: {line}")))
+    }
+}
+
+/// Make an entry out of a file parsed by suppaftp
+fn ftp_file_to_entry(f: &FtpFile) -> ListEntry {
+    let file_type = if f.is_symlink() {
+        FileType::Symlink
+    } else if f.is_directory() {
+        FileType::Directory
+    } else {
+        FileType::File
+    };
+    let metadata = Metadata {
+        accessed: None,
+        created: None,
+        file_type,
+        gid: f.gid(),
+        mode: Some(query_unix_pex(f)),
+        modified: Some(f.modified()),
+        size: f.size() as u64,
+        symlink: f.symlink().map(|x| x.to_path_buf()),
+        uid: None,
+    };
+    ListEntry {
+        name: f.name().to_string(),
+        metadata,
+    }
+}
+
+/// Returns unix pex from ftp file pex
+fn query_unix_pex(f: &FtpFile) -> UnixPex {
+    UnixPex::new(
+        UnixPexClass::new(
+            f.can_read(PosixPexQuery::Owner),
+            f.can_write(PosixPexQuery::Owner),
+            f.can_execute(PosixPexQuery::Owner),
+        ),
+        UnixPexClass::new(
+            f.can_read(PosixPexQuery::Group),
+            f.can_write(PosixPexQuery::Group),
+            f.can_execute(PosixPexQuery::Group),
+        ),
+        UnixPexClass::new(
+            f.can_read(PosixPexQuery::Others),
+            f.can_write(PosixPexQuery::Others),
+            f.can_execute(PosixPexQuery::Others),
+        ),
+    )
+}
+
+/// Parse a `ls -l` line which suppaftp rejects, such as `-rw-r--r-- 1 root 1024 Jan 01 12:00 a.txt`
+/// (no group) or `drwxr-xr-x root root 0 Jan 01 2020 pub` (no link count)
+fn parse_unix_line(line: &str) -> Result<ListEntry, String> {
+    let tokens = tokens(line);
+    let Some((_, pex)) = tokens.first() else {
+        return Err(format!("not a Unix line: {line}"));
+    };
+    let file_type = match pex.chars().next() {
+        Some('d') => FileType::Directory,
+        Some('l') => FileType::Symlink,
+        Some('-' | 'b' | 'c' | 'p' | 's') => FileType::File,
+        _ => return Err(format!("not a Unix line: {line}")),
+    };
+    let mode = parse_unix_pex(pex).ok_or_else(|| format!("bad permissions: {pex}"))?;
+    // the date is the first month name followed by a day and a time or a year
+    let (date_at, modified) = (2..tokens.len().saturating_sub(3))
+        .find_map(|i| {
+            parse_unix_date(tokens[i].1, tokens[i + 1].1, tokens[i + 2].1).map(|x| (i, x))
+        })
+        .ok_or_else(|| format!("missing date: {line}"))?;
+    let size = tokens[date_at - 1].1.parse::<u64>().unwrap_or_default();
+    let name = &line[tokens[date_at + 3].0..];
+    let (name, symlink) = match (file_type, name.split_once(" -> ")) {
+        (FileType::Symlink, Some((name, target))) => (name, Some(PathBuf::from(target))),
+        _ => (name, None),
+    };
+    Ok(ListEntry {
+        name: name.to_string(),
+        metadata: Metadata {
+            file_type,
+            mode: Some(mode),
+            modified: Some(modified),
+            size,
+            symlink,
+            ..Default::default()
+        },
+    })
+}
+
+/// Parse `ls -l` permissions, such as `drwxr-sr-t`
+fn parse_unix_pex(pex: &str) -> Option<UnixPex> {
+    let bits: Vec<char> = pex.chars().skip(1).take(9).collect();
+    if bits.len() != 9 {
+        return None;
+    }
+    let class =
+        |x: &[char]| UnixPexClass::new(x[0] == 'r', x[1] == 'w', matches!(x[2], 'x' | 's' | 't'));
+    Some(UnixPex::new(
+        class(&bits[0..3]),
+        class(&bits[3..6]),
+        class(&bits[6..9]),
+    ))
+}
+
+/// Parse a `ls -l` date: `Jan 01 12:00`, in the last year, or `Jan 01 2020`
+fn parse_unix_date(month: &str, day: &str, time_or_year: &str) -> Option<SystemTime> {
+    let month = MONTHS.iter().position(|x| x.eq_ignore_ascii_case(month))? as u32 + 1;
+    let day = day.parse::<u32>().ok()?;
+    let datetime = match NaiveTime::parse_from_str(time_or_year, "%H:%M") {
+        Ok(time) => {
+            let now = Utc::now().naive_utc();
+            let datetime = NaiveDate::from_ymd_opt(now.year(), month, day)?.and_time(time);
+            // dates with a time are within the last year
+            match datetime > now + chrono::Duration::days(1) {
+                true => NaiveDate::from_ymd_opt(now.year() - 1, month, day)?.and_time(time),
+                false => datetime,
+            }
+        }
+        Err(_) => NaiveDate::from_ymd_opt(time_or_year.parse::<i32>().ok()?, month, day)?
+            .and_time(NaiveTime::MIN),
+    };
+    Some(to_system_time(datetime))
+}
+
+/// Parse a DOS date: `MM-DD-YY` or `MM-DD-YYYY`; years on 2 digits before 70 are in the 2000s
+fn parse_dos_date(date: &str) -> Option<NaiveDate> {
+    let mut parts = date.split(['-', '/']);
+    let month = parts.next()?.parse::<u32>().ok()?;
+    let day = parts.next()?.parse::<u32>().ok()?;
+    let year = parts.next()?;
+    if parts.next().is_some() {
+        return None;
+    }
+    let year = match (year.len(), year.parse::<i32>().ok()?) {
+        (2, year) if year < 70 => 2000 + year,
+        (2, year) => 1900 + year,
+        (4, year) => year,
+        _ => return None,
+    };
+    NaiveDate::from_ymd_opt(year, month, day)
+}
+
+/// Parse a DOS time: `03:19PM` or `15:19`
+fn parse_dos_time(time: &str) -> Option<NaiveTime> {
+    NaiveTime::parse_from_str(time, "%I:%M%p")
+        .or_else(|_| NaiveTime::parse_from_str(time, "%H:%M"))
+        .ok()
+}
+
+/// Parse a VMS protection, `(system,owner,group,world)` with `R`, `W`, `E` and `D` rights,
+/// into the permissions of owner, group and others
+fn parse_vms_protection(protection: &str) -> Option<UnixPex> {
+    let classes: Vec<&str> = protection
+        .trim_start_matches('(')
+        .trim_end_matches(')')
+        .split(',')
+        .collect();
+    let [_, owner, group, world] = classes.as_slice() else {
+        return None;
+    };
+    let class = |x: &str| UnixPexClass::new(x.contains('R'), x.contains('W'), x.contains('E'));
+    Some(UnixPex::new(class(owner), class(group), class(world)))
+}
+
+/// Split `line` on whitespaces, returning the tokens with their byte offset
+fn tokens(line: &str) -> Vec<(usize, &str)> {
+    let mut tokens = Vec::new();
+    let mut start = None;
+    for (i, ch) in line.char_indices() {
+        match (ch.is_whitespace(), start) {
+            (true, Some(at)) => {
+                tokens.push((at, &line[at..i]));
+                start = None;
+            }
+            (false, None) => start = Some(i),
+            _ => {}
+        }
+    }
+    if let Some(at) = start {
+        tokens.push((at, &line[at..]));
+    }
+    tokens
+}
+
+fn starts_with_ignore_case(text: &str, prefix: &str) -> bool {
+    text.get(..prefix.len())
+        .is_some_and(|x| x.eq_ignore_ascii_case(prefix))
+}
+
+/// Times in listings have no time zone: they're taken as UTC
+fn to_system_time(datetime: NaiveDateTime) -> SystemTime {
+    SystemTime::from(datetime.and_utc())
+}
+
+#[cfg(test)]
+mod test {
+
+    use std::time::Duration;
+
+    use pretty_assertions::assert_eq;
+
+    use super::*;
+
+    fn time(secs: u64) -> Option<SystemTime> {
+        Some(SystemTime::UNIX_EPOCH + Duration::from_secs(secs))
+    }
+
+    fn parse(parser: &dyn ListParser, line: &str) -> ListEntry {
+        parser.parse_line(line).unwrap().unwrap()
+    }
+
+    #[test]
+    fn should_parse_unix_lines() {
+        let entry = parse(
+            &UnixParser,
+            "-rw-r--r--    1 0        0           8192 Nov 05  2018 omar.txt",
+        );
+        assert_eq!(entry.name.as_str(), "omar.txt");
+        assert_eq!(entry.metadata.size, 8192);
+        assert_eq!(entry.metadata.file_type, FileType::File);
+        assert_eq!(entry.metadata.mode, Some(UnixPex::from(0o644)));
+        assert_eq!(entry.metadata.modified, time(1541376000));
+        // no group
+        let entry = parse(
+            &UnixParser,
+            "lrwxrwxrwx 1 root 11 Jan 01 2020 my link -> /tmp/target",
+        );
+        assert_eq!(entry.name.as_str(), "my link");
+        assert_eq!(entry.metadata.file_type, FileType::Symlink);
+        assert_eq!(entry.metadata.size, 11);
+        assert_eq!(entry.metadata.symlink, Some(PathBuf::from("/tmp/target")));
+        assert_eq!(entry.metadata.modified, time(1577836800));
+        // no link count
+        let entry = parse(&UnixParser, "drwxr-x--- root root 0 Feb 29 2020 pub");
+        assert_eq!(entry.name.as_str(), "pub");
+        assert_eq!(entry.metadata.file_type, FileType::Directory);
+        assert_eq!(entry.metadata.mode, Some(UnixPex::from(0o750)));
+        // entries with a time are within the last year
+        let entry = parse(&UnixParser, "-rw------- 1 root 1 Jan 01 12:00 recent");
+        let modified = entry.metadata.modified.unwrap();
+        assert!(modified <= SystemTime::now() + Duration::from_secs(86400));
+        assert!(modified > SystemTime::now() - Duration::from_secs(366 * 86400));
+        assert_eq!(UnixParser.parse_line("total 8").unwrap(), None);
+        assert!(UnixParser
+            .parse_line("10-19-20  03:19PM <DIR> pub")
+            .is_err());
+    }
+
+    #[test]
+    fn should_parse_dos_lines() {
+        let entry = parse(&DosParser, "10-19-20  03:19PM       <DIR>          pub");
+        assert_eq!(entry.name.as_str(), "pub");
+        assert_eq!(entry.metadata.file_type, FileType::Directory);
+        assert_eq!(entry.metadata.modified, time(1603120740));
+        let entry = parse(
+            &DosParser,
+            "04-08-2014  15:09         1,403,227 read me.txt",
+        );
+        assert_eq!(entry.name.as_str(), "read me.txt");
+        assert_eq!(entry.metadata.file_type, FileType::File);
+        assert_eq!(entry.metadata.size, 1403227);
+        assert_eq!(entry.metadata.modified, time(1396969740));
+        let entry = parse(&DosParser, "04-08-14  03:09 AM  403 readme.txt");
+        assert_eq!(entry.metadata.size, 403);
+        assert_eq!(entry.metadata.modified, time(1396926540));
+        assert!(DosParser
+            .parse_line("13-45-14  03:09PM  403 bad.txt")
+            .is_err());
+        assert!(DosParser
+            .parse_line("-rw-r--r-- 1 0 0 8192 Nov 05 2018 omar.txt")
+            .is_err());
+    }
+
+    #[test]
+    fn should_parse_vms_lines() {
+        let entry = parse(
+            &VmsParser,
+            "LOGIN.COM;12              2/16     12-JAN-2020 10:15:30.50  [USER,OMAR]  (RWED,RWED,RE,)",
+        );
+        assert_eq!(entry.name.as_str(), "LOGIN.COM");
+        assert_eq!(entry.metadata.file_type, FileType::File);
+        assert_eq!(entry.metadata.size, 1024);
+        assert_eq!(entry.metadata.modified, time(1578824130));
+        assert_eq!(entry.metadata.mode, Some(UnixPex::from(0o750)));
+        let entry = parse(&VmsParser, "SUBDIR.DIR;1  1  3-MAR-2019 08:00");
+        assert_eq!(entry.name.as_str(), "SUBDIR");
+        assert_eq!(entry.metadata.file_type, FileType::Directory);
+        assert_eq!(entry.metadata.size, 0);
+        assert_eq!(entry.metadata.mode, None);
+        assert_eq!(
+            VmsParser.parse_line("Directory DISK$USER:[OMAR]").unwrap(),
+            None
+        );
+        assert_eq!(
+            VmsParser
+                .parse_line("Total of 2 files, 3/32 blocks.")
+                .unwrap(),
+            None
+        );
+        assert!(VmsParser.parse_line("VERYLONGFILENAME.TXT;1").is_err());
+        // the name must not be sliced across a multi-byte char
+        let entry = parse(&VmsParser, "X€€;1  1  3-MAR-2019 08:00");
+        assert_eq!(entry.name.as_str(), "X€€");
+        assert_eq!(entry.metadata.file_type, FileType::File);
+    }
+
+    #[test]
+    fn should_parse_as400_lines() {
+        let entry = parse(
+            &As400Parser,
+            "QSYS            77824 02/23/00 15:09:55 *DIR       QOpenSys/",
+        );
+        assert_eq!(entry.name.as_str(), "QOpenSys");
+        assert_eq!(entry.metadata.file_type, FileType::Directory);
+        assert_eq!(entry.metadata.size, 0);
+        assert_eq!(entry.metadata.modified, time(951318595));
+        let entry = parse(
+            &As400Parser,
+            "OMAR            1024 23.02.00 15:09:55 *STMF      data file.txt",
+        );
+        assert_eq!(entry.name.as_str(), "data file.txt");
+        assert_eq!(entry.metadata.file_type, FileType::File);
+        assert_eq!(entry.metadata.size, 1024);
+        assert_eq!(entry.metadata.modified, time(951318595));
+        let entry = parse(&As400Parser, "OMAR  *MEM  MYLIB.FILE/MBR.MBR");
+        assert_eq!(entry.name.as_str(), "MYLIB.FILE/MBR.MBR");
+        assert_eq!(entry.metadata.file_type, FileType::File);
+        assert_eq!(entry.metadata.modified, None);
+        assert!(As400Parser
+            .parse_line("-rw-r--r-- 1 0 0 8192 Nov 05 2018 omar.txt")
+            .is_err());
+    }
+
+    #[test]
+    fn should_parse_lines_automatically() {
+        let parser = AutoParser::default();
+        assert_eq!(
+            parse(&parser, "-rw-r--r-- 1 0 0 8192 Nov 05 2018 omar.txt")
+                .name
+                .as_str(),
+            "omar.txt"
+        );
+        assert_eq!(
+            parse(&parser, "10-19-20  03:19PM <DIR> pub").name.as_str(),
+            "pub"
+        );
+        assert_eq!(
+            parse(&parser, "LOGIN.COM;12 2/16 12-JAN-2020 10:15:30")
+                .name
+                .as_str(),
+            "LOGIN.COM"
+        );
+        assert_eq!(
+            parse(&parser, "QSYS 77824 02/23/00 15:09:55 *DIR QOpenSys/")
+                .name
+                .as_str(),
+            "QOpenSys"
+        );
+        // the error is the one of the preferred parser
+        let error = AutoParser::for_system(Some("Windows_NT"))
+            .parse_line("garbage")
+            .unwrap_err();
+        assert_eq!(error.as_str(), "not a DOS line: garbage");
+        let error = AutoParser::for_system(Some("UNIX Type: L8"))
+            .parse_line("garbage")
+            .unwrap_err();
+        assert_eq!(error.as_str(), "not a Unix line: garbage");
+    }
+}
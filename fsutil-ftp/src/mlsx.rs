@@ -0,0 +1,254 @@
+//! ## Mlsx
+//!
+//! parser for the machine-processable listings returned by `MLSD` and `MLST` (RFC 3659)
+
+use std::path::PathBuf;
+use std::time::{Duration, SystemTime};
+
+use chrono::NaiveDateTime;
+use fsutil_core::fs::{FileType, Metadata, UnixPex};
+
+/// Facts requested to the server with `OPTS MLST`, when supported
+pub const WANTED_FACTS: &[&str] = &[
+    "type",
+    "size",
+    "modify",
+    "create",
+    "perm",
+    "unix.mode",
+    "unix.uid",
+    "unix.gid",
+];
+
+/// An entry of a `MLSD` or `MLST` listing
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MlsxEntry {
+    /// Name of the entry, as sent by the server; for `MLST` this is usually the full path
+    pub name: String,
+    pub metadata: Metadata,
+    /// Whether the entry is the listed directory (`cdir`) or its parent (`pdir`)
+    pub special: bool,
+}
+
+/// Parse the supported facts in the value of the `MLST` feature, e.g. `type*;size*;modify*;perm;`.
+///
+/// Returns the facts, with whether each one is enabled
+pub fn parse_mlst_feature(value: &str) -> Vec<(String, bool)> {
+    value
+        .split(';')
+        .filter(|x| !x.is_empty())
+        .map(|fact| match fact.strip_suffix('*') {
+            Some(fact) => (fact.to_lowercase(), true),
+            None => (fact.to_lowercase(), false),
+        })
+        .collect()
+}
+
+/// Parse a `MLSD` or `MLST` line: `fact=value;fact=value; name`
+pub fn parse_line(line: &str) -> Result<MlsxEntry, String> {
+    let line = line.trim_start().trim_end_matches(['\r', '\n']);
+    let (facts, name) = line
+        .split_once(' ')
+        .ok_or_else(|| format!("missing file name: {line}"))?;
+    if name.is_empty() {
+        return Err(format!("missing file name: {line}"));
+    }
+    let mut metadata = Metadata::default();
+    let mut special = false;
+    let mut perm: Option<&str> = None;
+    for fact in facts.split(';').filter(|x| !x.is_empty()) {
+        let Some((key, value)) = fact.split_once('=') else {
+            return Err(format!("bad fact: {fact}"));
+        };
+        match key.to_lowercase().as_str() {
+            "type" => {
+                let (file_type, is_special) = parse_type(value)?;
+                metadata.file_type = file_type;
+                special = is_special;
+                if let Some(target) = value
+                    .split_once(':')
+                    .filter(|(kind, _)| kind.eq_ignore_ascii_case("os.unix=slink"))
+                    .map(|(_, target)| target)
+                    .filter(|x| !x.is_empty())
+                {
+                    metadata.symlink = Some(PathBuf::from(target));
+                }
+            }
+            "size" | "sizd" => {
+                metadata.size = value
+                    .parse::<u64>()
+                    .map_err(|_| format!("bad size: {value}"))?;
+            }
+            "modify" => metadata.modified = Some(parse_time(value)?),
+            "create" => metadata.created = Some(parse_time(value)?),
+            "unix.mode" => {
+                let mode =
+                    u32::from_str_radix(value, 8).map_err(|_| format!("bad unix.mode: {value}"))?;
+                metadata.mode = Some(UnixPex::from(mode & 0o777));
+            }
+            "unix.uid" => metadata.uid = value.parse::<u32>().ok(),
+            "unix.gid" => metadata.gid = value.parse::<u32>().ok(),
+            "perm" => perm = Some(value),
+            _ => {}
+        }
+    }
+    // without unix.mode, tell the owner permissions from the perm fact
+    if metadata.mode.is_none() {
+        metadata.mode = perm.map(|perm| perm_to_mode(perm, metadata.file_type.is_dir()));
+    }
+    Ok(MlsxEntry {
+        name: name.to_string(),
+        metadata,
+        special,
+    })
+}
+
+/// Parse the `type` fact. Returns the file type and whether the entry is `cdir` or `pdir`
+fn parse_type(value: &str) -> Result<(FileType, bool), String> {
+    let value = value.to_lowercase();
+    match value.as_str() {
+        "file" => Ok((FileType::File, false)),
+        "dir" => Ok((FileType::Directory, false)),
+        "cdir" | "pdir" => Ok((FileType::Directory, true)),
+        // OS.unix=symlink, OS.unix=slink:/target
+        x if x.starts_with("os.unix=slink") || x.starts_with("os.unix=symlink") => {
+            Ok((FileType::Symlink, false))
+        }
+        // other OS specific types, such as devices
+        x if x.starts_with("os.") => Ok((FileType::File, false)),
+        _ => Err(format!("bad type: {value}")),
+    }
+}
+
+/// Parse a `time-val` (`YYYYMMDDHHMMSS[.sss]`, UTC)
+fn parse_time(value: &str) -> Result<SystemTime, String> {
+    let (secs, fraction) = value.split_once('.').unwrap_or((value, ""));
+    let datetime = NaiveDateTime::parse_from_str(secs, "%Y%m%d%H%M%S")
+        .map_err(|_| format!("bad time: {value}"))?;
+    if !fraction.chars().all(|x| x.is_ascii_digit()) {
+        return Err(format!("bad time: {value}"));
+    }
+    // pad or truncate the fraction to milliseconds
+    let millis = fraction
+        .chars()
+        .chain(std::iter::repeat('0'))
+        .take(3)
+        .fold(0, |millis, digit| {
+            millis * 10 + u64::from(digit.to_digit(10).unwrap_or_default())
+        });
+    let timestamp =
+        u64::try_from(datetime.and_utc().timestamp()).map_err(|_| format!("bad time: {value}"))?;
+    Ok(SystemTime::UNIX_EPOCH + Duration::from_secs(timestamp) + Duration::from_millis(millis))
+}
+
+/// Make owner permissions out of the `perm` fact
+fn perm_to_mode(perm: &str, is_dir: bool) -> UnixPex {
+    let perm = perm.to_lowercase();
+    let has = |flags: &str| flags.chars().any(|x| perm.contains(x));
+    let (read, write, execute) = if is_dir {
+        (has("l"), has("cmp"), has("e"))
+    } else {
+        (has("r"), has("aw"), false)
+    };
+    let mode = (u32::from(read) << 8) | (u32::from(write) << 7) | (u32::from(execute) << 6);
+    UnixPex::from(mode)
+}
+
+#[cfg(test)]
+mod test {
+
+    use pretty_assertions::assert_eq;
+
+    use super::*;
+
+    #[test]
+    fn should_parse_mlst_feature() {
+        assert_eq!(
+            parse_mlst_feature("Type*;Size*;Modify*;Perm;UNIX.mode;"),
+            vec![
+                ("type".to_string(), true),
+                ("size".to_string(), true),
+                ("modify".to_string(), true),
+                ("perm".to_string(), false),
+                ("unix.mode".to_string(), false),
+            ]
+        );
+        assert!(parse_mlst_feature("").is_empty());
+    }
+
+    #[test]
+    fn should_parse_file_line() {
+        let entry = parse_line(
+            "type=file;size=1024;modify=20240115103000.123;perm=adfrw;unix.mode=0644;unix.uid=1000;unix.gid=100; my file.txt",
+        )
+        .unwrap();
+        assert_eq!(entry.name.as_str(), "my file.txt");
+        assert!(!entry.special);
+        assert_eq!(entry.metadata.file_type, FileType::File);
+        assert_eq!(entry.metadata.size, 1024);
+        assert_eq!(
+            entry.metadata.modified,
+            Some(SystemTime::UNIX_EPOCH + Duration::from_millis(1705314600123))
+        );
+        assert_eq!(entry.metadata.mode, Some(UnixPex::from(0o644)));
+        assert_eq!(entry.metadata.uid, Some(1000));
+        assert_eq!(entry.metadata.gid, Some(100));
+    }
+
+    #[test]
+    fn should_parse_dir_lines() {
+        let entry = parse_line("Type=dir;Modify=20240115103000;Perm=flcdmpe; docs").unwrap();
+        assert_eq!(entry.metadata.file_type, FileType::Directory);
+        assert!(!entry.special);
+        // mode from perm
+        assert_eq!(entry.metadata.mode, Some(UnixPex::from(0o700)));
+        let entry = parse_line("type=cdir;perm=el; /home/test").unwrap();
+        assert!(entry.special);
+        assert_eq!(entry.name.as_str(), "/home/test");
+        assert_eq!(entry.metadata.mode, Some(UnixPex::from(0o500)));
+        assert!(parse_line("type=pdir; ..").unwrap().special);
+    }
+
+    #[test]
+    fn should_parse_symlink_lines() {
+        let entry = parse_line("type=OS.unix=slink:/etc/hosts;size=10; hosts").unwrap();
+        assert_eq!(entry.metadata.file_type, FileType::Symlink);
+        assert_eq!(entry.metadata.symlink, Some(PathBuf::from("/etc/hosts")));
+        let entry = parse_line("type=OS.unix=symlink;unix.mode=0777; link").unwrap();
+        assert_eq!(entry.metadata.file_type, FileType::Symlink);
+        assert!(entry.metadata.symlink.is_none());
+    }
+
+    #[test]
+    fn should_parse_file_with_read_only_perm() {
+        let entry = parse_line("type=file;size=0;perm=r; readme").unwrap();
+        assert_eq!(entry.metadata.mode, Some(UnixPex::from(0o400)));
+        let entry = parse_line("type=file;size=0; readme").unwrap();
+        assert!(entry.metadata.mode.is_none());
+    }
+
+    #[test]
+    fn should_not_parse_bad_lines() {
+        assert!(parse_line("type=file;size=10;").is_err());
+        assert!(parse_line("type=file;size=abc; a.txt").is_err());
+        assert!(parse_line("type=unknown; a.txt").is_err());
+        assert!(parse_line("type=file;modify=2024; a.txt").is_err());
+        assert!(parse_line("type=file;bad; a.txt").is_err());
+        assert!(parse_line("type=file;modify=20240115103000.éé; a.txt").is_err());
+        assert!(parse_line("type=file;modify=20240115103000.1a; a.txt").is_err());
+    }
+
+    #[test]
+    fn should_parse_time_fraction() {
+        let time = SystemTime::UNIX_EPOCH + Duration::from_secs(1705314600);
+        assert_eq!(parse_time("20240115103000").unwrap(), time);
+        assert_eq!(
+            parse_time("20240115103000.5").unwrap(),
+            time + Duration::from_millis(500)
+        );
+        assert_eq!(
+            parse_time("20240115103000.123456").unwrap(),
+            time + Duration::from_millis(123)
+        );
+    }
+}
@@ -0,0 +1,168 @@
+//! ## Reader
+//!
+//! read stream which can seek by restarting the transfer at the new offset with `REST` and `RETR`
+
+use std::io::{self, Read, Seek, SeekFrom};
+use std::sync::{Arc, Mutex, MutexGuard};
+
+use fsutil_core::fs::stream::ReadAndSeek;
+
+use crate::client::FtpStream;
+use crate::data::DataChannel;
+
+/// Forward seeks up to this amount of bytes are done by reading and discarding the data,
+/// which is cheaper than restarting the transfer
+const SKIP_THRESHOLD: u64 = 64 * 1024;
+
+/// Control connection of a client, lent to a [`FtpReader`] while the transfer is in progress
+#[derive(Default)]
+pub struct Control {
+    pub stream: Option<FtpStream>,
+    /// Whether the server has still to reply to the last `RETR`
+    pub pending: bool,
+}
+
+/// Control connection shared between the client and its reader
+pub type SharedControl = Arc<Mutex<Control>>;
+
+/// Lock the shared control connection
+pub fn lock(control: &SharedControl) -> MutexGuard<'_, Control> {
+    control.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+/// Read stream of a file on the FTP server.
+///
+/// Seeking drops the data connection and opens a new one at the new offset
+/// through the control connection, as long as the client hasn't taken it back
+pub struct FtpReader {
+    control: SharedControl,
+    channel: DataChannel,
+    data: Option<Box<dyn Read + Send>>,
+    path: String,
+    offset: u64,
+}
+
+impl FtpReader {
+    /// Make a reader for the transfer of `path` started at `offset` on `data`
+    pub fn new(
+        control: SharedControl,
+        channel: DataChannel,
+        data: Box<dyn Read + Send>,
+        path: String,
+        offset: u64,
+    ) -> Self {
+        Self {
+            control,
+            channel,
+            data: Some(data),
+            path,
+            offset,
+        }
+    }
+
+    /// Finalize the current transfer and start a new one at `offset`
+    fn restart(&mut self, offset: u64) -> io::Result<()> {
+        trace!("Restarting transfer of {} at {}", self.path, offset);
+        let mut control = lock(&self.control);
+        let Control { stream, pending } = &mut *control;
+        let stream = stream.as_mut().ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::NotConnected,
+                "the control connection has been taken back by the client",
+            )
+        })?;
+        if let Some(data) = self.data.take() {
+            // the server replies 426 if the transfer was still in progress
+            match stream.finalize_retr_stream(data) {
+                Ok(()) | Err(suppaftp::FtpError::UnexpectedResponse(_)) => {}
+                Err(e) => return Err(io::Error::other(e)),
+            }
+            *pending = false;
+        }
+        if offset > 0 {
+            stream
+                .resume_transfer(offset as usize)
+                .map_err(io::Error::other)?;
+        }
+        let data = self
+            .channel
+            .retr(stream, self.path.as_str())
+            .map_err(io::Error::other)?;
+        *pending = true;
+        self.data = Some(data);
+        self.offset = offset;
+        Ok(())
+    }
+}
+
+impl Read for FtpReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let data = self.data.as_mut().ok_or_else(|| {
+            io::Error::new(io::ErrorKind::NotConnected, "transfer failed to restart")
+        })?;
+        let read = data.read(buf)?;
+        self.offset += read as u64;
+        Ok(read)
+    }
+}
+
+impl Seek for FtpReader {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let target = seek_target(self.offset, pos)?;
+        if target == self.offset {
+            return Ok(target);
+        }
+        if target > self.offset && target - self.offset <= SKIP_THRESHOLD && self.data.is_some() {
+            let distance = target - self.offset;
+            let skipped = io::copy(&mut self.by_ref().take(distance), &mut io::sink())?;
+            trace!("Skipped {} bytes", skipped);
+            return Ok(self.offset);
+        }
+        self.restart(target)?;
+        Ok(target)
+    }
+}
+
+impl ReadAndSeek for FtpReader {}
+
+/// Get the offset to seek to from `offset` for `pos`.
+/// Seeking from the end is not supported, since the size of the file is unknown
+fn seek_target(offset: u64, pos: SeekFrom) -> io::Result<u64> {
+    match pos {
+        SeekFrom::Start(x) => Ok(x),
+        SeekFrom::Current(x) => offset.checked_add_signed(x).ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "invalid seek to a negative or overflowing position",
+            )
+        }),
+        SeekFrom::End(_) => Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "can't seek from the end of a FTP transfer",
+        )),
+    }
+}
+
+#[cfg(test)]
+mod test {
+
+    use pretty_assertions::assert_eq;
+
+    use super::*;
+
+    #[test]
+    fn should_get_seek_target() {
+        assert_eq!(seek_target(10, SeekFrom::Start(2048)).unwrap(), 2048);
+        assert_eq!(seek_target(10, SeekFrom::Current(0)).unwrap(), 10);
+        assert_eq!(seek_target(10, SeekFrom::Current(5)).unwrap(), 15);
+        assert_eq!(seek_target(10, SeekFrom::Current(-10)).unwrap(), 0);
+        assert_eq!(
+            seek_target(10, SeekFrom::Current(-11)).unwrap_err().kind(),
+            io::ErrorKind::InvalidInput
+        );
+        assert_eq!(
+            seek_target(10, SeekFrom::End(0)).unwrap_err().kind(),
+            io::ErrorKind::Unsupported
+        );
+    }
+}
@@ -0,0 +1,85 @@
+//! ## Setstat
+//!
+//! commands to set file attributes on FTP servers (`MFMT`, `SITE UTIME` and `SITE CHMOD`)
+
+use std::path::Path;
+use std::time::SystemTime;
+
+use chrono::{DateTime, Utc};
+use fsutil_core::fs::UnixPex;
+// the report types are shared with the other backends
+pub use fsutil_core::fs::{MetadataField, SetStatReport};
+
+/// `MFMT <time> <path>` (draft-somers-ftp-mfxx)
+pub(crate) fn mfmt(path: &Path, modified: SystemTime) -> String {
+    format!("MFMT {} {}", fmt_time(modified), path.to_string_lossy())
+}
+
+/// `SITE UTIME <path> <atime> <mtime> <ctime> UTC`, supported by Pure-FTPd and ProFTPD
+pub(crate) fn site_utime(path: &Path, accessed: SystemTime, modified: SystemTime) -> String {
+    format!(
+        "SITE UTIME {} {} {} {} UTC",
+        path.to_string_lossy(),
+        fmt_time(accessed),
+        fmt_time(modified),
+        fmt_time(modified)
+    )
+}
+
+/// `SITE UTIME <mtime> <path>`, the short form supported by ProFTPD
+pub(crate) fn site_utime_short(path: &Path, modified: SystemTime) -> String {
+    format!(
+        "SITE UTIME {} {}",
+        fmt_time(modified),
+        path.to_string_lossy()
+    )
+}
+
+/// `SITE CHMOD <mode> <path>`
+pub(crate) fn site_chmod(path: &Path, mode: UnixPex) -> String {
+    format!(
+        "SITE CHMOD {:o} {}",
+        u32::from(mode),
+        path.to_string_lossy()
+    )
+}
+
+/// Format time as `YYYYMMDDHHMMSS` in UTC
+fn fmt_time(time: SystemTime) -> String {
+    DateTime::<Utc>::from(time)
+        .format("%Y%m%d%H%M%S")
+        .to_string()
+}
+
+#[cfg(test)]
+mod test {
+
+    use std::time::Duration;
+
+    use pretty_assertions::assert_eq;
+
+    use super::*;
+
+    #[test]
+    fn should_format_setstat_commands() {
+        let modified = SystemTime::UNIX_EPOCH + Duration::from_secs(1705314600);
+        let accessed = SystemTime::UNIX_EPOCH + Duration::from_secs(1705314660);
+        let path = Path::new("/home/test/a b.txt");
+        assert_eq!(
+            mfmt(path, modified),
+            "MFMT 20240115103000 /home/test/a b.txt"
+        );
+        assert_eq!(
+            site_utime(path, accessed, modified),
+            "SITE UTIME /home/test/a b.txt 20240115103100 20240115103000 20240115103000 UTC"
+        );
+        assert_eq!(
+            site_utime_short(path, modified),
+            "SITE UTIME 20240115103000 /home/test/a b.txt"
+        );
+        assert_eq!(
+            site_chmod(path, UnixPex::from(0o640)),
+            "SITE CHMOD 640 /home/test/a b.txt"
+        );
+    }
+}
@@ -0,0 +1,343 @@
+//! ## Tls
+//!
+//! TLS configuration for FTPS, common to the native-tls and rustls backends
+
+#[cfg(feature = "rustls")]
+use std::sync::Arc;
+
+use fsutil_core::fs::{RemoteError, RemoteErrorType, RemoteResult};
+#[cfg(feature = "native-tls")]
+use suppaftp::native_tls::{Certificate, Identity, TlsConnector as NativeTlsConnector};
+#[cfg(feature = "rustls")]
+use suppaftp::rustls::{
+    self,
+    client::danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier},
+    client::WebPkiServerVerifier,
+    crypto::{verify_tls12_signature, verify_tls13_signature, WebPkiSupportedAlgorithms},
+    pki_types::{pem::PemObject, CertificateDer, PrivateKeyDer, ServerName, UnixTime},
+    CertificateError, ClientConfig, DigitallySignedStruct, RootCertStore, SignatureScheme,
+};
+
+/// Port of implicit FTPS servers
+pub const IMPLICIT_PORT: u16 = 990;
+
+/// TLS configuration used to secure the connections
+#[cfg(feature = "native-tls")]
+pub type TlsConfig = NativeTlsConnector;
+/// TLS configuration used to secure the connections
+#[cfg(feature = "rustls")]
+pub type TlsConfig = Arc<ClientConfig>;
+
+/// TLS configuration for [`crate::FtpFileSystem`].
+///
+/// By default the session is secured with `AUTH TLS` (explicit FTPS), the server certificate is
+/// checked against the built-in roots and data connections are encrypted too (`PROT P`).
+///
+/// Certificate pinning and session reuse are only supported with the `rustls` feature
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct FtpTls {
+    /// Negotiate TLS as soon as connected, instead of with `AUTH TLS`
+    pub(crate) implicit: bool,
+    /// PEM certificates trusted in addition to the built-in roots
+    pub(crate) ca_bundle: Vec<u8>,
+    /// PEM certificate chain and PKCS #8 private key to authenticate with
+    pub(crate) client_certificate: Option<(Vec<u8>, Vec<u8>)>,
+    /// SHA-256 fingerprints of the server certificates to accept
+    pub(crate) pins: Vec<[u8; 32]>,
+    pub(crate) accept_invalid_certs: bool,
+    pub(crate) accept_invalid_hostnames: bool,
+    /// Require data connections to resume the TLS session of the control connection
+    pub(crate) session_reuse: bool,
+    /// Leave data connections unencrypted (`PROT C`)
+    pub(crate) clear_data_channel: bool,
+}
+
+impl FtpTls {
+    /// Use implicit FTPS: TLS is negotiated as soon as connected, usually on port [`IMPLICIT_PORT`]
+    pub fn implicit(mut self, implicit: bool) -> Self {
+        self.implicit = implicit;
+        self
+    }
+
+    /// Trust the PEM certificates in `pem`, in addition to the built-in roots.
+    /// Can be called more than once
+    pub fn ca_bundle(mut self, pem: &[u8]) -> Self {
+        self.ca_bundle.extend_from_slice(pem);
+        self.ca_bundle.push(b'\n');
+        self
+    }
+
+    /// Authenticate with the PEM certificate chain `cert` and the PEM PKCS #8 private key `key`
+    pub fn client_certificate(mut self, cert: &[u8], key: &[u8]) -> Self {
+        self.client_certificate = Some((cert.to_vec(), key.to_vec()));
+        self
+    }
+
+    /// Accept the server certificate with SHA-256 `fingerprint` (of its DER encoding), even if it
+    /// isn't trusted or doesn't match the hostname. Once a pin is set, any other certificate is rejected.
+    /// Can be called more than once.
+    ///
+    /// See [`parse_fingerprint`] to parse fingerprints such as `AB:CD:...`
+    pub fn pin_sha256(mut self, fingerprint: [u8; 32]) -> Self {
+        self.pins.push(fingerprint);
+        self
+    }
+
+    /// Accept invalid or untrusted server certificates
+    pub fn accept_invalid_certs(mut self, accept: bool) -> Self {
+        self.accept_invalid_certs = accept;
+        self
+    }
+
+    /// Accept server certificates which don't match the hostname
+    pub fn accept_invalid_hostnames(mut self, accept: bool) -> Self {
+        self.accept_invalid_hostnames = accept;
+        self
+    }
+
+    /// Require data connections to resume the TLS session of the control connection,
+    /// as servers such as vsftpd with `require_ssl_reuse` do
+    pub fn session_reuse(mut self, reuse: bool) -> Self {
+        self.session_reuse = reuse;
+        self
+    }
+
+    /// Leave data connections unencrypted with `PROT C`; only the control connection is secured
+    pub fn clear_data_channel(mut self, clear: bool) -> Self {
+        self.clear_data_channel = clear;
+        self
+    }
+
+    /// Build the TLS configuration
+    #[cfg(feature = "native-tls")]
+    pub(crate) fn build(&self) -> RemoteResult<TlsConfig> {
+        if !self.pins.is_empty() {
+            return Err(RemoteError::new_ex(
+                RemoteErrorType::UnsupportedFeature,
+                "certificate pinning requires the rustls feature",
+            ));
+        }
+        if self.session_reuse {
+            return Err(RemoteError::new_ex(
+                RemoteErrorType::UnsupportedFeature,
+                "TLS session reuse requires the rustls feature",
+            ));
+        }
+        let mut builder = NativeTlsConnector::builder();
+        builder
+            .danger_accept_invalid_certs(self.accept_invalid_certs)
+            .danger_accept_invalid_hostnames(self.accept_invalid_hostnames);
+        if !self.ca_bundle.is_empty() {
+            for cert in Certificate::stack_from_pem(&self.ca_bundle).map_err(ssl_error)? {
+                builder.add_root_certificate(cert);
+            }
+        }
+        if let Some((cert, key)) = self.client_certificate.as_ref() {
+            builder.identity(Identity::from_pkcs8(cert, key).map_err(ssl_error)?);
+        }
+        builder.build().map_err(ssl_error)
+    }
+
+    /// Build the TLS configuration.
+    ///
+    /// Sessions are resumed on data connections, since they share the session cache of the configuration
+    #[cfg(feature = "rustls")]
+    pub(crate) fn build(&self) -> RemoteResult<TlsConfig> {
+        let provider = Arc::new(rustls::crypto::ring::default_provider());
+        let mut roots = RootCertStore::empty();
+        roots.extend(webpki_roots::TLS_SERVER_ROOTS.iter().map(|ta| {
+            rustls_pki_types::TrustAnchor {
+                subject: ta.subject.clone(),
+                subject_public_key_info: ta.subject_public_key_info.clone(),
+                name_constraints: ta.name_constraints.clone(),
+            }
+        }));
+        for cert in CertificateDer::pem_slice_iter(&self.ca_bundle) {
+            roots.add(cert.map_err(ssl_error)?).map_err(ssl_error)?;
+        }
+        let verifier = PinningVerifier {
+            inner: WebPkiServerVerifier::builder_with_provider(Arc::new(roots), provider.clone())
+                .build()
+                .map_err(ssl_error)?,
+            algorithms: provider.signature_verification_algorithms,
+            pins: self.pins.clone(),
+            accept_invalid_certs: self.accept_invalid_certs,
+            accept_invalid_hostnames: self.accept_invalid_hostnames,
+        };
+        let builder = ClientConfig::builder_with_provider(provider)
+            .with_safe_default_protocol_versions()
+            .map_err(ssl_error)?
+            .dangerous()
+            .with_custom_certificate_verifier(Arc::new(verifier));
+        let config = match self.client_certificate.as_ref() {
+            None => builder.with_no_client_auth(),
+            Some((cert, key)) => {
+                let chain = CertificateDer::pem_slice_iter(cert)
+                    .collect::<Result<Vec<_>, _>>()
+                    .map_err(ssl_error)?;
+                let key = PrivateKeyDer::from_pem_slice(key).map_err(ssl_error)?;
+                builder
+                    .with_client_auth_cert(chain, key)
+                    .map_err(ssl_error)?
+            }
+        };
+        Ok(Arc::new(config))
+    }
+}
+
+/// Parse a SHA-256 fingerprint written in hex, with or without `:` separators
+/// (e.g. `AB:CD:...` as printed by `openssl x509 -fingerprint -sha256`)
+pub fn parse_fingerprint(fingerprint: &str) -> Option<[u8; 32]> {
+    let digits: Vec<u8> = fingerprint.trim().bytes().filter(|x| *x != b':').collect();
+    if digits.len() != 64 {
+        return None;
+    }
+    let mut bytes = [0; 32];
+    for (byte, pair) in bytes.iter_mut().zip(digits.chunks(2)) {
+        *byte = u8::from_str_radix(std::str::from_utf8(pair).ok()?, 16).ok()?;
+    }
+    Some(bytes)
+}
+
+fn ssl_error<E: std::fmt::Display>(e: E) -> RemoteError {
+    error!("Failed to setup TLS configuration: {}", e);
+    RemoteError::new_ex(RemoteErrorType::SslError, e.to_string())
+}
+
+/// Server certificate verifier which accepts pinned certificates and optionally invalid ones,
+/// and otherwise verifies the certificate against the roots
+#[cfg(feature = "rustls")]
+#[derive(Debug)]
+struct PinningVerifier {
+    inner: Arc<WebPkiServerVerifier>,
+    algorithms: WebPkiSupportedAlgorithms,
+    pins: Vec<[u8; 32]>,
+    accept_invalid_certs: bool,
+    accept_invalid_hostnames: bool,
+}
+
+#[cfg(feature = "rustls")]
+impl ServerCertVerifier for PinningVerifier {
+    fn verify_server_cert(
+        &self,
+        end_entity: &CertificateDer<'_>,
+        intermediates: &[CertificateDer<'_>],
+        server_name: &ServerName<'_>,
+        ocsp_response: &[u8],
+        now: UnixTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        if !self.pins.is_empty() {
+            let fingerprint = ring::digest::digest(&ring::digest::SHA256, end_entity);
+            return match self.pins.iter().any(|x| x == fingerprint.as_ref()) {
+                true => Ok(ServerCertVerified::assertion()),
+                false => Err(rustls::Error::InvalidCertificate(
+                    CertificateError::ApplicationVerificationFailure,
+                )),
+            };
+        }
+        if self.accept_invalid_certs {
+            return Ok(ServerCertVerified::assertion());
+        }
+        match self.inner.verify_server_cert(
+            end_entity,
+            intermediates,
+            server_name,
+            ocsp_response,
+            now,
+        ) {
+            Err(rustls::Error::InvalidCertificate(
+                CertificateError::NotValidForName | CertificateError::NotValidForNameContext { .. },
+            )) if self.accept_invalid_hostnames => Ok(ServerCertVerified::assertion()),
+            result => result,
+        }
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        verify_tls12_signature(message, cert, dss, &self.algorithms)
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        verify_tls13_signature(message, cert, dss, &self.algorithms)
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.algorithms.supported_schemes()
+    }
+}
+
+#[cfg(test)]
+mod test {
+
+    use pretty_assertions::assert_eq;
+
+    use super::*;
+
+    #[test]
+    fn should_parse_fingerprint() {
+        let expected: [u8; 32] = std::array::from_fn(|i| (i * 8) as u8);
+        let hex: Vec<String> = expected.iter().map(|x| format!("{x:02X}")).collect();
+        assert_eq!(parse_fingerprint(&hex.join(":")), Some(expected));
+        assert_eq!(
+            parse_fingerprint(&hex.join("").to_lowercase()),
+            Some(expected)
+        );
+        assert_eq!(parse_fingerprint("AB:CD"), None);
+        assert_eq!(parse_fingerprint(&"ZZ".repeat(32)), None);
+        assert_eq!(parse_fingerprint(&"é".repeat(32)), None);
+    }
+
+    #[test]
+    fn should_build_tls_options() {
+        let tls = FtpTls::default()
+            .implicit(true)
+            .ca_bundle(b"-----BEGIN CERTIFICATE-----")
+            .client_certificate(b"cert", b"key")
+            .pin_sha256([1; 32])
+            .accept_invalid_hostnames(true)
+            .session_reuse(true)
+            .clear_data_channel(true);
+        assert_eq!(tls.implicit, true);
+        assert_eq!(tls.ca_bundle.as_slice(), b"-----BEGIN CERTIFICATE-----\n");
+        assert_eq!(
+            tls.client_certificate,
+            Some((b"cert".to_vec(), b"key".to_vec()))
+        );
+        assert_eq!(tls.pins, vec![[1; 32]]);
+        assert_eq!(tls.accept_invalid_certs, false);
+        assert_eq!(tls.accept_invalid_hostnames, true);
+        assert_eq!(tls.session_reuse, true);
+        assert_eq!(tls.clear_data_channel, true);
+    }
+
+    #[test]
+    fn should_build_default_tls_config() {
+        assert!(FtpTls::default().build().is_ok());
+        assert!(FtpTls::default()
+            .ca_bundle(b"-----BEGIN CERTIFICATE-----\nnot base64\n-----END CERTIFICATE-----\n")
+            .build()
+            .is_err());
+    }
+
+    #[test]
+    #[cfg(feature = "native-tls")]
+    fn should_not_pin_with_native_tls() {
+        assert_eq!(
+            FtpTls::default()
+                .pin_sha256([0; 32])
+                .build()
+                .unwrap_err()
+                .kind,
+            RemoteErrorType::UnsupportedFeature
+        );
+    }
+}
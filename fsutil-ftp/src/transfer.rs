@@ -0,0 +1,104 @@
+//! ## Transfer
+//!
+//! transfer type of files: binary (`TYPE I`) or ASCII (`TYPE A`), where the server converts line endings
+//! from and to `\r\n` on the data connection
+
+use std::path::Path;
+
+/// Extensions of the files transferred in ASCII by [`TransferMode::auto`]
+pub const TEXT_EXTENSIONS: &[&str] = &[
+    "asp",
+    "bat",
+    "c",
+    "cfg",
+    "cgi",
+    "cmd",
+    "conf",
+    "cpp",
+    "cs",
+    "css",
+    "csv",
+    "h",
+    "hpp",
+    "htm",
+    "html",
+    "ini",
+    "java",
+    "js",
+    "json",
+    "jsp",
+    "log",
+    "md",
+    "php",
+    "pl",
+    "properties",
+    "py",
+    "rb",
+    "rs",
+    "sh",
+    "shtml",
+    "sql",
+    "svg",
+    "tex",
+    "toml",
+    "ts",
+    "txt",
+    "xml",
+    "yaml",
+    "yml",
+];
+
+/// Transfer type of the files read and written by [`crate::FtpFileSystem`]
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub enum TransferMode {
+    /// Transfer files as they are
+    #[default]
+    Binary,
+    /// Transfer files as text: line endings are converted by the server and by the client
+    Ascii,
+    /// Transfer files with one of these extensions as text, any other one as binary.
+    /// Extensions are matched case-insensitively, without the leading dot
+    Auto(Vec<String>),
+}
+
+impl TransferMode {
+    /// Automatic mode with the extensions in [`TEXT_EXTENSIONS`]
+    pub fn auto() -> Self {
+        Self::Auto(TEXT_EXTENSIONS.iter().map(|x| x.to_string()).collect())
+    }
+
+    /// Returns whether the file at `path` is transferred in ASCII
+    pub fn is_ascii(&self, path: &Path) -> bool {
+        match self {
+            Self::Binary => false,
+            Self::Ascii => true,
+            Self::Auto(extensions) => path
+                .extension()
+                .and_then(|x| x.to_str())
+                .is_some_and(|ext| extensions.iter().any(|x| x.eq_ignore_ascii_case(ext))),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+
+    use pretty_assertions::assert_eq;
+
+    use super::*;
+
+    #[test]
+    fn should_tell_whether_file_is_transferred_in_ascii() {
+        assert_eq!(TransferMode::default(), TransferMode::Binary);
+        assert!(!TransferMode::Binary.is_ascii(Path::new("/tmp/a.txt")));
+        assert!(TransferMode::Ascii.is_ascii(Path::new("/tmp/a.bin")));
+        let mode = TransferMode::auto();
+        assert!(mode.is_ascii(Path::new("/tmp/a.txt")));
+        assert!(mode.is_ascii(Path::new("/tmp/README.MD")));
+        assert!(!mode.is_ascii(Path::new("/tmp/a.tar.gz")));
+        assert!(!mode.is_ascii(Path::new("/tmp/txt")));
+        let mode = TransferMode::Auto(vec!["JCL".to_string()]);
+        assert!(mode.is_ascii(Path::new("PAYROLL.jcl")));
+        assert!(!mode.is_ascii(Path::new("/tmp/a.txt")));
+    }
+}
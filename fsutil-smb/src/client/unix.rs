@@ -2,11 +2,18 @@
 //!
 //! UNIX implementation of Smb fs client
 
+mod file_stream;
+
 // -- exports
 use std::io::{self, Read, Write};
 use std::path::{Path, PathBuf};
+use std::sync::Arc;
 
-use fsutil_core::fs::{File, Metadata, ReadStream, UnixPex, Welcome, WriteStream};
+use file_stream::FileStream;
+use fsutil_core::fs::stream::{ReadAndSeek, WriteAndSeek};
+use fsutil_core::fs::{
+    File, Metadata, MetadataField, ReadStream, SetStatReport, UnixPex, Welcome, WriteStream,
+};
 use fsutil_core::{RemoteError, RemoteErrorType, RemoteFileSystem, RemoteResult};
 use libc::mode_t;
 pub use pavao::{SmbClient, SmbCredentials, SmbEncryptionLevel, SmbOptions, SmbShareMode};
@@ -16,7 +23,10 @@ use crate::utils::{path as path_utils, smb as smb_utils};
 
 /// SMB file system client
 pub struct SmbFileSystem {
-    client: SmbClient,
+    /// Shared with the open streams: dropping the client frees the smb context, which they use
+    client: Arc<SmbClient>,
+    /// Url of the share, for the calls pavao doesn't bind
+    share_url: Option<String>,
     wrkdir: PathBuf,
 }
 
@@ -24,9 +34,12 @@ impl SmbFileSystem {
     /// Try to create a new `SmbFileSystem`.
     /// Fails if it is not possible to instantiate a smb context.
     pub fn try_new(credentials: SmbCredentials, options: SmbOptions) -> RemoteResult<Self> {
+        let share_url = smb_utils::share_url(&credentials);
+        let client = SmbClient::new(credentials, options)
+            .map_err(|e| RemoteError::new_ex(RemoteErrorType::BadAddress, e))?;
         Ok(Self {
-            client: SmbClient::new(credentials, options)
-                .map_err(|e| RemoteError::new_ex(RemoteErrorType::BadAddress, e))?,
+            client: Arc::new(client),
+            share_url,
             wrkdir: PathBuf::from("/"),
         })
     }
@@ -36,16 +49,82 @@ impl SmbFileSystem {
         &self.client
     }
 
-    /// Return a mutable reference to the inner `pavao::SmbClient`
+    /// Return a mutable reference to the inner `pavao::SmbClient`.
+    ///
+    /// Panics if a stream opened by the file system is still open, since the streams share the client
     pub fn client_mut(&mut self) -> &mut SmbClient {
-        &mut self.client
+        Arc::get_mut(&mut self.client).expect("smb client is shared with open streams")
+    }
+
+    /// Set the attributes in `metadata` on file at `path`, as far as the smb client supports it:
+    /// `mode` is set through `chmod` and the times through `utimes`.
+    ///
+    /// Attributes which can't be set, such as the owner, don't fail the call,
+    /// but are reported in the returned [`SetStatReport`]
+    pub fn setstat_with_report(
+        &mut self,
+        path: &Path,
+        metadata: &Metadata,
+    ) -> RemoteResult<SetStatReport> {
+        self.check_connection()?;
+        let path = self.get_uri(path);
+        trace!("setting attributes for {}", path);
+        let stat = self
+            .client()
+            .stat(path.as_str())
+            .map_err(|e| RemoteError::new_ex(RemoteErrorType::StatFailed, e))?;
+        let mut applied = Vec::new();
+        if let Some(mode) = metadata.mode {
+            trace!("setting mode {:o} for {}", u32::from(mode), path);
+            self.client()
+                .chmod(path.as_str(), SmbMode::from(u32::from(mode) as mode_t))
+                .map_err(|e| RemoteError::new_ex(RemoteErrorType::PexError, e))?;
+            applied.push(MetadataField::Mode);
+        }
+        if metadata.accessed.is_some() || metadata.modified.is_some() {
+            // utimes sets both times, so the one which isn't set is kept
+            let accessed = metadata.accessed.unwrap_or(stat.accessed);
+            let modified = metadata.modified.unwrap_or(stat.modified);
+            if let Some(share_url) = self.share_url.as_deref() {
+                trace!("setting times for {}", path);
+                smb_utils::utimes(
+                    self.client(),
+                    format!("{share_url}{path}").as_str(),
+                    accessed,
+                    modified,
+                )
+                .map_err(|e| RemoteError::new_ex(RemoteErrorType::ProtocolError, e))?;
+                applied.extend(
+                    [
+                        (metadata.accessed, MetadataField::Accessed),
+                        (metadata.modified, MetadataField::Modified),
+                    ]
+                    .into_iter()
+                    .filter_map(|(time, field)| time.map(|_| field)),
+                );
+            }
+        }
+        let report = SetStatReport::new(metadata, applied);
+        if !report.is_complete() {
+            warn!(
+                "cannot set {} for {}",
+                report
+                    .unsupported
+                    .iter()
+                    .map(|x| x.to_string())
+                    .collect::<Vec<String>>()
+                    .join(", "),
+                path
+            );
+        }
+        Ok(report)
     }
 
     // -- private
 
     fn check_connection(&self) -> RemoteResult<()> {
         trace!("checking connection...");
-        match self.client.get_user() {
+        match self.client().get_user() {
             Err(e) => {
                 error!("connection ERROR: {}", e);
                 Err(RemoteError::new_ex(RemoteErrorType::ConnectionError, e))
@@ -61,6 +140,44 @@ impl SmbFileSystem {
         let p = path_utils::absolutize(self.wrkdir.as_path(), p.as_ref());
         p.to_string_lossy().to_string()
     }
+
+    /// Open file at `path` for write; the file is truncated unless `append` is set
+    fn open_for_write(
+        &self,
+        path: &Path,
+        metadata: &Metadata,
+        append: bool,
+    ) -> RemoteResult<FileStream> {
+        let path = self.get_uri(path);
+        trace!(
+            "opening file at {} for {}",
+            path,
+            if append { "append" } else { "write" }
+        );
+        FileStream::open(
+            &self.client,
+            path.as_str(),
+            SmbOpenOptions::default()
+                .create(true)
+                .append(append)
+                .truncate(!append)
+                .write(true)
+                .mode(u32::from(metadata.mode.unwrap_or_else(|| UnixPex::from(0o644))) as mode_t),
+        )
+        .map_err(|e| RemoteError::new_ex(RemoteErrorType::CouldNotOpenFile, e))
+    }
+
+    /// Open file at `path` for read
+    fn open_for_read(&self, path: &Path) -> RemoteResult<FileStream> {
+        let path = self.get_uri(path);
+        trace!("opening file at {} for read", path);
+        FileStream::open(
+            &self.client,
+            path.as_str(),
+            SmbOpenOptions::default().read(true),
+        )
+        .map_err(|e| RemoteError::new_ex(RemoteErrorType::CouldNotOpenFile, e))
+    }
 }
 
 impl RemoteFileSystem for SmbFileSystem {
@@ -106,7 +223,7 @@ impl RemoteFileSystem for SmbFileSystem {
         let path = self.get_uri(path);
         trace!("listing files at {}", path);
         let dirents = self
-            .client
+            .client()
             .list_dir(path.as_str())
             .map_err(|e| RemoteError::new_ex(RemoteErrorType::StatFailed, e))?;
         // stat each dirent (NOTE: KEEP ONLY FILES AND DIRECTORIES)
@@ -128,14 +245,14 @@ impl RemoteFileSystem for SmbFileSystem {
         self.check_connection()?;
         let path = self.get_uri(path);
         trace!("get stat for {}", path);
-        self.client
+        self.client()
             .stat(path.as_str())
             .map_err(|e| RemoteError::new_ex(RemoteErrorType::StatFailed, e))
             .map(|stat| smb_utils::smbstat_to_file(path, stat))
     }
 
-    fn setstat(&mut self, _path: &Path, _metadata: Metadata) -> RemoteResult<()> {
-        Err(RemoteError::new(RemoteErrorType::UnsupportedFeature))
+    fn setstat(&mut self, path: &Path, metadata: Metadata) -> RemoteResult<()> {
+        self.setstat_with_report(path, &metadata).map(|_| ())
     }
 
     fn exists(&mut self, path: &Path) -> RemoteResult<bool> {
@@ -154,7 +271,7 @@ impl RemoteFileSystem for SmbFileSystem {
         self.check_connection()?;
         let path = self.get_uri(path);
         trace!("removing file {}", path);
-        self.client
+        self.client()
             .unlink(path)
             .map_err(|e| RemoteError::new_ex(RemoteErrorType::CouldNotRemoveFile, e))
     }
@@ -163,7 +280,7 @@ impl RemoteFileSystem for SmbFileSystem {
         self.check_connection()?;
         let path = self.get_uri(path);
         trace!("removing directory at {}", path);
-        self.client
+        self.client()
             .rmdir(path)
             .map_err(|e| RemoteError::new_ex(RemoteErrorType::CouldNotRemoveFile, e))
     }
@@ -176,7 +293,7 @@ impl RemoteFileSystem for SmbFileSystem {
         let path = self.get_uri(path);
         trace!("making directory at {}", path);
         // check if directory exists
-        self.client
+        self.client()
             .mkdir(path, SmbMode::from(u32::from(mode) as mode_t))
             .map_err(|e| RemoteError::new_ex(RemoteErrorType::FileCreateDenied, e))
     }
@@ -185,8 +302,56 @@ impl RemoteFileSystem for SmbFileSystem {
         Err(RemoteError::new(RemoteErrorType::UnsupportedFeature))
     }
 
-    fn copy(&mut self, _src: &Path, _dest: &Path) -> RemoteResult<()> {
-        Err(RemoteError::new(RemoteErrorType::UnsupportedFeature))
+    fn copy(&mut self, src: &Path, dest: &Path) -> RemoteResult<()> {
+        self.check_connection()?;
+        let src = self.stat(src)?;
+        let dest = path_utils::absolutize(self.wrkdir.as_path(), dest);
+        debug!("copying {} to {}", src.path().display(), dest.display());
+        if src.is_dir() {
+            if dest.starts_with(src.path()) {
+                error!("cannot copy {} into itself", src.path().display());
+                return Err(RemoteError::new_ex(
+                    RemoteErrorType::BadFile,
+                    "cannot copy a directory into itself",
+                ));
+            }
+            // If destination path doesn't exist, create destination
+            if !self.exists(dest.as_path())? {
+                debug!("directory {} doesn't exist; creating it", dest.display());
+                self.create_dir(
+                    dest.as_path(),
+                    src.metadata().mode.unwrap_or_else(|| UnixPex::from(0o755)),
+                )?;
+            }
+            // Copy directory entries recursively
+            for entry in self.list_dir(src.path())? {
+                self.copy(entry.path(), dest.join(entry.name()).as_path())?;
+            }
+        } else {
+            // If destination path is a directory, push file name
+            let dest = match self.stat(dest.as_path()) {
+                Ok(file) if file.is_dir() => dest.join(src.name()),
+                _ => dest,
+            };
+            // opening `dest` for write would truncate `src`
+            if dest.as_path() == src.path() {
+                error!("cannot copy {} onto itself", src.path().display());
+                return Err(RemoteError::new_ex(
+                    RemoteErrorType::BadFile,
+                    "cannot copy a file onto itself",
+                ));
+            }
+            // Stream file content through the client
+            let mut reader = self.open_for_read(src.path())?;
+            let mut writer = self.open_for_write(dest.as_path(), src.metadata(), false)?;
+            let bytes = io::copy(&mut reader, &mut writer)
+                .map_err(|e| RemoteError::new_ex(RemoteErrorType::IoError, e))?;
+            drop(writer);
+            drop(reader);
+            debug!("copied {} bytes to {}", bytes, dest.display());
+            self.setstat_with_report(dest.as_path(), src.metadata())?;
+        }
+        Ok(())
     }
 
     fn mov(&mut self, src: &Path, dest: &Path) -> RemoteResult<()> {
@@ -195,7 +360,7 @@ impl RemoteFileSystem for SmbFileSystem {
         let dest = self.get_uri(dest);
         trace!("moving {} to {}", src, dest);
         // check if directory exists
-        self.client
+        self.client()
             .rename(src, dest)
             .map_err(|e| RemoteError::new_ex(RemoteErrorType::ProtocolError, e))
     }
@@ -211,21 +376,7 @@ impl RemoteFileSystem for SmbFileSystem {
         mut reader: Box<dyn Read + Send>,
     ) -> RemoteResult<u64> {
         self.check_connection()?;
-        let path = self.get_uri(path);
-        trace!("opening file at {} for append", path);
-        let mut file = self
-            .client
-            .open_with(
-                path,
-                SmbOpenOptions::default()
-                    .create(true)
-                    .append(true)
-                    .write(true)
-                    .mode(
-                        u32::from(metadata.mode.unwrap_or_else(|| UnixPex::from(0o644))) as mode_t,
-                    ),
-            )
-            .map_err(|e| RemoteError::new_ex(RemoteErrorType::CouldNotOpenFile, e))?;
+        let mut file = self.open_for_write(path, metadata, true)?;
         io::copy(&mut reader, &mut file)
             .map_err(|e| RemoteError::new_ex(RemoteErrorType::IoError, e))
     }
@@ -237,45 +388,45 @@ impl RemoteFileSystem for SmbFileSystem {
         mut reader: Box<dyn Read + Send>,
     ) -> RemoteResult<u64> {
         self.check_connection()?;
-        let path = self.get_uri(path);
-        trace!("creating file at {}", path);
-        let mut file = self
-            .client
-            .open_with(
-                path,
-                SmbOpenOptions::default()
-                    .create(true)
-                    .write(true)
-                    .mode(
-                        u32::from(metadata.mode.unwrap_or_else(|| UnixPex::from(0o644))) as mode_t,
-                    ),
-            )
-            .map_err(|e| RemoteError::new_ex(RemoteErrorType::CouldNotOpenFile, e))?;
+        let mut file = self.open_for_write(path, metadata, false)?;
         io::copy(&mut reader, &mut file)
             .map_err(|e| RemoteError::new_ex(RemoteErrorType::IoError, e))
     }
 
     fn open_file(&mut self, path: &Path, mut dest: Box<dyn Write + Send>) -> RemoteResult<u64> {
         self.check_connection()?;
-        let path = self.get_uri(path);
-        trace!("opening file at {} for read", path);
-        let mut file = self
-            .client
-            .open_with(path, SmbOpenOptions::default().read(true))
-            .map_err(|e| RemoteError::new_ex(RemoteErrorType::CouldNotOpenFile, e))?;
+        let mut file = self.open_for_read(path)?;
         io::copy(&mut file, &mut dest).map_err(|e| RemoteError::new_ex(RemoteErrorType::IoError, e))
     }
 
-    fn append(&mut self, _path: &Path, _metadata: &Metadata) -> RemoteResult<WriteStream> {
-        Err(RemoteError::new(RemoteErrorType::UnsupportedFeature))
+    fn append(&mut self, path: &Path, metadata: &Metadata) -> RemoteResult<WriteStream> {
+        self.check_connection()?;
+        self.open_for_write(path, metadata, true)
+            .map(|file| WriteStream::from(Box::new(file) as Box<dyn WriteAndSeek>))
     }
 
-    fn create(&mut self, _path: &Path, _metadata: &Metadata) -> RemoteResult<WriteStream> {
-        Err(RemoteError::new(RemoteErrorType::UnsupportedFeature))
+    fn create(&mut self, path: &Path, metadata: &Metadata) -> RemoteResult<WriteStream> {
+        self.check_connection()?;
+        self.open_for_write(path, metadata, false)
+            .map(|file| WriteStream::from(Box::new(file) as Box<dyn WriteAndSeek>))
     }
 
-    fn open(&mut self, _path: &Path) -> RemoteResult<ReadStream> {
-        Err(RemoteError::new(RemoteErrorType::UnsupportedFeature))
+    fn open(&mut self, path: &Path) -> RemoteResult<ReadStream> {
+        self.check_connection()?;
+        self.open_for_read(path)
+            .map(|file| ReadStream::from(Box::new(file) as Box<dyn ReadAndSeek>))
+    }
+
+    fn on_written(&mut self, mut writable: WriteStream) -> RemoteResult<()> {
+        trace!("closing written file");
+        writable
+            .flush()
+            .map_err(|e| RemoteError::new_ex(RemoteErrorType::IoError, e))
+    }
+
+    fn on_read(&mut self, _readable: ReadStream) -> RemoteResult<()> {
+        trace!("closing read file");
+        Ok(())
     }
 }
 
@@ -283,7 +434,7 @@ impl RemoteFileSystem for SmbFileSystem {
 mod test {
 
     #[cfg(feature = "with-containers")]
-    use std::io::Cursor;
+    use std::io::{Cursor, Seek};
     #[cfg(feature = "with-containers")]
     use std::time::Duration;
 
@@ -366,6 +517,95 @@ mod test {
         finalize_client(client);
     }
 
+    #[test]
+    #[cfg(feature = "with-containers")]
+    #[serial]
+    fn should_copy_file() {
+        crate::mock::logger();
+        let mut client = init_client();
+        // Create file
+        let p = Path::new("/cargo-test/a.txt");
+        let file_data = "test data\n";
+        let reader = Cursor::new(file_data.as_bytes());
+        assert!(client
+            .create_file(p, &Metadata::default(), Box::new(reader))
+            .is_ok());
+        assert!(client.copy(p, Path::new("/cargo-test/b.txt")).is_ok());
+        assert_eq!(
+            client
+                .stat(Path::new("/cargo-test/b.txt"))
+                .ok()
+                .unwrap()
+                .metadata()
+                .size,
+            10
+        );
+        // Copy into directory
+        assert!(client
+            .create_dir(Path::new("/cargo-test/mydir"), UnixPex::from(0o755))
+            .is_ok());
+        assert!(client.copy(p, Path::new("/cargo-test/mydir")).is_ok());
+        assert!(client
+            .exists(Path::new("/cargo-test/mydir/a.txt"))
+            .ok()
+            .unwrap());
+        // Copy onto itself
+        assert!(client.copy(p, p).is_err());
+        assert!(client.copy(p, Path::new("/cargo-test")).is_err());
+        assert_eq!(client.stat(p).ok().unwrap().metadata().size, 10);
+        finalize_client(client);
+    }
+
+    #[test]
+    #[cfg(feature = "with-containers")]
+    #[serial]
+    fn should_copy_directory() {
+        crate::mock::logger();
+        let mut client = init_client();
+        // Create tree
+        assert!(client
+            .create_dir(Path::new("/cargo-test/src"), UnixPex::from(0o755))
+            .is_ok());
+        assert!(client
+            .create_dir(Path::new("/cargo-test/src/sub"), UnixPex::from(0o755))
+            .is_ok());
+        let file_data = "test data\n";
+        let reader = Cursor::new(file_data.as_bytes());
+        assert!(client
+            .create_file(
+                Path::new("/cargo-test/src/sub/a.txt"),
+                &Metadata::default(),
+                Box::new(reader)
+            )
+            .is_ok());
+        // Copy
+        assert!(client
+            .copy(Path::new("/cargo-test/src"), Path::new("/cargo-test/dest"))
+            .is_ok());
+        assert_eq!(
+            client
+                .stat(Path::new("/cargo-test/dest/sub/a.txt"))
+                .ok()
+                .unwrap()
+                .metadata()
+                .size,
+            10
+        );
+        // Copy into itself
+        assert_eq!(
+            client
+                .copy(
+                    Path::new("/cargo-test/src"),
+                    Path::new("/cargo-test/src/sub/dest")
+                )
+                .err()
+                .unwrap()
+                .kind,
+            RemoteErrorType::BadFile
+        );
+        finalize_client(client);
+    }
+
     #[test]
     #[cfg(feature = "with-containers")]
     #[serial]
@@ -618,6 +858,95 @@ mod test {
         finalize_client(client);
     }
 
+    #[test]
+    #[cfg(feature = "with-containers")]
+    #[serial]
+    fn should_open_file_as_stream() {
+        crate::mock::logger();
+        let mut client = init_client();
+        // Create file
+        let p = Path::new("/cargo-test/a.txt");
+        let file_data = "test data\nHello, world!\n";
+        let reader = Cursor::new(file_data.as_bytes());
+        assert!(client
+            .create_file(p, &Metadata::default().size(24), Box::new(reader))
+            .is_ok());
+        // Seek and read partially
+        let mut stream = client.open(p).ok().unwrap();
+        assert!(stream.seekable());
+        assert_eq!(stream.seek(io::SeekFrom::Start(10)).unwrap(), 10);
+        let mut buffer = [0u8; 5];
+        stream.read_exact(&mut buffer).unwrap();
+        assert_eq!(&buffer, b"Hello");
+        assert!(client.on_read(stream).is_ok());
+        finalize_client(client);
+    }
+
+    #[test]
+    #[cfg(feature = "with-containers")]
+    #[serial]
+    fn should_create_and_append_with_streams() {
+        crate::mock::logger();
+        let mut client = init_client();
+        let p = Path::new("/cargo-test/a.txt");
+        // Write incrementally
+        let mut stream = client.create(p, &Metadata::default()).ok().unwrap();
+        assert!(client.client_mut().get_user().is_ok());
+        stream.write_all(b"test ").unwrap();
+        stream.write_all(b"data\n").unwrap();
+        assert!(client.on_written(stream).is_ok());
+        assert_eq!(client.stat(p).ok().unwrap().metadata().size, 10);
+        // Append
+        let mut stream = client.append(p, &Metadata::default()).ok().unwrap();
+        stream.write_all(b"Hello, world!\n").unwrap();
+        assert!(client.on_written(stream).is_ok());
+        assert_eq!(client.stat(p).ok().unwrap().metadata().size, 24);
+        // Create truncates the file
+        let mut stream = client.create(p, &Metadata::default()).ok().unwrap();
+        stream.write_all(b"test").unwrap();
+        assert!(client.on_written(stream).is_ok());
+        assert_eq!(client.stat(p).ok().unwrap().metadata().size, 4);
+        finalize_client(client);
+    }
+
+    #[test]
+    #[cfg(feature = "with-containers")]
+    #[serial]
+    fn should_keep_streams_open_after_dropping_client() {
+        crate::mock::logger();
+        let mut client = init_client();
+        let p = Path::new("/cargo-test/a.txt");
+        let mut stream = client.create(p, &Metadata::default()).ok().unwrap();
+        // the stream keeps the smb client, and so the context, alive
+        drop(client);
+        stream.write_all(b"test data\n").unwrap();
+        // the context is freed along with the last user of the client
+        drop(stream);
+        let mut client = new_client();
+        assert_eq!(client.stat(p).ok().unwrap().metadata().size, 10);
+        finalize_client(client);
+    }
+
+    #[test]
+    #[cfg(feature = "with-containers")]
+    #[serial]
+    fn should_not_open_file_as_stream() {
+        crate::mock::logger();
+        let mut client = init_client();
+        assert_eq!(
+            client
+                .open(Path::new("/tmp/aashafb/hhh"))
+                .err()
+                .unwrap()
+                .kind,
+            RemoteErrorType::CouldNotOpenFile
+        );
+        assert!(client
+            .create(Path::new("/tmp/aashafb/hhh"), &Metadata::default())
+            .is_err());
+        finalize_client(client);
+    }
+
     #[test]
     #[cfg(feature = "with-containers")]
     #[serial]
@@ -739,6 +1068,40 @@ mod test {
         finalize_client(client);
     }
 
+    #[test]
+    #[cfg(feature = "with-containers")]
+    #[serial]
+    fn should_setstat_file() {
+        crate::mock::logger();
+        let mut client = init_client();
+        // Create file
+        let p = Path::new("/cargo-test/a.sh");
+        let file_data = "echo 5\n";
+        let reader = Cursor::new(file_data.as_bytes());
+        assert!(client
+            .create_file(p, &Metadata::default().size(7), Box::new(reader))
+            .is_ok());
+        let report = client
+            .setstat_with_report(
+                p,
+                &Metadata::default()
+                    .mode(UnixPex::from(0o644))
+                    .modified(std::time::UNIX_EPOCH),
+            )
+            .ok()
+            .unwrap();
+        assert_eq!(
+            report.applied,
+            vec![MetadataField::Modified, MetadataField::Mode]
+        );
+        assert!(report.is_complete());
+        assert_eq!(
+            client.stat(p).ok().unwrap().metadata().modified,
+            Some(std::time::UNIX_EPOCH)
+        );
+        finalize_client(client);
+    }
+
     #[test]
     #[cfg(feature = "with-containers")]
     #[serial]
@@ -857,7 +1220,15 @@ mod test {
     #[cfg(feature = "with-containers")]
     fn init_client() -> SmbFileSystem {
         let _ = std::fs::remove_dir_all(Path::new("/tmp/cargo-test"));
-        let client = SmbFileSystem::try_new(
+        let client = new_client();
+        // make test dir
+        let _ = std::fs::create_dir(Path::new("/tmp/cargo-test"));
+        client
+    }
+
+    #[cfg(feature = "with-containers")]
+    fn new_client() -> SmbFileSystem {
+        SmbFileSystem::try_new(
             SmbCredentials::default()
                 .server("smb://localhost:3445")
                 .share("/temp")
@@ -868,10 +1239,7 @@ mod test {
                 .case_sensitive(true)
                 .one_share_per_server(true),
         )
-        .unwrap();
-        // make test dir
-        let _ = std::fs::create_dir(Path::new("/tmp/cargo-test"));
-        client
+        .unwrap()
     }
 
     #[cfg(feature = "with-containers")]
@@ -0,0 +1,66 @@
+use std::io::{Read, Seek, Write};
+use std::sync::Arc;
+
+use fsutil_core::fs::stream::{ReadAndSeek, WriteAndSeek};
+use pavao::{SmbClient, SmbFile, SmbOpenOptions, SmbResult};
+
+/// A file opened on the SMB share.
+/// The file is closed on drop
+pub struct FileStream {
+    // NOTE: `file` must be declared before `_client`, so that the file is closed before the client may be dropped
+    file: SmbFile<'static>,
+    _client: Arc<SmbClient>,
+}
+
+impl FileStream {
+    /// Open the file at `path` with the provided options.
+    ///
+    /// The stream shares `client` with the file system, so the smb context is freed only once both are dropped
+    pub fn open(client: &Arc<SmbClient>, path: &str, options: SmbOpenOptions) -> SmbResult<Self> {
+        let client = Arc::clone(client);
+        // SAFETY: the client is allocated by the `Arc`, so it never moves, and the `Arc` stored along with the file
+        // keeps it alive until the file has been closed, since fields are dropped in declaration order
+        let borrowed: &'static SmbClient = unsafe { &*Arc::as_ptr(&client) };
+        Ok(Self {
+            file: borrowed.open_with(path, options)?,
+            _client: client,
+        })
+    }
+}
+
+// SAFETY: `SmbFile` is `!Send` only because it holds the raw `SMBCFILE*` returned by libsmbclient.
+// - The pointer is owned by this stream: it is only used through `&mut self` and closed once, when `file` is dropped.
+//   `FileStream` is not `Sync`, so moving it to another thread never lets two threads use the pointer at once.
+// - The pointer belongs to pavao's global `SMBCCTX`, which every call looks up through pavao's context mutex.
+//   The context stays alive while the file is open: it is freed when the `SmbClient` is dropped,
+//   and the stream holds the client until `file` has been closed.
+// - The mutex only guards the lookup of the context, not the libsmbclient call. A call on the stream can thus run
+//   at the same time as a call on the file system from another thread. pavao allows the same with `SmbClient`,
+//   which is `Send + Sync` and uses the same context, so the stream adds no requirement beyond pavao's own.
+unsafe impl Send for FileStream {}
+
+impl Read for FileStream {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        self.file.read(buf)
+    }
+}
+
+impl Seek for FileStream {
+    fn seek(&mut self, pos: std::io::SeekFrom) -> std::io::Result<u64> {
+        self.file.seek(pos)
+    }
+}
+
+impl ReadAndSeek for FileStream {}
+
+impl Write for FileStream {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.file.write(buf)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.file.flush()
+    }
+}
+
+impl WriteAndSeek for FileStream {}
@@ -2,12 +2,23 @@
 //!
 //! SMB protocol utilities
 
+use std::ffi::{c_char, c_int, c_void, CString};
+use std::io;
 use std::path::PathBuf;
+use std::time::{SystemTime, UNIX_EPOCH};
 
 use fsutil_core::fs::{FileType, Metadata, UnixPex};
 use fsutil_core::File;
 use libc::mode_t;
-use pavao::SmbStat;
+use pavao::{SmbClient, SmbCredentials, SmbError, SmbResult, SmbStat};
+
+/// `smbc_utimes_fn` of libsmbclient
+type UtimesFn = unsafe extern "C" fn(*mut c_void, *const c_char, *mut libc::timeval) -> c_int;
+
+// NOTE: pavao doesn't bind `utimes`, but libsmbclient, which pavao links, exports its getter
+extern "C" {
+    fn smbc_getFunctionUtimes(ctx: *mut c_void) -> Option<UtimesFn>;
+}
 
 /// Convert `SmbStat` to `File`
 pub fn smbstat_to_file<S: AsRef<str>>(uri: S, stat: SmbStat) -> File {
@@ -30,6 +41,81 @@ pub fn smbstat_to_file<S: AsRef<str>>(uri: S, stat: SmbStat) -> File {
     }
 }
 
+/// Url of the share `credentials` point to, built as `SmbClient` does (e.g. `smb://localhost:3445/temp`).
+///
+/// pavao keeps the fields of `SmbCredentials` private, so they are read from its `Debug` representation;
+/// returns `None` if they can't be
+pub fn share_url(credentials: &SmbCredentials) -> Option<String> {
+    let debug = format!("{credentials:?}");
+    let server = debug_str_field(debug.as_str(), "server")?;
+    let share = debug_str_field(debug.as_str(), "share")?;
+    Some(format!(
+        "{}{}{}",
+        server,
+        match share.starts_with('/') {
+            true => "",
+            false => "/",
+        },
+        share
+    ))
+}
+
+/// Set the access and modification times of file at `url` with `smbc_utimes`
+pub fn utimes(
+    client: &SmbClient,
+    url: &str,
+    accessed: SystemTime,
+    modified: SystemTime,
+) -> SmbResult<()> {
+    let url = CString::new(url).map_err(SmbError::NulInPath)?;
+    let ctx = client.ctx()?.cast::<c_void>();
+    if ctx.is_null() {
+        return Err(SmbError::BadValue);
+    }
+    let mut times = [timeval(accessed), timeval(modified)];
+    // SAFETY: `ctx` is the live context of `client`; `url` and `times` outlive the call
+    let rc = unsafe {
+        let utimes_fn = smbc_getFunctionUtimes(ctx)
+            .ok_or_else(|| io::Error::from_raw_os_error(libc::EINVAL))?;
+        utimes_fn(ctx, url.as_ptr(), times.as_mut_ptr())
+    };
+    match rc {
+        0 => Ok(()),
+        _ => Err(SmbError::Io(io::Error::last_os_error())),
+    }
+}
+
+fn timeval(time: SystemTime) -> libc::timeval {
+    let since_epoch = time.duration_since(UNIX_EPOCH).unwrap_or_default();
+    libc::timeval {
+        tv_sec: since_epoch.as_secs() as libc::time_t,
+        tv_usec: since_epoch.subsec_micros() as libc::suseconds_t,
+    }
+}
+
+/// Read string field `name` from the derived `Debug` representation of a struct
+fn debug_str_field(debug: &str, name: &str) -> Option<String> {
+    // quotes in values are escaped, so the field can't be matched inside another value
+    let start = debug.find(format!("{name}: \"").as_str())? + name.len() + 3;
+    let mut value = String::new();
+    let mut chars = debug[start..].chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' => return Some(value),
+            '\\' => match chars.next()? {
+                c @ ('"' | '\\' | '\'') => value.push(c),
+                'n' => value.push('\n'),
+                'r' => value.push('\r'),
+                't' => value.push('\t'),
+                '0' => value.push('\0'),
+                _ => return None,
+            },
+            c => value.push(c),
+        }
+    }
+    None
+}
+
 fn get_file_type_from_stat(stat: &SmbStat) -> FileType {
     match stat.mode {
         mode if mode.is_dir() => FileType::Directory,
@@ -37,3 +123,42 @@ fn get_file_type_from_stat(stat: &SmbStat) -> FileType {
         _ => FileType::File,
     }
 }
+
+#[cfg(test)]
+mod test {
+
+    use pretty_assertions::assert_eq;
+
+    use super::*;
+
+    #[test]
+    fn should_get_share_url() {
+        assert_eq!(
+            share_url(
+                &SmbCredentials::default()
+                    .server("smb://localhost:3445")
+                    .share("/temp")
+                    .username("test")
+                    .password("pass\"word\\ share: \"x\"")
+            )
+            .as_deref(),
+            Some("smb://localhost:3445/temp")
+        );
+        assert_eq!(
+            share_url(
+                &SmbCredentials::default()
+                    .server("smb://server")
+                    .share("my \"share\"")
+            )
+            .as_deref(),
+            Some("smb://server/my \"share\"")
+        );
+    }
+
+    #[test]
+    fn should_convert_time_to_timeval() {
+        let time = timeval(UNIX_EPOCH + std::time::Duration::from_micros(1_500_000));
+        assert_eq!(time.tv_sec, 1);
+        assert_eq!(time.tv_usec, 500_000);
+    }
+}
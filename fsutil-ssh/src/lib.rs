@@ -6,8 +6,10 @@ extern crate tracing;
 
 mod ssh;
 pub use ssh::{
-    KeyMethod, MethodType, ParseRule as SshConfigParseRule, ScpFileSystem, SftpFileSystem,
-    SshAgentIdentity, SshKeyStorage, SshOpts,
+    ChainedKeyStorage, ConfigKeyStorage, DirKeyStorage, ExecHandle, ExecOptions, ExecReader,
+    ExecWriter, KeyMethod, MemoryKeyStorage, MethodType, ParseRule as SshConfigParseRule,
+    PipelineOpts, PtyOptions, ScpFileSystem, SftpExtension, SftpFileSystem, SshAgentIdentity,
+    SshKey, SshKeyStorage, SshOpts, StatVfs, Sudo,
 };
 
 // -- utils
@@ -4,64 +4,31 @@
 
 use std::io::Read;
 use std::net::{SocketAddr, TcpStream, ToSocketAddrs};
-use std::path::{Path, PathBuf};
+use std::path::Path;
 use std::str::FromStr;
 use std::time::Duration;
 
-use fsutil_core::{RemoteError, RemoteErrorType, RemoteResult};
+use fsutil_core::{Proxy, RemoteError, RemoteErrorType, RemoteResult};
 use ssh2::{MethodType as SshMethodType, Session};
 
 use super::config::Config;
-use super::SshOpts;
+use super::keepalive::Keepalive;
+use super::{ExecHandle, ExecOptions, SshKey, SshOpts};
+use crate::utils::shell as shell_utils;
 use crate::SshAgentIdentity;
 
 // -- connect
 
 /// Establish connection with remote server and in case of success, return the generated [`Session`]
-pub fn connect(opts: &SshOpts) -> RemoteResult<Session> {
+/// along with its [`Keepalive`] settings
+pub fn connect(opts: &SshOpts) -> RemoteResult<(Session, Keepalive)> {
     // parse configuration
     let ssh_config = Config::try_from(opts)?;
-    // Resolve host
     debug!("Connecting to '{}'", ssh_config.address);
     // setup tcp stream
-    let socket_addresses: Vec<SocketAddr> = match ssh_config.address.to_socket_addrs() {
-        Ok(s) => s.collect(),
-        Err(err) => {
-            return Err(RemoteError::new_ex(
-                RemoteErrorType::BadAddress,
-                err.to_string(),
-            ));
-        }
-    };
-    let mut stream = None;
-    for _ in 0..ssh_config.connection_attempts {
-        for socket_addr in socket_addresses.iter() {
-            trace!(
-                "Trying to connect to socket address '{}' (timeout: {}s)",
-                socket_addr,
-                ssh_config.connection_timeout.as_secs()
-            );
-            if let Ok(tcp_stream) = tcp_connect(socket_addr, ssh_config.connection_timeout) {
-                debug!("Connection established with address {}", socket_addr);
-                stream = Some(tcp_stream);
-                break;
-            }
-        }
-        // break from attempts cycle if some
-        if stream.is_some() {
-            break;
-        }
-    }
-    // If stream is None, return connection timeout
-    let stream = match stream {
-        Some(s) => s,
-        None => {
-            error!("No suitable socket address found; connection timeout");
-            return Err(RemoteError::new_ex(
-                RemoteErrorType::ConnectionError,
-                "connection timeout",
-            ));
-        }
+    let stream = match opts.proxy.as_ref() {
+        Some(proxy) => proxy_connect(proxy, &ssh_config)?,
+        None => direct_connect(&ssh_config)?,
     };
     // Create session
     let mut session = match Session::new() {
@@ -86,7 +53,8 @@ pub fn connect(opts: &SshOpts) -> RemoteResult<Session> {
         match session_auth_with_agent(&mut session, &ssh_config.username, ssh_agent_config) {
             Ok(_) => {
                 info!("Authenticated with ssh agent");
-                return Ok(session);
+                let keepalive = Keepalive::setup(&session, &ssh_config);
+                return Ok((session, keepalive));
             }
             Err(err) => {
                 error!("Could not authenticate with ssh agent: {}", err);
@@ -94,35 +62,92 @@ pub fn connect(opts: &SshOpts) -> RemoteResult<Session> {
         }
     }
 
-    // Authenticate with password or key
+    // Authenticate with keys, then with password
     if !session.authenticated() {
-        match opts.key_storage.as_ref().and_then(|x| {
-            x.resolve(ssh_config.host.as_str(), ssh_config.username.as_str())
-                .or(x.resolve(
-                    ssh_config.resolved_host.as_str(),
-                    ssh_config.username.as_str(),
-                ))
-        }) {
-            Some(rsa_key) => {
-                session_auth_with_rsakey(
-                    &mut session,
-                    &ssh_config.username,
-                    rsa_key.as_path(),
-                    opts.password.as_deref(),
-                    ssh_config.params.identity_file.as_deref(),
-                )?;
+        let keys = resolve_keys(opts, &ssh_config);
+        if keys.is_empty()
+            || session_auth_with_keys(
+                &mut session,
+                &ssh_config.username,
+                &keys,
+                opts.password.as_deref(),
+            )
+            .is_err()
+        {
+            session_auth_with_password(
+                &mut session,
+                &ssh_config.username,
+                opts.password.as_deref(),
+            )?;
+        }
+    }
+    // Setup keepalive and return session
+    let keepalive = Keepalive::setup(&session, &ssh_config);
+    Ok((session, keepalive))
+}
+
+/// Resolve the server address and connect to it
+fn direct_connect(ssh_config: &Config) -> RemoteResult<TcpStream> {
+    let socket_addresses: Vec<SocketAddr> = match ssh_config.address.to_socket_addrs() {
+        Ok(s) => s.collect(),
+        Err(err) => {
+            return Err(RemoteError::new_ex(
+                RemoteErrorType::BadAddress,
+                err.to_string(),
+            ));
+        }
+    };
+    let mut stream = None;
+    for _ in 0..ssh_config.connection_attempts {
+        for socket_addr in socket_addresses.iter() {
+            trace!(
+                "Trying to connect to socket address '{}' (timeout: {}s)",
+                socket_addr,
+                ssh_config.connection_timeout.as_secs()
+            );
+            if let Ok(tcp_stream) = tcp_connect(socket_addr, ssh_config.connection_timeout) {
+                debug!("Connection established with address {}", socket_addr);
+                stream = Some(tcp_stream);
+                break;
             }
-            None => {
-                session_auth_with_password(
-                    &mut session,
-                    &ssh_config.username,
-                    opts.password.as_deref(),
-                )?;
+        }
+        // break from attempts cycle if some
+        if stream.is_some() {
+            break;
+        }
+    }
+    // If stream is None, return connection timeout
+    stream.ok_or_else(|| {
+        error!("No suitable socket address found; connection timeout");
+        RemoteError::new_ex(RemoteErrorType::ConnectionError, "connection timeout")
+    })
+}
+
+/// Connect to the server through `proxy`, which resolves the host
+fn proxy_connect(proxy: &Proxy, ssh_config: &Config) -> RemoteResult<TcpStream> {
+    let timeout = Some(ssh_config.connection_timeout).filter(|x| !x.is_zero());
+    let mut last_error = None;
+    for _ in 0..ssh_config.connection_attempts {
+        match proxy.connect(ssh_config.resolved_host.as_str(), ssh_config.port, timeout) {
+            Ok(stream) => {
+                debug!("Connection established through proxy {}", proxy.address());
+                return Ok(stream);
+            }
+            Err(err) => {
+                warn!(
+                    "Could not connect through proxy {}: {}",
+                    proxy.address(),
+                    err
+                );
+                last_error = Some(err);
             }
         }
     }
-    // Return session
-    Ok(session)
+    error!("Could not connect through proxy {}", proxy.address());
+    Err(match last_error {
+        Some(err) => RemoteError::new_ex(RemoteErrorType::ConnectionError, err),
+        None => RemoteError::new_ex(RemoteErrorType::ConnectionError, "connection timeout"),
+    })
 }
 
 /// connect to socket address with provided timeout.
@@ -249,36 +274,75 @@ fn session_auth_with_agent(
     connection_result
 }
 
-/// Authenticate on session with private key
-fn session_auth_with_rsakey(
+/// Collect the keys to authenticate with: the keys in the key storage for the host and for the resolved host,
+/// then the identity files in the ssh configuration
+fn resolve_keys(opts: &SshOpts, ssh_config: &Config) -> Vec<SshKey> {
+    let mut keys: Vec<SshKey> = Vec::new();
+    if let Some(storage) = opts.key_storage.as_ref() {
+        keys.extend(storage.resolve_keys(ssh_config.host.as_str(), ssh_config.username.as_str()));
+        keys.extend(storage.resolve_keys(
+            ssh_config.resolved_host.as_str(),
+            ssh_config.username.as_str(),
+        ));
+    }
+    if let Some(identity_file) = ssh_config.params.identity_file.as_deref() {
+        keys.extend(identity_file.iter().cloned().map(SshKey::File));
+    }
+    let mut unique_keys: Vec<SshKey> = Vec::with_capacity(keys.len());
+    for key in keys {
+        if !unique_keys.contains(&key) {
+            unique_keys.push(key);
+        }
+    }
+    unique_keys
+}
+
+/// Authenticate on session with private keys, trying each of them in order
+fn session_auth_with_keys(
     session: &mut Session,
     username: &str,
-    private_key: &Path,
+    keys: &[SshKey],
     password: Option<&str>,
-    identity_file: Option<&[PathBuf]>,
 ) -> RemoteResult<()> {
-    debug!("Authenticating with username '{}' and RSA key", username);
-    let mut keys = vec![private_key];
-    if let Some(identity_file) = identity_file {
-        let other_keys: Vec<&Path> = identity_file.iter().map(|x| x.as_path()).collect();
-        keys.extend(other_keys);
-    }
-    // iterate over keys
-    for key in keys.into_iter() {
-        trace!("Trying to authenticate with RSA key at '{}'", key.display());
-        match session.userauth_pubkey_file(username, None, key, password) {
+    debug!(
+        "Authenticating with username '{}' and {} keys",
+        username,
+        keys.len()
+    );
+    for key in keys {
+        trace!("Trying to authenticate with key {:?}", key);
+        let result = match key {
+            SshKey::File(p) => session.userauth_pubkey_file(username, None, p, password),
+            #[cfg(any(unix, feature = "ssh2-vendored"))]
+            SshKey::Memory {
+                private_key,
+                public_key,
+                passphrase,
+            } => session.userauth_pubkey_memory(
+                username,
+                public_key.as_deref(),
+                private_key,
+                passphrase.as_deref().or(password),
+            ),
+            #[cfg(not(any(unix, feature = "ssh2-vendored")))]
+            SshKey::Memory { .. } => {
+                warn!("In-memory keys require the ssh2-vendored feature on this platform");
+                continue;
+            }
+        };
+        match result {
             Ok(_) => {
-                debug!("Authenticated with key at '{}'", key.display());
+                debug!("Authenticated with key {:?}", key);
                 return Ok(());
             }
             Err(err) => {
-                error!("Authentication failed: {}", err);
+                error!("Authentication with key {:?} failed: {}", key, err);
             }
         }
     }
     Err(RemoteError::new_ex(
         RemoteErrorType::AuthenticationFailed,
-        "could not find any suitable RSA key to authenticate with",
+        "could not find any suitable key to authenticate with",
     ))
 }
 
@@ -339,13 +403,66 @@ pub fn perform_shell_cmd<S: AsRef<str>>(session: &mut Session, cmd: S) -> Remote
     }
 }
 
+/// Perform shell command in current SSH session and return exit code and raw output.
+///
+/// Unlike [`perform_shell_cmd_with_rc`], the output is not required to be valid UTF-8
+/// and the exit code is read from the channel exit status
+pub fn perform_shell_cmd_with_raw_output<S: AsRef<str>>(
+    session: &mut Session,
+    cmd: S,
+) -> RemoteResult<(u32, Vec<u8>)> {
+    trace!("Running command: {}", cmd.as_ref());
+    let mut channel = session.channel_session().map_err(|err| {
+        RemoteError::new_ex(
+            RemoteErrorType::ProtocolError,
+            format!("Could not open channel: {err}"),
+        )
+    })?;
+    channel.exec(cmd.as_ref()).map_err(|err| {
+        RemoteError::new_ex(
+            RemoteErrorType::ProtocolError,
+            format!("Could not execute command \"{}\": {}", cmd.as_ref(), err),
+        )
+    })?;
+    let mut output: Vec<u8> = Vec::new();
+    channel.read_to_end(&mut output).map_err(|err| {
+        RemoteError::new_ex(
+            RemoteErrorType::ProtocolError,
+            format!("Could not read output: {err}"),
+        )
+    })?;
+    let _ = channel.wait_close();
+    let rc = channel
+        .exit_status()
+        .map_err(|err| RemoteError::new_ex(RemoteErrorType::ProtocolError, err))?;
+    debug!("Command returned {} bytes; exit code: {}", output.len(), rc);
+    Ok((rc as u32, output))
+}
+
 /// Perform shell command at specified path and return exit code and output
 pub fn perform_shell_cmd_at_with_rc<S: AsRef<str>>(
     session: &mut Session,
     cmd: S,
     p: &Path,
 ) -> RemoteResult<(u32, String)> {
-    perform_shell_cmd_with_rc(session, format!("cd \"{}\"; {}", p.display(), cmd.as_ref()))
+    perform_shell_cmd_with_rc(
+        session,
+        format!("cd {}; {}", shell_utils::quote_path(p), cmd.as_ref()),
+    )
+}
+
+/// Spawn shell command at specified path and return the [`ExecHandle`] to interact with it
+pub fn spawn_shell_cmd_at<S: AsRef<str>>(
+    session: &mut Session,
+    cmd: S,
+    p: &Path,
+    opts: &ExecOptions,
+) -> RemoteResult<ExecHandle> {
+    ExecHandle::spawn(
+        session,
+        format!("cd {}; {}", shell_utils::quote_path(p), cmd.as_ref()).as_str(),
+        opts,
+    )
 }
 
 /// Perform shell command and collect return code and output
@@ -389,6 +506,40 @@ mod test {
     use super::*;
     use crate::mock::ssh as ssh_mock;
 
+    #[test]
+    fn should_connect_through_proxy() {
+        use std::io::Write;
+        use std::net::TcpListener;
+
+        // proxy stand-in recording the requested host, then telling it's unreachable
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let proxy = Proxy::socks5(listener.local_addr().unwrap().to_string());
+        let handle = std::thread::spawn(move || {
+            let (mut client, _) = listener.accept().unwrap();
+            let mut greeting = [0; 3];
+            client.read_exact(&mut greeting).unwrap();
+            client.write_all(&[5, 0]).unwrap();
+            let mut request = [0; 5];
+            client.read_exact(&mut request).unwrap();
+            let mut host = vec![0; usize::from(request[4])];
+            client.read_exact(&mut host).unwrap();
+            let mut port = [0; 2];
+            client.read_exact(&mut port).unwrap();
+            client.write_all(&[5, 4, 0, 1, 0, 0, 0, 0, 0, 0]).unwrap();
+            (String::from_utf8(host).unwrap(), u16::from_be_bytes(port))
+        });
+        let opts = SshOpts::new("sftp.invalid")
+            .port(2022)
+            .connection_timeout(Duration::from_secs(5))
+            .proxy(proxy);
+        assert_eq!(
+            connect(&opts).err().unwrap().kind,
+            RemoteErrorType::ConnectionError
+        );
+        // the host is resolved by the proxy
+        assert_eq!(handle.join().unwrap(), ("sftp.invalid".to_string(), 2022));
+    }
+
     #[test]
 
     fn should_connect_to_ssh_server_auth_user_password() {
@@ -406,7 +557,7 @@ mod test {
         if let Err(err) = connect(&opts) {
             panic!("Could not connect to server: {}", err);
         }
-        let session = connect(&opts).unwrap();
+        let (session, _) = connect(&opts).unwrap();
         assert!(session.authenticated());
 
         drop(container);
@@ -425,7 +576,35 @@ mod test {
         let opts = SshOpts::new("sftp")
             .config_file(config_file.path(), ParseRule::ALLOW_UNKNOWN_FIELDS)
             .key_storage(Box::new(ssh_mock::MockSshKeyStorage::default()));
-        let session = connect(&opts).unwrap();
+        let (session, _) = connect(&opts).unwrap();
+        assert!(session.authenticated());
+    }
+
+    #[test]
+    fn should_connect_to_ssh_server_trying_all_keys() {
+        use crate::ssh::container::OpensshServer;
+        use crate::{ChainedKeyStorage, MemoryKeyStorage, SshKey};
+
+        let container = OpensshServer::start();
+        let port = container.port();
+
+        crate::mock::logger();
+        let config_file = ssh_mock::create_ssh_config(port);
+        let storage = ChainedKeyStorage::default()
+            .storage(Box::new(MemoryKeyStorage::default().key(
+                "sftp",
+                "sftp",
+                SshKey::File(std::path::PathBuf::from("/tmp/this-key-does-not-exist")),
+            )))
+            .storage(Box::new(MemoryKeyStorage::default().key(
+                "*",
+                "sftp",
+                SshKey::memory(ssh_mock::PRIVATE_KEY),
+            )));
+        let opts = SshOpts::new("sftp")
+            .config_file(config_file.path(), ParseRule::ALLOW_UNKNOWN_FIELDS)
+            .key_storage(Box::new(storage));
+        let (session, _) = connect(&opts).unwrap();
         assert!(session.authenticated());
     }
 
@@ -440,7 +619,7 @@ mod test {
             .port(port)
             .username("sftp")
             .password("password");
-        let mut session = connect(&opts).unwrap();
+        let (mut session, _) = connect(&opts).unwrap();
         assert!(session.authenticated());
         // run commands
         assert!(perform_shell_cmd(&mut session, "pwd").is_ok());
@@ -457,7 +636,7 @@ mod test {
             .port(port)
             .username("sftp")
             .password("password");
-        let mut session = connect(&opts).unwrap();
+        let (mut session, _) = connect(&opts).unwrap();
         assert!(session.authenticated());
         // run commands
         assert_eq!(
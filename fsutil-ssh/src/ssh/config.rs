@@ -20,9 +20,15 @@ pub struct Config {
     pub resolved_host: String,
     /// Address is host:port
     pub address: String,
+    /// Port of the server
+    pub port: u16,
     pub username: String,
     pub connection_timeout: Duration,
     pub connection_attempts: usize,
+    /// Interval between keepalive messages; `None` if disabled
+    pub keepalive_interval: Option<Duration>,
+    /// Number of keepalive messages which may be left unanswered before the server is considered dead
+    pub keepalive_count_max: u32,
 }
 
 impl Config {
@@ -34,9 +40,12 @@ impl Config {
             host: opts.host.to_string(),
             resolved_host: Self::resolve_host(&params, opts),
             address: Self::resolve_address(&params, opts),
+            port: Self::resolve_port(&params, opts),
             username: Self::resolve_username(&params, opts),
             connection_timeout: Self::resolve_connection_timeout(&params, opts),
             connection_attempts: Self::resolve_connection_attempts(&params),
+            keepalive_interval: Self::resolve_keepalive_interval(&params, opts),
+            keepalive_count_max: Self::resolve_keepalive_count_max(&params, opts),
             params,
         }
     }
@@ -73,12 +82,17 @@ impl Config {
     /// Given host params and ssh options, returns resolved remote address
     fn resolve_address(params: &HostParams, opts: &SshOpts) -> String {
         let host = Self::resolve_host(params, opts);
+        let port = Self::resolve_port(params, opts);
+        format!("{host}:{port}")
+    }
+
+    /// Given host params and ssh options, returns resolved remote port
+    fn resolve_port(params: &HostParams, opts: &SshOpts) -> u16 {
         // Opts.port has priority
-        let port = match opts.port {
+        match opts.port {
             None => params.port.unwrap_or(22),
             Some(p) => p,
-        };
-        format!("{host}:{port}")
+        }
     }
 
     /// Resolve username from opts and params.
@@ -107,6 +121,29 @@ impl Config {
     fn resolve_connection_attempts(params: &HostParams) -> usize {
         params.connection_attempts.unwrap_or(1)
     }
+
+    /// Given host params and ssh options, resolve keepalive interval.
+    /// A zero interval disables keepalive
+    fn resolve_keepalive_interval(params: &HostParams, opts: &SshOpts) -> Option<Duration> {
+        opts.keepalive_interval
+            .or(params.server_alive_interval)
+            .filter(|x| x.as_secs() > 0)
+    }
+
+    /// Given host params and ssh options, resolve the max amount of unanswered keepalive messages.
+    /// `ServerAliveCountMax` is only available if the configuration was parsed with `ALLOW_UNSUPPORTED_FIELDS`.
+    /// If `none`, gets 3, as ssh does
+    fn resolve_keepalive_count_max(params: &HostParams, opts: &SshOpts) -> u32 {
+        opts.keepalive_count_max
+            .or_else(|| {
+                params
+                    .unsupported_fields
+                    .get("serveralivecountmax")
+                    .and_then(|args| args.first())
+                    .and_then(|x| x.parse().ok())
+            })
+            .unwrap_or(3)
+    }
 }
 
 impl TryFrom<&SshOpts> for Config {
@@ -126,6 +163,8 @@ impl TryFrom<&SshOpts> for Config {
 #[cfg(test)]
 mod test {
 
+    use std::io::Write;
+
     use pretty_assertions::{assert_eq, assert_ne};
 
     use super::*;
@@ -137,6 +176,8 @@ mod test {
         let config = Config::try_from(&opts).ok().unwrap();
         assert_eq!(config.connection_attempts, 1);
         assert_eq!(config.connection_timeout, Duration::from_secs(30));
+        assert!(config.keepalive_interval.is_none());
+        assert_eq!(config.keepalive_count_max, 3);
         assert_eq!(config.address.as_str(), "192.168.1.1:22");
         assert_eq!(config.host.as_str(), "192.168.1.1");
         assert!(config.username.is_empty());
@@ -157,6 +198,7 @@ mod test {
         assert_eq!(config.connection_timeout, Duration::from_secs(10));
         assert_eq!(config.host.as_str(), "192.168.1.1");
         assert_eq!(config.address.as_str(), "192.168.1.1:2222");
+        assert_eq!(config.port, 2222);
         assert_eq!(config.username.as_str(), "omar");
         assert_eq!(
             config.params,
@@ -201,4 +243,41 @@ mod test {
             HostParams::new(&DefaultAlgorithms::default())
         );
     }
+
+    #[test]
+    fn should_resolve_keepalive_from_opts() {
+        let opts = SshOpts::new("192.168.1.1")
+            .keepalive_interval(Duration::from_secs(20))
+            .keepalive_count_max(5);
+        let config = Config::try_from(&opts).ok().unwrap();
+        assert_eq!(config.keepalive_interval, Some(Duration::from_secs(20)));
+        assert_eq!(config.keepalive_count_max, 5);
+        // zero disables keepalive
+        let opts = SshOpts::new("192.168.1.1").keepalive_interval(Duration::ZERO);
+        let config = Config::try_from(&opts).ok().unwrap();
+        assert!(config.keepalive_interval.is_none());
+    }
+
+    #[test]
+    fn should_resolve_keepalive_from_file() {
+        let mut config_file = tempfile::NamedTempFile::new().unwrap();
+        writeln!(
+            config_file,
+            "Host sftp\n  ServerAliveInterval 30\n  ServerAliveCountMax 6"
+        )
+        .unwrap();
+        let opts = SshOpts::new("sftp")
+            .config_file(config_file.path(), ParseRule::ALLOW_UNSUPPORTED_FIELDS);
+        let config = Config::try_from(&opts).ok().unwrap();
+        assert_eq!(config.keepalive_interval, Some(Duration::from_secs(30)));
+        assert_eq!(config.keepalive_count_max, 6);
+        // opts have priority
+        let opts = SshOpts::new("sftp")
+            .config_file(config_file.path(), ParseRule::ALLOW_UNSUPPORTED_FIELDS)
+            .keepalive_interval(Duration::from_secs(10))
+            .keepalive_count_max(2);
+        let config = Config::try_from(&opts).ok().unwrap();
+        assert_eq!(config.keepalive_interval, Some(Duration::from_secs(10)));
+        assert_eq!(config.keepalive_count_max, 2);
+    }
 }
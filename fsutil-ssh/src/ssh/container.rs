@@ -31,7 +31,7 @@ impl Image for OpensshServerImage {
             ("PUID", "1000"),
             ("PGID", "1000"),
             ("TZ", "Europe/London"),
-            ("SUDO_ACCESS", "false"),
+            ("SUDO_ACCESS", "true"),
             ("PASSWORD_ACCESS", "true"),
             (
                 "PUBLIC_KEY",
@@ -0,0 +1,395 @@
+//! ## Exec
+//!
+//! streaming command execution over an ssh exec channel
+
+use std::io::{self, Read, Write};
+use std::time::Duration;
+
+use fsutil_core::{RemoteError, RemoteErrorType, RemoteResult};
+use ssh2::{Channel, Session, Stream};
+
+/// Stream id of the stderr extended data
+const STDERR_STREAM_ID: i32 = 1;
+/// Interval between reads while waiting for output on both stdout and stderr
+const DRAIN_POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+// -- options
+
+/// Pseudo terminal configuration for [`ExecHandle`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PtyOptions {
+    /// Terminal type (e.g. `xterm`)
+    term: String,
+    /// Terminal width in characters
+    width: u32,
+    /// Terminal height in characters
+    height: u32,
+}
+
+impl Default for PtyOptions {
+    fn default() -> Self {
+        Self {
+            term: String::from("xterm"),
+            width: 80,
+            height: 24,
+        }
+    }
+}
+
+impl PtyOptions {
+    /// Set terminal type
+    pub fn term<S: AsRef<str>>(mut self, term: S) -> Self {
+        self.term = term.as_ref().to_string();
+        self
+    }
+
+    /// Set terminal size in characters
+    pub fn size(mut self, width: u32, height: u32) -> Self {
+        self.width = width;
+        self.height = height;
+        self
+    }
+}
+
+/// Options used to spawn a command with [`ExecHandle`]
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ExecOptions {
+    /// Pseudo terminal to allocate; if `None` no pty is requested
+    pty: Option<PtyOptions>,
+}
+
+impl ExecOptions {
+    /// Allocate a pseudo terminal for the command.
+    ///
+    /// When a pty is allocated, the remote side merges stderr into stdout
+    pub fn pty(mut self, pty: PtyOptions) -> Self {
+        self.pty = Some(pty);
+        self
+    }
+}
+
+// -- handle
+
+/// Handle to a command running on the remote host.
+///
+/// Output can be consumed as it arrives through [`ExecHandle::stdout`] and [`ExecHandle::stderr`],
+/// while input can be provided through [`ExecHandle::stdin`].
+///
+/// ### ⚠️ Warning
+///
+/// stdout and stderr share the same channel window: if the command writes a lot on stderr,
+/// stderr must be drained as well, otherwise the remote process may block.
+pub struct ExecHandle {
+    session: Session,
+    channel: Channel,
+    stdin_closed: bool,
+}
+
+impl ExecHandle {
+    /// Open a new channel on `session` and execute `cmd` on it
+    pub(crate) fn spawn(
+        session: &mut Session,
+        cmd: &str,
+        opts: &ExecOptions,
+    ) -> RemoteResult<Self> {
+        trace!("Spawning command: {}", cmd);
+        let mut channel = session.channel_session().map_err(|err| {
+            RemoteError::new_ex(
+                RemoteErrorType::ProtocolError,
+                format!("Could not open channel: {err}"),
+            )
+        })?;
+        if let Some(pty) = opts.pty.as_ref() {
+            trace!("Requesting pty {} ({}x{})", pty.term, pty.width, pty.height);
+            channel
+                .request_pty(pty.term.as_str(), None, Some((pty.width, pty.height, 0, 0)))
+                .map_err(|err| {
+                    RemoteError::new_ex(
+                        RemoteErrorType::ProtocolError,
+                        format!("Could not allocate pty: {err}"),
+                    )
+                })?;
+        }
+        channel.exec(cmd).map_err(|err| {
+            RemoteError::new_ex(
+                RemoteErrorType::ProtocolError,
+                format!("Could not execute command \"{cmd}\": {err}"),
+            )
+        })?;
+        Ok(Self {
+            session: session.clone(),
+            channel,
+            stdin_closed: false,
+        })
+    }
+
+    /// Get a reader for the command stdout
+    pub fn stdout(&self) -> ExecReader {
+        ExecReader {
+            stream: self.channel.stream(0),
+        }
+    }
+
+    /// Get a reader for the command stderr
+    pub fn stderr(&self) -> ExecReader {
+        ExecReader {
+            stream: self.channel.stream(STDERR_STREAM_ID),
+        }
+    }
+
+    /// Get a writer for the command stdin
+    pub fn stdin(&self) -> ExecWriter {
+        ExecWriter {
+            stream: self.channel.stream(0),
+        }
+    }
+
+    /// Close stdin, sending EOF to the remote process.
+    /// Calling this method more than once has no effect
+    pub fn close_stdin(&mut self) -> RemoteResult<()> {
+        if self.stdin_closed {
+            return Ok(());
+        }
+        trace!("Sending EOF to remote process");
+        self.channel
+            .send_eof()
+            .map_err(|err| RemoteError::new_ex(RemoteErrorType::ProtocolError, err))?;
+        self.stdin_closed = true;
+        Ok(())
+    }
+
+    /// Deliver a signal to the remote process.
+    /// `signal` is the signal name without the `SIG` prefix (e.g. `TERM`, `INT`, `KILL`).
+    ///
+    /// The server must support the `signal` channel request (e.g. OpenSSH 8.1 or later),
+    /// otherwise [`RemoteErrorType::UnsupportedFeature`] is returned
+    pub fn signal(&mut self, signal: &str) -> RemoteResult<()> {
+        debug!("Sending signal {} to remote process", signal);
+        self.channel
+            .process_startup("signal", Some(signal))
+            .map_err(|err| {
+                error!("Could not deliver signal {}: {}", signal, err);
+                RemoteError::new_ex(RemoteErrorType::UnsupportedFeature, err)
+            })
+    }
+
+    /// Returns whether the remote process has closed its output
+    pub fn eof(&self) -> bool {
+        self.channel.eof()
+    }
+
+    /// Close stdin and wait for the remote process to terminate, then return its exit code.
+    /// Any output which has not been consumed yet is discarded
+    pub fn wait(self) -> RemoteResult<u32> {
+        self.wait_with_output().map(|(rc, _, _)| rc)
+    }
+
+    /// Close stdin and wait for the remote process to terminate, then return its exit code
+    /// and the output on stdout and stderr which has not been consumed yet.
+    ///
+    /// stdout and stderr are drained at the same time, so the remote process never blocks on either
+    pub fn wait_with_output(mut self) -> RemoteResult<(u32, String, String)> {
+        self.close_stdin()?;
+        let (stdout, stderr) = self
+            .drain_output()
+            .map_err(|err| RemoteError::new_ex(RemoteErrorType::IoError, err))?;
+        self.channel
+            .wait_close()
+            .map_err(|err| RemoteError::new_ex(RemoteErrorType::ProtocolError, err))?;
+        let rc = self
+            .channel
+            .exit_status()
+            .map_err(|err| RemoteError::new_ex(RemoteErrorType::ProtocolError, err))?;
+        debug!("Remote process exited with code {}", rc);
+        Ok((
+            rc as u32,
+            String::from_utf8_lossy(&stdout).to_string(),
+            String::from_utf8_lossy(&stderr).to_string(),
+        ))
+    }
+
+    /// Read stdout and stderr until the remote process closes them.
+    /// The session is switched to non-blocking mode meanwhile, so that reading one stream never waits
+    /// for data while the other one is filling the channel window
+    fn drain_output(&mut self) -> io::Result<(Vec<u8>, Vec<u8>)> {
+        self.session.set_blocking(false);
+        let result = self.drain_output_nonblocking();
+        self.session.set_blocking(true);
+        result
+    }
+
+    fn drain_output_nonblocking(&mut self) -> io::Result<(Vec<u8>, Vec<u8>)> {
+        let mut stdout = Vec::new();
+        let mut stderr = Vec::new();
+        let mut buffer = [0u8; 8192];
+        loop {
+            let mut progress = false;
+            for (id, output) in [(0, &mut stdout), (STDERR_STREAM_ID, &mut stderr)] {
+                match self.channel.stream(id).read(&mut buffer) {
+                    Ok(0) => {}
+                    Ok(bytes) => {
+                        output.extend_from_slice(&buffer[..bytes]);
+                        progress = true;
+                    }
+                    Err(err) if err.kind() == io::ErrorKind::WouldBlock => {}
+                    Err(err) => return Err(err),
+                }
+            }
+            if !progress {
+                // eof is reported only once there is no data left to read
+                if self.channel.eof() {
+                    return Ok((stdout, stderr));
+                }
+                std::thread::sleep(DRAIN_POLL_INTERVAL);
+            }
+        }
+    }
+}
+
+// -- streams
+
+/// Reader for stdout or stderr of a command spawned with [`ExecHandle`]
+pub struct ExecReader {
+    stream: Stream,
+}
+
+impl Read for ExecReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.stream.read(buf)
+    }
+}
+
+/// Writer for stdin of a command spawned with [`ExecHandle`]
+pub struct ExecWriter {
+    stream: Stream,
+}
+
+impl Write for ExecWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.stream.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.stream.flush()
+    }
+}
+
+#[cfg(test)]
+mod test {
+
+    use pretty_assertions::assert_eq;
+
+    use super::*;
+    use crate::ssh::commons;
+    use crate::SshOpts;
+
+    #[test]
+    fn should_build_exec_options() {
+        let opts = ExecOptions::default();
+        assert!(opts.pty.is_none());
+        let opts = ExecOptions::default().pty(PtyOptions::default().term("vt100").size(132, 50));
+        let pty = opts.pty.unwrap();
+        assert_eq!(pty.term.as_str(), "vt100");
+        assert_eq!(pty.width, 132);
+        assert_eq!(pty.height, 50);
+    }
+
+    #[test]
+    fn should_stream_command_output() {
+        crate::mock::logger();
+        let container = crate::ssh::container::OpensshServer::start();
+        let port = container.port();
+
+        let opts = SshOpts::new("127.0.0.1")
+            .port(port)
+            .username("sftp")
+            .password("password");
+        let (mut session, _) = commons::connect(&opts).unwrap();
+        let handle = ExecHandle::spawn(
+            &mut session,
+            "echo hello; echo oops 1>&2; exit 3",
+            &ExecOptions::default(),
+        )
+        .unwrap();
+        let mut stdout = String::new();
+        handle.stdout().read_to_string(&mut stdout).unwrap();
+        let mut stderr = String::new();
+        handle.stderr().read_to_string(&mut stderr).unwrap();
+        assert_eq!(stdout.as_str(), "hello\n");
+        assert_eq!(stderr.as_str(), "oops\n");
+        assert_eq!(handle.wait().unwrap(), 3);
+    }
+
+    #[test]
+    fn should_write_to_command_stdin() {
+        crate::mock::logger();
+        let container = crate::ssh::container::OpensshServer::start();
+        let port = container.port();
+
+        let opts = SshOpts::new("127.0.0.1")
+            .port(port)
+            .username("sftp")
+            .password("password");
+        let (mut session, _) = commons::connect(&opts).unwrap();
+        let mut handle = ExecHandle::spawn(&mut session, "cat", &ExecOptions::default()).unwrap();
+        handle.stdin().write_all(b"ping\n").unwrap();
+        handle.close_stdin().unwrap();
+        let mut stdout = String::new();
+        handle.stdout().read_to_string(&mut stdout).unwrap();
+        assert_eq!(stdout.as_str(), "ping\n");
+        assert_eq!(handle.wait().unwrap(), 0);
+    }
+
+    #[test]
+    fn should_wait_with_output() {
+        crate::mock::logger();
+        let container = crate::ssh::container::OpensshServer::start();
+        let port = container.port();
+
+        let opts = SshOpts::new("127.0.0.1")
+            .port(port)
+            .username("sftp")
+            .password("password");
+        let (mut session, _) = commons::connect(&opts).unwrap();
+        // more than the channel window on stderr, before anything is written on stdout
+        let handle = ExecHandle::spawn(
+            &mut session,
+            "head -c 4194304 /dev/zero | tr '\\0' x >&2; echo done; exit 2",
+            &ExecOptions::default(),
+        )
+        .unwrap();
+        let (rc, stdout, stderr) = handle.wait_with_output().unwrap();
+        assert_eq!(rc, 2);
+        assert_eq!(stdout.as_str(), "done\n");
+        assert_eq!(stderr.len(), 4194304);
+        // the session is usable in blocking mode afterwards
+        assert!(session.is_blocking());
+        assert_eq!(
+            ExecHandle::spawn(&mut session, "true", &ExecOptions::default())
+                .unwrap()
+                .wait()
+                .unwrap(),
+            0
+        );
+    }
+
+    #[test]
+    fn should_exec_with_pty() {
+        crate::mock::logger();
+        let container = crate::ssh::container::OpensshServer::start();
+        let port = container.port();
+
+        let opts = SshOpts::new("127.0.0.1")
+            .port(port)
+            .username("sftp")
+            .password("password");
+        let (mut session, _) = commons::connect(&opts).unwrap();
+        let handle = ExecHandle::spawn(
+            &mut session,
+            "test -t 1",
+            &ExecOptions::default().pty(PtyOptions::default()),
+        )
+        .unwrap();
+        assert_eq!(handle.wait().unwrap(), 0);
+    }
+}
@@ -0,0 +1,122 @@
+//! ## Keepalive
+//!
+//! keepalive and liveness checks for ssh sessions
+
+use std::time::Duration;
+
+use fsutil_core::{RemoteError, RemoteErrorType, RemoteResult};
+use ssh2::Session;
+
+use super::config::Config;
+
+/// Keepalive settings of a connected session
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Keepalive {
+    /// Interval between keepalive messages; `None` if keepalive is disabled
+    interval: Option<Duration>,
+    /// Time the server is given to answer a liveness probe
+    probe_timeout: Duration,
+}
+
+impl Keepalive {
+    /// Configure keepalive on `session` as resolved in `config`.
+    ///
+    /// The server is considered dead if it doesn't answer within `ServerAliveInterval * ServerAliveCountMax`;
+    /// when keepalive is disabled, the connection timeout is used instead
+    pub fn setup(session: &Session, config: &Config) -> Self {
+        let probe_timeout = match config.keepalive_interval {
+            Some(interval) => {
+                debug!(
+                    "Sending keepalive every {}s (max {} missed)",
+                    interval.as_secs(),
+                    config.keepalive_count_max
+                );
+                session.set_keepalive(true, interval.as_secs() as u32);
+                interval * config.keepalive_count_max.max(1)
+            }
+            None => config.connection_timeout,
+        };
+        Self {
+            interval: config.keepalive_interval,
+            probe_timeout,
+        }
+    }
+
+    /// Send a keepalive message to the server, if one is due.
+    ///
+    /// Returns the time before the next keepalive message is due;
+    /// if keepalive is disabled, nothing is sent and `None` is returned
+    pub fn send(&self, session: &Session) -> RemoteResult<Option<Duration>> {
+        if self.interval.is_none() {
+            return Ok(None);
+        }
+        session
+            .keepalive_send()
+            .map(|secs| Some(Duration::from_secs(secs as u64)))
+            .map_err(|e| {
+                error!("Failed to send keepalive: {}", e);
+                RemoteError::new_ex(RemoteErrorType::ConnectionError, e)
+            })
+    }
+
+    /// Run `probe`, which must perform a round trip with the server, bounding it to the probe timeout.
+    ///
+    /// Returns whether the server answered
+    pub fn probe<F>(&self, session: &Session, probe: F) -> bool
+    where
+        F: FnOnce() -> bool,
+    {
+        let timeout = session.timeout();
+        session.set_timeout(self.probe_timeout.as_millis().min(u32::MAX as u128) as u32);
+        let alive = probe();
+        session.set_timeout(timeout);
+        if !alive {
+            warn!(
+                "Server didn't answer within {}s; session is dead",
+                self.probe_timeout.as_secs()
+            );
+        }
+        alive
+    }
+}
+
+#[cfg(test)]
+mod test {
+
+    use pretty_assertions::assert_eq;
+
+    use super::*;
+    use crate::SshOpts;
+
+    #[test]
+    fn should_setup_keepalive() {
+        let session = Session::new().unwrap();
+        let opts = SshOpts::new("localhost")
+            .keepalive_interval(Duration::from_secs(15))
+            .keepalive_count_max(4);
+        let keepalive = Keepalive::setup(&session, &Config::try_from(&opts).unwrap());
+        assert_eq!(keepalive.interval, Some(Duration::from_secs(15)));
+        assert_eq!(keepalive.probe_timeout, Duration::from_secs(60));
+    }
+
+    #[test]
+    fn should_use_connection_timeout_to_probe_without_keepalive() {
+        let session = Session::new().unwrap();
+        let opts = SshOpts::new("localhost").connection_timeout(Duration::from_secs(10));
+        let keepalive = Keepalive::setup(&session, &Config::try_from(&opts).unwrap());
+        assert!(keepalive.interval.is_none());
+        assert_eq!(keepalive.probe_timeout, Duration::from_secs(10));
+        assert_eq!(keepalive.send(&session).unwrap(), None);
+    }
+
+    #[test]
+    fn should_restore_timeout_after_probe() {
+        let session = Session::new().unwrap();
+        session.set_timeout(1000);
+        let opts = SshOpts::new("localhost").connection_timeout(Duration::from_secs(10));
+        let keepalive = Keepalive::setup(&session, &Config::try_from(&opts).unwrap());
+        assert!(keepalive.probe(&session, || session.timeout() == 10_000));
+        assert!(!keepalive.probe(&session, || false));
+        assert_eq!(session.timeout(), 1000);
+    }
+}
@@ -0,0 +1,473 @@
+//! ## Key storage
+//!
+//! built-in implementations of `SshKeyStorage`
+
+use std::collections::HashMap;
+use std::fmt;
+use std::fs::File;
+use std::io::BufReader;
+use std::path::{Path, PathBuf};
+
+use fsutil_core::{RemoteError, RemoteErrorType, RemoteResult};
+use ssh2_config::{ParseRule, SshConfig};
+
+use super::SshKeyStorage;
+
+/// Default identity files, in the order ssh tries them
+const DEFAULT_IDENTITIES: &[&str] = &[
+    "id_rsa",
+    "id_ecdsa",
+    "id_ecdsa_sk",
+    "id_ed25519",
+    "id_ed25519_sk",
+    "id_xmss",
+    "id_dsa",
+];
+
+/// A private key to authenticate with
+#[derive(Clone, PartialEq, Eq)]
+pub enum SshKey {
+    /// Private key stored at path
+    File(PathBuf),
+    /// Private key held in memory, e.g. injected from a secrets vault
+    Memory {
+        /// Private key in PEM or OpenSSH format
+        private_key: String,
+        /// Public key; computed from the private key if `None`
+        public_key: Option<String>,
+        /// Passphrase to decrypt the private key with; if `None` the password of the [`super::SshOpts`] is used
+        passphrase: Option<String>,
+    },
+}
+
+impl SshKey {
+    /// Create a new in-memory key from its private key
+    pub fn memory<S: AsRef<str>>(private_key: S) -> Self {
+        Self::Memory {
+            private_key: private_key.as_ref().to_string(),
+            public_key: None,
+            passphrase: None,
+        }
+    }
+
+    /// Set the passphrase of an in-memory key. Has no effect on keys stored at path
+    pub fn passphrase<S: AsRef<str>>(mut self, passphrase: S) -> Self {
+        if let Self::Memory { passphrase: p, .. } = &mut self {
+            *p = Some(passphrase.as_ref().to_string());
+        }
+        self
+    }
+
+    /// Set the public key of an in-memory key. Has no effect on keys stored at path
+    pub fn public_key<S: AsRef<str>>(mut self, public_key: S) -> Self {
+        if let Self::Memory { public_key: p, .. } = &mut self {
+            *p = Some(public_key.as_ref().to_string());
+        }
+        self
+    }
+}
+
+impl From<PathBuf> for SshKey {
+    fn from(p: PathBuf) -> Self {
+        Self::File(p)
+    }
+}
+
+impl fmt::Debug for SshKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // never print key material
+        match self {
+            Self::File(p) => f.debug_tuple("File").field(p).finish(),
+            Self::Memory { .. } => f.write_str("Memory(..)"),
+        }
+    }
+}
+
+// -- dir
+
+/// Key storage which scans a directory (`~/.ssh` by default) for `id_*` private keys,
+/// regardless of host and username.
+///
+/// The default identities are returned first, in the same order ssh tries them; then all the other `id_*` files.
+pub struct DirKeyStorage {
+    dir: PathBuf,
+}
+
+impl DirKeyStorage {
+    /// Create a new [`DirKeyStorage`] scanning `dir`
+    pub fn new<P: AsRef<Path>>(dir: P) -> Self {
+        Self {
+            dir: dir.as_ref().to_path_buf(),
+        }
+    }
+
+    /// Create a new [`DirKeyStorage`] scanning `~/.ssh`.
+    /// Returns `None` if the home directory couldn't be determined
+    pub fn home() -> Option<Self> {
+        std::env::home_dir().map(|home| Self::new(home.join(".ssh")))
+    }
+
+    /// Scan directory for private keys
+    fn scan(&self) -> Vec<PathBuf> {
+        let entries = match std::fs::read_dir(self.dir.as_path()) {
+            Ok(entries) => entries,
+            Err(err) => {
+                debug!("Could not scan {} for keys: {}", self.dir.display(), err);
+                return Vec::new();
+            }
+        };
+        let mut names: Vec<String> = entries
+            .flatten()
+            .filter(|x| x.path().is_file())
+            .filter_map(|x| x.file_name().to_str().map(String::from))
+            .filter(|x| x.starts_with("id_") && !x.ends_with(".pub"))
+            .collect();
+        names.sort_by_key(|name| {
+            let rank = DEFAULT_IDENTITIES
+                .iter()
+                .position(|x| x == name)
+                .unwrap_or(DEFAULT_IDENTITIES.len());
+            (rank, name.clone())
+        });
+        names.into_iter().map(|x| self.dir.join(x)).collect()
+    }
+}
+
+impl SshKeyStorage for DirKeyStorage {
+    fn resolve(&self, _host: &str, _username: &str) -> Option<PathBuf> {
+        self.scan().into_iter().next()
+    }
+
+    fn resolve_keys(&self, _host: &str, _username: &str) -> Vec<SshKey> {
+        self.scan().into_iter().map(SshKey::File).collect()
+    }
+}
+
+// -- config
+
+/// Key storage which resolves keys from the `IdentityFile` list of the host in a ssh configuration.
+///
+/// The `%d`, `%h`, `%r` and `%%` tokens are expanded in paths; keys which don't exist are skipped
+pub struct ConfigKeyStorage {
+    config: SshConfig,
+}
+
+impl ConfigKeyStorage {
+    /// Parse ssh configuration at `p` with `rules`
+    pub fn new<P: AsRef<Path>>(p: P, rules: ParseRule) -> RemoteResult<Self> {
+        let p = p.as_ref();
+        let mut reader = BufReader::new(File::open(p).map_err(|e| {
+            RemoteError::new_ex(
+                RemoteErrorType::IoError,
+                format!("Could not open configuration file: {e}"),
+            )
+        })?);
+        SshConfig::default()
+            .parse(&mut reader, rules)
+            .map(Self::from)
+            .map_err(|e| {
+                RemoteError::new_ex(
+                    RemoteErrorType::IoError,
+                    format!("Could not parse configuration file: {e}"),
+                )
+            })
+    }
+
+    /// Expand tokens in identity file path
+    fn expand(p: &Path, host: &str, username: &str) -> PathBuf {
+        let p = p.to_string_lossy();
+        if !p.contains('%') {
+            return PathBuf::from(p.as_ref());
+        }
+        let home = std::env::home_dir()
+            .map(|x| x.to_string_lossy().to_string())
+            .unwrap_or_default();
+        let mut expanded = String::with_capacity(p.len());
+        let mut chars = p.chars();
+        while let Some(c) = chars.next() {
+            if c != '%' {
+                expanded.push(c);
+                continue;
+            }
+            match chars.next() {
+                Some('d') => expanded.push_str(home.as_str()),
+                Some('h') => expanded.push_str(host),
+                Some('r') => expanded.push_str(username),
+                Some('%') => expanded.push('%'),
+                Some(other) => {
+                    expanded.push('%');
+                    expanded.push(other);
+                }
+                None => expanded.push('%'),
+            }
+        }
+        PathBuf::from(expanded)
+    }
+
+    /// Get identity files for host
+    fn identity_files(&self, host: &str, username: &str) -> Vec<PathBuf> {
+        self.config
+            .query(host)
+            .identity_file
+            .unwrap_or_default()
+            .iter()
+            .map(|p| Self::expand(p, host, username))
+            .filter(|p| p.exists())
+            .collect()
+    }
+}
+
+impl From<SshConfig> for ConfigKeyStorage {
+    fn from(config: SshConfig) -> Self {
+        Self { config }
+    }
+}
+
+impl SshKeyStorage for ConfigKeyStorage {
+    fn resolve(&self, host: &str, username: &str) -> Option<PathBuf> {
+        self.identity_files(host, username).into_iter().next()
+    }
+
+    fn resolve_keys(&self, host: &str, username: &str) -> Vec<SshKey> {
+        self.identity_files(host, username)
+            .into_iter()
+            .map(SshKey::File)
+            .collect()
+    }
+}
+
+// -- memory
+
+/// Key storage which holds keys by host and username.
+///
+/// Use `*` as host or username to match any host or username
+#[derive(Default)]
+pub struct MemoryKeyStorage {
+    keys: HashMap<(String, String), Vec<SshKey>>,
+}
+
+impl MemoryKeyStorage {
+    /// Add `key` for `username` at `host`
+    pub fn key<S: AsRef<str>>(mut self, host: S, username: S, key: SshKey) -> Self {
+        self.insert(host, username, key);
+        self
+    }
+
+    /// Add `key` for `username` at `host`
+    pub fn insert<S: AsRef<str>>(&mut self, host: S, username: S, key: SshKey) {
+        self.keys
+            .entry((host.as_ref().to_string(), username.as_ref().to_string()))
+            .or_default()
+            .push(key);
+    }
+
+    /// Iterate over keys for host and username, most specific first
+    fn keys<'a>(&'a self, host: &'a str, username: &'a str) -> impl Iterator<Item = &'a SshKey> {
+        [(host, username), (host, "*"), ("*", username), ("*", "*")]
+            .into_iter()
+            .filter_map(|(h, u)| self.keys.get(&(h.to_string(), u.to_string())))
+            .flatten()
+    }
+}
+
+impl SshKeyStorage for MemoryKeyStorage {
+    fn resolve(&self, host: &str, username: &str) -> Option<PathBuf> {
+        self.keys(host, username).find_map(|key| match key {
+            SshKey::File(p) => Some(p.clone()),
+            SshKey::Memory { .. } => None,
+        })
+    }
+
+    fn resolve_keys(&self, host: &str, username: &str) -> Vec<SshKey> {
+        self.keys(host, username).cloned().collect()
+    }
+}
+
+// -- chained
+
+/// Key storage which tries several storages in order
+#[derive(Default)]
+pub struct ChainedKeyStorage {
+    storages: Vec<Box<dyn SshKeyStorage>>,
+}
+
+impl ChainedKeyStorage {
+    /// Append `storage` to the chain
+    pub fn storage(mut self, storage: Box<dyn SshKeyStorage>) -> Self {
+        self.storages.push(storage);
+        self
+    }
+}
+
+impl SshKeyStorage for ChainedKeyStorage {
+    fn resolve(&self, host: &str, username: &str) -> Option<PathBuf> {
+        self.storages.iter().find_map(|x| x.resolve(host, username))
+    }
+
+    fn resolve_keys(&self, host: &str, username: &str) -> Vec<SshKey> {
+        let mut keys: Vec<SshKey> = Vec::new();
+        for key in self
+            .storages
+            .iter()
+            .flat_map(|x| x.resolve_keys(host, username))
+        {
+            if !keys.contains(&key) {
+                keys.push(key);
+            }
+        }
+        keys
+    }
+}
+
+#[cfg(test)]
+mod test {
+
+    use std::io::Write;
+
+    use pretty_assertions::assert_eq;
+    use tempfile::TempDir;
+
+    use super::*;
+
+    #[test]
+    fn should_scan_dir_for_keys() {
+        let dir = TempDir::new().unwrap();
+        for name in [
+            "id_ed25519",
+            "id_ed25519.pub",
+            "id_rsa",
+            "id_work",
+            "known_hosts",
+            "config",
+        ] {
+            std::fs::write(dir.path().join(name), b"key").unwrap();
+        }
+        std::fs::create_dir(dir.path().join("id_dir")).unwrap();
+        let storage = DirKeyStorage::new(dir.path());
+        assert_eq!(
+            storage.resolve_keys("example.com", "user"),
+            vec![
+                SshKey::File(dir.path().join("id_rsa")),
+                SshKey::File(dir.path().join("id_ed25519")),
+                SshKey::File(dir.path().join("id_work")),
+            ]
+        );
+        assert_eq!(
+            storage.resolve("example.com", "user"),
+            Some(dir.path().join("id_rsa"))
+        );
+        assert!(DirKeyStorage::new(dir.path().join("missing"))
+            .resolve_keys("example.com", "user")
+            .is_empty());
+    }
+
+    #[test]
+    fn should_resolve_keys_from_config() {
+        let dir = TempDir::new().unwrap();
+        std::fs::write(dir.path().join("deploy"), b"key").unwrap();
+        std::fs::write(dir.path().join("example.com-omar"), b"key").unwrap();
+        let mut config = tempfile::NamedTempFile::new().unwrap();
+        writeln!(
+            config,
+            "Host example.com\n    IdentityFile {dir}/missing {dir}/deploy {dir}/%h-%r",
+            dir = dir.path().display()
+        )
+        .unwrap();
+        let storage = ConfigKeyStorage::new(config.path(), ParseRule::STRICT).unwrap();
+        assert_eq!(
+            storage.resolve_keys("example.com", "omar"),
+            vec![
+                SshKey::File(dir.path().join("deploy")),
+                SshKey::File(dir.path().join("example.com-omar")),
+            ]
+        );
+        assert!(storage.resolve_keys("other.com", "omar").is_empty());
+        assert!(ConfigKeyStorage::new(dir.path().join("missing"), ParseRule::STRICT).is_err());
+    }
+
+    #[test]
+    fn should_expand_identity_file_tokens() {
+        assert_eq!(
+            ConfigKeyStorage::expand(Path::new("/keys/%h/%r_100%%_%x"), "host", "user"),
+            PathBuf::from("/keys/host/user_100%_%x")
+        );
+    }
+
+    #[test]
+    fn should_resolve_keys_from_memory() {
+        let storage = MemoryKeyStorage::default()
+            .key("*", "*", SshKey::File(PathBuf::from("/keys/any")))
+            .key(
+                "example.com",
+                "omar",
+                SshKey::memory("secret").passphrase("pass"),
+            )
+            .key(
+                "example.com",
+                "*",
+                SshKey::File(PathBuf::from("/keys/host")),
+            );
+        assert_eq!(
+            storage.resolve_keys("example.com", "omar"),
+            vec![
+                SshKey::Memory {
+                    private_key: "secret".to_string(),
+                    public_key: None,
+                    passphrase: Some("pass".to_string()),
+                },
+                SshKey::File(PathBuf::from("/keys/host")),
+                SshKey::File(PathBuf::from("/keys/any")),
+            ]
+        );
+        assert_eq!(
+            storage.resolve("example.com", "omar"),
+            Some(PathBuf::from("/keys/host"))
+        );
+        assert_eq!(
+            storage.resolve_keys("other.com", "omar"),
+            vec![SshKey::File(PathBuf::from("/keys/any"))]
+        );
+    }
+
+    #[test]
+    fn should_not_print_key_material() {
+        assert_eq!(
+            format!("{:?}", SshKey::memory("secret").passphrase("pass")),
+            "Memory(..)"
+        );
+    }
+
+    #[test]
+    fn should_chain_key_storages() {
+        let storage = ChainedKeyStorage::default()
+            .storage(Box::new(MemoryKeyStorage::default().key(
+                "example.com",
+                "omar",
+                SshKey::memory("secret"),
+            )))
+            .storage(Box::new(MemoryKeyStorage::default().key(
+                "*",
+                "*",
+                SshKey::File(PathBuf::from("/keys/any")),
+            )))
+            .storage(Box::new(MemoryKeyStorage::default().key(
+                "*",
+                "*",
+                SshKey::File(PathBuf::from("/keys/any")),
+            )));
+        assert_eq!(
+            storage.resolve_keys("example.com", "omar"),
+            vec![
+                SshKey::memory("secret"),
+                SshKey::File(PathBuf::from("/keys/any")),
+            ]
+        );
+        assert_eq!(
+            storage.resolve("example.com", "omar"),
+            Some(PathBuf::from("/keys/any"))
+        );
+        assert!(ChainedKeyStorage::default()
+            .resolve_keys("example.com", "omar")
+            .is_empty());
+    }
+}
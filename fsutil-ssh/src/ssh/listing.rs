@@ -0,0 +1,411 @@
+//! ## Listing
+//!
+//! machine-readable file listings for the SCP file system, built on the tools of the remote userland
+
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+use fsutil_core::fs::{FileType, Metadata, UnixPex};
+
+use crate::utils::shell as shell_utils;
+
+/// Command used to detect the remote userland; prints one of `gnu`, `busybox`, `bsd` or `unknown`
+pub const DETECT_USERLAND_CMD: &str =
+    "if find / -maxdepth 0 -printf '' >/dev/null 2>&1; then echo gnu; \
+elif stat -c '%f' / >/dev/null 2>&1; then echo busybox; \
+elif stat -f '%Xp' / >/dev/null 2>&1; then echo bsd; \
+else echo unknown; fi";
+
+/// `find -printf` format; each entry is made up of [`FIND_FIELDS`] NUL-terminated fields:
+/// type, mode, size, uid, gid, atime, mtime, symlink target, name
+const FIND_FORMAT: &str = r"%y\0%m\0%s\0%U\0%G\0%A@\0%T@\0%l\0%f\0";
+const FIND_FIELDS: usize = 9;
+
+/// Stat format for GNU-like `stat -c`: raw mode (hex), size, uid, gid, atime, mtime
+const STAT_C_FORMAT: &str = "%f %s %u %g %X %Y";
+/// Stat format for BSD `stat -f`: raw mode (hex), size, uid, gid, atime, mtime
+const STAT_F_FORMAT: &str = "%Xp %z %u %g %a %m";
+/// Each entry printed by the stat script is made up of [`STAT_FIELDS`] NUL-terminated fields:
+/// stat output, symlink target, path
+const STAT_FIELDS: usize = 3;
+
+/// File type bits of `st_mode`
+const S_IFMT: u32 = 0o170000;
+const S_IFDIR: u32 = 0o040000;
+const S_IFREG: u32 = 0o100000;
+const S_IFLNK: u32 = 0o120000;
+
+/// Flavour of the tools available on the remote host
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Userland {
+    /// GNU coreutils and findutils; `find -printf` is available
+    Gnu,
+    /// BusyBox or any other userland providing `stat -c`
+    Busybox,
+    /// BSD and macOS; `stat -f` is available
+    Bsd,
+    /// None of the above; `ls` output must be parsed
+    Unknown,
+}
+
+impl Userland {
+    /// Get userland from the output of [`DETECT_USERLAND_CMD`]
+    pub fn from_detect_output(output: &str) -> Self {
+        match output.trim() {
+            "gnu" => Self::Gnu,
+            "busybox" => Self::Busybox,
+            "bsd" => Self::Bsd,
+            _ => Self::Unknown,
+        }
+    }
+
+    /// Get the command to list the entries of directory at `dir`.
+    /// Returns `None` if `ls` must be used instead
+    pub fn list_cmd(&self, dir: &Path) -> Option<String> {
+        // list `dir/.`, so that the directory is resolved even if `dir` is a symlink
+        let dir = shell_utils::quote_path(dir.join(".").as_path());
+        match self {
+            Self::Gnu => Some(format!(
+                "find {dir} -mindepth 1 -maxdepth 1 -printf {}",
+                shell_utils::quote(FIND_FORMAT)
+            )),
+            Self::Busybox | Self::Bsd => Some(format!(
+                "find {dir} -mindepth 1 -maxdepth 1 -exec sh -c {} sh {{}} +",
+                shell_utils::quote(self.stat_script()?.as_str())
+            )),
+            Self::Unknown => None,
+        }
+    }
+
+    /// Get the command to stat the file at `path`; symbolic links are not followed.
+    /// Returns `None` if `ls` must be used instead
+    pub fn stat_cmd(&self, path: &Path) -> Option<String> {
+        let path = shell_utils::quote_path(path);
+        match self {
+            Self::Gnu => Some(format!(
+                "find {path} -maxdepth 0 -printf {}",
+                shell_utils::quote(FIND_FORMAT)
+            )),
+            Self::Busybox | Self::Bsd => Some(format!(
+                "sh -c {} sh {path}",
+                shell_utils::quote(self.stat_script()?.as_str())
+            )),
+            Self::Unknown => None,
+        }
+    }
+
+    /// Parse the output of [`Userland::list_cmd`] or [`Userland::stat_cmd`] into a list of
+    /// file names and their metadata.
+    ///
+    /// Entries which can't be parsed and special files (devices, pipes, sockets) are skipped
+    pub fn parse_entries(&self, output: &[u8]) -> Vec<(PathBuf, Metadata)> {
+        let mut fields: Vec<&[u8]> = output.split(|x| *x == 0).collect();
+        // output is NUL-terminated, so the last field is always empty
+        if fields.last().map(|x| x.is_empty()).unwrap_or(false) {
+            fields.pop();
+        }
+        let chunk_size = match self {
+            Self::Gnu => FIND_FIELDS,
+            Self::Busybox | Self::Bsd => STAT_FIELDS,
+            Self::Unknown => return Vec::new(),
+        };
+        if !fields.len().is_multiple_of(chunk_size) {
+            warn!(
+                "Listing output has {} fields, which is not a multiple of {}; last entry will be ignored",
+                fields.len(),
+                chunk_size
+            );
+        }
+        fields
+            .chunks_exact(chunk_size)
+            .filter_map(|entry| {
+                let parsed = match self {
+                    Self::Gnu => Self::parse_find_entry(entry),
+                    _ => Self::parse_stat_entry(entry),
+                };
+                if parsed.is_none() {
+                    warn!(
+                        "Could not parse listing entry: {:?}",
+                        entry
+                            .iter()
+                            .map(|x| String::from_utf8_lossy(x))
+                            .collect::<Vec<_>>()
+                    );
+                }
+                parsed.flatten()
+            })
+            .collect()
+    }
+
+    /// Script printing the stat entry of each argument
+    fn stat_script(&self) -> Option<String> {
+        let stat = match self {
+            Self::Busybox => format!("stat -c {}", shell_utils::quote(STAT_C_FORMAT)),
+            Self::Bsd => format!("stat -f {}", shell_utils::quote(STAT_F_FORMAT)),
+            Self::Gnu | Self::Unknown => return None,
+        };
+        Some(format!(
+            r#"for f; do m=$({stat} "$f") || exit 1; printf '%s\0' "$m" "$(readlink "$f" 2>/dev/null)" "$f"; done"#
+        ))
+    }
+
+    /// Parse an entry printed with [`FIND_FORMAT`].
+    /// Returns `Some(None)` for special files
+    fn parse_find_entry(entry: &[&[u8]]) -> Option<Option<(PathBuf, Metadata)>> {
+        let text = |i: usize| std::str::from_utf8(entry[i]).ok();
+        let file_type = match text(0)? {
+            "f" => FileType::File,
+            "d" => FileType::Directory,
+            "l" => FileType::Symlink,
+            _ => return Some(None),
+        };
+        let mode = u32::from_str_radix(text(1)?, 8).ok()?;
+        let metadata = Metadata {
+            accessed: parse_epoch(text(5)?),
+            created: None,
+            file_type,
+            gid: text(4)?.parse::<u32>().ok(),
+            mode: Some(UnixPex::from(mode)),
+            modified: parse_epoch(text(6)?),
+            size: text(2)?.parse::<u64>().ok()?,
+            symlink: symlink_target(file_type, entry[7]),
+            uid: text(3)?.parse::<u32>().ok(),
+        };
+        Some(Some((path_from_bytes(entry[8]), metadata)))
+    }
+
+    /// Parse an entry printed by [`Userland::stat_script`].
+    /// Returns `Some(None)` for special files
+    fn parse_stat_entry(entry: &[&[u8]]) -> Option<Option<(PathBuf, Metadata)>> {
+        let stat = std::str::from_utf8(entry[0]).ok()?;
+        let stat: Vec<&str> = stat.split_whitespace().collect();
+        if stat.len() != 6 {
+            return None;
+        }
+        let raw_mode = u32::from_str_radix(stat[0], 16).ok()?;
+        let file_type = match raw_mode & S_IFMT {
+            S_IFREG => FileType::File,
+            S_IFDIR => FileType::Directory,
+            S_IFLNK => FileType::Symlink,
+            _ => return Some(None),
+        };
+        let metadata = Metadata {
+            accessed: parse_epoch(stat[4]),
+            created: None,
+            file_type,
+            gid: stat[3].parse::<u32>().ok(),
+            // keep the special bits, as `find` does with `%m`
+            mode: Some(UnixPex::from(raw_mode & 0o7777)),
+            modified: parse_epoch(stat[5]),
+            size: stat[1].parse::<u64>().ok()?,
+            symlink: symlink_target(file_type, entry[1]),
+            uid: stat[2].parse::<u32>().ok(),
+        };
+        Some(Some((path_from_bytes(entry[2]), metadata)))
+    }
+}
+
+/// Parse a unix timestamp with an optional fractional part (e.g. `1700000000.123456789`)
+fn parse_epoch(s: &str) -> Option<SystemTime> {
+    let (secs, fraction) = s.split_once('.').unwrap_or((s, ""));
+    let secs = secs.parse::<u64>().ok()?;
+    let nanos = if fraction.is_empty() {
+        0
+    } else {
+        // keep nanoseconds precision; GNU find prints up to 10 digits
+        let digits: String = fraction.chars().take(9).collect();
+        format!("{digits:0<9}").parse::<u32>().ok()?
+    };
+    SystemTime::UNIX_EPOCH.checked_add(Duration::new(secs, nanos))
+}
+
+/// Get symlink target for entry of type `file_type`
+fn symlink_target(file_type: FileType, target: &[u8]) -> Option<PathBuf> {
+    match file_type {
+        FileType::Symlink if !target.is_empty() => Some(path_from_bytes(target)),
+        _ => None,
+    }
+}
+
+#[cfg(target_family = "unix")]
+fn path_from_bytes(bytes: &[u8]) -> PathBuf {
+    use std::ffi::OsStr;
+    use std::os::unix::ffi::OsStrExt as _;
+
+    PathBuf::from(OsStr::from_bytes(bytes))
+}
+
+#[cfg(target_os = "windows")]
+fn path_from_bytes(bytes: &[u8]) -> PathBuf {
+    PathBuf::from(String::from_utf8_lossy(bytes).to_string())
+}
+
+#[cfg(test)]
+mod test {
+
+    use pretty_assertions::assert_eq;
+
+    use super::*;
+
+    #[test]
+    fn should_get_userland_from_detect_output() {
+        assert_eq!(Userland::from_detect_output("gnu\n"), Userland::Gnu);
+        assert_eq!(Userland::from_detect_output("busybox\n"), Userland::Busybox);
+        assert_eq!(Userland::from_detect_output("bsd\n"), Userland::Bsd);
+        assert_eq!(Userland::from_detect_output("unknown\n"), Userland::Unknown);
+        assert_eq!(Userland::from_detect_output(""), Userland::Unknown);
+    }
+
+    #[test]
+    fn should_not_make_commands_for_unknown_userland() {
+        assert!(Userland::Unknown.list_cmd(Path::new("/tmp")).is_none());
+        assert!(Userland::Unknown.stat_cmd(Path::new("/tmp")).is_none());
+    }
+
+    #[test]
+    fn should_parse_find_entries() {
+        let output = b"f\x00644\x0010\x001000\x001000\x001700000000.5000000000\x001700000001.1234567890\x00\x00a b -> c.txt\x00\
+d\x00755\x004096\x000\x000\x001700000000\x001700000000\x00\x00dir\x00\
+l\x00777\x006\x001000\x001000\x001700000000.0\x001700000000.0\x00../a\nb\x00link\x00\
+p\x00644\x000\x000\x000\x000\x000\x00\x00fifo\x00";
+        let entries = Userland::Gnu.parse_entries(output);
+        assert_eq!(entries.len(), 3);
+        let (name, metadata) = &entries[0];
+        assert_eq!(name.as_path(), Path::new("a b -> c.txt"));
+        assert_eq!(metadata.file_type, FileType::File);
+        assert_eq!(metadata.mode.unwrap(), UnixPex::from(0o644));
+        assert_eq!(metadata.size, 10);
+        assert_eq!(metadata.uid, Some(1000));
+        assert_eq!(metadata.gid, Some(1000));
+        assert_eq!(
+            metadata.accessed.unwrap(),
+            SystemTime::UNIX_EPOCH + Duration::new(1700000000, 500_000_000)
+        );
+        assert_eq!(
+            metadata.modified.unwrap(),
+            SystemTime::UNIX_EPOCH + Duration::new(1700000001, 123_456_789)
+        );
+        assert!(metadata.symlink.is_none());
+        let (name, metadata) = &entries[1];
+        assert_eq!(name.as_path(), Path::new("dir"));
+        assert_eq!(metadata.file_type, FileType::Directory);
+        assert_eq!(metadata.mode.unwrap(), UnixPex::from(0o755));
+        let (name, metadata) = &entries[2];
+        assert_eq!(name.as_path(), Path::new("link"));
+        assert_eq!(metadata.file_type, FileType::Symlink);
+        assert_eq!(metadata.symlink.as_deref().unwrap(), Path::new("../a\nb"));
+    }
+
+    #[test]
+    fn should_parse_stat_entries() {
+        let output = b"81a4 10 1000 1000 1700000000 1700000001\x00\x00/tmp/a\nb.txt\x00\
+43ff 4096 0 0 1700000000 1700000000\x00\x00/tmp/dir\x00\
+a1ff 5 1000 1000 1700000000 1700000000\x00a.txt\x00/tmp/link\x00\
+2190 0 0 0 0 0\x00\x00/dev/null\x00";
+        for userland in [Userland::Busybox, Userland::Bsd] {
+            let entries = userland.parse_entries(output);
+            assert_eq!(entries.len(), 3);
+            let (name, metadata) = &entries[0];
+            assert_eq!(name.as_path(), Path::new("/tmp/a\nb.txt"));
+            assert_eq!(metadata.file_type, FileType::File);
+            assert_eq!(metadata.mode.unwrap(), UnixPex::from(0o644));
+            assert_eq!(metadata.size, 10);
+            assert_eq!(metadata.uid, Some(1000));
+            assert_eq!(metadata.gid, Some(1000));
+            assert_eq!(
+                metadata.modified.unwrap(),
+                SystemTime::UNIX_EPOCH + Duration::from_secs(1700000001)
+            );
+            let (_, metadata) = &entries[1];
+            assert_eq!(metadata.file_type, FileType::Directory);
+            // special bits don't break the permissions
+            assert_eq!(metadata.mode.unwrap(), UnixPex::from(0o777));
+            let (_, metadata) = &entries[2];
+            assert_eq!(metadata.file_type, FileType::Symlink);
+            assert_eq!(metadata.symlink.as_deref().unwrap(), Path::new("a.txt"));
+        }
+    }
+
+    #[test]
+    fn should_skip_bad_entries() {
+        assert!(Userland::Gnu.parse_entries(b"").is_empty());
+        assert!(Userland::Gnu.parse_entries(b"f\x00644\x00").is_empty());
+        assert!(Userland::Busybox
+            .parse_entries(b"81a4 10\x00\x00/tmp/a.txt\x00")
+            .is_empty());
+        assert!(Userland::Unknown.parse_entries(b"whatever").is_empty());
+    }
+
+    #[test]
+    fn should_parse_epoch() {
+        assert_eq!(
+            parse_epoch("1700000000").unwrap(),
+            SystemTime::UNIX_EPOCH + Duration::from_secs(1700000000)
+        );
+        assert_eq!(
+            parse_epoch("1700000000.25").unwrap(),
+            SystemTime::UNIX_EPOCH + Duration::new(1700000000, 250_000_000)
+        );
+        assert!(parse_epoch("-1").is_none());
+        assert!(parse_epoch("abc").is_none());
+    }
+
+    #[cfg(target_os = "linux")]
+    mod local {
+
+        use std::os::unix::fs::symlink;
+        use std::process::Command;
+
+        use pretty_assertions::assert_eq;
+
+        use super::*;
+
+        fn run(cmd: &str) -> Vec<u8> {
+            let output = Command::new("sh")
+                .arg("-c")
+                .arg(cmd)
+                .output()
+                .expect("failed to run sh");
+            assert!(output.status.success());
+            output.stdout
+        }
+
+        #[test]
+        fn should_list_local_directory() {
+            let tempdir = tempfile::tempdir().unwrap();
+            std::fs::write(tempdir.path().join("a b\n'$(x)'.txt"), b"hello").unwrap();
+            std::fs::create_dir(tempdir.path().join("dir")).unwrap();
+            symlink("dir", tempdir.path().join("link")).unwrap();
+            // GNU userland supports both find -printf and stat -c
+            for userland in [Userland::Gnu, Userland::Busybox] {
+                let cmd = userland.list_cmd(tempdir.path()).unwrap();
+                let mut entries: Vec<(String, Metadata)> = userland
+                    .parse_entries(&run(cmd.as_str()))
+                    .into_iter()
+                    .map(|(name, metadata)| {
+                        (
+                            name.file_name().unwrap().to_string_lossy().to_string(),
+                            metadata,
+                        )
+                    })
+                    .collect();
+                entries.sort_by(|a, b| a.0.cmp(&b.0));
+                assert_eq!(entries.len(), 3);
+                assert_eq!(entries[0].0.as_str(), "a b\n'$(x)'.txt");
+                assert_eq!(entries[0].1.file_type, FileType::File);
+                assert_eq!(entries[0].1.size, 5);
+                assert_eq!(entries[1].0.as_str(), "dir");
+                assert_eq!(entries[1].1.file_type, FileType::Directory);
+                assert_eq!(entries[2].0.as_str(), "link");
+                assert_eq!(entries[2].1.file_type, FileType::Symlink);
+                assert_eq!(entries[2].1.symlink.as_deref().unwrap(), Path::new("dir"));
+                // stat
+                let path = tempdir.path().join("link");
+                let cmd = userland.stat_cmd(path.as_path()).unwrap();
+                let entries = userland.parse_entries(&run(cmd.as_str()));
+                assert_eq!(entries.len(), 1);
+                assert_eq!(entries[0].1.file_type, FileType::Symlink);
+            }
+        }
+    }
+}
@@ -6,27 +6,56 @@
 use std::path::{Path, PathBuf};
 use std::time::Duration;
 
+use fsutil_core::Proxy;
+
 // -- modules
 mod commons;
 mod config;
 #[cfg(test)]
 mod container;
+mod exec;
+mod keepalive;
+mod key_storage;
+mod listing;
+mod pipeline;
 mod scp;
+mod scp_tree;
 mod sftp;
+mod sftp_ext;
 mod stream;
+mod sudo;
 // -- export
+pub use exec::{ExecHandle, ExecOptions, ExecReader, ExecWriter, PtyOptions};
+pub use key_storage::{
+    ChainedKeyStorage, ConfigKeyStorage, DirKeyStorage, MemoryKeyStorage, SshKey,
+};
+pub use pipeline::PipelineOpts;
 pub use scp::ScpFileSystem;
 pub use sftp::SftpFileSystem;
+pub use sftp_ext::{SftpExtension, StatVfs};
 pub use ssh2::MethodType as SshMethodType;
 pub use ssh2_config::ParseRule;
-use stream::{SftpReadStream, SftpWriteStream};
+use stream::{ExecWriteStatus, ExecWriteStream, SftpReadStream, SftpWriteStream};
+pub use sudo::Sudo;
 
 // -- Ssh key storage
 
 /// This trait must be implemented in order to use ssh keys for authentication for sftp/scp.
+///
+/// Built-in implementations are [`DirKeyStorage`], [`ConfigKeyStorage`], [`MemoryKeyStorage`] and [`ChainedKeyStorage`]
 pub trait SshKeyStorage: Send + Sync {
     /// Return RSA key path from host and username
     fn resolve(&self, host: &str, username: &str) -> Option<PathBuf>;
+
+    /// Return all the keys to try for host and username, in order.
+    ///
+    /// By default returns the key returned by [`SshKeyStorage::resolve`]
+    fn resolve_keys(&self, host: &str, username: &str) -> Vec<SshKey> {
+        self.resolve(host, username)
+            .map(SshKey::File)
+            .into_iter()
+            .collect()
+    }
 }
 
 // -- key method
@@ -122,6 +151,12 @@ pub struct SshOpts {
     parse_rules: ParseRule,
     /// Ssh agent configuration for authentication
     ssh_agent_identity: Option<SshAgentIdentity>,
+    /// Interval between keepalive messages
+    keepalive_interval: Option<Duration>,
+    /// Max amount of unanswered keepalive messages
+    keepalive_count_max: Option<u32>,
+    /// Proxy to connect through
+    proxy: Option<Proxy>,
 }
 
 impl SshOpts {
@@ -142,6 +177,9 @@ impl SshOpts {
             methods: Vec::default(),
             parse_rules: ParseRule::STRICT,
             ssh_agent_identity: None,
+            keepalive_interval: None,
+            keepalive_count_max: None,
+            proxy: None,
         }
     }
 
@@ -159,7 +197,8 @@ impl SshOpts {
         self
     }
 
-    /// Set password to authenticate with
+    /// Set password to authenticate with.
+    /// The password is also given to sudo when elevation is enabled with [`Sudo::Password`]
     pub fn password<S: AsRef<str>>(mut self, password: S) -> Self {
         self.password = Some(password.as_ref().to_string());
         self
@@ -172,6 +211,26 @@ impl SshOpts {
         self
     }
 
+    /// Set the interval between keepalive messages sent to the server; a zero interval disables keepalive.
+    /// This option will override an eventual `ServerAliveInterval` specified for the current host in the ssh configuration
+    pub fn keepalive_interval(mut self, interval: Duration) -> Self {
+        self.keepalive_interval = Some(interval);
+        self
+    }
+
+    /// Set the amount of keepalive messages which may be left unanswered before the server is considered dead (default 3).
+    /// This option will override an eventual `ServerAliveCountMax` specified for the current host in the ssh configuration
+    pub fn keepalive_count_max(mut self, count: u32) -> Self {
+        self.keepalive_count_max = Some(count);
+        self
+    }
+
+    /// Connect through `proxy`; the host, as resolved by the ssh configuration, is resolved by the proxy
+    pub fn proxy(mut self, proxy: Proxy) -> Self {
+        self.proxy = Some(proxy);
+        self
+    }
+
     /// Set configuration for ssh agent
     ///
     /// If `None` the ssh agent will be disabled
@@ -197,6 +256,8 @@ impl SshOpts {
     /// - HostKeyAlgorithms
     /// - ConnectionAttempts
     /// - ConnectTimeout
+    /// - ServerAliveInterval
+    /// - ServerAliveCountMax (only if parsed with [`ParseRule::ALLOW_UNSUPPORTED_FIELDS`])
     pub fn config_file<P: AsRef<Path>>(mut self, p: P, rules: ParseRule) -> Self {
         self.config_file = Some(p.as_ref().to_path_buf());
         self.parse_rules = rules;
@@ -299,6 +360,8 @@ mod test {
         assert!(opts.config_file.is_none());
         assert!(opts.key_storage.is_none());
         assert!(opts.methods.is_empty());
+        assert!(opts.keepalive_interval.is_none());
+        assert!(opts.keepalive_count_max.is_none());
     }
 
     #[test]
@@ -308,6 +371,8 @@ mod test {
             .username("foobar")
             .password("qwerty123")
             .connection_timeout(Duration::from_secs(10))
+            .keepalive_interval(Duration::from_secs(30))
+            .keepalive_count_max(5)
             .config_file(Path::new("/home/user0/.ssh/config"), ParseRule::STRICT)
             .key_storage(Box::new(MockSshKeyStorage::default()))
             .method(KeyMethod::new(
@@ -325,6 +390,8 @@ mod test {
         assert_eq!(opts.username.as_deref().unwrap(), "foobar");
         assert_eq!(opts.password.as_deref().unwrap(), "qwerty123");
         assert_eq!(opts.connection_timeout.unwrap(), Duration::from_secs(10));
+        assert_eq!(opts.keepalive_interval.unwrap(), Duration::from_secs(30));
+        assert_eq!(opts.keepalive_count_max.unwrap(), 5);
         assert_eq!(
             opts.config_file.as_deref().unwrap(),
             Path::new("/home/user0/.ssh/config")
@@ -0,0 +1,496 @@
+//! ## Pipeline
+//!
+//! pipelined SFTP transfers, which keep many read or write requests in flight
+
+use std::collections::{BTreeMap, HashMap};
+use std::io::{Read, Write};
+use std::path::Path;
+
+use fsutil_core::fs::{RemoteError, RemoteErrorType, RemoteResult};
+
+use super::sftp_ext::{
+    put_string, status_error, Reply, SftpExtChannel, MAX_PACKET_LEN, SSH_FXF_READ, SSH_FXP_READ,
+    SSH_FXP_WRITE, SSH_FX_EOF, SSH_FX_OK,
+};
+
+/// Max size of a single read or write request; leaves room for the packet header
+const MAX_CHUNK_SIZE: usize = MAX_PACKET_LEN - 1024;
+
+/// Options for pipelined SFTP transfers
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PipelineOpts {
+    /// Max amount of requests in flight
+    window: usize,
+    /// Size of each read or write request
+    chunk_size: usize,
+}
+
+impl Default for PipelineOpts {
+    fn default() -> Self {
+        Self {
+            window: 64,
+            chunk_size: 32768,
+        }
+    }
+}
+
+impl PipelineOpts {
+    /// Set the max amount of requests in flight. A window of `1` disables pipelining
+    pub fn window(mut self, window: usize) -> Self {
+        self.window = window.max(1);
+        self
+    }
+
+    /// Set the size of each request.
+    /// The size is capped to the maximum packet length accepted by the server (256KiB)
+    pub fn chunk_size(mut self, chunk_size: usize) -> Self {
+        self.chunk_size = chunk_size.clamp(1, MAX_CHUNK_SIZE);
+        self
+    }
+}
+
+/// Download file at `path` into `writer`, keeping up to `opts.window` read requests in flight.
+/// Returns the amount of bytes written to `writer`
+pub fn download(
+    channel: &mut SftpExtChannel,
+    path: &Path,
+    writer: &mut dyn Write,
+    opts: &PipelineOpts,
+) -> RemoteResult<u64> {
+    let handle = channel.open_handle(path, SSH_FXF_READ, None)?;
+    let result = download_handle(channel, &handle, writer, opts);
+    channel.close_handle(&handle);
+    result
+}
+
+/// Upload the content of `reader` to the file opened on the server with `flags`, keeping up to
+/// `opts.window` write requests in flight. If the file is created, `mode` is applied to it.
+/// If `fsync` is `true`, the file is synchronized to disk before being closed.
+/// Returns the amount of bytes read from `reader`
+pub fn upload(
+    channel: &mut SftpExtChannel,
+    path: &Path,
+    reader: &mut dyn Read,
+    flags: u32,
+    mode: u32,
+    fsync: bool,
+    opts: &PipelineOpts,
+) -> RemoteResult<u64> {
+    let handle = channel.open_handle(path, flags, Some(mode))?;
+    let result = upload_handle(channel, &handle, reader, opts).and_then(|bytes| {
+        if fsync {
+            trace!("Synchronizing file to disk");
+            channel.fsync(&handle)?;
+        }
+        Ok(bytes)
+    });
+    channel.close_handle(&handle);
+    result
+}
+
+fn download_handle(
+    channel: &mut SftpExtChannel,
+    handle: &[u8],
+    writer: &mut dyn Write,
+    opts: &PipelineOpts,
+) -> RemoteResult<u64> {
+    // requests in flight: id => (offset, length)
+    let mut in_flight: HashMap<u32, (u64, u32)> = HashMap::with_capacity(opts.window);
+    // ranges to request again after a short read
+    let mut retry: Vec<(u64, u32)> = Vec::new();
+    // chunks received out of order, waiting to be written
+    let mut pending: BTreeMap<u64, Vec<u8>> = BTreeMap::new();
+    let mut next_offset: u64 = 0;
+    let mut write_offset: u64 = 0;
+    let mut eof: Option<u64> = None;
+    let mut transfer = || -> RemoteResult<()> {
+        loop {
+            // fill window
+            while in_flight.len() < opts.window {
+                let (offset, len) = match retry.pop() {
+                    Some(range) => range,
+                    None if eof.is_none() => {
+                        let range = (next_offset, opts.chunk_size as u32);
+                        next_offset += opts.chunk_size as u64;
+                        range
+                    }
+                    None => break,
+                };
+                let id = channel.send_request(SSH_FXP_READ, &read_request(handle, offset, len))?;
+                in_flight.insert(id, (offset, len));
+            }
+            if in_flight.is_empty() {
+                return Ok(());
+            }
+            let (id, reply) = channel.recv_reply()?;
+            let Some((offset, len)) = in_flight.remove(&id) else {
+                return Err(unexpected_reply());
+            };
+            match reply {
+                Reply::Data(data) if !data.is_empty() && data.len() <= len as usize => {
+                    if data.len() < len as usize {
+                        trace!("Short read at {}; requesting remaining data", offset);
+                        retry.push((offset + data.len() as u64, len - data.len() as u32));
+                    }
+                    pending.insert(offset, data);
+                }
+                Reply::Status(SSH_FX_EOF, _) => {
+                    trace!("Got EOF at offset {}", offset);
+                    eof = Some(eof.map(|x| x.min(offset)).unwrap_or(offset));
+                }
+                Reply::Status(code, msg) => {
+                    return Err(status_error(code, msg, RemoteErrorType::IoError))
+                }
+                _ => return Err(unexpected_reply()),
+            }
+            // write contiguous chunks
+            while let Some(data) = pending.remove(&write_offset) {
+                writer.write_all(&data).map_err(|e| {
+                    error!("Failed to write to file: {}", e);
+                    RemoteError::new_ex(RemoteErrorType::IoError, e)
+                })?;
+                write_offset += data.len() as u64;
+            }
+        }
+    };
+    if let Err(err) = transfer() {
+        drain(channel, in_flight.len());
+        return Err(err);
+    }
+    // every chunk before EOF must have been written
+    if !pending.is_empty() || eof.map(|x| x != write_offset).unwrap_or(false) {
+        return Err(RemoteError::new_ex(
+            RemoteErrorType::ProtocolError,
+            "file changed during transfer",
+        ));
+    }
+    Ok(write_offset)
+}
+
+fn upload_handle(
+    channel: &mut SftpExtChannel,
+    handle: &[u8],
+    reader: &mut dyn Read,
+    opts: &PipelineOpts,
+) -> RemoteResult<u64> {
+    let mut in_flight: usize = 0;
+    let mut offset: u64 = 0;
+    let mut buffer = vec![0u8; opts.chunk_size];
+    let mut transfer = || -> RemoteResult<()> {
+        let mut done = false;
+        while !done || in_flight > 0 {
+            // fill window
+            while !done && in_flight < opts.window {
+                let len = read_chunk(reader, &mut buffer)?;
+                if len == 0 {
+                    done = true;
+                    break;
+                }
+                channel.send_request(
+                    SSH_FXP_WRITE,
+                    &write_request(handle, offset, &buffer[..len]),
+                )?;
+                offset += len as u64;
+                in_flight += 1;
+            }
+            if in_flight > 0 {
+                let reply = channel.recv_reply()?;
+                in_flight -= 1;
+                match reply {
+                    (_, Reply::Status(SSH_FX_OK, _)) => {}
+                    (_, Reply::Status(code, msg)) => {
+                        return Err(status_error(code, msg, RemoteErrorType::IoError))
+                    }
+                    _ => return Err(unexpected_reply()),
+                }
+            }
+        }
+        Ok(())
+    };
+    if let Err(err) = transfer() {
+        drain(channel, in_flight);
+        return Err(err);
+    }
+    Ok(offset)
+}
+
+/// Discard the replies to the `in_flight` requests, so that the channel can be used again
+fn drain(channel: &mut SftpExtChannel, in_flight: usize) {
+    trace!("Discarding {} replies", in_flight);
+    for _ in 0..in_flight {
+        if channel.recv_reply().is_err() {
+            break;
+        }
+    }
+}
+
+/// Fill `buffer` from `reader`; a shorter length is returned only at the end of the stream
+fn read_chunk(reader: &mut dyn Read, buffer: &mut [u8]) -> RemoteResult<usize> {
+    let mut len = 0;
+    while len < buffer.len() {
+        match reader.read(&mut buffer[len..]) {
+            Ok(0) => break,
+            Ok(n) => len += n,
+            Err(e) if e.kind() == std::io::ErrorKind::Interrupted => {}
+            Err(e) => {
+                error!("Failed to read from file: {}", e);
+                return Err(RemoteError::new_ex(RemoteErrorType::IoError, e));
+            }
+        }
+    }
+    Ok(len)
+}
+
+fn read_request(handle: &[u8], offset: u64, len: u32) -> Vec<u8> {
+    let mut body = Vec::with_capacity(handle.len() + 16);
+    put_string(&mut body, handle);
+    body.extend_from_slice(&offset.to_be_bytes());
+    body.extend_from_slice(&len.to_be_bytes());
+    body
+}
+
+fn write_request(handle: &[u8], offset: u64, data: &[u8]) -> Vec<u8> {
+    let mut body = Vec::with_capacity(handle.len() + data.len() + 16);
+    put_string(&mut body, handle);
+    body.extend_from_slice(&offset.to_be_bytes());
+    put_string(&mut body, data);
+    body
+}
+
+fn unexpected_reply() -> RemoteError {
+    RemoteError::new_ex(RemoteErrorType::ProtocolError, "unexpected SFTP reply")
+}
+
+#[cfg(test)]
+mod test {
+
+    use std::collections::VecDeque;
+    use std::io::Cursor;
+    use std::sync::{Arc, Mutex};
+
+    use pretty_assertions::assert_eq;
+
+    use super::*;
+    use crate::ssh::sftp_ext::{SSH_FXF_CREAT, SSH_FXF_TRUNC, SSH_FXF_WRITE};
+
+    /// In-process SFTP server serving a single file.
+    /// Replies are sent in reverse order and reads return at most `max_read` bytes
+    struct FakeServer {
+        file: Vec<u8>,
+        written: Arc<Mutex<Vec<u8>>>,
+        max_read: usize,
+        input: Vec<u8>,
+        replies: Vec<Vec<u8>>,
+        output: VecDeque<u8>,
+    }
+
+    impl FakeServer {
+        fn new(file: &[u8], max_read: usize) -> (Self, Arc<Mutex<Vec<u8>>>) {
+            let written = Arc::new(Mutex::new(Vec::new()));
+            let server = Self {
+                file: file.to_vec(),
+                written: written.clone(),
+                max_read,
+                input: Vec::new(),
+                replies: Vec::new(),
+                output: VecDeque::new(),
+            };
+            (server, written)
+        }
+
+        fn reply(&mut self, packet_type: u8, payload: &[u8]) {
+            let mut packet = ((payload.len() + 1) as u32).to_be_bytes().to_vec();
+            packet.push(packet_type);
+            packet.extend_from_slice(payload);
+            self.replies.push(packet);
+        }
+
+        fn status(&mut self, id: &[u8], code: u32) {
+            let mut payload = id.to_vec();
+            payload.extend_from_slice(&code.to_be_bytes());
+            put_string(&mut payload, b"");
+            put_string(&mut payload, b"");
+            self.reply(101, &payload);
+        }
+
+        fn handle_packet(&mut self, packet_type: u8, payload: &[u8]) {
+            let u32_at = |i: usize| u32::from_be_bytes(payload[i..i + 4].try_into().unwrap());
+            let u64_at = |i: usize| u64::from_be_bytes(payload[i..i + 8].try_into().unwrap());
+            if packet_type == 1 {
+                // init
+                let mut version = 3u32.to_be_bytes().to_vec();
+                put_string(&mut version, b"fsync@openssh.com");
+                put_string(&mut version, b"1");
+                self.reply(2, &version);
+                return;
+            }
+            let id = payload[..4].to_vec();
+            // skip path or handle
+            let pos = 8 + u32_at(4) as usize;
+            match packet_type {
+                3 => {
+                    let mut handle = id.clone();
+                    put_string(&mut handle, b"handle");
+                    self.reply(102, &handle);
+                }
+                5 => {
+                    let offset = u64_at(pos) as usize;
+                    let len = (u32_at(pos + 8) as usize).min(self.max_read);
+                    if offset >= self.file.len() {
+                        self.status(&id, SSH_FX_EOF);
+                    } else {
+                        let end = (offset + len).min(self.file.len());
+                        let mut data = id.clone();
+                        put_string(&mut data, &self.file[offset..end]);
+                        self.reply(103, &data);
+                    }
+                }
+                6 => {
+                    let offset = u64_at(pos) as usize;
+                    let len = u32_at(pos + 8) as usize;
+                    let data = &payload[pos + 12..pos + 12 + len];
+                    let mut written = self.written.lock().unwrap();
+                    if written.len() < offset + len {
+                        written.resize(offset + len, 0);
+                    }
+                    written[offset..offset + len].copy_from_slice(data);
+                    drop(written);
+                    self.status(&id, SSH_FX_OK);
+                }
+                _ => self.status(&id, SSH_FX_OK),
+            }
+        }
+    }
+
+    impl Write for FakeServer {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.input.extend_from_slice(buf);
+            while self.input.len() >= 5 {
+                let len = u32::from_be_bytes(self.input[..4].try_into().unwrap()) as usize;
+                if self.input.len() < len + 4 {
+                    break;
+                }
+                let packet: Vec<u8> = self.input.drain(..len + 4).collect();
+                self.handle_packet(packet[4], &packet[5..]);
+            }
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl Read for FakeServer {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            if self.output.is_empty() {
+                // complete requests in reverse order
+                while let Some(reply) = self.replies.pop() {
+                    self.output.extend(reply);
+                }
+            }
+            self.output.read(buf)
+        }
+    }
+
+    fn test_data(len: usize) -> Vec<u8> {
+        (0..len).map(|x| (x % 251) as u8).collect()
+    }
+
+    #[test]
+    fn should_build_pipeline_opts() {
+        let opts = PipelineOpts::default();
+        assert_eq!(opts.window, 64);
+        assert_eq!(opts.chunk_size, 32768);
+        let opts = PipelineOpts::default().window(0).chunk_size(1 << 20);
+        assert_eq!(opts.window, 1);
+        assert_eq!(opts.chunk_size, MAX_CHUNK_SIZE);
+    }
+
+    #[test]
+    fn should_make_requests() {
+        let body = read_request(b"h1", 65536, 32768);
+        assert_eq!(
+            body,
+            vec![0, 0, 0, 2, b'h', b'1', 0, 0, 0, 0, 0, 1, 0, 0, 0, 0, 128, 0]
+        );
+        let body = write_request(b"h1", 1, b"abc");
+        assert_eq!(
+            body,
+            vec![0, 0, 0, 2, b'h', b'1', 0, 0, 0, 0, 0, 0, 0, 1, 0, 0, 0, 3, b'a', b'b', b'c']
+        );
+    }
+
+    #[test]
+    fn should_read_full_chunks() {
+        // reader which returns at most 3 bytes for each read
+        struct Slow(Cursor<Vec<u8>>);
+        impl Read for Slow {
+            fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+                let len = buf.len().min(3);
+                self.0.read(&mut buf[..len])
+            }
+        }
+        let mut reader = Slow(Cursor::new(b"hello world".to_vec()));
+        let mut buffer = [0u8; 8];
+        assert_eq!(read_chunk(&mut reader, &mut buffer).unwrap(), 8);
+        assert_eq!(&buffer, b"hello wo");
+        assert_eq!(read_chunk(&mut reader, &mut buffer).unwrap(), 3);
+        assert_eq!(&buffer[..3], b"rld");
+        assert_eq!(read_chunk(&mut reader, &mut buffer).unwrap(), 0);
+    }
+
+    #[test]
+    fn should_download_with_out_of_order_replies_and_short_reads() {
+        let data = test_data(100_000);
+        let (server, _) = FakeServer::new(&data, 1000);
+        let mut channel = SftpExtChannel::init(Box::new(server)).unwrap();
+        let opts = PipelineOpts::default().window(8).chunk_size(4096);
+        let mut dest: Vec<u8> = Vec::new();
+        assert_eq!(
+            download(&mut channel, Path::new("/a.bin"), &mut dest, &opts).unwrap(),
+            100_000
+        );
+        assert_eq!(dest, data);
+    }
+
+    #[test]
+    fn should_download_empty_file() {
+        let (server, _) = FakeServer::new(b"", 1000);
+        let mut channel = SftpExtChannel::init(Box::new(server)).unwrap();
+        let mut dest: Vec<u8> = Vec::new();
+        assert_eq!(
+            download(
+                &mut channel,
+                Path::new("/a.bin"),
+                &mut dest,
+                &PipelineOpts::default()
+            )
+            .unwrap(),
+            0
+        );
+        assert!(dest.is_empty());
+    }
+
+    #[test]
+    fn should_upload_with_out_of_order_replies() {
+        let data = test_data(100_000);
+        let (server, written) = FakeServer::new(b"", 1000);
+        let mut channel = SftpExtChannel::init(Box::new(server)).unwrap();
+        let opts = PipelineOpts::default().window(8).chunk_size(4096);
+        assert_eq!(
+            upload(
+                &mut channel,
+                Path::new("/a.bin"),
+                &mut Cursor::new(data.clone()),
+                SSH_FXF_WRITE | SSH_FXF_CREAT | SSH_FXF_TRUNC,
+                0o644,
+                true,
+                &opts
+            )
+            .unwrap(),
+            100_000
+        );
+        assert_eq!(*written.lock().unwrap(), data);
+    }
+}
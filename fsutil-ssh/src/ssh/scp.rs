@@ -16,8 +16,16 @@ use lazy_regex::{Lazy, Regex};
 // -- export
 pub use ssh2::Session as SshSession;
 
-use super::{commons, SshOpts};
-use crate::utils::{fmt as fmt_utils, parser as parser_utils, path as path_utils};
+use super::keepalive::Keepalive;
+use super::listing::{Userland, DETECT_USERLAND_CMD};
+use super::scp_tree::TreeTransfer;
+use super::sudo::{Elevation, Sudo};
+use super::{
+    commons, scp_tree, ExecHandle, ExecOptions, ExecWriteStatus, ExecWriteStream, SshOpts,
+};
+use crate::utils::{
+    fmt as fmt_utils, parser as parser_utils, path as path_utils, shell as shell_utils,
+};
 
 /// NOTE: about this damn regex <https://stackoverflow.com/questions/32480890/is-there-a-regex-to-parse-the-values-from-an-ftp-directory-listing>
 static LS_RE: Lazy<Regex> = lazy_regex!(
@@ -27,8 +35,14 @@ static LS_RE: Lazy<Regex> = lazy_regex!(
 /// SCP "filesystem" client
 pub struct ScpFileSystem {
     session: Option<SshSession>,
+    keepalive: Option<Keepalive>,
     wrkdir: PathBuf,
     opts: SshOpts,
+    userland: Option<Userland>,
+    sudo: Option<Sudo>,
+    elevation: Option<Elevation>,
+    /// Outcome of the command behind the last write stream, checked by `on_written`
+    write_status: Option<ExecWriteStatus>,
 }
 
 impl ScpFileSystem {
@@ -36,8 +50,32 @@ impl ScpFileSystem {
     pub fn new(opts: SshOpts) -> Self {
         Self {
             session: None,
+            keepalive: None,
             wrkdir: PathBuf::from("/"),
             opts,
+            userland: None,
+            sudo: None,
+            elevation: None,
+            write_status: None,
+        }
+    }
+
+    /// Run the operations which mutate the remote file system through `sudo`.
+    /// Sudo access is checked on the first elevated operation
+    pub fn sudo(mut self, sudo: Sudo) -> Self {
+        self.sudo = Some(sudo);
+        self
+    }
+
+    /// Send a keepalive message to the server, if one is due.
+    ///
+    /// Keepalive messages are also sent before each operation, but callers which leave the session
+    /// idle for long should call this method periodically.
+    /// Returns the time before the next keepalive message is due, or `None` if keepalive is disabled
+    pub fn keepalive_send(&mut self) -> RemoteResult<Option<Duration>> {
+        match (self.session.as_ref(), self.keepalive.as_ref()) {
+            (Some(session), Some(keepalive)) => keepalive.send(session),
+            _ => Err(RemoteError::new(RemoteErrorType::NotConnected)),
         }
     }
 
@@ -46,14 +84,266 @@ impl ScpFileSystem {
         self.session.as_mut()
     }
 
+    /// Execute a command in the current working directory and return an [`ExecHandle`]
+    /// which streams its output as it arrives.
+    ///
+    /// Use this method instead of [`RemoteFileSystem::exec`] for long running commands
+    pub fn exec_stream(&mut self, cmd: &str, opts: ExecOptions) -> RemoteResult<ExecHandle> {
+        self.check_connection()?;
+        debug!(r#"Spawning command "{}""#, cmd);
+        commons::spawn_shell_cmd_at(
+            self.session.as_mut().unwrap(),
+            cmd,
+            self.wrkdir.as_path(),
+            &opts,
+        )
+    }
+
+    /// Upload the local directory `local` to `remote` recursively, preserving modes and times.
+    ///
+    /// The tree is sent over a single channel with the scp protocol (`scp -r -p -t`).
+    /// If the remote scp doesn't support recursive transfers, or sudo elevation is enabled,
+    /// each directory and file is transferred on its own instead.
+    /// Entries the remote scp refuses with a warning are skipped and logged.
+    /// Returns the amount of bytes of file data transferred
+    pub fn upload_dir(&mut self, local: &Path, remote: &Path) -> RemoteResult<u64> {
+        self.check_connection()?;
+        let remote = path_utils::absolutize(self.wrkdir.as_path(), remote);
+        debug!("Uploading {} to {}", local.display(), remote.display());
+        if self.elevation()?.is_none() {
+            let Some(parent) = remote.parent() else {
+                return Err(RemoteError::new_ex(
+                    RemoteErrorType::BadAddress,
+                    "can't upload to /",
+                ));
+            };
+            let name = scp_tree::file_name_bytes(remote.as_path());
+            let cmd = format!("scp -r -p -t -- {}", shell_utils::quote_path(parent));
+            match self.perform_scp_transfer(cmd.as_str(), |channel| {
+                scp_tree::upload(channel, local, name.as_slice())
+            }) {
+                Err(err) if err.kind == RemoteErrorType::UnsupportedFeature => {
+                    warn!("Remote scp doesn't support recursive transfers; uploading each file");
+                }
+                result => return result,
+            }
+        }
+        self.upload_dir_per_file(local, remote.as_path())
+    }
+
+    /// Download the remote directory `remote` to `local` recursively, preserving modes and times.
+    ///
+    /// The tree is received over a single channel with the scp protocol (`scp -r -p -f`).
+    /// If the remote scp doesn't support recursive transfers, each directory and file is transferred on its own instead.
+    /// Entries the remote scp can't send are skipped and logged.
+    /// Returns the amount of bytes of file data transferred
+    pub fn download_dir(&mut self, remote: &Path, local: &Path) -> RemoteResult<u64> {
+        self.check_connection()?;
+        let remote = path_utils::absolutize(self.wrkdir.as_path(), remote);
+        debug!("Downloading {} to {}", remote.display(), local.display());
+        let cmd = format!(
+            "scp -r -p -f -- {}",
+            shell_utils::quote_path(remote.as_path())
+        );
+        match self.perform_scp_transfer(cmd.as_str(), |channel| scp_tree::download(channel, local))
+        {
+            Err(err) if err.kind == RemoteErrorType::UnsupportedFeature => {
+                warn!("Remote scp doesn't support recursive transfers; downloading each file");
+                self.download_dir_per_file(remote.as_path(), local)
+            }
+            result => result,
+        }
+    }
+
     // -- private
 
-    /// Check connection status
+    /// Run the remote scp with `cmd` on a new channel and let `transfer` speak the scp protocol with it
+    fn perform_scp_transfer<F>(&mut self, cmd: &str, transfer: F) -> RemoteResult<u64>
+    where
+        F: FnOnce(&mut ssh2::Channel) -> RemoteResult<TreeTransfer>,
+    {
+        let session = self.session.as_mut().unwrap();
+        session.set_blocking(true);
+        trace!("blocked channel");
+        let mut channel = session
+            .channel_session()
+            .and_then(|mut channel| channel.exec(cmd).map(|_| channel))
+            .map_err(|err| {
+                error!("Failed to run remote scp: {}", err);
+                RemoteError::new_ex(RemoteErrorType::ProtocolError, err)
+            })?;
+        let result = transfer(&mut channel);
+        // let the remote scp terminate, whatever the result
+        let _ = channel.send_eof();
+        let _ = channel.wait_close();
+        let transfer = result?;
+        match channel.exit_status() {
+            Ok(rc) => transfer.finish(rc),
+            Err(err) => Err(RemoteError::new_ex(RemoteErrorType::ProtocolError, err)),
+        }
+    }
+
+    /// Upload `local` to `remote` creating each directory and file on its own
+    fn upload_dir_per_file(&mut self, local: &Path, remote: &Path) -> RemoteResult<u64> {
+        let metadata = std::fs::metadata(local).map_err(|err| {
+            RemoteError::new_ex(
+                RemoteErrorType::NoSuchFileOrDirectory,
+                format!("{}: {err}", local.display()),
+            )
+        })?;
+        let dir_metadata = scp_tree::local_metadata(&metadata);
+        if !self.exists(remote)? {
+            self.create_dir(
+                remote,
+                dir_metadata.mode.unwrap_or_else(|| UnixPex::from(0o755)),
+            )?;
+        }
+        let mut entries: Vec<PathBuf> = std::fs::read_dir(local)
+            .and_then(|entries| entries.map(|x| x.map(|x| x.path())).collect())
+            .map_err(|err| {
+                RemoteError::new_ex(
+                    RemoteErrorType::IoError,
+                    format!("{}: {err}", local.display()),
+                )
+            })?;
+        entries.sort();
+        let mut bytes: u64 = 0;
+        for entry in entries {
+            let Some(name) = entry.file_name() else {
+                continue;
+            };
+            let dest = remote.join(name);
+            let metadata = std::fs::metadata(entry.as_path())
+                .map_err(|err| RemoteError::new_ex(RemoteErrorType::IoError, err))?;
+            if metadata.is_dir() {
+                bytes += self.upload_dir_per_file(entry.as_path(), dest.as_path())?;
+            } else if metadata.is_file() {
+                let reader = std::fs::File::open(entry.as_path())
+                    .map_err(|err| RemoteError::new_ex(RemoteErrorType::IoError, err))?;
+                let metadata = scp_tree::local_metadata(&metadata);
+                bytes += self.create_file(dest.as_path(), &metadata, Box::new(reader))?;
+                // elevated writes don't preserve mode and times
+                if self.sudo.is_some() {
+                    self.setstat(dest.as_path(), metadata)?;
+                }
+            } else {
+                warn!("Skipping {}: not a regular file", entry.display());
+            }
+        }
+        // set times once the content has been written
+        self.setstat(remote, dir_metadata)?;
+        Ok(bytes)
+    }
+
+    /// Download `remote` to `local` opening each file on its own
+    fn download_dir_per_file(&mut self, remote: &Path, local: &Path) -> RemoteResult<u64> {
+        let dir = self.stat(remote)?;
+        if !dir.is_dir() {
+            return Err(RemoteError::new_ex(
+                RemoteErrorType::BadFile,
+                format!("{} is not a directory", remote.display()),
+            ));
+        }
+        if !local.is_dir() {
+            std::fs::create_dir(local).map_err(|err| {
+                RemoteError::new_ex(
+                    RemoteErrorType::IoError,
+                    format!("{}: {err}", local.display()),
+                )
+            })?;
+        }
+        let mut bytes: u64 = 0;
+        for file in self.list_dir(remote)? {
+            let name = file.name();
+            if name == "." || name == ".." {
+                continue;
+            }
+            let dest = local.join(name);
+            if file.is_dir() {
+                bytes += self.download_dir_per_file(file.path(), dest.as_path())?;
+            } else if file.is_file() {
+                let writer = std::fs::File::create(dest.as_path())
+                    .map_err(|err| RemoteError::new_ex(RemoteErrorType::IoError, err))?;
+                bytes += self.open_file(file.path(), Box::new(writer))?;
+                scp_tree::set_local_metadata(dest.as_path(), file.metadata())?;
+            } else {
+                warn!("Skipping {}: not a regular file", file.path().display());
+            }
+        }
+        scp_tree::set_local_metadata(local, dir.metadata())?;
+        Ok(bytes)
+    }
+
+    /// Check connection status, sending a keepalive message if one is due
     fn check_connection(&mut self) -> RemoteResult<()> {
-        if self.is_connected() {
-            Ok(())
-        } else {
-            Err(RemoteError::new(RemoteErrorType::NotConnected))
+        match (self.session.as_ref(), self.keepalive.as_ref()) {
+            (Some(session), Some(keepalive)) if session.authenticated() => {
+                keepalive.send(session).map(|_| ())
+            }
+            _ => Err(RemoteError::new(RemoteErrorType::NotConnected)),
+        }
+    }
+
+    /// Get sudo access, checking it on first use; `None` if elevation is disabled
+    fn elevation(&mut self) -> RemoteResult<Option<Elevation>> {
+        let Some(sudo) = self.sudo else {
+            return Ok(None);
+        };
+        if self.elevation.is_none() {
+            let session = self
+                .session
+                .as_mut()
+                .ok_or_else(|| RemoteError::new(RemoteErrorType::NotConnected))?;
+            self.elevation = Some(Elevation::check(
+                session,
+                sudo,
+                self.opts.password.as_deref(),
+            )?);
+        }
+        Ok(self.elevation.clone())
+    }
+
+    /// Run a command which mutates the remote file system, through sudo if elevation is enabled
+    fn perform_mutating_cmd<S: AsRef<str>>(&mut self, cmd: S) -> RemoteResult<(u32, String)> {
+        match self.elevation()? {
+            Some(elevation) => elevation.perform(self.session.as_mut().unwrap(), cmd.as_ref()),
+            None => commons::perform_shell_cmd_with_rc(self.session.as_mut().unwrap(), cmd),
+        }
+    }
+
+    /// Get the userland of the remote host, detecting it on first use
+    fn userland(&mut self) -> RemoteResult<Userland> {
+        if let Some(userland) = self.userland {
+            return Ok(userland);
+        }
+        let output =
+            commons::perform_shell_cmd(self.session.as_mut().unwrap(), DETECT_USERLAND_CMD)?;
+        let userland = Userland::from_detect_output(output.as_str());
+        debug!("Detected remote userland: {:?}", userland);
+        self.userland = Some(userland);
+        Ok(userland)
+    }
+
+    /// Run a listing command built for `userland` and parse its output.
+    /// Returns `None` if there's no command for `userland` or if it failed; in this case `ls` must be used
+    fn run_listing_cmd(
+        &mut self,
+        userland: Userland,
+        cmd: Option<String>,
+    ) -> RemoteResult<Option<Vec<(PathBuf, Metadata)>>> {
+        let Some(cmd) = cmd else {
+            return Ok(None);
+        };
+        match commons::perform_shell_cmd_with_raw_output(self.session.as_mut().unwrap(), cmd) {
+            Ok((0, output)) => Ok(Some(userland.parse_entries(&output))),
+            Ok((rc, _)) => {
+                warn!(
+                    "Listing command exited with code {}; falling back to ls",
+                    rc
+                );
+                Ok(None)
+            }
+            Err(err) => Err(RemoteError::new_ex(RemoteErrorType::ProtocolError, err)),
         }
     }
 
@@ -185,10 +475,9 @@ impl ScpFileSystem {
 
     /// Execute setstat command and assert result is 0
     fn assert_stat_command(&mut self, cmd: String) -> RemoteResult<()> {
-        match commons::perform_shell_cmd_with_rc(self.session.as_mut().unwrap(), cmd) {
-            Ok((0, _)) => Ok(()),
-            Ok(_) => Err(RemoteError::new(RemoteErrorType::StatFailed)),
-            Err(err) => Err(RemoteError::new_ex(RemoteErrorType::ProtocolError, err)),
+        match self.perform_mutating_cmd(cmd)? {
+            (0, _) => Ok(()),
+            _ => Err(RemoteError::new(RemoteErrorType::StatFailed)),
         }
     }
 
@@ -197,7 +486,7 @@ impl ScpFileSystem {
         let path = path_utils::absolutize(self.wrkdir.as_path(), path);
         match commons::perform_shell_cmd_with_rc(
             self.session.as_mut().unwrap(),
-            format!("test -d \"{}\"", path.display()),
+            format!("test -d {}", shell_utils::quote_path(path.as_path())),
         ) {
             Ok((0, _)) => Ok(true),
             Ok(_) => Ok(false),
@@ -209,7 +498,7 @@ impl ScpFileSystem {
 impl RemoteFileSystem for ScpFileSystem {
     fn connect(&mut self) -> RemoteResult<Welcome> {
         debug!("Initializing SFTP connection...");
-        let mut session = commons::connect(&self.opts)?;
+        let (mut session, keepalive) = commons::connect(&self.opts)?;
         // Get banner
         let banner: Option<String> = session.banner().map(String::from);
         debug!(
@@ -220,8 +509,11 @@ impl RemoteFileSystem for ScpFileSystem {
         debug!("Getting working directory...");
         self.wrkdir = commons::perform_shell_cmd(&mut session, "pwd")
             .map(|x| PathBuf::from(x.as_str().trim()))?;
-        // Set session
+        // Set session; userland will be detected again on first listing
         self.session = Some(session);
+        self.keepalive = Some(keepalive);
+        self.userland = None;
+        self.elevation = None;
         info!(
             "Connection established; working directory: {}",
             self.wrkdir.display()
@@ -237,6 +529,9 @@ impl RemoteFileSystem for ScpFileSystem {
                 Ok(_) => {
                     // Set session and sftp to none
                     self.session = None;
+                    self.keepalive = None;
+                    self.userland = None;
+                    self.elevation = None;
                     Ok(())
                 }
                 Err(err) => Err(RemoteError::new_ex(RemoteErrorType::ConnectionError, err)),
@@ -247,10 +542,26 @@ impl RemoteFileSystem for ScpFileSystem {
     }
 
     fn is_connected(&mut self) -> bool {
-        self.session
-            .as_ref()
-            .map(|x| x.authenticated())
-            .unwrap_or(false)
+        let alive = match (self.session.as_ref(), self.keepalive.as_ref()) {
+            (Some(session), Some(keepalive)) if session.authenticated() => {
+                // opening a channel takes a round trip, without running anything on the server
+                keepalive.probe(session, || {
+                    session
+                        .channel_session()
+                        .and_then(|mut channel| channel.close())
+                        .is_ok()
+                })
+            }
+            _ => return false,
+        };
+        if !alive {
+            // drop dead session
+            self.session = None;
+            self.keepalive = None;
+            self.userland = None;
+            self.elevation = None;
+        }
+        alive
     }
 
     fn pwd(&mut self) -> RemoteResult<PathBuf> {
@@ -264,7 +575,10 @@ impl RemoteFileSystem for ScpFileSystem {
         debug!("Changing working directory to {}", dir.display());
         match commons::perform_shell_cmd(
             self.session.as_mut().unwrap(),
-            format!("cd \"{}\"; echo $?; pwd", dir.display()),
+            format!(
+                "cd {}; echo $?; pwd",
+                shell_utils::quote_path(dir.as_path())
+            ),
         ) {
             Ok(output) => {
                 // Trim
@@ -296,9 +610,27 @@ impl RemoteFileSystem for ScpFileSystem {
         if !self.exists(path.as_path()).ok().unwrap_or(false) {
             return Err(RemoteError::new(RemoteErrorType::NoSuchFileOrDirectory));
         }
+        let userland = self.userland()?;
+        if let Some(entries) = self.run_listing_cmd(userland, userland.list_cmd(path.as_path()))? {
+            let entries: Vec<File> = entries
+                .into_iter()
+                .filter_map(|(name, metadata)| {
+                    name.file_name().map(|name| File {
+                        path: path.join(name),
+                        metadata,
+                    })
+                })
+                .collect();
+            debug!("Found {} file entries", entries.len());
+            return Ok(entries);
+        }
         match commons::perform_shell_cmd(
             self.session.as_mut().unwrap(),
-            format!("unset LANG; ls -la \"{}/\"", path.display()).as_str(),
+            format!(
+                "unset LANG; ls -la {}/",
+                shell_utils::quote_path(path.as_path())
+            )
+            .as_str(),
         ) {
             Ok(output) => {
                 // Split output by (\r)\n
@@ -326,10 +658,19 @@ impl RemoteFileSystem for ScpFileSystem {
         self.check_connection()?;
         let path = path_utils::absolutize(self.wrkdir.as_path(), path);
         debug!("Stat {}", path.display());
+        let userland = self.userland()?;
+        if let Some(mut entries) =
+            self.run_listing_cmd(userland, userland.stat_cmd(path.as_path()))?
+        {
+            return match entries.pop() {
+                Some((_, metadata)) => Ok(File { path, metadata }),
+                None => Err(RemoteError::new(RemoteErrorType::NoSuchFileOrDirectory)),
+            };
+        }
         // make command; Directories require `-d` option
         let cmd = match self.is_directory(path.as_path())? {
-            true => format!("ls -ld \"{}\"", path.display()),
-            false => format!("ls -l \"{}\"", path.display()),
+            true => format!("ls -ld {}", shell_utils::quote_path(path.as_path())),
+            false => format!("ls -l {}", shell_utils::quote_path(path.as_path())),
         };
         match commons::perform_shell_cmd(self.session.as_mut().unwrap(), cmd.as_str()) {
             Ok(line) => {
@@ -357,7 +698,7 @@ impl RemoteFileSystem for ScpFileSystem {
         let path = path_utils::absolutize(self.wrkdir.as_path(), path);
         match commons::perform_shell_cmd_with_rc(
             self.session.as_mut().unwrap(),
-            format!("test -e \"{}\"", path.display()),
+            format!("test -e {}", shell_utils::quote_path(path.as_path())),
         ) {
             Ok((0, _)) => Ok(true),
             Ok(_) => Ok(false),
@@ -375,32 +716,32 @@ impl RemoteFileSystem for ScpFileSystem {
         // set mode with chmod
         if let Some(mode) = metadata.mode {
             self.assert_stat_command(format!(
-                "chmod {:o} \"{}\"",
+                "chmod {:o} {}",
                 u32::from(mode),
-                path.display()
+                shell_utils::quote_path(path.as_path())
             ))?;
         }
         if let Some(user) = metadata.uid {
             self.assert_stat_command(format!(
-                "chown {}{} \"{}\"",
+                "chown {}{} {}",
                 user,
                 metadata.gid.map(|x| format!(":{x}")).unwrap_or_default(),
-                path.display()
+                shell_utils::quote_path(path.as_path())
             ))?;
         }
         // set times
         if let Some(accessed) = metadata.accessed {
             self.assert_stat_command(format!(
-                "touch -a -t {} \"{}\"",
+                "touch -a -t {} {}",
                 fmt_utils::fmt_time_utc(accessed, "%Y%m%d%H%M.%S"),
-                path.display()
+                shell_utils::quote_path(path.as_path())
             ))?;
         }
         if let Some(modified) = metadata.modified {
             self.assert_stat_command(format!(
-                "touch -m -t {} \"{}\"",
+                "touch -m -t {} {}",
                 fmt_utils::fmt_time_utc(modified, "%Y%m%d%H%M.%S"),
-                path.display()
+                shell_utils::quote_path(path.as_path())
             ))?;
         }
         Ok(())
@@ -413,13 +754,11 @@ impl RemoteFileSystem for ScpFileSystem {
             return Err(RemoteError::new(RemoteErrorType::NoSuchFileOrDirectory));
         }
         debug!("Removing file {}", path.display());
-        match commons::perform_shell_cmd_with_rc(
-            self.session.as_mut().unwrap(),
-            format!("rm -f \"{}\"", path.display()),
-        ) {
-            Ok((0, _)) => Ok(()),
-            Ok(_) => Err(RemoteError::new(RemoteErrorType::CouldNotRemoveFile)),
-            Err(err) => Err(RemoteError::new_ex(RemoteErrorType::ProtocolError, err)),
+        match self
+            .perform_mutating_cmd(format!("rm -f {}", shell_utils::quote_path(path.as_path())))?
+        {
+            (0, _) => Ok(()),
+            _ => Err(RemoteError::new(RemoteErrorType::CouldNotRemoveFile)),
         }
     }
 
@@ -430,13 +769,11 @@ impl RemoteFileSystem for ScpFileSystem {
             return Err(RemoteError::new(RemoteErrorType::NoSuchFileOrDirectory));
         }
         debug!("Removing directory {}", path.display());
-        match commons::perform_shell_cmd_with_rc(
-            self.session.as_mut().unwrap(),
-            format!("rmdir \"{}\"", path.display()),
-        ) {
-            Ok((0, _)) => Ok(()),
-            Ok(_) => Err(RemoteError::new(RemoteErrorType::DirectoryNotEmpty)),
-            Err(err) => Err(RemoteError::new_ex(RemoteErrorType::ProtocolError, err)),
+        match self
+            .perform_mutating_cmd(format!("rmdir {}", shell_utils::quote_path(path.as_path())))?
+        {
+            (0, _) => Ok(()),
+            _ => Err(RemoteError::new(RemoteErrorType::DirectoryNotEmpty)),
         }
     }
 
@@ -447,13 +784,12 @@ impl RemoteFileSystem for ScpFileSystem {
             return Err(RemoteError::new(RemoteErrorType::NoSuchFileOrDirectory));
         }
         debug!("Removing directory {} recursively", path.display());
-        match commons::perform_shell_cmd_with_rc(
-            self.session.as_mut().unwrap(),
-            format!("rm -rf \"{}\"", path.display()),
-        ) {
-            Ok((0, _)) => Ok(()),
-            Ok(_) => Err(RemoteError::new(RemoteErrorType::CouldNotRemoveFile)),
-            Err(err) => Err(RemoteError::new_ex(RemoteErrorType::ProtocolError, err)),
+        match self.perform_mutating_cmd(format!(
+            "rm -rf {}",
+            shell_utils::quote_path(path.as_path())
+        ))? {
+            (0, _) => Ok(()),
+            _ => Err(RemoteError::new(RemoteErrorType::CouldNotRemoveFile)),
         }
     }
 
@@ -469,13 +805,13 @@ impl RemoteFileSystem for ScpFileSystem {
             path.display(),
             mode
         );
-        match commons::perform_shell_cmd_with_rc(
-            self.session.as_mut().unwrap(),
-            format!("mkdir -m {} \"{}\"", mode, path.display()),
-        ) {
-            Ok((0, _)) => Ok(()),
-            Ok(_) => Err(RemoteError::new(RemoteErrorType::FileCreateDenied)),
-            Err(err) => Err(RemoteError::new_ex(RemoteErrorType::ProtocolError, err)),
+        match self.perform_mutating_cmd(format!(
+            "mkdir -m {} {}",
+            mode,
+            shell_utils::quote_path(path.as_path())
+        ))? {
+            (0, _) => Ok(()),
+            _ => Err(RemoteError::new(RemoteErrorType::FileCreateDenied)),
         }
     }
 
@@ -493,13 +829,13 @@ impl RemoteFileSystem for ScpFileSystem {
         if self.exists(path.as_path()).ok().unwrap_or(false) {
             return Err(RemoteError::new(RemoteErrorType::FileCreateDenied));
         }
-        match commons::perform_shell_cmd_with_rc(
-            self.session.as_mut().unwrap(),
-            format!("ln -s \"{}\" \"{}\"", target.display(), path.display()),
-        ) {
-            Ok((0, _)) => Ok(()),
-            Ok(_) => Err(RemoteError::new(RemoteErrorType::FileCreateDenied)),
-            Err(err) => Err(RemoteError::new_ex(RemoteErrorType::ProtocolError, err)),
+        match self.perform_mutating_cmd(format!(
+            "ln -s {} {}",
+            shell_utils::quote_path(target),
+            shell_utils::quote_path(path.as_path())
+        ))? {
+            (0, _) => Ok(()),
+            _ => Err(RemoteError::new(RemoteErrorType::FileCreateDenied)),
         }
     }
 
@@ -512,20 +848,20 @@ impl RemoteFileSystem for ScpFileSystem {
         }
         let dest = path_utils::absolutize(self.wrkdir.as_path(), dest);
         debug!("Copying {} to {}", src.display(), dest.display());
-        match commons::perform_shell_cmd_with_rc(
-            self.session.as_mut().unwrap(),
-            format!("cp -rf \"{}\" \"{}\"", src.display(), dest.display()).as_str(),
-        ) {
-            Ok((0, _)) => Ok(()),
-            Ok(_) => Err(RemoteError::new_ex(
+        match self.perform_mutating_cmd(
+            format!(
+                "cp -rf {} {}",
+                shell_utils::quote_path(src.as_path()),
+                shell_utils::quote_path(dest.as_path())
+            )
+            .as_str(),
+        )? {
+            (0, _) => Ok(()),
+            _ => Err(RemoteError::new_ex(
                 // Could not copy file
                 RemoteErrorType::FileCreateDenied,
                 format!("\"{}\"", dest.display()),
             )),
-            Err(err) => Err(RemoteError::new_ex(
-                RemoteErrorType::ProtocolError,
-                err.to_string(),
-            )),
         }
     }
 
@@ -538,17 +874,20 @@ impl RemoteFileSystem for ScpFileSystem {
         }
         let dest = path_utils::absolutize(self.wrkdir.as_path(), dest);
         debug!("Moving {} to {}", src.display(), dest.display());
-        match commons::perform_shell_cmd_with_rc(
-            self.session.as_mut().unwrap(),
-            format!("mv -f \"{}\" \"{}\"", src.display(), dest.display()).as_str(),
-        ) {
-            Ok((0, _)) => Ok(()),
-            Ok(_) => Err(RemoteError::new_ex(
+        match self.perform_mutating_cmd(
+            format!(
+                "mv -f {} {}",
+                shell_utils::quote_path(src.as_path()),
+                shell_utils::quote_path(dest.as_path())
+            )
+            .as_str(),
+        )? {
+            (0, _) => Ok(()),
+            _ => Err(RemoteError::new_ex(
                 // Could not copy file
                 RemoteErrorType::FileCreateDenied,
                 format!("\"{}\"", dest.display()),
             )),
-            Err(err) => Err(RemoteError::new_ex(RemoteErrorType::ProtocolError, err)),
         }
     }
 
@@ -562,14 +901,65 @@ impl RemoteFileSystem for ScpFileSystem {
         )
     }
 
-    fn append(&mut self, _path: &Path, _metadata: &Metadata) -> RemoteResult<WriteStream> {
-        Err(RemoteError::new(RemoteErrorType::UnsupportedFeature))
+    fn append(&mut self, path: &Path, metadata: &Metadata) -> RemoteResult<WriteStream> {
+        self.check_connection()?;
+        self.write_status = None;
+        let path = path_utils::absolutize(self.wrkdir.as_path(), path);
+        debug!("Opening file at {} for appending", path.display());
+        let mode = metadata.mode.map(u32::from).unwrap_or(0o644);
+        if let Some(elevation) = self.elevation()? {
+            let stream = elevation.open_write(
+                self.session.as_mut().unwrap(),
+                path.as_path(),
+                UnixPex::from(mode),
+                true,
+            )?;
+            self.write_status = Some(stream.status());
+            return Ok(WriteStream::from(stream));
+        }
+        // create the file with mode, if it doesn't exist
+        match self.perform_mutating_cmd(format!(
+            "test -e {path} || {{ : > {path} && chmod {mode:o} {path}; }}",
+            path = shell_utils::quote_path(path.as_path()),
+        ))? {
+            (0, _) => {}
+            _ => {
+                error!("Failed to create file {}", path.display());
+                return Err(RemoteError::new_ex(
+                    RemoteErrorType::CouldNotOpenFile,
+                    format!("\"{}\"", path.display()),
+                ));
+            }
+        }
+        let stream = ExecHandle::spawn(
+            self.session.as_mut().unwrap(),
+            format!("cat >> {}", shell_utils::quote_path(path.as_path())).as_str(),
+            &ExecOptions::default(),
+        )
+        .map(ExecWriteStream::from)
+        .map_err(|err| {
+            error!("Append failed: {}", err);
+            RemoteError::new_ex(RemoteErrorType::CouldNotOpenFile, err)
+        })?;
+        self.write_status = Some(stream.status());
+        Ok(WriteStream::from(stream))
     }
 
     fn create(&mut self, path: &Path, metadata: &Metadata) -> RemoteResult<WriteStream> {
         self.check_connection()?;
+        self.write_status = None;
         let path = path_utils::absolutize(self.wrkdir.as_path(), path);
         debug!("Creating file {}", path.display());
+        if let Some(elevation) = self.elevation()? {
+            let stream = elevation.open_write(
+                self.session.as_mut().unwrap(),
+                path.as_path(),
+                metadata.mode.unwrap_or_else(|| UnixPex::from(0o644)),
+                false,
+            )?;
+            self.write_status = Some(stream.status());
+            return Ok(WriteStream::from(stream));
+        }
         // blocking channel
         self.session.as_mut().unwrap().set_blocking(true);
         trace!("blocked channel");
@@ -626,6 +1016,15 @@ impl RemoteFileSystem for ScpFileSystem {
             }
         }
     }
+
+    fn on_written(&mut self, writable: WriteStream) -> RemoteResult<()> {
+        // dropping the stream closes stdin of the command writing the file and waits for it
+        drop(writable);
+        match self.write_status.take() {
+            Some(status) => status.take(),
+            None => Ok(()),
+        }
+    }
 }
 
 #[cfg(test)]
@@ -654,15 +1053,75 @@ mod test {
     }
 
     #[test]
-    fn should_not_append_to_file() {
+    fn should_append_to_file() {
         crate::mock::logger();
-
         let TestCtx {
             mut client,
             container: _container,
         } = setup_client();
         // Create file
         let p = Path::new("a.txt");
+        let file_data = "test data\n";
+        let reader = Cursor::new(file_data.as_bytes());
+        assert_eq!(
+            client
+                .create_file(p, &Metadata::default().size(10), Box::new(reader))
+                .ok()
+                .unwrap(),
+            10
+        );
+        // Verify size
+        assert_eq!(client.stat(p).ok().unwrap().metadata().size, 10);
+        // Append to file
+        let file_data = "Hello, world!\n";
+        let reader = Cursor::new(file_data.as_bytes());
+        assert_eq!(
+            client
+                .append_file(p, &Metadata::default().size(14), Box::new(reader))
+                .ok()
+                .unwrap(),
+            14
+        );
+        assert_eq!(client.stat(p).ok().unwrap().metadata().size, 24);
+        finalize_client(client);
+    }
+
+    #[test]
+    fn should_create_file_with_mode_on_append() {
+        crate::mock::logger();
+        let TestCtx {
+            mut client,
+            container: _container,
+        } = setup_client();
+        let p = Path::new("a.sh");
+        let file_data = "echo 5\n";
+        let reader = Cursor::new(file_data.as_bytes());
+        assert_eq!(
+            client
+                .append_file(
+                    p,
+                    &Metadata::default().mode(UnixPex::from(0o755)),
+                    Box::new(reader)
+                )
+                .ok()
+                .unwrap(),
+            7
+        );
+        let meta = client.stat(p).ok().unwrap().metadata;
+        assert_eq!(meta.size, 7);
+        assert_eq!(meta.mode.unwrap(), UnixPex::from(0o755));
+        finalize_client(client);
+    }
+
+    #[test]
+    fn should_not_append_to_file() {
+        crate::mock::logger();
+        let TestCtx {
+            mut client,
+            container: _container,
+        } = setup_client();
+        // Create file
+        let p = Path::new("/tmp/aaaaaaa/hbbbbb/a.txt");
         // Append to file
         let file_data = "Hello, world!\n";
         let reader = Cursor::new(file_data.as_bytes());
@@ -672,6 +1131,32 @@ mod test {
         finalize_client(client);
     }
 
+    #[test]
+    fn should_not_append_to_read_only_file() {
+        crate::mock::logger();
+        let TestCtx {
+            mut client,
+            container: _container,
+        } = setup_client();
+        // Create read-only file
+        let p = Path::new("a.txt");
+        let reader = Cursor::new("test data\n".as_bytes());
+        assert!(client
+            .create_file(p, &Metadata::default().size(10), Box::new(reader))
+            .is_ok());
+        assert_eq!(client.exec("chmod 444 a.txt").ok().unwrap().0, 0);
+        // Append to file
+        let reader = Cursor::new("Hello, world!\n".as_bytes());
+        let err = client
+            .append_file(p, &Metadata::default().size(14), Box::new(reader))
+            .err()
+            .unwrap();
+        assert_eq!(err.kind, RemoteErrorType::IoError);
+        assert_eq!(client.stat(p).ok().unwrap().metadata().size, 10);
+        assert_eq!(client.exec("chmod 644 a.txt").ok().unwrap().0, 0);
+        finalize_client(client);
+    }
+
     #[test]
     fn should_change_directory() {
         crate::mock::logger();
@@ -771,6 +1256,31 @@ mod test {
         finalize_client(client);
     }
 
+    #[test]
+    fn should_handle_paths_with_shell_metacharacters() {
+        crate::mock::logger();
+        let TestCtx {
+            mut client,
+            container: _container,
+        } = setup_client();
+        let dir = Path::new("my \"dir\" $(touch pwned) `id` 'x'");
+        assert!(client.create_dir(dir, UnixPex::from(0o755)).is_ok());
+        assert!(client.exists(dir).unwrap());
+        assert!(client.change_dir(dir).is_ok());
+        assert_eq!(client.list_dir(Path::new(".")).unwrap().len(), 0);
+        let p = Path::new("a;b && c.txt");
+        let file_data = "test data\n";
+        let reader = Cursor::new(file_data.as_bytes());
+        assert!(client
+            .create_file(p, &Metadata::default().size(10), Box::new(reader))
+            .is_ok());
+        assert_eq!(client.stat(p).unwrap().metadata().size, 10);
+        assert!(client.remove_file(p).is_ok());
+        assert!(!client.exists(Path::new("../pwned")).unwrap());
+        assert!(client.change_dir(Path::new("..")).is_ok());
+        finalize_client(client);
+    }
+
     #[test]
     fn should_not_create_directory_cause_already_exists() {
         crate::mock::logger();
@@ -867,6 +1377,23 @@ mod test {
         finalize_client(client);
     }
 
+    #[test]
+    fn should_exec_stream_command() {
+        crate::mock::logger();
+        let TestCtx {
+            mut client,
+            container: _container,
+        } = setup_client();
+        let handle = client
+            .exec_stream("pwd; echo 5", ExecOptions::default())
+            .unwrap();
+        let mut output = String::new();
+        assert!(handle.stdout().read_to_string(&mut output).is_ok());
+        assert_eq!(output, format!("{}\n5\n", client.pwd().unwrap().display()));
+        assert_eq!(handle.wait().unwrap(), 0);
+        finalize_client(client);
+    }
+
     #[test]
     fn should_tell_whether_file_exists() {
         crate::mock::logger();
@@ -925,6 +1452,41 @@ mod test {
         finalize_client(client);
     }
 
+    #[test]
+    fn should_list_dir_with_exact_metadata() {
+        crate::mock::logger();
+        let TestCtx {
+            mut client,
+            container: _container,
+        } = setup_client();
+        let wrkdir = client.pwd().ok().unwrap();
+        // names which can't be told apart in `ls -l` output
+        let p = Path::new("my file -> link.txt");
+        let reader = Cursor::new("test data\n".as_bytes());
+        assert!(client
+            .create_file(p, &Metadata::default().size(10), Box::new(reader))
+            .is_ok());
+        let modified = SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_042);
+        assert!(client
+            .setstat(p, Metadata::default().modified(modified))
+            .is_ok());
+        assert!(client.symlink(Path::new("link"), p).is_ok());
+        let mut files = client.list_dir(wrkdir.as_path()).ok().unwrap();
+        files.sort_by_key(|x| x.name());
+        assert_eq!(files.len(), 2);
+        assert_eq!(files[0].name().as_str(), "link");
+        assert!(files[0].is_symlink());
+        assert_eq!(files[0].metadata.symlink.as_deref().unwrap(), p);
+        assert_eq!(files[1].name().as_str(), "my file -> link.txt");
+        assert_eq!(files[1].metadata.size, 10);
+        assert_eq!(files[1].metadata.modified, Some(modified));
+        assert_eq!(files[1].metadata.uid, Some(1000));
+        // stat
+        let file = client.stat(p).ok().unwrap();
+        assert_eq!(file.metadata.modified, Some(modified));
+        finalize_client(client);
+    }
+
     #[test]
     fn should_not_list_dir() {
         crate::mock::logger();
@@ -1001,6 +1563,78 @@ mod test {
         finalize_client(client);
     }
 
+    #[test]
+    fn should_upload_and_download_dir() {
+        crate::mock::logger();
+        let TestCtx {
+            mut client,
+            container: _container,
+        } = setup_client();
+        let tempdir = tempfile::TempDir::new().unwrap();
+        let local = tempdir.path().join("src");
+        std::fs::create_dir_all(local.join("sub")).unwrap();
+        std::fs::write(local.join("a.txt"), b"test data\n").unwrap();
+        std::fs::write(local.join("sub").join("b b.txt"), b"hello").unwrap();
+        let modified = SystemTime::UNIX_EPOCH + Duration::from_secs(1_600_000_000);
+        std::fs::File::open(local.join("a.txt"))
+            .unwrap()
+            .set_modified(modified)
+            .unwrap();
+        assert_eq!(
+            client
+                .upload_dir(local.as_path(), Path::new("tree"))
+                .unwrap(),
+            15
+        );
+        let file = client.stat(Path::new("tree/sub/b b.txt")).unwrap();
+        assert_eq!(file.metadata().size, 5);
+        assert_eq!(
+            client
+                .stat(Path::new("tree/a.txt"))
+                .unwrap()
+                .metadata()
+                .modified,
+            Some(modified)
+        );
+        // download
+        let dest = tempdir.path().join("dest");
+        assert_eq!(
+            client
+                .download_dir(Path::new("tree"), dest.as_path())
+                .unwrap(),
+            15
+        );
+        assert_eq!(
+            std::fs::read(dest.join("sub").join("b b.txt")).unwrap(),
+            b"hello"
+        );
+        assert_eq!(
+            std::fs::metadata(dest.join("a.txt"))
+                .unwrap()
+                .modified()
+                .unwrap(),
+            modified
+        );
+        finalize_client(client);
+    }
+
+    #[test]
+    fn should_not_download_dir() {
+        crate::mock::logger();
+        let TestCtx {
+            mut client,
+            container: _container,
+        } = setup_client();
+        let tempdir = tempfile::TempDir::new().unwrap();
+        assert!(client
+            .download_dir(
+                Path::new("/tmp/aaaaaaa/hbbbbb"),
+                tempdir.path().join("dest").as_path()
+            )
+            .is_err());
+        finalize_client(client);
+    }
+
     #[test]
     fn should_not_open_file() {
         crate::mock::logger();
@@ -1132,6 +1766,64 @@ mod test {
         finalize_client(client);
     }
 
+    #[test]
+    fn should_write_root_owned_files_with_sudo() {
+        crate::mock::logger();
+        let container = OpensshServer::start();
+        let port = container.port();
+        let config_file = ssh_mock::create_ssh_config(port);
+        let mut client = ScpFileSystem::new(
+            SshOpts::new("scp")
+                .config_file(config_file.path(), ParseRule::ALLOW_UNKNOWN_FIELDS)
+                .password("password"),
+        )
+        .sudo(Sudo::Password);
+        assert!(client.connect().is_ok());
+        let dir = Path::new("/etc/fsutil-sudo-test");
+        assert!(client.create_dir(dir, UnixPex::from(0o755)).is_ok());
+        let p = dir.join("a.txt");
+        let file_data = "test data\n";
+        assert_eq!(
+            client
+                .create_file(
+                    p.as_path(),
+                    &Metadata::default().size(10),
+                    Box::new(Cursor::new(file_data.as_bytes()))
+                )
+                .ok()
+                .unwrap(),
+            10
+        );
+        let file = client.stat(p.as_path()).ok().unwrap();
+        assert_eq!(file.metadata().size, 10);
+        assert_eq!(file.metadata().uid, Some(0));
+        assert!(client.mov(p.as_path(), dir.join("b.txt").as_path()).is_ok());
+        assert!(client.remove_dir_all(dir).is_ok());
+        assert!(!client.exists(dir).ok().unwrap());
+        assert!(client.disconnect().is_ok());
+    }
+
+    #[test]
+    fn should_report_sudo_permission_error() {
+        crate::mock::logger();
+        let container = OpensshServer::start();
+        let port = container.port();
+        let config_file = ssh_mock::create_ssh_config(port);
+        let mut client = ScpFileSystem::new(
+            SshOpts::new("scp")
+                .config_file(config_file.path(), ParseRule::ALLOW_UNKNOWN_FIELDS)
+                .password("password"),
+        )
+        .sudo(Sudo::NoPassword);
+        assert!(client.connect().is_ok());
+        // sudo requires a password on the test server
+        let err = client
+            .create_dir(Path::new("/etc/fsutil-sudo-test"), UnixPex::from(0o755))
+            .unwrap_err();
+        assert_eq!(err.kind, RemoteErrorType::PexError);
+        assert!(client.disconnect().is_ok());
+    }
+
     #[test]
     fn should_setstat_file() {
         crate::mock::logger();
@@ -1165,7 +1857,7 @@ mod test {
             .is_ok());
         let entry = client.stat(p).ok().unwrap();
         let stat = entry.metadata();
-        assert_eq!(stat.accessed, None);
+        assert_eq!(stat.accessed, Some(SystemTime::UNIX_EPOCH));
         assert_eq!(stat.created, None);
         assert_eq!(stat.modified, Some(SystemTime::UNIX_EPOCH));
         assert_eq!(stat.mode.unwrap(), UnixPex::from(0o755));
@@ -0,0 +1,872 @@
+//! ## Scp tree
+//!
+//! recursive transfers speaking the scp protocol over a single channel (`scp -r -p -t` / `scp -r -p -f`).
+//!
+//! Each record is a line: `T<mtime> 0 <atime> 0` sets the times of the next entry,
+//! `D<mode> 0 <name>` enters a directory, `E` leaves it and `C<mode> <size> <name>` is followed by the file data.
+//! Every record is acknowledged by the peer with a `\0` byte, or with `\x01`/`\x02` followed by an error message.
+//! `\x01` is a warning: the entry is skipped and the transfer goes on; `\x02` aborts the transfer.
+
+use std::fs::{self, File as LocalFile, FileTimes};
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use fsutil_core::fs::{Metadata, UnixPex};
+use fsutil_core::{RemoteError, RemoteErrorType, RemoteResult};
+
+/// Max length of a record line
+const MAX_RECORD_LEN: usize = 65536;
+
+/// Acknowledgement of a record by the peer
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Ack {
+    Ok,
+    /// The peer couldn't handle the record (`\x01`), but the transfer goes on
+    Warning(String),
+}
+
+/// Outcome of a tree transfer
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct TreeTransfer {
+    /// Amount of bytes of file data transferred
+    pub bytes: u64,
+    /// Warnings sent by the remote scp, for the entries which have been skipped
+    pub warnings: Vec<String>,
+}
+
+impl TreeTransfer {
+    /// Check the exit code of the remote scp once the transfer is over, and return the bytes transferred.
+    ///
+    /// scp exits with 1 whenever it sent or received a warning, so that is a partial success if there are any
+    pub fn finish(self, rc: i32) -> RemoteResult<u64> {
+        match rc {
+            0 => Ok(self.bytes),
+            1 if !self.warnings.is_empty() => {
+                warn!(
+                    "Remote scp skipped {} entries: {}",
+                    self.warnings.len(),
+                    self.warnings.join("; ")
+                );
+                Ok(self.bytes)
+            }
+            rc => {
+                error!("Remote scp exited with code {}", rc);
+                Err(RemoteError::new_ex(
+                    RemoteErrorType::ProtocolError,
+                    format!("scp exited with code {rc}"),
+                ))
+            }
+        }
+    }
+
+    /// Record a warning of the remote scp
+    fn warn(&mut self, msg: String) {
+        self.warnings.push(msg);
+    }
+}
+
+/// Access and modification times of an entry, as sent in `T` records
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Times {
+    modified: SystemTime,
+    accessed: SystemTime,
+}
+
+/// Upload local directory `local` through `stream`, connected to `scp -r -p -t`, naming it `name` on the remote side.
+///
+/// Returns the amount of bytes of file data written and the warnings of the remote scp.
+/// If the remote scp doesn't start the transfer, [`RemoteErrorType::UnsupportedFeature`] is returned,
+/// so that callers can fall back to a per-file transfer
+pub fn upload<S: Read + Write>(
+    stream: &mut S,
+    local: &Path,
+    name: &[u8],
+) -> RemoteResult<TreeTransfer> {
+    // the sink signals it's ready with an ack
+    let ready = match read_ack(stream) {
+        Ok(Ack::Ok) => Ok(()),
+        Ok(Ack::Warning(msg)) => Err(msg),
+        Err(err) => Err(err.to_string()),
+    };
+    ready.map_err(|err| {
+        warn!("Remote scp didn't start recursive transfer: {}", err);
+        RemoteError::new_ex(RemoteErrorType::UnsupportedFeature, err)
+    })?;
+    let metadata = fs::metadata(local).map_err(|err| {
+        RemoteError::new_ex(
+            RemoteErrorType::NoSuchFileOrDirectory,
+            format!("{}: {err}", local.display()),
+        )
+    })?;
+    if !metadata.is_dir() {
+        return Err(RemoteError::new_ex(
+            RemoteErrorType::BadFile,
+            format!("{} is not a directory", local.display()),
+        ));
+    }
+    // unlike its content, the directory itself can't be skipped
+    if let Ack::Warning(msg) = enter_dir(stream, name, &metadata)? {
+        error!("Remote scp failed: {}", msg);
+        return Err(RemoteError::new_ex(RemoteErrorType::ProtocolError, msg));
+    }
+    let mut transfer = TreeTransfer::default();
+    send_dir_content(stream, local, &mut transfer)?;
+    Ok(transfer)
+}
+
+/// Download a remote directory through `stream`, connected to `scp -r -p -f`, to local directory `local`.
+///
+/// Returns the amount of bytes of file data read and the warnings of the remote scp.
+/// If the remote scp closes the connection without sending anything, [`RemoteErrorType::UnsupportedFeature`] is returned
+pub fn download<S: Read + Write>(stream: &mut S, local: &Path) -> RemoteResult<TreeTransfer> {
+    let mut dirs: Vec<(PathBuf, Option<Times>)> = Vec::new();
+    let mut times: Option<Times> = None;
+    let mut transfer = TreeTransfer::default();
+    let mut started = false;
+    // the source starts sending once we're ready
+    write_ack(stream)?;
+    loop {
+        let Some(record) = read_record(stream)? else {
+            if let (false, Some(msg)) = (started, transfer.warnings.pop()) {
+                // e.g. the source doesn't exist
+                return Err(RemoteError::new_ex(RemoteErrorType::ProtocolError, msg));
+            }
+            if !started {
+                warn!("Remote scp closed the connection before sending any record");
+                return Err(RemoteError::new(RemoteErrorType::UnsupportedFeature));
+            }
+            return Err(RemoteError::new_ex(
+                RemoteErrorType::ProtocolError,
+                "connection closed during transfer",
+            ));
+        };
+        started |= !matches!(record.first(), Some(1));
+        match record.first() {
+            Some(b'T') => {
+                times = Some(parse_times(&record[1..])?);
+                write_ack(stream)?;
+            }
+            Some(b'D') => {
+                let (mode, _, name) = parse_entry(&record[1..])?;
+                let path = match dirs.last() {
+                    None => local.to_path_buf(),
+                    Some((parent, _)) => parent.join(name),
+                };
+                trace!("Entering directory {}", path.display());
+                if !path.is_dir() {
+                    fs::create_dir(path.as_path()).map_err(|err| local_error(&path, err))?;
+                }
+                set_mode(path.as_path(), mode)?;
+                dirs.push((path, times.take()));
+                write_ack(stream)?;
+            }
+            Some(b'E') => {
+                let Some((path, dir_times)) = dirs.pop() else {
+                    return Err(RemoteError::new_ex(
+                        RemoteErrorType::ProtocolError,
+                        "unexpected end of directory",
+                    ));
+                };
+                // set times once the content has been written
+                if let Some(dir_times) = dir_times {
+                    set_times(path.as_path(), dir_times)?;
+                }
+                write_ack(stream)?;
+                if dirs.is_empty() {
+                    break;
+                }
+            }
+            Some(b'C') => {
+                let (mode, size, name) = parse_entry(&record[1..])?;
+                let path = match dirs.last() {
+                    None => local.to_path_buf(),
+                    Some((parent, _)) => parent.join(name),
+                };
+                trace!("Receiving file {} ({} bytes)", path.display(), size);
+                write_ack(stream)?;
+                recv_file(stream, path.as_path(), mode, size, times.take())?;
+                transfer.bytes += size;
+                if let Ack::Warning(msg) = read_ack(stream)? {
+                    warn!("Remote scp failed to send {}: {}", path.display(), msg);
+                    transfer.warn(msg);
+                }
+                write_ack(stream)?;
+                if dirs.is_empty() {
+                    break;
+                }
+            }
+            Some(1) => {
+                let msg = String::from_utf8_lossy(&record[1..]).to_string();
+                warn!("Remote scp: {}", msg);
+                transfer.warn(msg);
+            }
+            Some(2) => {
+                let msg = String::from_utf8_lossy(&record[1..]).to_string();
+                error!("Remote scp failed: {}", msg);
+                return Err(RemoteError::new_ex(RemoteErrorType::ProtocolError, msg));
+            }
+            _ => {
+                return Err(RemoteError::new_ex(
+                    RemoteErrorType::ProtocolError,
+                    format!("unexpected record: {}", String::from_utf8_lossy(&record)),
+                ));
+            }
+        }
+    }
+    Ok(transfer)
+}
+
+/// Get the [`Metadata`] of a local entry, as sent by scp: size, mode and times
+pub fn local_metadata(metadata: &fs::Metadata) -> Metadata {
+    let mut local = Metadata::default()
+        .size(metadata.len())
+        .mode(UnixPex::from(mode_of(metadata) & 0o7777));
+    if let Ok(accessed) = metadata.accessed() {
+        local = local.accessed(accessed);
+    }
+    if let Ok(modified) = metadata.modified() {
+        local = local.modified(modified);
+    }
+    local
+}
+
+/// Apply mode and times in `metadata` to local entry at `path`, as scp does on receive
+pub fn set_local_metadata(path: &Path, metadata: &Metadata) -> RemoteResult<()> {
+    if let (Some(accessed), Some(modified)) = (metadata.accessed, metadata.modified) {
+        set_times(path, Times { modified, accessed })?;
+    }
+    match metadata.mode {
+        Some(mode) => set_mode(path, u32::from(mode)),
+        None => Ok(()),
+    }
+}
+
+// -- send
+
+/// Send directory at `path` as `name`, with its content
+fn send_dir<S: Read + Write>(
+    stream: &mut S,
+    path: &Path,
+    name: &[u8],
+    metadata: &fs::Metadata,
+    transfer: &mut TreeTransfer,
+) -> RemoteResult<()> {
+    trace!("Sending directory {}", path.display());
+    if let Ack::Warning(msg) = enter_dir(stream, name, metadata)? {
+        warn!("Skipping {}: {}", path.display(), msg);
+        transfer.warn(msg);
+        return Ok(());
+    }
+    send_dir_content(stream, path, transfer)
+}
+
+/// Send the `T` and `D` records of a directory named `name`
+fn enter_dir<S: Read + Write>(
+    stream: &mut S,
+    name: &[u8],
+    metadata: &fs::Metadata,
+) -> RemoteResult<Ack> {
+    match send_times(stream, metadata)? {
+        Ack::Ok => send_record(stream, b'D', mode_of(metadata), 0, name),
+        warning => Ok(warning),
+    }
+}
+
+/// Send the entries of directory at `path`, then leave it
+fn send_dir_content<S: Read + Write>(
+    stream: &mut S,
+    path: &Path,
+    transfer: &mut TreeTransfer,
+) -> RemoteResult<()> {
+    let mut entries: Vec<PathBuf> = fs::read_dir(path)
+        .map_err(|err| local_error(path, err))?
+        .map(|x| x.map(|x| x.path()))
+        .collect::<io::Result<_>>()
+        .map_err(|err| local_error(path, err))?;
+    entries.sort();
+    for entry in entries {
+        // like scp, follow symbolic links
+        let metadata = fs::metadata(entry.as_path()).map_err(|err| local_error(&entry, err))?;
+        let name = file_name_bytes(entry.as_path());
+        if metadata.is_dir() {
+            send_dir(
+                stream,
+                entry.as_path(),
+                name.as_slice(),
+                &metadata,
+                transfer,
+            )?;
+        } else if metadata.is_file() {
+            send_file(
+                stream,
+                entry.as_path(),
+                name.as_slice(),
+                &metadata,
+                transfer,
+            )?;
+        } else {
+            warn!("Skipping {}: not a regular file", entry.display());
+        }
+    }
+    stream.write_all(b"E\n").map_err(io_error)?;
+    if let Ack::Warning(msg) = read_ack(stream)? {
+        warn!("Remote scp failed to complete {}: {}", path.display(), msg);
+        transfer.warn(msg);
+    }
+    Ok(())
+}
+
+/// Send file at `path` as `name`
+fn send_file<S: Read + Write>(
+    stream: &mut S,
+    path: &Path,
+    name: &[u8],
+    metadata: &fs::Metadata,
+    transfer: &mut TreeTransfer,
+) -> RemoteResult<()> {
+    trace!("Sending file {} ({} bytes)", path.display(), metadata.len());
+    let mut file = LocalFile::open(path).map_err(|err| local_error(path, err))?;
+    if let Ack::Warning(msg) = send_times(stream, metadata)? {
+        warn!("Skipping {}: {}", path.display(), msg);
+        transfer.warn(msg);
+        return Ok(());
+    }
+    if let Ack::Warning(msg) = send_record(stream, b'C', mode_of(metadata), metadata.len(), name)? {
+        warn!("Skipping {}: {}", path.display(), msg);
+        transfer.warn(msg);
+        return Ok(());
+    }
+    let written = io::copy(&mut (&mut file).take(metadata.len()), stream).map_err(io_error)?;
+    if written != metadata.len() {
+        return Err(RemoteError::new_ex(
+            RemoteErrorType::IoError,
+            format!("{} changed during transfer", path.display()),
+        ));
+    }
+    write_ack(stream)?;
+    match read_ack(stream)? {
+        Ack::Ok => transfer.bytes += written,
+        Ack::Warning(msg) => {
+            warn!("Remote scp failed to write {}: {}", path.display(), msg);
+            transfer.warn(msg);
+        }
+    }
+    Ok(())
+}
+
+/// Send `T` record with the times in `metadata`
+fn send_times<S: Read + Write>(stream: &mut S, metadata: &fs::Metadata) -> RemoteResult<Ack> {
+    let secs = |t: io::Result<SystemTime>| {
+        t.ok()
+            .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+            .unwrap_or(Duration::ZERO)
+            .as_secs()
+    };
+    let modified = secs(metadata.modified());
+    let accessed = secs(metadata.accessed());
+    writeln!(stream, "T{modified} 0 {accessed} 0").map_err(io_error)?;
+    read_ack(stream)
+}
+
+/// Send `D` or `C` record and wait for the ack
+fn send_record<S: Read + Write>(
+    stream: &mut S,
+    kind: u8,
+    mode: u32,
+    size: u64,
+    name: &[u8],
+) -> RemoteResult<Ack> {
+    if name.is_empty() || name.contains(&b'\n') || name.contains(&b'/') {
+        return Err(RemoteError::new_ex(
+            RemoteErrorType::BadFile,
+            format!(
+                "file name can't be sent with scp: {:?}",
+                String::from_utf8_lossy(name)
+            ),
+        ));
+    }
+    let mut record = format!("{}{:04o} {size} ", kind as char, mode & 0o7777).into_bytes();
+    record.extend_from_slice(name);
+    record.push(b'\n');
+    stream.write_all(record.as_slice()).map_err(io_error)?;
+    read_ack(stream)
+}
+
+// -- receive
+
+/// Receive `size` bytes of file data into local file at `path`
+fn recv_file<S: Read>(
+    stream: &mut S,
+    path: &Path,
+    mode: u32,
+    size: u64,
+    times: Option<Times>,
+) -> RemoteResult<()> {
+    let mut file = LocalFile::create(path).map_err(|err| local_error(path, err))?;
+    let read = io::copy(&mut stream.take(size), &mut file).map_err(io_error)?;
+    if read != size {
+        return Err(RemoteError::new_ex(
+            RemoteErrorType::ProtocolError,
+            "connection closed during transfer",
+        ));
+    }
+    drop(file);
+    if let Some(times) = times {
+        set_times(path, times)?;
+    }
+    set_mode(path, mode)
+}
+
+/// Parse the `<mode> <size> <name>` part of `D` and `C` records
+fn parse_entry(record: &[u8]) -> RemoteResult<(u32, u64, &str)> {
+    let bad_record = || {
+        RemoteError::new_ex(
+            RemoteErrorType::ProtocolError,
+            format!("bad record: {}", String::from_utf8_lossy(record)),
+        )
+    };
+    let record = std::str::from_utf8(record).map_err(|_| bad_record())?;
+    let mut tokens = record.splitn(3, ' ');
+    let mode = tokens
+        .next()
+        .and_then(|x| u32::from_str_radix(x, 8).ok())
+        .ok_or_else(bad_record)?;
+    let size = tokens
+        .next()
+        .and_then(|x| x.parse::<u64>().ok())
+        .ok_or_else(bad_record)?;
+    let name = tokens.next().ok_or_else(bad_record)?;
+    // never let the remote write outside of the destination
+    if name.is_empty() || name == "." || name == ".." || name.contains(['/', '\\']) {
+        return Err(RemoteError::new_ex(
+            RemoteErrorType::ProtocolError,
+            format!("invalid file name: {name}"),
+        ));
+    }
+    Ok((mode, size, name))
+}
+
+/// Parse the `<mtime> 0 <atime> 0` part of `T` records
+fn parse_times(record: &[u8]) -> RemoteResult<Times> {
+    let bad_record = || {
+        RemoteError::new_ex(
+            RemoteErrorType::ProtocolError,
+            format!("bad record: {}", String::from_utf8_lossy(record)),
+        )
+    };
+    let tokens: Vec<u64> = std::str::from_utf8(record)
+        .map_err(|_| bad_record())?
+        .split(' ')
+        .map(|x| x.parse::<u64>().map_err(|_| bad_record()))
+        .collect::<RemoteResult<_>>()?;
+    match tokens.as_slice() {
+        [modified, _, accessed, _] => Ok(Times {
+            modified: UNIX_EPOCH + Duration::from_secs(*modified),
+            accessed: UNIX_EPOCH + Duration::from_secs(*accessed),
+        }),
+        _ => Err(bad_record()),
+    }
+}
+
+// -- protocol utils
+
+/// Read a record line, without the trailing newline. Returns `None` on end of stream
+fn read_record<S: Read>(stream: &mut S) -> RemoteResult<Option<Vec<u8>>> {
+    let mut record: Vec<u8> = Vec::new();
+    let mut byte = [0u8; 1];
+    loop {
+        match stream.read(&mut byte) {
+            Ok(0) if record.is_empty() => return Ok(None),
+            Ok(0) => {
+                return Err(RemoteError::new_ex(
+                    RemoteErrorType::ProtocolError,
+                    "connection closed during transfer",
+                ));
+            }
+            Ok(_) if byte[0] == b'\n' => return Ok(Some(record)),
+            Ok(_) if record.len() >= MAX_RECORD_LEN => {
+                return Err(RemoteError::new_ex(
+                    RemoteErrorType::ProtocolError,
+                    "record too long",
+                ));
+            }
+            Ok(_) => record.push(byte[0]),
+            Err(err) if err.kind() == io::ErrorKind::Interrupted => continue,
+            Err(err) => return Err(io_error(err)),
+        }
+    }
+}
+
+/// Read the peer acknowledgement. A fatal error (`\x02`) is returned as an error
+fn read_ack<S: Read>(stream: &mut S) -> RemoteResult<Ack> {
+    let mut byte = [0u8; 1];
+    stream.read_exact(&mut byte).map_err(|err| {
+        RemoteError::new_ex(
+            RemoteErrorType::ProtocolError,
+            format!("remote scp closed the connection: {err}"),
+        )
+    })?;
+    if byte[0] == 0 {
+        return Ok(Ack::Ok);
+    }
+    let msg = read_record(stream)?
+        .map(|x| String::from_utf8_lossy(x.as_slice()).to_string())
+        .unwrap_or_default();
+    if byte[0] == 1 {
+        return Ok(Ack::Warning(msg));
+    }
+    error!("Remote scp failed: {}", msg);
+    Err(RemoteError::new_ex(RemoteErrorType::ProtocolError, msg))
+}
+
+/// Acknowledge the last record
+fn write_ack<S: Write>(stream: &mut S) -> RemoteResult<()> {
+    stream.write_all(&[0]).map_err(io_error)
+}
+
+fn io_error(err: io::Error) -> RemoteError {
+    RemoteError::new_ex(RemoteErrorType::IoError, err)
+}
+
+fn local_error(path: &Path, err: io::Error) -> RemoteError {
+    RemoteError::new_ex(
+        RemoteErrorType::IoError,
+        format!("{}: {err}", path.display()),
+    )
+}
+
+// -- local fs
+
+#[cfg(unix)]
+pub fn file_name_bytes(p: &Path) -> Vec<u8> {
+    use std::os::unix::ffi::OsStrExt as _;
+
+    p.file_name()
+        .map(|x| x.as_bytes().to_vec())
+        .unwrap_or_default()
+}
+
+#[cfg(windows)]
+pub fn file_name_bytes(p: &Path) -> Vec<u8> {
+    p.file_name()
+        .map(|x| x.to_string_lossy().as_bytes().to_vec())
+        .unwrap_or_default()
+}
+
+#[cfg(unix)]
+fn mode_of(metadata: &fs::Metadata) -> u32 {
+    use std::os::unix::fs::PermissionsExt as _;
+
+    metadata.permissions().mode()
+}
+
+#[cfg(windows)]
+fn mode_of(metadata: &fs::Metadata) -> u32 {
+    match (metadata.is_dir(), metadata.permissions().readonly()) {
+        (true, _) => 0o755,
+        (false, true) => 0o444,
+        (false, false) => 0o644,
+    }
+}
+
+#[cfg(unix)]
+fn set_mode(path: &Path, mode: u32) -> RemoteResult<()> {
+    use std::os::unix::fs::PermissionsExt as _;
+
+    fs::set_permissions(path, fs::Permissions::from_mode(mode & 0o7777))
+        .map_err(|err| local_error(path, err))
+}
+
+#[cfg(windows)]
+fn set_mode(_path: &Path, _mode: u32) -> RemoteResult<()> {
+    Ok(())
+}
+
+#[cfg(unix)]
+fn set_times(path: &Path, times: Times) -> RemoteResult<()> {
+    LocalFile::open(path)
+        .and_then(|file| {
+            file.set_times(
+                FileTimes::new()
+                    .set_modified(times.modified)
+                    .set_accessed(times.accessed),
+            )
+        })
+        .map_err(|err| local_error(path, err))
+}
+
+#[cfg(windows)]
+fn set_times(path: &Path, times: Times) -> RemoteResult<()> {
+    // directories can't be opened for writing on windows
+    if path.is_dir() {
+        return Ok(());
+    }
+    fs::OpenOptions::new()
+        .write(true)
+        .open(path)
+        .and_then(|file| {
+            file.set_times(
+                FileTimes::new()
+                    .set_modified(times.modified)
+                    .set_accessed(times.accessed),
+            )
+        })
+        .map_err(|err| local_error(path, err))
+}
+
+#[cfg(test)]
+mod test {
+
+    use std::io::Cursor;
+
+    use pretty_assertions::assert_eq;
+    use tempfile::TempDir;
+
+    use super::*;
+
+    /// Peer replaying `input` and recording what is written to it
+    struct Peer {
+        input: Cursor<Vec<u8>>,
+        output: Vec<u8>,
+    }
+
+    impl Peer {
+        fn new(input: &[u8]) -> Self {
+            Self {
+                input: Cursor::new(input.to_vec()),
+                output: Vec::new(),
+            }
+        }
+    }
+
+    impl Read for Peer {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            self.input.read(buf)
+        }
+    }
+
+    impl Write for Peer {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.output.write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn set_times(path: &Path, secs: u64) {
+        LocalFile::open(path)
+            .unwrap()
+            .set_times(
+                FileTimes::new()
+                    .set_modified(UNIX_EPOCH + Duration::from_secs(secs))
+                    .set_accessed(UNIX_EPOCH + Duration::from_secs(secs)),
+            )
+            .unwrap();
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn should_upload_tree() {
+        let tempdir = TempDir::new().unwrap();
+        let root = tempdir.path().join("root");
+        fs::create_dir_all(root.join("sub")).unwrap();
+        fs::write(root.join("a.txt"), b"hello").unwrap();
+        fs::write(root.join("sub").join("b.txt"), b"").unwrap();
+        set_mode(root.as_path(), 0o755).unwrap();
+        set_mode(root.join("sub").as_path(), 0o700).unwrap();
+        set_mode(root.join("a.txt").as_path(), 0o644).unwrap();
+        set_mode(root.join("sub").join("b.txt").as_path(), 0o600).unwrap();
+        set_times(root.join("a.txt").as_path(), 1000);
+        set_times(root.join("sub").join("b.txt").as_path(), 2000);
+        set_times(root.join("sub").as_path(), 3000);
+        set_times(root.as_path(), 4000);
+        // the peer acks everything
+        let mut peer = Peer::new(&[0; 64]);
+        assert_eq!(
+            upload(&mut peer, root.as_path(), b"dest").unwrap(),
+            TreeTransfer {
+                bytes: 5,
+                warnings: Vec::new()
+            }
+        );
+        assert_eq!(
+            String::from_utf8(peer.output).unwrap(),
+            "T4000 0 4000 0\nD0755 0 dest\nT1000 0 1000 0\nC0644 5 a.txt\nhello\0T3000 0 3000 0\nD0700 0 sub\nT2000 0 2000 0\nC0600 0 b.txt\n\0E\nE\n"
+        );
+    }
+
+    #[test]
+    fn should_not_upload_when_remote_scp_does_not_start() {
+        let tempdir = TempDir::new().unwrap();
+        let mut peer = Peer::new(b"\x01scp: illegal option -- r\n");
+        assert_eq!(
+            upload(&mut peer, tempdir.path(), b"dest").unwrap_err().kind,
+            RemoteErrorType::UnsupportedFeature
+        );
+        let mut peer = Peer::new(b"");
+        assert_eq!(
+            upload(&mut peer, tempdir.path(), b"dest").unwrap_err().kind,
+            RemoteErrorType::UnsupportedFeature
+        );
+    }
+
+    #[test]
+    fn should_report_remote_errors_on_upload() {
+        let tempdir = TempDir::new().unwrap();
+        let mut peer = Peer::new(b"\0\0\x01scp: /etc/dest: Permission denied\n");
+        let err = upload(&mut peer, tempdir.path(), b"dest").unwrap_err();
+        assert_eq!(err.kind, RemoteErrorType::ProtocolError);
+        assert_eq!(
+            err.msg.as_deref(),
+            Some("scp: /etc/dest: Permission denied")
+        );
+    }
+
+    #[test]
+    fn should_skip_entries_on_upload_warnings() {
+        let tempdir = TempDir::new().unwrap();
+        fs::write(tempdir.path().join("a.txt"), b"hello").unwrap();
+        fs::write(tempdir.path().join("b.txt"), b"bye").unwrap();
+        // `C` record of a.txt is refused
+        let mut peer = Peer::new(b"\0\0\0\0\x01scp: a.txt: Permission denied\n\0\0\0\0");
+        let transfer = upload(&mut peer, tempdir.path(), b"dest").unwrap();
+        assert_eq!(transfer.bytes, 3);
+        assert_eq!(
+            transfer.warnings,
+            vec!["scp: a.txt: Permission denied".to_string()]
+        );
+        let output = String::from_utf8(peer.output).unwrap();
+        assert!(!output.contains("hello"));
+        assert!(output.contains("bye"));
+        // fatal error aborts the transfer
+        let mut peer = Peer::new(b"\0\0\0\0\x02scp: protocol error\n\0\0\0\0");
+        let err = upload(&mut peer, tempdir.path(), b"dest").unwrap_err();
+        assert_eq!(err.kind, RemoteErrorType::ProtocolError);
+        assert_eq!(err.msg.as_deref(), Some("scp: protocol error"));
+    }
+
+    #[test]
+    fn should_finish_transfer_with_warnings() {
+        let transfer = TreeTransfer {
+            bytes: 3,
+            warnings: vec!["scp: a.txt: Permission denied".to_string()],
+        };
+        assert_eq!(transfer.clone().finish(0).unwrap(), 3);
+        // scp exits with 1 after a warning
+        assert_eq!(transfer.clone().finish(1).unwrap(), 3);
+        assert_eq!(
+            transfer.finish(2).unwrap_err().kind,
+            RemoteErrorType::ProtocolError
+        );
+        let transfer = TreeTransfer {
+            bytes: 3,
+            warnings: Vec::new(),
+        };
+        assert!(transfer.finish(1).is_err());
+    }
+
+    #[test]
+    fn should_download_tree() {
+        let tempdir = TempDir::new().unwrap();
+        let root = tempdir.path().join("root");
+        let mut peer = Peer::new(
+            b"T4000 0 4000 0\nD0755 0 src\nT1000 0 1000 0\nC0644 5 a.txt\nhello\0D0700 0 sub\nC0600 0 b.txt\n\0E\nE\n",
+        );
+        assert_eq!(
+            download(&mut peer, root.as_path()).unwrap(),
+            TreeTransfer {
+                bytes: 5,
+                warnings: Vec::new()
+            }
+        );
+        // one ack to start, then one per record, plus one per file
+        assert_eq!(peer.output, vec![0; 11]);
+        assert_eq!(fs::read(root.join("a.txt")).unwrap(), b"hello");
+        assert_eq!(fs::read(root.join("sub").join("b.txt")).unwrap(), b"");
+        let modified = |p: PathBuf| fs::metadata(p).unwrap().modified().unwrap();
+        assert_eq!(
+            modified(root.join("a.txt")),
+            UNIX_EPOCH + Duration::from_secs(1000)
+        );
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt as _;
+
+            let mode = |p: PathBuf| fs::metadata(p).unwrap().permissions().mode() & 0o7777;
+            assert_eq!(
+                modified(root.clone()),
+                UNIX_EPOCH + Duration::from_secs(4000)
+            );
+            assert_eq!(mode(root.join("sub")), 0o700);
+            assert_eq!(mode(root.join("sub").join("b.txt")), 0o600);
+        }
+    }
+
+    #[test]
+    fn should_not_download_outside_of_destination() {
+        let tempdir = TempDir::new().unwrap();
+        for record in [
+            b"D0755 0 src\nC0644 5 ../a.txt\nhello\0E\n".as_slice(),
+            b"D0755 0 src\nD0755 0 ..\nE\nE\n".as_slice(),
+            b"D0755 0 src\nC0644 5 a/b.txt\nhello\0E\n".as_slice(),
+        ] {
+            let mut peer = Peer::new(record);
+            assert_eq!(
+                download(&mut peer, tempdir.path().join("root").as_path())
+                    .unwrap_err()
+                    .kind,
+                RemoteErrorType::ProtocolError
+            );
+        }
+        assert!(!tempdir.path().join("a.txt").exists());
+    }
+
+    #[test]
+    fn should_report_remote_errors_on_download() {
+        let tempdir = TempDir::new().unwrap();
+        let mut peer = Peer::new(b"\x01scp: /src: No such file or directory\n");
+        let err = download(&mut peer, tempdir.path().join("root").as_path()).unwrap_err();
+        assert_eq!(
+            err.msg.as_deref(),
+            Some("scp: /src: No such file or directory")
+        );
+        // warnings are skipped, errors abort the transfer
+        let mut peer =
+            Peer::new(b"D0755 0 src\n\x01scp: a.txt: Permission denied\nC0644 5 b.txt\nhello\0E\n");
+        assert_eq!(
+            download(&mut peer, tempdir.path().join("root").as_path()).unwrap(),
+            TreeTransfer {
+                bytes: 5,
+                warnings: vec!["scp: a.txt: Permission denied".to_string()]
+            }
+        );
+        let mut peer =
+            Peer::new(b"D0755 0 src\n\x02scp: protocol error\nC0644 5 b.txt\nhello\0E\n");
+        assert!(download(&mut peer, tempdir.path().join("other").as_path()).is_err());
+        assert!(!tempdir.path().join("other").join("b.txt").exists());
+        // truncated transfer
+        let mut peer = Peer::new(b"D0755 0 src\nC0644 5 a.txt\nhel");
+        assert!(download(&mut peer, tempdir.path().join("root").as_path()).is_err());
+    }
+
+    #[test]
+    fn should_parse_records() {
+        assert_eq!(
+            parse_entry(b"0644 12 file with spaces.txt").unwrap(),
+            (0o644, 12, "file with spaces.txt")
+        );
+        assert!(parse_entry(b"0644 file").is_err());
+        assert!(parse_entry(b"xyz 12 file").is_err());
+        assert_eq!(
+            parse_times(b"1000 0 2000 0").unwrap(),
+            Times {
+                modified: UNIX_EPOCH + Duration::from_secs(1000),
+                accessed: UNIX_EPOCH + Duration::from_secs(2000),
+            }
+        );
+        assert!(parse_times(b"1000 0").is_err());
+    }
+}
@@ -15,15 +15,32 @@ use ssh2::{FileStat, OpenFlags, OpenType, RenameFlags};
 // -- export
 pub use ssh2::{Session as SshSession, Sftp as SshSftp};
 
-use super::{commons, SftpReadStream, SftpWriteStream, SshOpts};
-use crate::utils::path as path_utils;
+use super::keepalive::Keepalive;
+use super::sftp_ext::{
+    SftpExtChannel, SftpExtension, StatVfs, SSH_FXF_APPEND, SSH_FXF_CREAT, SSH_FXF_TRUNC,
+    SSH_FXF_WRITE,
+};
+use super::sudo::{Elevation, Sudo};
+use super::{
+    commons, pipeline, ExecHandle, ExecOptions, ExecWriteStatus, PipelineOpts, SftpReadStream,
+    SftpWriteStream, SshOpts,
+};
+use crate::utils::{path as path_utils, shell as shell_utils};
 
 /// Sftp "filesystem" client
 pub struct SftpFileSystem {
     session: Option<SshSession>,
+    keepalive: Option<Keepalive>,
     sftp: Option<SshSftp>,
+    ext: Option<SftpExtChannel>,
     wrkdir: PathBuf,
     opts: SshOpts,
+    fsync: bool,
+    pipeline: Option<PipelineOpts>,
+    sudo: Option<Sudo>,
+    elevation: Option<Elevation>,
+    /// Outcome of the sudo command behind the last write stream, checked by `on_written`
+    write_status: Option<ExecWriteStatus>,
 }
 
 impl SftpFileSystem {
@@ -31,9 +48,49 @@ impl SftpFileSystem {
     pub fn new(opts: SshOpts) -> Self {
         Self {
             session: None,
+            keepalive: None,
             sftp: None,
+            ext: None,
             wrkdir: PathBuf::from("/"),
             opts,
+            fsync: false,
+            pipeline: Some(PipelineOpts::default()),
+            sudo: None,
+            elevation: None,
+            write_status: None,
+        }
+    }
+
+    /// Run the operations which mutate the remote file system through `sudo`, instead of through SFTP.
+    /// Sudo access is checked on the first elevated operation
+    pub fn sudo(mut self, sudo: Sudo) -> Self {
+        self.sudo = Some(sudo);
+        self
+    }
+
+    /// Set the options for the pipelined transfers used by `create_file`, `append_file` and `open_file`.
+    /// Pipelining is enabled by default; pass `None` to transfer files through libssh2 instead
+    pub fn pipeline(mut self, opts: Option<PipelineOpts>) -> Self {
+        self.pipeline = opts;
+        self
+    }
+
+    /// Synchronize files to disk once they've been written through `create` or `append`.
+    /// Requires the `fsync@openssh.com` extension; if the server doesn't support it, this option is ignored
+    pub fn fsync_on_write(mut self, fsync: bool) -> Self {
+        self.fsync = fsync;
+        self
+    }
+
+    /// Send a keepalive message to the server, if one is due.
+    ///
+    /// Keepalive messages are also sent before each operation, but callers which leave the session
+    /// idle for long should call this method periodically.
+    /// Returns the time before the next keepalive message is due, or `None` if keepalive is disabled
+    pub fn keepalive_send(&mut self) -> RemoteResult<Option<Duration>> {
+        match (self.session.as_ref(), self.keepalive.as_ref()) {
+            (Some(session), Some(keepalive)) => keepalive.send(session),
+            _ => Err(RemoteError::new(RemoteErrorType::NotConnected)),
         }
     }
 
@@ -47,14 +104,175 @@ impl SftpFileSystem {
         self.sftp.as_mut()
     }
 
+    /// Execute a command in the current working directory and return an [`ExecHandle`]
+    /// which streams its output as it arrives.
+    ///
+    /// Use this method instead of [`RemoteFileSystem::exec`] for long running commands
+    pub fn exec_stream(&mut self, cmd: &str, opts: ExecOptions) -> RemoteResult<ExecHandle> {
+        self.check_connection()?;
+        debug!(r#"Spawning command "{}""#, cmd);
+        commons::spawn_shell_cmd_at(
+            self.session.as_mut().unwrap(),
+            cmd,
+            self.wrkdir.as_path(),
+            &opts,
+        )
+    }
+
+    /// Returns whether the server supports the protocol `extension`
+    pub fn has_extension(&self, extension: SftpExtension) -> bool {
+        self.ext
+            .as_ref()
+            .map(|x| x.supports(extension))
+            .unwrap_or(false)
+    }
+
+    /// Create a hard link at `path` pointing to `target`.
+    /// Requires the `hardlink@openssh.com` extension
+    pub fn hardlink(&mut self, path: &Path, target: &Path) -> RemoteResult<()> {
+        self.check_connection()?;
+        let path = path_utils::absolutize(self.wrkdir.as_path(), path);
+        let target = path_utils::absolutize(self.wrkdir.as_path(), target);
+        debug!(
+            "Creating hard link at {} pointing to {}",
+            path.display(),
+            target.display()
+        );
+        self.ext_channel()?
+            .hardlink(path.as_path(), target.as_path())
+    }
+
+    /// Get statistics of the file system containing `path`, such as its size and free space.
+    /// Requires the `statvfs@openssh.com` extension
+    pub fn statvfs(&mut self, path: &Path) -> RemoteResult<StatVfs> {
+        self.check_connection()?;
+        let path = path_utils::absolutize(self.wrkdir.as_path(), path);
+        debug!("Getting file system statistics for {}", path.display());
+        self.ext_channel()?.statvfs(path.as_path())
+    }
+
     // -- private
 
-    /// Check connection status
-    fn check_connection(&mut self) -> RemoteResult<()> {
-        if self.is_connected() {
+    /// Get the extensions channel
+    fn ext_channel(&mut self) -> RemoteResult<&mut SftpExtChannel> {
+        self.ext.as_mut().ok_or_else(|| {
+            RemoteError::new_ex(
+                RemoteErrorType::UnsupportedFeature,
+                "SFTP extensions are not available",
+            )
+        })
+    }
+
+    /// Get sudo access, checking it on first use; `None` if elevation is disabled
+    fn elevation(&mut self) -> RemoteResult<Option<Elevation>> {
+        let Some(sudo) = self.sudo else {
+            return Ok(None);
+        };
+        if self.elevation.is_none() {
+            let session = self
+                .session
+                .as_mut()
+                .ok_or_else(|| RemoteError::new(RemoteErrorType::NotConnected))?;
+            self.elevation = Some(Elevation::check(
+                session,
+                sudo,
+                self.opts.password.as_deref(),
+            )?);
+        }
+        Ok(self.elevation.clone())
+    }
+
+    /// Upload `reader` to `path` with a pipelined transfer, if available.
+    /// Returns `None` if pipelining is disabled or not available, or if writes are elevated through sudo
+    fn pipelined_upload(
+        &mut self,
+        path: &Path,
+        metadata: &Metadata,
+        reader: &mut dyn Read,
+        flags: u32,
+    ) -> Option<RemoteResult<u64>> {
+        if self.sudo.is_some() {
+            return None;
+        }
+        let opts = self.pipeline?;
+        let fsync = self.fsync && self.has_extension(SftpExtension::Fsync);
+        let ext = self.ext.as_mut()?;
+        let path = path_utils::absolutize(self.wrkdir.as_path(), path);
+        let mode = metadata.mode.map(u32::from).unwrap_or(0o644);
+        debug!("Uploading file to {} (pipelined)", path.display());
+        // like the non pipelined transfer, write at most `metadata.size` bytes
+        let mut reader = reader.take(metadata.size);
+        Some(
+            pipeline::upload(ext, path.as_path(), &mut reader, flags, mode, fsync, &opts).map_err(
+                |e| {
+                    error!("Upload failed: {}", e);
+                    e
+                },
+            ),
+        )
+    }
+
+    /// Copy `src` to `dest` on the server side with the `copy-data` extension, recursing into directories.
+    /// Symbolic links are copied as links
+    fn copy_with_extension(&mut self, src: &File, dest: &Path) -> RemoteResult<()> {
+        let mode = src.metadata.mode.map(u32::from).unwrap_or(0o644);
+        if let Some(target) = src.metadata.symlink.as_deref() {
+            trace!(
+                "Copying symlink {} to {}",
+                src.path.display(),
+                dest.display()
+            );
+            self.sftp
+                .as_ref()
+                .unwrap()
+                .symlink(target, dest)
+                .map_err(|e| {
+                    error!("Symlink failed: {}", e);
+                    RemoteError::new_ex(RemoteErrorType::FileCreateDenied, e)
+                })
+        } else if src.is_dir() {
+            trace!(
+                "Copying directory {} to {}",
+                src.path.display(),
+                dest.display()
+            );
+            // merge into an existing directory, as `cp -rf` does
+            match self.create_dir(dest, UnixPex::from(mode)) {
+                Err(err) if err.kind == RemoteErrorType::DirectoryAlreadyExists => {
+                    trace!("Directory {} already exists", dest.display());
+                }
+                result => result?,
+            }
+            for entry in self.list_dir(src.path.as_path())? {
+                self.copy_with_extension(&entry, dest.join(entry.name()).as_path())?;
+            }
             Ok(())
         } else {
-            Err(RemoteError::new(RemoteErrorType::NotConnected))
+            trace!("Copying file {} to {}", src.path.display(), dest.display());
+            self.ext_channel()?
+                .copy_data(src.path.as_path(), dest, mode)
+        }
+    }
+
+    /// Fail if `dest` is `src` or is inside it, which would make a copy recurse endlessly
+    fn check_copy_into_itself(src: &Path, dest: &Path) -> RemoteResult<()> {
+        if dest.starts_with(src) {
+            error!("Can't copy {} into itself", src.display());
+            return Err(RemoteError::new_ex(
+                RemoteErrorType::BadAddress,
+                "can't copy a file into itself",
+            ));
+        }
+        Ok(())
+    }
+
+    /// Check connection status, sending a keepalive message if one is due
+    fn check_connection(&mut self) -> RemoteResult<()> {
+        match (self.session.as_ref(), self.keepalive.as_ref()) {
+            (Some(session), Some(keepalive)) if session.authenticated() => {
+                keepalive.send(session).map(|_| ())
+            }
+            _ => Err(RemoteError::new(RemoteErrorType::NotConnected)),
         }
     }
 
@@ -142,7 +360,7 @@ impl SftpFileSystem {
 impl RemoteFileSystem for SftpFileSystem {
     fn connect(&mut self) -> RemoteResult<Welcome> {
         debug!("Initializing SFTP connection...");
-        let session = commons::connect(&self.opts)?;
+        let (session, keepalive) = commons::connect(&self.opts)?;
         // Set blocking to true
         session.set_blocking(true);
         // Get Sftp client
@@ -160,7 +378,18 @@ impl RemoteFileSystem for SftpFileSystem {
             Ok(p) => p,
             Err(err) => return Err(RemoteError::new_ex(RemoteErrorType::ProtocolError, err)),
         };
+        // Open channel for protocol extensions
+        debug!("Getting SFTP extensions...");
+        self.ext = match SftpExtChannel::open(&session) {
+            Ok(ext) => Some(ext),
+            Err(err) => {
+                warn!("Could not get SFTP extensions: {}", err);
+                None
+            }
+        };
         self.session = Some(session);
+        self.keepalive = Some(keepalive);
+        self.elevation = None;
         self.sftp = Some(sftp);
         let banner: Option<String> = self.session.as_ref().unwrap().banner().map(String::from);
         debug!(
@@ -179,7 +408,10 @@ impl RemoteFileSystem for SftpFileSystem {
                 Ok(_) => {
                     // Set session and sftp to none
                     self.session = None;
+                    self.keepalive = None;
+                    self.elevation = None;
                     self.sftp = None;
+                    self.ext = None;
                     Ok(())
                 }
                 Err(err) => Err(RemoteError::new_ex(RemoteErrorType::ConnectionError, err)),
@@ -190,10 +422,25 @@ impl RemoteFileSystem for SftpFileSystem {
     }
 
     fn is_connected(&mut self) -> bool {
-        self.session
-            .as_ref()
-            .map(|x| x.authenticated())
-            .unwrap_or(false)
+        let alive = match (
+            self.session.as_ref(),
+            self.sftp.as_ref(),
+            self.keepalive.as_ref(),
+        ) {
+            (Some(session), Some(sftp), Some(keepalive)) if session.authenticated() => {
+                keepalive.probe(session, || sftp.realpath(Path::new(".")).is_ok())
+            }
+            _ => return false,
+        };
+        if !alive {
+            // drop dead session
+            self.ext = None;
+            self.sftp = None;
+            self.session = None;
+            self.keepalive = None;
+            self.elevation = None;
+        }
+        alive
     }
 
     fn pwd(&mut self) -> RemoteResult<PathBuf> {
@@ -251,6 +498,11 @@ impl RemoteFileSystem for SftpFileSystem {
     }
 
     fn setstat(&mut self, path: &Path, metadata: Metadata) -> RemoteResult<()> {
+        if let Some(elevation) = self.elevation()? {
+            let path = path_utils::absolutize(self.wrkdir.as_path(), path);
+            debug!("Setting metadata for {} (sudo)", path.display());
+            return elevation.setstat(self.session.as_mut().unwrap(), path.as_path(), &metadata);
+        }
         if let Some(sftp) = self.sftp.as_ref() {
             let path = path_utils::absolutize(self.wrkdir.as_path(), path);
             debug!("Setting metadata for {}", path.display());
@@ -277,6 +529,15 @@ impl RemoteFileSystem for SftpFileSystem {
     }
 
     fn remove_file(&mut self, path: &Path) -> RemoteResult<()> {
+        if let Some(elevation) = self.elevation()? {
+            let path = path_utils::absolutize(self.wrkdir.as_path(), path);
+            debug!("Remove file {} (sudo)", path.display());
+            return elevation.execute(
+                self.session.as_mut().unwrap(),
+                format!("rm -f {}", shell_utils::quote_path(path.as_path())).as_str(),
+                RemoteErrorType::CouldNotRemoveFile,
+            );
+        }
         if let Some(sftp) = self.sftp.as_ref() {
             let path = path_utils::absolutize(self.wrkdir.as_path(), path);
             debug!("Remove file {}", path.display());
@@ -290,6 +551,15 @@ impl RemoteFileSystem for SftpFileSystem {
     }
 
     fn remove_dir(&mut self, path: &Path) -> RemoteResult<()> {
+        if let Some(elevation) = self.elevation()? {
+            let path = path_utils::absolutize(self.wrkdir.as_path(), path);
+            debug!("Remove dir {} (sudo)", path.display());
+            return elevation.execute(
+                self.session.as_mut().unwrap(),
+                format!("rmdir {}", shell_utils::quote_path(path.as_path())).as_str(),
+                RemoteErrorType::CouldNotRemoveFile,
+            );
+        }
         if let Some(sftp) = self.sftp.as_ref() {
             let path = path_utils::absolutize(self.wrkdir.as_path(), path);
             debug!("Remove dir {}", path.display());
@@ -315,6 +585,18 @@ impl RemoteFileSystem for SftpFileSystem {
             error!("directory {} already exists", path.display());
             return Err(RemoteError::new(RemoteErrorType::DirectoryAlreadyExists));
         }
+        if let Some(elevation) = self.elevation()? {
+            return elevation.execute(
+                self.session.as_mut().unwrap(),
+                format!(
+                    "mkdir -m {:o} {}",
+                    u32::from(mode),
+                    shell_utils::quote_path(path.as_path())
+                )
+                .as_str(),
+                RemoteErrorType::FileCreateDenied,
+            );
+        }
         self.sftp
             .as_ref()
             .unwrap()
@@ -338,6 +620,18 @@ impl RemoteFileSystem for SftpFileSystem {
             error!("target {} doesn't exist", target.display());
             return Err(RemoteError::new(RemoteErrorType::NoSuchFileOrDirectory));
         }
+        if let Some(elevation) = self.elevation()? {
+            return elevation.execute(
+                self.session.as_mut().unwrap(),
+                format!(
+                    "ln -s {} {}",
+                    shell_utils::quote_path(target),
+                    shell_utils::quote_path(path.as_path())
+                )
+                .as_str(),
+                RemoteErrorType::FileCreateDenied,
+            );
+        }
         self.sftp
             .as_ref()
             .unwrap()
@@ -357,11 +651,32 @@ impl RemoteFileSystem for SftpFileSystem {
         }
         let dest = path_utils::absolutize(self.wrkdir.as_path(), dest);
         debug!("Copying {} to {}", src.display(), dest.display());
+        Self::check_copy_into_itself(src.as_path(), dest.as_path())?;
+        let cmd = format!(
+            "cp -rf {} {}",
+            shell_utils::quote_path(src.as_path()),
+            shell_utils::quote_path(dest.as_path())
+        );
+        if let Some(elevation) = self.elevation()? {
+            return elevation.execute(
+                self.session.as_mut().unwrap(),
+                cmd.as_str(),
+                RemoteErrorType::FileCreateDenied,
+            );
+        }
+        // Copy on the server side if `copy-data` is supported
+        if self.has_extension(SftpExtension::CopyData) {
+            let src = self.stat(src.as_path())?;
+            // like `cp`, copy into `dest` if it is a directory
+            let dest = match self.stat(dest.as_path()) {
+                Ok(file) if file.is_dir() => dest.join(src.name()),
+                _ => dest,
+            };
+            Self::check_copy_into_itself(src.path.as_path(), dest.as_path())?;
+            return self.copy_with_extension(&src, dest.as_path());
+        }
         // Run `cp -rf`
-        match commons::perform_shell_cmd_with_rc(
-            self.session.as_mut().unwrap(),
-            format!("cp -rf \"{}\" \"{}\"", src.display(), dest.display()).as_str(),
-        ) {
+        match commons::perform_shell_cmd_with_rc(self.session.as_mut().unwrap(), cmd.as_str()) {
             Ok((0, _)) => Ok(()),
             Ok(_) => Err(RemoteError::new_ex(
                 // Could not copy file
@@ -381,6 +696,23 @@ impl RemoteFileSystem for SftpFileSystem {
         }
         let dest = path_utils::absolutize(self.wrkdir.as_path(), dest);
         debug!("Moving {} to {}", src.display(), dest.display());
+        if let Some(elevation) = self.elevation()? {
+            return elevation.execute(
+                self.session.as_mut().unwrap(),
+                format!(
+                    "mv -f {} {}",
+                    shell_utils::quote_path(src.as_path()),
+                    shell_utils::quote_path(dest.as_path())
+                )
+                .as_str(),
+                RemoteErrorType::FileCreateDenied,
+            );
+        }
+        if self.has_extension(SftpExtension::PosixRename) {
+            return self
+                .ext_channel()?
+                .posix_rename(src.as_path(), dest.as_path());
+        }
         self.sftp
             .as_ref()
             .unwrap()
@@ -402,6 +734,20 @@ impl RemoteFileSystem for SftpFileSystem {
     }
 
     fn append(&mut self, path: &Path, metadata: &Metadata) -> RemoteResult<WriteStream> {
+        self.write_status = None;
+        if let Some(elevation) = self.elevation()? {
+            let path = path_utils::absolutize(self.wrkdir.as_path(), path);
+            debug!("Opening file at {} for appending (sudo)", path.display());
+            let stream = elevation.open_write(
+                self.session.as_mut().unwrap(),
+                path.as_path(),
+                metadata.mode.unwrap_or_else(|| UnixPex::from(0o644)),
+                true,
+            )?;
+            self.write_status = Some(stream.status());
+            return Ok(WriteStream::from(stream));
+        }
+        let fsync = self.fsync && self.has_extension(SftpExtension::Fsync);
         if let Some(sftp) = self.sftp.as_ref() {
             let path = path_utils::absolutize(self.wrkdir.as_path(), path);
             debug!("Opening file at {} for appending", path.display());
@@ -412,7 +758,7 @@ impl RemoteFileSystem for SftpFileSystem {
                 mode,
                 OpenType::File,
             )
-            .map(SftpWriteStream::from)
+            .map(|file| SftpWriteStream::from(file).fsync_on_drop(fsync))
             .map(WriteStream::from)
             .map_err(|e| {
                 error!("Append failed: {}", e);
@@ -424,6 +770,20 @@ impl RemoteFileSystem for SftpFileSystem {
     }
 
     fn create(&mut self, path: &Path, metadata: &Metadata) -> RemoteResult<WriteStream> {
+        self.write_status = None;
+        if let Some(elevation) = self.elevation()? {
+            let path = path_utils::absolutize(self.wrkdir.as_path(), path);
+            debug!("Creating file at {} (sudo)", path.display());
+            let stream = elevation.open_write(
+                self.session.as_mut().unwrap(),
+                path.as_path(),
+                metadata.mode.unwrap_or_else(|| UnixPex::from(0o644)),
+                false,
+            )?;
+            self.write_status = Some(stream.status());
+            return Ok(WriteStream::from(stream));
+        }
+        let fsync = self.fsync && self.has_extension(SftpExtension::Fsync);
         if let Some(sftp) = self.sftp.as_ref() {
             let path = path_utils::absolutize(self.wrkdir.as_path(), path);
             debug!("Creating file at {}", path.display());
@@ -434,7 +794,7 @@ impl RemoteFileSystem for SftpFileSystem {
                 mode,
                 OpenType::File,
             )
-            .map(SftpWriteStream::from)
+            .map(|file| SftpWriteStream::from(file).fsync_on_drop(fsync))
             .map(WriteStream::from)
             .map_err(|e| {
                 error!("Create failed: {}", e);
@@ -472,6 +832,14 @@ impl RemoteFileSystem for SftpFileSystem {
         mut reader: Box<dyn Read + Send>,
     ) -> RemoteResult<u64> {
         if self.is_connected() {
+            if let Some(result) = self.pipelined_upload(
+                path,
+                metadata,
+                &mut reader,
+                SSH_FXF_WRITE | SSH_FXF_CREAT | SSH_FXF_APPEND,
+            ) {
+                return result;
+            }
             let mut stream = self.append(path, metadata)?;
             trace!("Opened remote file");
             let mut bytes: usize = 0;
@@ -506,6 +874,14 @@ impl RemoteFileSystem for SftpFileSystem {
         mut reader: Box<dyn std::io::Read + Send>,
     ) -> RemoteResult<u64> {
         if self.is_connected() {
+            if let Some(result) = self.pipelined_upload(
+                path,
+                metadata,
+                &mut reader,
+                SSH_FXF_WRITE | SSH_FXF_CREAT | SSH_FXF_TRUNC,
+            ) {
+                return result;
+            }
             let mut stream = self.create(path, metadata)?;
             trace!("Opened remote file");
             let mut bytes: usize = 0;
@@ -535,6 +911,14 @@ impl RemoteFileSystem for SftpFileSystem {
 
     fn open_file(&mut self, src: &Path, mut dest: Box<dyn Write + Send>) -> RemoteResult<u64> {
         if self.is_connected() {
+            if let (Some(opts), Some(ext)) = (self.pipeline, self.ext.as_mut()) {
+                let src = path_utils::absolutize(self.wrkdir.as_path(), src);
+                debug!("Downloading file {} (pipelined)", src.display());
+                return pipeline::download(ext, src.as_path(), &mut dest, &opts).map_err(|e| {
+                    error!("Download failed: {}", e);
+                    e
+                });
+            }
             let transfer_size = self.stat(src)?.metadata().size as usize;
             let mut stream = self.open(src)?;
             trace!("File opened");
@@ -561,6 +945,15 @@ impl RemoteFileSystem for SftpFileSystem {
             Err(RemoteError::new(RemoteErrorType::NotConnected))
         }
     }
+
+    fn on_written(&mut self, writable: WriteStream) -> RemoteResult<()> {
+        // dropping the stream closes stdin of the sudo command writing the file and waits for it
+        drop(writable);
+        match self.write_status.take() {
+            Some(status) => status.take(),
+            None => Ok(()),
+        }
+    }
 }
 
 #[cfg(test)]
@@ -580,8 +973,89 @@ mod test {
         let mut client = SftpFileSystem::new(SshOpts::new("127.0.0.1"));
         assert!(client.session.is_none());
         assert!(client.sftp.is_none());
+        assert!(client.ext.is_none());
         assert_eq!(client.wrkdir, PathBuf::from("/"));
         assert_eq!(client.is_connected(), false);
+        assert_eq!(client.has_extension(SftpExtension::PosixRename), false);
+    }
+
+    #[test]
+    fn should_detect_extensions() {
+        crate::mock::logger();
+        let TestCtx {
+            client,
+            container: _container,
+        } = setup_client();
+        assert!(client.has_extension(SftpExtension::PosixRename));
+        assert!(client.has_extension(SftpExtension::Hardlink));
+        assert!(client.has_extension(SftpExtension::Fsync));
+        assert!(client.has_extension(SftpExtension::Statvfs));
+        finalize_client(client);
+    }
+
+    #[test]
+    fn should_create_file_with_fsync() {
+        crate::mock::logger();
+        let TestCtx {
+            client,
+            container: _container,
+        } = setup_client();
+        let mut client = client.fsync_on_write(true);
+        let p = Path::new("a.txt");
+        let file_data = "test data\n";
+        let reader = Cursor::new(file_data.as_bytes());
+        assert_eq!(
+            client
+                .create_file(p, &Metadata::default().size(10), Box::new(reader))
+                .ok()
+                .unwrap(),
+            10
+        );
+        assert_eq!(client.stat(p).ok().unwrap().metadata().size, 10);
+        finalize_client(client);
+    }
+
+    #[test]
+    fn should_make_hardlink() {
+        crate::mock::logger();
+        let TestCtx {
+            mut client,
+            container: _container,
+        } = setup_client();
+        let p = Path::new("a.txt");
+        let file_data = "test data\n";
+        let reader = Cursor::new(file_data.as_bytes());
+        assert!(client
+            .create_file(p, &Metadata::default().size(10), Box::new(reader))
+            .is_ok());
+        let link = Path::new("b.txt");
+        assert!(client.hardlink(link, p).is_ok());
+        let file = client.stat(link).ok().unwrap();
+        assert!(file.is_file());
+        assert_eq!(file.metadata().size, 10);
+        // link already exists
+        assert!(client.hardlink(link, p).is_err());
+        finalize_client(client);
+    }
+
+    #[test]
+    fn should_get_statvfs() {
+        crate::mock::logger();
+        let TestCtx {
+            mut client,
+            container: _container,
+        } = setup_client();
+        let stat = client.statvfs(Path::new(".")).ok().unwrap();
+        assert!(stat.blocks > 0);
+        assert!(stat.total_space() >= stat.available_space());
+        assert_eq!(
+            client
+                .statvfs(Path::new("/this/does/not/exist"))
+                .unwrap_err()
+                .kind,
+            RemoteErrorType::NoSuchFileOrDirectory
+        );
+        finalize_client(client);
     }
 
     #[test]
@@ -682,6 +1156,73 @@ mod test {
         finalize_client(client);
     }
 
+    #[test]
+    fn should_copy_directory_into_existing_directory() {
+        crate::mock::logger();
+        let TestCtx {
+            mut client,
+            container: _container,
+        } = setup_client();
+        assert!(client
+            .create_dir(Path::new("src"), UnixPex::from(0o755))
+            .is_ok());
+        let reader = Cursor::new(b"test data\n");
+        assert!(client
+            .create_file(
+                Path::new("src/a.txt"),
+                &Metadata::default(),
+                Box::new(reader)
+            )
+            .is_ok());
+        // `dst/src` already exists: the copy is merged into it
+        assert!(client
+            .create_dir(Path::new("dst"), UnixPex::from(0o755))
+            .is_ok());
+        assert!(client
+            .create_dir(Path::new("dst/src"), UnixPex::from(0o755))
+            .is_ok());
+        assert!(client.copy(Path::new("src"), Path::new("dst")).is_ok());
+        assert_eq!(
+            client
+                .stat(Path::new("dst/src/a.txt"))
+                .ok()
+                .unwrap()
+                .metadata()
+                .size,
+            10
+        );
+        finalize_client(client);
+    }
+
+    #[test]
+    fn should_not_copy_directory_into_itself() {
+        crate::mock::logger();
+        let TestCtx {
+            mut client,
+            container: _container,
+        } = setup_client();
+        assert!(client
+            .create_dir(Path::new("src"), UnixPex::from(0o755))
+            .is_ok());
+        assert_eq!(
+            client
+                .copy(Path::new("src"), Path::new("src/sub"))
+                .unwrap_err()
+                .kind,
+            RemoteErrorType::BadAddress
+        );
+        assert_eq!(
+            client
+                .copy(Path::new("src"), Path::new("src"))
+                .unwrap_err()
+                .kind,
+            RemoteErrorType::BadAddress
+        );
+        assert!(!client.exists(Path::new("src/sub")).unwrap());
+        assert!(!client.exists(Path::new("src/src")).unwrap());
+        finalize_client(client);
+    }
+
     #[test]
     fn should_not_copy_file() {
         crate::mock::logger();
@@ -808,6 +1349,23 @@ mod test {
         finalize_client(client);
     }
 
+    #[test]
+    fn should_exec_stream_command() {
+        crate::mock::logger();
+        let TestCtx {
+            mut client,
+            container: _container,
+        } = setup_client();
+        let handle = client
+            .exec_stream("pwd; echo 5", ExecOptions::default())
+            .unwrap();
+        let mut output = String::new();
+        assert!(handle.stdout().read_to_string(&mut output).is_ok());
+        assert_eq!(output, format!("{}\n5\n", client.pwd().unwrap().display()));
+        assert_eq!(handle.wait().unwrap(), 0);
+        finalize_client(client);
+    }
+
     #[test]
     fn should_tell_whether_file_exists() {
         crate::mock::logger();
@@ -900,6 +1458,29 @@ mod test {
         finalize_client(client);
     }
 
+    #[test]
+    fn should_move_file_replacing_destination() {
+        crate::mock::logger();
+        let TestCtx {
+            mut client,
+            container: _container,
+        } = setup_client();
+        let p = Path::new("a.txt");
+        let reader = Cursor::new("test data\n".as_bytes());
+        assert!(client
+            .create_file(p, &Metadata::default().size(10), Box::new(reader))
+            .is_ok());
+        let dest = Path::new("b.txt");
+        let reader = Cursor::new("hello\n".as_bytes());
+        assert!(client
+            .create_file(dest, &Metadata::default().size(6), Box::new(reader))
+            .is_ok());
+        assert!(client.mov(p, dest).is_ok());
+        assert_eq!(client.exists(p).ok().unwrap(), false);
+        assert_eq!(client.stat(dest).ok().unwrap().metadata().size, 10);
+        finalize_client(client);
+    }
+
     #[test]
     fn should_not_move_file() {
         crate::mock::logger();
@@ -958,6 +1539,66 @@ mod test {
         finalize_client(client);
     }
 
+    #[test]
+    fn should_transfer_large_file_pipelined() {
+        crate::mock::logger();
+        let TestCtx {
+            mut client,
+            container: _container,
+        } = setup_client();
+        let p = Path::new("large.bin");
+        let file_data: Vec<u8> = (0..1_048_576u32).map(|x| (x % 251) as u8).collect();
+        let metadata = Metadata::default().size(file_data.len() as u64);
+        assert_eq!(
+            client
+                .create_file(p, &metadata, Box::new(Cursor::new(file_data.clone())))
+                .ok()
+                .unwrap(),
+            1_048_576
+        );
+        assert_eq!(client.stat(p).ok().unwrap().metadata().size, 1_048_576);
+        let buffer = SharedBuffer::default();
+        assert_eq!(
+            client.open_file(p, Box::new(buffer.clone())).ok().unwrap(),
+            1_048_576
+        );
+        assert!(*buffer.0.lock().unwrap() == file_data);
+        finalize_client(client);
+    }
+
+    /// Compares pipelined and sequential transfers against the test container.
+    /// Run with `cargo test -p fsutil-ssh bench_pipelined_transfers -- --ignored --nocapture`
+    #[test]
+    #[ignore]
+    fn bench_pipelined_transfers() {
+        crate::mock::logger();
+        let TestCtx {
+            mut client,
+            container: _container,
+        } = setup_client();
+        let p = Path::new("bench.bin");
+        let file_data = vec![0x55u8; 32 * 1_048_576];
+        let metadata = Metadata::default().size(file_data.len() as u64);
+        for pipeline in [None, Some(PipelineOpts::default())] {
+            client.pipeline = pipeline;
+            let t_start = std::time::Instant::now();
+            assert!(client
+                .create_file(p, &metadata, Box::new(Cursor::new(file_data.clone())))
+                .is_ok());
+            let upload = t_start.elapsed();
+            let t_start = std::time::Instant::now();
+            assert!(client.open_file(p, Box::new(std::io::sink())).is_ok());
+            let download = t_start.elapsed();
+            println!(
+                "pipelined: {}; upload: {:?}; download: {:?}",
+                pipeline.is_some(),
+                upload,
+                download
+            );
+        }
+        finalize_client(client);
+    }
+
     #[test]
     fn should_print_working_directory() {
         crate::mock::logger();
@@ -1070,6 +1711,77 @@ mod test {
         finalize_client(client);
     }
 
+    #[test]
+    fn should_write_root_owned_files_with_sudo() {
+        crate::mock::logger();
+        let container = OpensshServer::start();
+        let port = container.port();
+        let config_file = ssh_mock::create_ssh_config(port);
+        let mut client = SftpFileSystem::new(
+            SshOpts::new("sftp")
+                .config_file(config_file.path(), ParseRule::ALLOW_UNKNOWN_FIELDS)
+                .password("password"),
+        )
+        .sudo(Sudo::Password);
+        assert!(client.connect().is_ok());
+        let dir = Path::new("/etc/fsutil-sudo-test");
+        assert!(client.create_dir(dir, UnixPex::from(0o755)).is_ok());
+        // writing over a directory makes the elevated command fail
+        assert_eq!(
+            client
+                .create_file(
+                    dir,
+                    &Metadata::default().size(10),
+                    Box::new(Cursor::new(b"test data\n"))
+                )
+                .err()
+                .unwrap()
+                .kind,
+            RemoteErrorType::IoError
+        );
+        let p = dir.join("a.txt");
+        let file_data = "test data\n";
+        assert_eq!(
+            client
+                .create_file(
+                    p.as_path(),
+                    &Metadata::default().size(10),
+                    Box::new(Cursor::new(file_data.as_bytes()))
+                )
+                .ok()
+                .unwrap(),
+            10
+        );
+        let file = client.stat(p.as_path()).ok().unwrap();
+        assert_eq!(file.metadata().size, 10);
+        assert_eq!(file.metadata().uid, Some(0));
+        assert!(client.mov(p.as_path(), dir.join("b.txt").as_path()).is_ok());
+        assert!(client.remove_dir_all(dir).is_ok());
+        assert!(!client.exists(dir).ok().unwrap());
+        assert!(client.disconnect().is_ok());
+    }
+
+    #[test]
+    fn should_report_sudo_permission_error() {
+        crate::mock::logger();
+        let container = OpensshServer::start();
+        let port = container.port();
+        let config_file = ssh_mock::create_ssh_config(port);
+        let mut client = SftpFileSystem::new(
+            SshOpts::new("sftp")
+                .config_file(config_file.path(), ParseRule::ALLOW_UNKNOWN_FIELDS)
+                .password("password"),
+        )
+        .sudo(Sudo::NoPassword);
+        assert!(client.connect().is_ok());
+        // sudo requires a password on the test server
+        let err = client
+            .create_dir(Path::new("/etc/fsutil-sudo-test"), UnixPex::from(0o755))
+            .unwrap_err();
+        assert_eq!(err.kind, RemoteErrorType::PexError);
+        assert!(client.disconnect().is_ok());
+    }
+
     #[test]
     fn should_setstat_file() {
         crate::mock::logger();
@@ -1282,6 +1994,19 @@ mod test {
 
     // -- test utils
 
+    #[derive(Clone, Default)]
+    struct SharedBuffer(std::sync::Arc<std::sync::Mutex<Vec<u8>>>);
+
+    impl std::io::Write for SharedBuffer {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
     struct TestCtx {
         client: SftpFileSystem,
         #[allow(dead_code)]
@@ -0,0 +1,873 @@
+//! ## SFTP extensions
+//!
+//! Support for the SFTP protocol extensions which are not exposed by libssh2.
+//! Extended requests are sent over a dedicated `sftp` subsystem channel, which speaks version 3 of the protocol.
+//! The same channel is used for pipelined transfers (see [`super::pipeline`]).
+
+use std::collections::HashMap;
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+
+use fsutil_core::fs::{RemoteError, RemoteErrorType, RemoteResult};
+use ssh2::Session;
+
+// -- packet types
+
+const SSH_FXP_INIT: u8 = 1;
+const SSH_FXP_VERSION: u8 = 2;
+const SSH_FXP_OPEN: u8 = 3;
+const SSH_FXP_CLOSE: u8 = 4;
+pub(super) const SSH_FXP_READ: u8 = 5;
+pub(super) const SSH_FXP_WRITE: u8 = 6;
+const SSH_FXP_FSTAT: u8 = 8;
+const SSH_FXP_FSETSTAT: u8 = 10;
+const SSH_FXP_REALPATH: u8 = 16;
+const SSH_FXP_STATUS: u8 = 101;
+const SSH_FXP_HANDLE: u8 = 102;
+const SSH_FXP_DATA: u8 = 103;
+const SSH_FXP_NAME: u8 = 104;
+const SSH_FXP_ATTRS: u8 = 105;
+const SSH_FXP_EXTENDED: u8 = 200;
+const SSH_FXP_EXTENDED_REPLY: u8 = 201;
+
+// -- status codes
+
+pub(super) const SSH_FX_OK: u32 = 0;
+pub(super) const SSH_FX_EOF: u32 = 1;
+const SSH_FX_NO_SUCH_FILE: u32 = 2;
+const SSH_FX_PERMISSION_DENIED: u32 = 3;
+const SSH_FX_OP_UNSUPPORTED: u32 = 8;
+
+// -- open flags and attributes
+
+pub(super) const SSH_FXF_READ: u32 = 0x01;
+pub(super) const SSH_FXF_WRITE: u32 = 0x02;
+pub(super) const SSH_FXF_APPEND: u32 = 0x04;
+pub(super) const SSH_FXF_CREAT: u32 = 0x08;
+pub(super) const SSH_FXF_TRUNC: u32 = 0x10;
+const SSH_FILEXFER_ATTR_SIZE: u32 = 0x01;
+const SSH_FILEXFER_ATTR_PERMISSIONS: u32 = 0x04;
+
+/// Protocol version requested by the client
+const SFTP_VERSION: u32 = 3;
+/// Max length accepted for a reply packet
+pub(super) const MAX_PACKET_LEN: usize = 256 * 1024;
+
+/// A protocol extension which may be supported by the SFTP server
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum SftpExtension {
+    /// `posix-rename@openssh.com`: rename which atomically replaces the destination
+    PosixRename,
+    /// `hardlink@openssh.com`: create hard links
+    Hardlink,
+    /// `fsync@openssh.com`: synchronize an open file to disk
+    Fsync,
+    /// `statvfs@openssh.com`: get file system statistics
+    Statvfs,
+    /// `copy-data`: copy data between two files on the server side
+    CopyData,
+}
+
+impl SftpExtension {
+    /// Returns the name the extension is advertised with
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::PosixRename => "posix-rename@openssh.com",
+            Self::Hardlink => "hardlink@openssh.com",
+            Self::Fsync => "fsync@openssh.com",
+            Self::Statvfs => "statvfs@openssh.com",
+            Self::CopyData => "copy-data",
+        }
+    }
+}
+
+/// File system statistics, as returned by `statvfs@openssh.com`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct StatVfs {
+    /// File system block size
+    pub block_size: u64,
+    /// Fundamental file system block size
+    pub fragment_size: u64,
+    /// Number of blocks (in units of `fragment_size`)
+    pub blocks: u64,
+    /// Free blocks in file system
+    pub blocks_free: u64,
+    /// Free blocks for non-root users
+    pub blocks_available: u64,
+    /// Total file inodes
+    pub files: u64,
+    /// Free file inodes
+    pub files_free: u64,
+    /// Free file inodes for non-root users
+    pub files_available: u64,
+    /// File system id
+    pub fs_id: u64,
+    /// Mount flags
+    pub flags: u64,
+    /// Maximum filename length
+    pub name_max: u64,
+}
+
+impl StatVfs {
+    /// Size of the file system in bytes
+    pub fn total_space(&self) -> u64 {
+        self.fragment_size.saturating_mul(self.blocks)
+    }
+
+    /// Free space in bytes, available to non-root users
+    pub fn available_space(&self) -> u64 {
+        self.fragment_size.saturating_mul(self.blocks_available)
+    }
+
+    /// Parse statvfs extended reply
+    fn decode(decoder: &mut Decoder<'_>) -> Option<Self> {
+        Some(Self {
+            block_size: decoder.u64()?,
+            fragment_size: decoder.u64()?,
+            blocks: decoder.u64()?,
+            blocks_free: decoder.u64()?,
+            blocks_available: decoder.u64()?,
+            files: decoder.u64()?,
+            files_free: decoder.u64()?,
+            files_available: decoder.u64()?,
+            fs_id: decoder.u64()?,
+            flags: decoder.u64()?,
+            name_max: decoder.u64()?,
+        })
+    }
+}
+
+/// Reply to a request
+pub(super) enum Reply {
+    Status(u32, String),
+    Handle(Vec<u8>),
+    Data(Vec<u8>),
+    /// First name of a `SSH_FXP_NAME` reply
+    Name(Vec<u8>),
+    Attrs(Vec<u8>),
+    Extended(Vec<u8>),
+}
+
+/// Byte stream SFTP packets are exchanged through
+pub(super) trait Transport: Read + Write + Send + Sync {}
+
+impl<T: Read + Write + Send + Sync> Transport for T {}
+
+/// An `sftp` subsystem channel used to send extended requests
+pub struct SftpExtChannel {
+    channel: Box<dyn Transport>,
+    request_id: u32,
+    extensions: HashMap<String, String>,
+}
+
+impl SftpExtChannel {
+    /// Open a new sftp channel on `session` and collect the extensions advertised by the server
+    pub fn open(session: &Session) -> RemoteResult<Self> {
+        let mut channel = session.channel_session().map_err(|err| {
+            RemoteError::new_ex(
+                RemoteErrorType::ProtocolError,
+                format!("Could not open channel: {err}"),
+            )
+        })?;
+        channel.subsystem("sftp").map_err(|err| {
+            RemoteError::new_ex(
+                RemoteErrorType::ProtocolError,
+                format!("Could not start sftp subsystem: {err}"),
+            )
+        })?;
+        Self::init(Box::new(channel))
+    }
+
+    /// Initialize the SFTP session over `channel` and collect the extensions advertised by the server
+    pub(super) fn init(channel: Box<dyn Transport>) -> RemoteResult<Self> {
+        let mut ext = Self {
+            channel,
+            request_id: 0,
+            extensions: HashMap::new(),
+        };
+        ext.send(SSH_FXP_INIT, &SFTP_VERSION.to_be_bytes())?;
+        let (packet_type, payload) = ext.recv()?;
+        if packet_type != SSH_FXP_VERSION {
+            return Err(RemoteError::new_ex(
+                RemoteErrorType::ProtocolError,
+                format!("expected SSH_FXP_VERSION, got packet type {packet_type}"),
+            ));
+        }
+        let mut decoder = Decoder::new(&payload);
+        let version = decoder.u32().ok_or_else(Self::bad_message)?;
+        while let (Some(name), Some(data)) = (decoder.string(), decoder.string()) {
+            let name = String::from_utf8_lossy(name).to_string();
+            let data = String::from_utf8_lossy(data).to_string();
+            trace!("Server supports SFTP extension {} ({})", name, data);
+            ext.extensions.insert(name, data);
+        }
+        debug!(
+            "SFTP server version {}; {} extensions",
+            version,
+            ext.extensions.len()
+        );
+        Ok(ext)
+    }
+
+    /// Returns whether the server supports `extension`
+    pub fn supports(&self, extension: SftpExtension) -> bool {
+        self.extensions.contains_key(extension.name())
+    }
+
+    /// Rename `src` to `dest` with `posix-rename@openssh.com`; `dest` is replaced if it exists
+    pub fn posix_rename(&mut self, src: &Path, dest: &Path) -> RemoteResult<()> {
+        let mut body = Vec::new();
+        put_path(&mut body, src);
+        put_path(&mut body, dest);
+        self.extended_status(
+            SftpExtension::PosixRename,
+            &body,
+            RemoteErrorType::FileCreateDenied,
+        )
+    }
+
+    /// Synchronize the file opened with `handle` to disk with `fsync@openssh.com`
+    pub fn fsync(&mut self, handle: &[u8]) -> RemoteResult<()> {
+        let mut body = Vec::new();
+        put_string(&mut body, handle);
+        self.extended_status(SftpExtension::Fsync, &body, RemoteErrorType::IoError)
+    }
+
+    /// Create a hard link at `path` pointing to `target` with `hardlink@openssh.com`
+    pub fn hardlink(&mut self, path: &Path, target: &Path) -> RemoteResult<()> {
+        let mut body = Vec::new();
+        put_path(&mut body, target);
+        put_path(&mut body, path);
+        self.extended_status(
+            SftpExtension::Hardlink,
+            &body,
+            RemoteErrorType::FileCreateDenied,
+        )
+    }
+
+    /// Get statistics of the file system containing `path` with `statvfs@openssh.com`
+    pub fn statvfs(&mut self, path: &Path) -> RemoteResult<StatVfs> {
+        let mut body = Vec::new();
+        put_path(&mut body, path);
+        match self.extended(SftpExtension::Statvfs, &body)? {
+            Reply::Extended(data) => {
+                StatVfs::decode(&mut Decoder::new(&data)).ok_or_else(Self::bad_message)
+            }
+            Reply::Status(code, msg) => Err(status_error(code, msg, RemoteErrorType::StatFailed)),
+            Reply::Handle(_) | Reply::Data(_) | Reply::Name(_) | Reply::Attrs(_) => {
+                Err(Self::bad_message())
+            }
+        }
+    }
+
+    /// Copy file at `src` to `dest` on the server side with `copy-data`.
+    /// `dest` is created with `mode` if it doesn't exist, or truncated otherwise.
+    ///
+    /// Fails if `src` and `dest` are the same file, as `cp` does
+    pub fn copy_data(&mut self, src: &Path, dest: &Path, mode: u32) -> RemoteResult<()> {
+        self.require(SftpExtension::CopyData)?;
+        if self.is_same_file(src, dest)? {
+            error!("{} and {} are the same file", src.display(), dest.display());
+            return Err(RemoteError::new_ex(
+                RemoteErrorType::BadFile,
+                format!("{} and {} are the same file", src.display(), dest.display()),
+            ));
+        }
+        let read_handle = self.open_handle(src, SSH_FXF_READ, None)?;
+        // `dest` is truncated once copied, since it may still be a hard link to `src`
+        let write_handle = match self.open_handle(dest, SSH_FXF_WRITE | SSH_FXF_CREAT, Some(mode)) {
+            Ok(handle) => handle,
+            Err(err) => {
+                self.close_handle(&read_handle);
+                return Err(err);
+            }
+        };
+        let result = self.copy_handle(&read_handle, &write_handle);
+        self.close_handle(&read_handle);
+        self.close_handle(&write_handle);
+        result
+    }
+
+    /// Resolve `path` on the server with `SSH_FXP_REALPATH`
+    pub fn realpath(&mut self, path: &Path) -> RemoteResult<PathBuf> {
+        let mut body = Vec::new();
+        put_path(&mut body, path);
+        match self.request(SSH_FXP_REALPATH, &body)? {
+            Reply::Name(name) => Ok(path_from_bytes(name)),
+            Reply::Status(code, msg) => Err(status_error(code, msg, RemoteErrorType::StatFailed)),
+            Reply::Handle(_) | Reply::Data(_) | Reply::Attrs(_) | Reply::Extended(_) => {
+                Err(Self::bad_message())
+            }
+        }
+    }
+
+    // -- private
+
+    /// Returns whether `src` and `dest` resolve to the same path; `dest` may not exist
+    fn is_same_file(&mut self, src: &Path, dest: &Path) -> RemoteResult<bool> {
+        let src = self.realpath(src)?;
+        match self.realpath(dest) {
+            Ok(dest) => Ok(src == dest),
+            Err(err) if err.kind == RemoteErrorType::NoSuchFileOrDirectory => Ok(false),
+            Err(err) => Err(err),
+        }
+    }
+
+    /// Copy the content of `read_handle` into `write_handle` with `copy-data`,
+    /// then truncate the written file to the size of the copied one
+    fn copy_handle(&mut self, read_handle: &[u8], write_handle: &[u8]) -> RemoteResult<()> {
+        let size = self.fstat_size(read_handle)?;
+        // read `size` bytes from offset 0 and write at offset 0
+        let mut body = Vec::new();
+        put_string(&mut body, read_handle);
+        body.extend_from_slice(&0u64.to_be_bytes());
+        body.extend_from_slice(&size.to_be_bytes());
+        put_string(&mut body, write_handle);
+        body.extend_from_slice(&0u64.to_be_bytes());
+        self.extended_status(
+            SftpExtension::CopyData,
+            &body,
+            RemoteErrorType::FileCreateDenied,
+        )?;
+        let mut body = Vec::new();
+        put_string(&mut body, write_handle);
+        body.extend_from_slice(&SSH_FILEXFER_ATTR_SIZE.to_be_bytes());
+        body.extend_from_slice(&size.to_be_bytes());
+        match self.request(SSH_FXP_FSETSTAT, &body)? {
+            Reply::Status(SSH_FX_OK, _) => Ok(()),
+            Reply::Status(code, msg) => {
+                Err(status_error(code, msg, RemoteErrorType::FileCreateDenied))
+            }
+            Reply::Handle(_)
+            | Reply::Data(_)
+            | Reply::Name(_)
+            | Reply::Attrs(_)
+            | Reply::Extended(_) => Err(Self::bad_message()),
+        }
+    }
+
+    /// Get the size of the file open as `handle` with `SSH_FXP_FSTAT`
+    fn fstat_size(&mut self, handle: &[u8]) -> RemoteResult<u64> {
+        let mut body = Vec::new();
+        put_string(&mut body, handle);
+        match self.request(SSH_FXP_FSTAT, &body)? {
+            Reply::Attrs(attrs) => {
+                let mut decoder = Decoder::new(&attrs);
+                match decoder.u32() {
+                    Some(flags) if flags & SSH_FILEXFER_ATTR_SIZE != 0 => {
+                        decoder.u64().ok_or_else(Self::bad_message)
+                    }
+                    _ => Err(Self::bad_message()),
+                }
+            }
+            Reply::Status(code, msg) => Err(status_error(code, msg, RemoteErrorType::StatFailed)),
+            Reply::Handle(_) | Reply::Data(_) | Reply::Name(_) | Reply::Extended(_) => {
+                Err(Self::bad_message())
+            }
+        }
+    }
+
+    /// Fail with [`RemoteErrorType::UnsupportedFeature`] if the server doesn't support `extension`
+    fn require(&self, extension: SftpExtension) -> RemoteResult<()> {
+        if self.supports(extension) {
+            Ok(())
+        } else {
+            Err(RemoteError::new_ex(
+                RemoteErrorType::UnsupportedFeature,
+                format!("server doesn't support {}", extension.name()),
+            ))
+        }
+    }
+
+    /// Open file at `path` with `flags` and return its handle
+    pub(super) fn open_handle(
+        &mut self,
+        path: &Path,
+        flags: u32,
+        mode: Option<u32>,
+    ) -> RemoteResult<Vec<u8>> {
+        let mut body = Vec::new();
+        put_path(&mut body, path);
+        body.extend_from_slice(&flags.to_be_bytes());
+        match mode {
+            Some(mode) => {
+                body.extend_from_slice(&SSH_FILEXFER_ATTR_PERMISSIONS.to_be_bytes());
+                body.extend_from_slice(&mode.to_be_bytes());
+            }
+            None => body.extend_from_slice(&0u32.to_be_bytes()),
+        }
+        match self.request(SSH_FXP_OPEN, &body)? {
+            Reply::Handle(handle) => Ok(handle),
+            Reply::Status(code, msg) => {
+                Err(status_error(code, msg, RemoteErrorType::CouldNotOpenFile))
+            }
+            Reply::Data(_) | Reply::Name(_) | Reply::Attrs(_) | Reply::Extended(_) => {
+                Err(Self::bad_message())
+            }
+        }
+    }
+
+    /// Close `handle`; errors are only logged
+    pub(super) fn close_handle(&mut self, handle: &[u8]) {
+        let mut body = Vec::new();
+        put_string(&mut body, handle);
+        match self.request(SSH_FXP_CLOSE, &body) {
+            Ok(Reply::Status(SSH_FX_OK, _)) => {}
+            Ok(Reply::Status(code, msg)) => {
+                error!("Failed to close handle: {} (status {})", msg, code);
+            }
+            Ok(_) => error!("Failed to close handle: unexpected reply"),
+            Err(err) => error!("Failed to close handle: {}", err),
+        }
+    }
+
+    /// Send an extended request, which is expected to be answered with a status
+    fn extended_status(
+        &mut self,
+        extension: SftpExtension,
+        body: &[u8],
+        error_kind: RemoteErrorType,
+    ) -> RemoteResult<()> {
+        match self.extended(extension, body)? {
+            Reply::Status(SSH_FX_OK, _) => Ok(()),
+            Reply::Status(code, msg) => Err(status_error(code, msg, error_kind)),
+            Reply::Handle(_)
+            | Reply::Data(_)
+            | Reply::Name(_)
+            | Reply::Attrs(_)
+            | Reply::Extended(_) => Err(Self::bad_message()),
+        }
+    }
+
+    /// Send an extended request for `extension`
+    fn extended(&mut self, extension: SftpExtension, body: &[u8]) -> RemoteResult<Reply> {
+        self.require(extension)?;
+        trace!("Sending extended request {}", extension.name());
+        let mut payload = Vec::with_capacity(body.len() + extension.name().len() + 4);
+        put_string(&mut payload, extension.name().as_bytes());
+        payload.extend_from_slice(body);
+        self.request(SSH_FXP_EXTENDED, &payload)
+    }
+
+    /// Send a request of `packet_type` and wait for its reply
+    fn request(&mut self, packet_type: u8, body: &[u8]) -> RemoteResult<Reply> {
+        let id = self.send_request(packet_type, body)?;
+        let (reply_id, reply) = self.recv_reply()?;
+        if reply_id != id {
+            return Err(RemoteError::new_ex(
+                RemoteErrorType::ProtocolError,
+                "SFTP reply id doesn't match request id",
+            ));
+        }
+        Ok(reply)
+    }
+
+    /// Send a request of `packet_type` without waiting for its reply; returns the request id
+    pub(super) fn send_request(&mut self, packet_type: u8, body: &[u8]) -> RemoteResult<u32> {
+        self.request_id = self.request_id.wrapping_add(1);
+        let id = self.request_id;
+        let mut payload = Vec::with_capacity(body.len() + 4);
+        payload.extend_from_slice(&id.to_be_bytes());
+        payload.extend_from_slice(body);
+        self.send(packet_type, &payload)?;
+        Ok(id)
+    }
+
+    /// Receive the next reply; returns the id of the request it answers and the reply
+    pub(super) fn recv_reply(&mut self) -> RemoteResult<(u32, Reply)> {
+        let (reply_type, data) = self.recv()?;
+        let mut decoder = Decoder::new(&data);
+        let id = decoder.u32().ok_or_else(Self::bad_message)?;
+        let reply = match reply_type {
+            SSH_FXP_STATUS => {
+                let code = decoder.u32().ok_or_else(Self::bad_message)?;
+                let msg = decoder
+                    .string()
+                    .map(|x| String::from_utf8_lossy(x).to_string())
+                    .unwrap_or_default();
+                Reply::Status(code, msg)
+            }
+            SSH_FXP_HANDLE => decoder
+                .string()
+                .map(|x| Reply::Handle(x.to_vec()))
+                .ok_or_else(Self::bad_message)?,
+            SSH_FXP_DATA => decoder
+                .string()
+                .map(|x| Reply::Data(x.to_vec()))
+                .ok_or_else(Self::bad_message)?,
+            SSH_FXP_NAME => decoder
+                .u32()
+                .filter(|count| *count > 0)
+                .and_then(|_| decoder.string())
+                .map(|x| Reply::Name(x.to_vec()))
+                .ok_or_else(Self::bad_message)?,
+            SSH_FXP_ATTRS => Reply::Attrs(decoder.remaining().to_vec()),
+            SSH_FXP_EXTENDED_REPLY => Reply::Extended(decoder.remaining().to_vec()),
+            other => {
+                return Err(RemoteError::new_ex(
+                    RemoteErrorType::ProtocolError,
+                    format!("unexpected SFTP packet type {other}"),
+                ))
+            }
+        };
+        Ok((id, reply))
+    }
+
+    /// Send a packet
+    fn send(&mut self, packet_type: u8, payload: &[u8]) -> RemoteResult<()> {
+        let mut packet = Vec::with_capacity(payload.len() + 5);
+        packet.extend_from_slice(&(payload.len() as u32 + 1).to_be_bytes());
+        packet.push(packet_type);
+        packet.extend_from_slice(payload);
+        self.channel
+            .write_all(&packet)
+            .and_then(|_| self.channel.flush())
+            .map_err(|err| RemoteError::new_ex(RemoteErrorType::IoError, err))
+    }
+
+    /// Receive a packet; returns its type and payload
+    fn recv(&mut self) -> RemoteResult<(u8, Vec<u8>)> {
+        let mut len = [0u8; 4];
+        self.channel
+            .read_exact(&mut len)
+            .map_err(|err| RemoteError::new_ex(RemoteErrorType::IoError, err))?;
+        let len = u32::from_be_bytes(len) as usize;
+        if len == 0 || len > MAX_PACKET_LEN {
+            return Err(Self::bad_message());
+        }
+        let mut packet = vec![0u8; len];
+        self.channel
+            .read_exact(&mut packet)
+            .map_err(|err| RemoteError::new_ex(RemoteErrorType::IoError, err))?;
+        let payload = packet.split_off(1);
+        Ok((packet[0], payload))
+    }
+
+    pub(super) fn bad_message() -> RemoteError {
+        RemoteError::new_ex(RemoteErrorType::ProtocolError, "bad SFTP message")
+    }
+}
+
+/// Convert SFTP status into a [`RemoteError`]; `kind` is used for generic failures
+pub(super) fn status_error(code: u32, msg: String, kind: RemoteErrorType) -> RemoteError {
+    let kind = match code {
+        SSH_FX_NO_SUCH_FILE => RemoteErrorType::NoSuchFileOrDirectory,
+        SSH_FX_PERMISSION_DENIED => RemoteErrorType::PexError,
+        SSH_FX_OP_UNSUPPORTED => RemoteErrorType::UnsupportedFeature,
+        _ => kind,
+    };
+    error!("SFTP request failed with status {}: {}", code, msg);
+    RemoteError::new_ex(kind, msg)
+}
+
+pub(super) fn put_string(buf: &mut Vec<u8>, s: &[u8]) {
+    buf.extend_from_slice(&(s.len() as u32).to_be_bytes());
+    buf.extend_from_slice(s);
+}
+
+#[cfg(target_family = "unix")]
+fn put_path(buf: &mut Vec<u8>, p: &Path) {
+    use std::os::unix::ffi::OsStrExt as _;
+
+    put_string(buf, p.as_os_str().as_bytes());
+}
+
+#[cfg(target_os = "windows")]
+fn put_path(buf: &mut Vec<u8>, p: &Path) {
+    put_string(buf, p.to_string_lossy().as_bytes());
+}
+
+#[cfg(target_family = "unix")]
+fn path_from_bytes(bytes: Vec<u8>) -> PathBuf {
+    use std::os::unix::ffi::OsStringExt as _;
+
+    PathBuf::from(std::ffi::OsString::from_vec(bytes))
+}
+
+#[cfg(target_os = "windows")]
+fn path_from_bytes(bytes: Vec<u8>) -> PathBuf {
+    PathBuf::from(String::from_utf8_lossy(&bytes).to_string())
+}
+
+/// Decoder for SFTP packet payloads
+struct Decoder<'a> {
+    buf: &'a [u8],
+}
+
+impl<'a> Decoder<'a> {
+    fn new(buf: &'a [u8]) -> Self {
+        Self { buf }
+    }
+
+    fn take(&mut self, n: usize) -> Option<&'a [u8]> {
+        if self.buf.len() < n {
+            return None;
+        }
+        let (head, tail) = self.buf.split_at(n);
+        self.buf = tail;
+        Some(head)
+    }
+
+    fn u32(&mut self) -> Option<u32> {
+        self.take(4)
+            .map(|x| u32::from_be_bytes([x[0], x[1], x[2], x[3]]))
+    }
+
+    fn u64(&mut self) -> Option<u64> {
+        self.take(8).map(|x| {
+            let mut bytes = [0u8; 8];
+            bytes.copy_from_slice(x);
+            u64::from_be_bytes(bytes)
+        })
+    }
+
+    fn string(&mut self) -> Option<&'a [u8]> {
+        let len = self.u32()? as usize;
+        self.take(len)
+    }
+
+    fn remaining(&self) -> &'a [u8] {
+        self.buf
+    }
+}
+
+#[cfg(test)]
+mod test {
+
+    use std::io::Cursor;
+    use std::sync::{Arc, Mutex};
+
+    use pretty_assertions::assert_eq;
+
+    use super::*;
+    use crate::ssh::commons;
+    use crate::SshOpts;
+
+    #[test]
+    fn should_get_extension_name() {
+        assert_eq!(
+            SftpExtension::PosixRename.name(),
+            "posix-rename@openssh.com"
+        );
+        assert_eq!(SftpExtension::Hardlink.name(), "hardlink@openssh.com");
+        assert_eq!(SftpExtension::Fsync.name(), "fsync@openssh.com");
+        assert_eq!(SftpExtension::Statvfs.name(), "statvfs@openssh.com");
+        assert_eq!(SftpExtension::CopyData.name(), "copy-data");
+    }
+
+    #[test]
+    fn should_decode_payload() {
+        let mut buf = Vec::new();
+        buf.extend_from_slice(&3u32.to_be_bytes());
+        put_string(&mut buf, b"hardlink@openssh.com");
+        buf.extend_from_slice(&u64::MAX.to_be_bytes());
+        let mut decoder = Decoder::new(&buf);
+        assert_eq!(decoder.u32(), Some(3));
+        assert_eq!(decoder.string(), Some(&b"hardlink@openssh.com"[..]));
+        assert_eq!(decoder.u64(), Some(u64::MAX));
+        assert!(decoder.u32().is_none());
+        // truncated string
+        let mut decoder = Decoder::new(&[0, 0, 0, 8, b'a']);
+        assert!(decoder.string().is_none());
+    }
+
+    #[test]
+    fn should_decode_statvfs() {
+        let mut buf = Vec::new();
+        for x in [4096u64, 1024, 100, 50, 40, 10, 5, 4, 1, 0, 255] {
+            buf.extend_from_slice(&x.to_be_bytes());
+        }
+        let stat = StatVfs::decode(&mut Decoder::new(&buf)).unwrap();
+        assert_eq!(stat.block_size, 4096);
+        assert_eq!(stat.fragment_size, 1024);
+        assert_eq!(stat.name_max, 255);
+        assert_eq!(stat.total_space(), 102400);
+        assert_eq!(stat.available_space(), 40960);
+        assert!(StatVfs::decode(&mut Decoder::new(&buf[..80])).is_none());
+    }
+
+    #[test]
+    fn should_map_status_to_error() {
+        assert_eq!(
+            status_error(
+                SSH_FX_NO_SUCH_FILE,
+                String::new(),
+                RemoteErrorType::StatFailed
+            )
+            .kind,
+            RemoteErrorType::NoSuchFileOrDirectory
+        );
+        assert_eq!(
+            status_error(
+                SSH_FX_OP_UNSUPPORTED,
+                String::new(),
+                RemoteErrorType::StatFailed
+            )
+            .kind,
+            RemoteErrorType::UnsupportedFeature
+        );
+        assert_eq!(
+            status_error(4, String::new(), RemoteErrorType::StatFailed).kind,
+            RemoteErrorType::StatFailed
+        );
+    }
+
+    /// SFTP server replaying `replies` and recording the packets sent to it
+    struct ScriptedServer {
+        replies: Cursor<Vec<u8>>,
+        requests: Arc<Mutex<Vec<u8>>>,
+    }
+
+    impl ScriptedServer {
+        /// Server advertising `copy-data`, then answering with `replies`, given as packet type and payload
+        fn new(replies: &[(u8, Vec<u8>)]) -> (Self, Arc<Mutex<Vec<u8>>>) {
+            let mut version = SFTP_VERSION.to_be_bytes().to_vec();
+            put_string(&mut version, b"copy-data");
+            put_string(&mut version, b"1");
+            let mut stream = Vec::new();
+            for (packet_type, payload) in
+                std::iter::once(&(SSH_FXP_VERSION, version)).chain(replies.iter())
+            {
+                stream.extend_from_slice(&(payload.len() as u32 + 1).to_be_bytes());
+                stream.push(*packet_type);
+                stream.extend_from_slice(payload);
+            }
+            let requests = Arc::new(Mutex::new(Vec::new()));
+            let server = Self {
+                replies: Cursor::new(stream),
+                requests: requests.clone(),
+            };
+            (server, requests)
+        }
+    }
+
+    impl Read for ScriptedServer {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            self.replies.read(buf)
+        }
+    }
+
+    impl Write for ScriptedServer {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.requests.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    /// Packet types of the requests in `stream`, after `SSH_FXP_INIT`
+    fn request_types(stream: &[u8]) -> Vec<u8> {
+        let mut types = Vec::new();
+        let mut stream = stream;
+        while stream.len() >= 5 {
+            let len = u32::from_be_bytes(stream[..4].try_into().unwrap()) as usize;
+            types.push(stream[4]);
+            stream = &stream[4 + len..];
+        }
+        types.split_off(1)
+    }
+
+    fn name(id: u32, name: &[u8]) -> (u8, Vec<u8>) {
+        let mut payload = id.to_be_bytes().to_vec();
+        payload.extend_from_slice(&1u32.to_be_bytes());
+        put_string(&mut payload, name);
+        put_string(&mut payload, name);
+        payload.extend_from_slice(&0u32.to_be_bytes());
+        (SSH_FXP_NAME, payload)
+    }
+
+    fn status(id: u32, code: u32) -> (u8, Vec<u8>) {
+        let mut payload = id.to_be_bytes().to_vec();
+        payload.extend_from_slice(&code.to_be_bytes());
+        put_string(&mut payload, b"");
+        put_string(&mut payload, b"");
+        (SSH_FXP_STATUS, payload)
+    }
+
+    fn handle(id: u32) -> (u8, Vec<u8>) {
+        let mut payload = id.to_be_bytes().to_vec();
+        put_string(&mut payload, &id.to_be_bytes());
+        (SSH_FXP_HANDLE, payload)
+    }
+
+    #[test]
+    fn should_not_copy_data_onto_same_file() {
+        let (server, requests) =
+            ScriptedServer::new(&[name(1, b"/home/a.txt"), name(2, b"/home/a.txt")]);
+        let mut ext = SftpExtChannel::init(Box::new(server)).unwrap();
+        assert_eq!(
+            ext.copy_data(Path::new("a.txt"), Path::new("/home/./a.txt"), 0o644)
+                .unwrap_err()
+                .kind,
+            RemoteErrorType::BadFile
+        );
+        // the files are never opened
+        assert_eq!(
+            request_types(&requests.lock().unwrap()),
+            vec![SSH_FXP_REALPATH, SSH_FXP_REALPATH]
+        );
+    }
+
+    #[test]
+    fn should_copy_data_and_truncate_destination() {
+        let mut attrs = 5u32.to_be_bytes().to_vec();
+        attrs.extend_from_slice(&SSH_FILEXFER_ATTR_SIZE.to_be_bytes());
+        attrs.extend_from_slice(&5u64.to_be_bytes());
+        let (server, requests) = ScriptedServer::new(&[
+            name(1, b"/home/a.txt"),
+            status(2, SSH_FX_NO_SUCH_FILE),
+            handle(3),
+            handle(4),
+            (SSH_FXP_ATTRS, attrs),
+            status(6, SSH_FX_OK),
+            status(7, SSH_FX_OK),
+            status(8, SSH_FX_OK),
+            status(9, SSH_FX_OK),
+        ]);
+        let mut ext = SftpExtChannel::init(Box::new(server)).unwrap();
+        assert!(ext
+            .copy_data(Path::new("a.txt"), Path::new("b.txt"), 0o644)
+            .is_ok());
+        assert_eq!(
+            request_types(&requests.lock().unwrap()),
+            vec![
+                SSH_FXP_REALPATH,
+                SSH_FXP_REALPATH,
+                SSH_FXP_OPEN,
+                SSH_FXP_OPEN,
+                SSH_FXP_FSTAT,
+                SSH_FXP_EXTENDED,
+                SSH_FXP_FSETSTAT,
+                SSH_FXP_CLOSE,
+                SSH_FXP_CLOSE
+            ]
+        );
+    }
+
+    #[test]
+    fn should_detect_server_extensions() {
+        crate::mock::logger();
+        let container = crate::ssh::container::OpensshServer::start();
+        let port = container.port();
+
+        let opts = SshOpts::new("127.0.0.1")
+            .port(port)
+            .username("sftp")
+            .password("password");
+        let (session, _) = commons::connect(&opts).unwrap();
+        let mut ext = SftpExtChannel::open(&session).unwrap();
+        assert!(ext.supports(SftpExtension::PosixRename));
+        assert!(ext.supports(SftpExtension::Hardlink));
+        assert!(ext.supports(SftpExtension::Fsync));
+        assert!(ext.supports(SftpExtension::Statvfs));
+        assert!(ext.statvfs(Path::new("/")).unwrap().blocks > 0);
+        assert_eq!(
+            ext.statvfs(Path::new("/this/does/not/exist"))
+                .unwrap_err()
+                .kind,
+            RemoteErrorType::NoSuchFileOrDirectory
+        );
+    }
+}
@@ -3,10 +3,14 @@
 //! ssh file stream
 
 use std::io::{Read, Seek, Write};
+use std::sync::{Arc, Mutex};
 
 use fsutil_core::fs::stream::{ReadAndSeek, ReadStream, WriteAndSeek, WriteStream};
+use fsutil_core::{RemoteError, RemoteErrorType, RemoteResult};
 use ssh2::File as Ssh2File;
 
+use super::{ExecHandle, ExecWriter};
+
 // -- read stream
 
 pub struct SftpReadStream {
@@ -43,11 +47,20 @@ impl From<SftpReadStream> for ReadStream {
 
 pub struct SftpWriteStream {
     file: Ssh2File,
+    fsync: bool,
+}
+
+impl SftpWriteStream {
+    /// Synchronize the file to disk (with `fsync@openssh.com`) once the stream is dropped
+    pub fn fsync_on_drop(mut self, fsync: bool) -> Self {
+        self.fsync = fsync;
+        self
+    }
 }
 
 impl From<Ssh2File> for SftpWriteStream {
     fn from(file: Ssh2File) -> Self {
-        Self { file }
+        Self { file, fsync: false }
     }
 }
 
@@ -69,8 +82,123 @@ impl Seek for SftpWriteStream {
 
 impl WriteAndSeek for SftpWriteStream {}
 
+impl Drop for SftpWriteStream {
+    fn drop(&mut self) {
+        if self.fsync {
+            trace!("Synchronizing file to disk");
+            if let Err(err) = self.file.fsync() {
+                error!("Failed to synchronize file to disk: {}", err);
+            }
+        }
+    }
+}
+
 impl From<SftpWriteStream> for WriteStream {
     fn from(stream: SftpWriteStream) -> Self {
         WriteStream::from(Box::new(stream) as Box<dyn WriteAndSeek>)
     }
 }
+
+// -- exec write stream
+
+/// Exit code and stderr of the command behind an [`ExecWriteStream`]
+type ExecOutcome = RemoteResult<(u32, String)>;
+
+/// Outcome of the command behind an [`ExecWriteStream`], set once the stream has been dropped
+#[derive(Clone, Default)]
+pub struct ExecWriteStatus {
+    outcome: Arc<Mutex<Option<ExecOutcome>>>,
+}
+
+impl ExecWriteStatus {
+    /// Take the outcome of the command: an error if it couldn't be waited for,
+    /// or if it exited with a non-zero exit code, reporting its stderr
+    pub fn take(&self) -> RemoteResult<()> {
+        let outcome = match self.outcome.lock() {
+            Ok(mut outcome) => outcome.take(),
+            Err(_) => None,
+        };
+        match outcome {
+            None | Some(Ok((0, _))) => Ok(()),
+            Some(Ok((rc, stderr))) => {
+                error!(
+                    "Write stream command exited with code {}: {}",
+                    rc,
+                    stderr.trim()
+                );
+                Err(RemoteError::new_ex(
+                    RemoteErrorType::IoError,
+                    match stderr.trim() {
+                        "" => format!("command exited with code {rc}"),
+                        stderr => stderr.to_string(),
+                    },
+                ))
+            }
+            Some(Err(err)) => Err(err),
+        }
+    }
+
+    fn set(&self, outcome: ExecOutcome) {
+        if let Ok(mut slot) = self.outcome.lock() {
+            *slot = Some(outcome);
+        }
+    }
+}
+
+/// Write stream which writes into the stdin of a remote command.
+/// Once dropped, stdin is closed and the command is waited for termination;
+/// its outcome is then available through [`ExecWriteStream::status`]
+pub struct ExecWriteStream {
+    handle: Option<ExecHandle>,
+    stdin: ExecWriter,
+    status: ExecWriteStatus,
+}
+
+impl ExecWriteStream {
+    /// Returns the status which receives the outcome of the command once the stream is dropped
+    pub fn status(&self) -> ExecWriteStatus {
+        self.status.clone()
+    }
+}
+
+impl From<ExecHandle> for ExecWriteStream {
+    fn from(handle: ExecHandle) -> Self {
+        Self {
+            stdin: handle.stdin(),
+            handle: Some(handle),
+            status: ExecWriteStatus::default(),
+        }
+    }
+}
+
+impl Write for ExecWriteStream {
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.stdin.flush()
+    }
+
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.stdin.write(buf)
+    }
+}
+
+impl Drop for ExecWriteStream {
+    fn drop(&mut self) {
+        if let Some(handle) = self.handle.take() {
+            let outcome = handle
+                .wait_with_output()
+                .map(|(rc, _, stderr)| (rc, stderr));
+            match outcome.as_ref() {
+                Ok((0, _)) => trace!("Write stream command terminated"),
+                Ok((rc, _)) => error!("Write stream command exited with code {}", rc),
+                Err(err) => error!("Failed to wait for write stream command: {}", err),
+            }
+            self.status.set(outcome);
+        }
+    }
+}
+
+impl From<ExecWriteStream> for WriteStream {
+    fn from(stream: ExecWriteStream) -> Self {
+        WriteStream::from(Box::new(stream) as Box<dyn Write + Send>)
+    }
+}
@@ -0,0 +1,255 @@
+//! ## Sudo
+//!
+//! privilege elevation through sudo for the mutating operations of the ssh file systems
+
+use std::io::Write;
+use std::path::Path;
+
+use fsutil_core::fs::{Metadata, UnixPex};
+use fsutil_core::{RemoteError, RemoteErrorType, RemoteResult};
+use ssh2::Session;
+
+use super::{ExecHandle, ExecOptions, ExecWriteStream};
+use crate::utils::{fmt as fmt_utils, shell as shell_utils};
+
+/// Prefix to run commands through sudo without a password
+const SUDO_NO_PASSWORD: &str = "sudo -n --";
+/// Prefix to run commands through sudo, reading the password from stdin.
+/// `-k` makes sudo always ask for the password, so that it never reaches the command instead
+const SUDO_PASSWORD: &str = "sudo -k -S -p '' --";
+
+/// Privilege elevation for the operations which mutate the remote file system
+/// (`create`, `append`, `remove_*`, `setstat`, `mov`, `copy`, `symlink` and `create_dir`).
+///
+/// Elevated operations are run as shell commands through `sudo` on exec channels.
+/// If sudo refuses to run them, a [`RemoteErrorType::PexError`] with the sudo message is returned
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Sudo {
+    /// Run `sudo -n`; the user must be allowed to run sudo without password
+    NoPassword,
+    /// Run `sudo -S`, writing the password of the [`super::SshOpts`] to its standard input if sudo requires one
+    Password,
+}
+
+/// Sudo access, checked on a connected session
+#[derive(Clone)]
+pub(super) struct Elevation {
+    /// Password to write to sudo stdin; `None` if sudo doesn't require one
+    password: Option<String>,
+}
+
+impl Elevation {
+    /// Check whether sudo can be used on `session`
+    pub fn check(session: &mut Session, sudo: Sudo, password: Option<&str>) -> RemoteResult<Self> {
+        debug!("Checking sudo access...");
+        let (rc, _, stderr) = run(session, format!("{SUDO_NO_PASSWORD} true").as_str(), None)?;
+        if rc == 0 {
+            debug!("sudo doesn't require a password");
+            return Ok(Self { password: None });
+        }
+        match (sudo, password) {
+            (Sudo::Password, Some(password)) => {
+                let (rc, _, stderr) = run(
+                    session,
+                    format!("{SUDO_PASSWORD} true").as_str(),
+                    Some(password),
+                )?;
+                if rc == 0 {
+                    debug!("sudo accepted password");
+                    Ok(Self {
+                        password: Some(password.to_string()),
+                    })
+                } else {
+                    Err(sudo_error(rc, stderr.as_str()))
+                }
+            }
+            (Sudo::Password, None) => Err(RemoteError::new_ex(
+                RemoteErrorType::PexError,
+                "sudo requires a password, but no password was set in the ssh options",
+            )),
+            (Sudo::NoPassword, _) => Err(sudo_error(rc, stderr.as_str())),
+        }
+    }
+
+    /// Run `cmd` through sudo and collect exit code and output
+    pub fn perform(&self, session: &mut Session, cmd: &str) -> RemoteResult<(u32, String)> {
+        let (rc, stdout, stderr) = run(session, self.command(cmd).as_str(), self.password())?;
+        if rc != 0 && is_sudo_error(stderr.as_str()) {
+            return Err(sudo_error(rc, stderr.as_str()));
+        }
+        Ok((rc, stdout))
+    }
+
+    /// Run `cmd` through sudo; if it fails, return an error of `kind`, reporting the command stderr
+    pub fn execute(
+        &self,
+        session: &mut Session,
+        cmd: &str,
+        kind: RemoteErrorType,
+    ) -> RemoteResult<()> {
+        let (rc, _, stderr) = run(session, self.command(cmd).as_str(), self.password())?;
+        match rc {
+            0 => Ok(()),
+            rc if is_sudo_error(stderr.as_str()) => Err(sudo_error(rc, stderr.as_str())),
+            rc => {
+                error!(
+                    "Elevated command exited with code {}: {}",
+                    rc,
+                    stderr.trim()
+                );
+                Err(RemoteError::new_ex(kind, stderr.trim()))
+            }
+        }
+    }
+
+    /// Set attributes of file at `path` through sudo
+    pub fn setstat(
+        &self,
+        session: &mut Session,
+        path: &Path,
+        metadata: &Metadata,
+    ) -> RemoteResult<()> {
+        let path = shell_utils::quote_path(path);
+        let mut cmds: Vec<String> = Vec::new();
+        if let Some(mode) = metadata.mode {
+            cmds.push(format!("chmod {:o} {path}", u32::from(mode)));
+        }
+        if let Some(user) = metadata.uid {
+            let group = metadata.gid.map(|x| format!(":{x}")).unwrap_or_default();
+            cmds.push(format!("chown {user}{group} {path}"));
+        }
+        if let Some(accessed) = metadata.accessed {
+            let time = fmt_utils::fmt_time_utc(accessed, "%Y%m%d%H%M.%S");
+            cmds.push(format!("touch -a -t {time} {path}"));
+        }
+        if let Some(modified) = metadata.modified {
+            let time = fmt_utils::fmt_time_utc(modified, "%Y%m%d%H%M.%S");
+            cmds.push(format!("touch -m -t {time} {path}"));
+        }
+        if cmds.is_empty() {
+            return Ok(());
+        }
+        self.execute(
+            session,
+            cmds.join(" && ").as_str(),
+            RemoteErrorType::StatFailed,
+        )
+    }
+
+    /// Open file at `path` for writing through sudo. If the file doesn't exist, it is created with `mode`.
+    ///
+    /// The outcome of the command writing the file is reported by [`ExecWriteStream::status`] once the stream is dropped
+    pub fn open_write(
+        &self,
+        session: &mut Session,
+        path: &Path,
+        mode: UnixPex,
+        append: bool,
+    ) -> RemoteResult<ExecWriteStream> {
+        let path = shell_utils::quote_path(path);
+        let mode = u32::from(mode);
+        self.execute(
+            session,
+            format!("test -e {path} || {{ : > {path} && chmod {mode:o} {path}; }}").as_str(),
+            RemoteErrorType::FileCreateDenied,
+        )?;
+        let redirect = if append { ">>" } else { ">" };
+        let handle = ExecHandle::spawn(
+            session,
+            self.command(format!("cat {redirect} {path}").as_str())
+                .as_str(),
+            &ExecOptions::default(),
+        )?;
+        if let Some(password) = self.password() {
+            writeln!(handle.stdin(), "{password}")
+                .map_err(|err| RemoteError::new_ex(RemoteErrorType::IoError, err))?;
+        }
+        Ok(ExecWriteStream::from(handle))
+    }
+
+    /// Wrap `cmd` to run it through sudo
+    fn command(&self, cmd: &str) -> String {
+        let prefix = match self.password {
+            Some(_) => SUDO_PASSWORD,
+            None => SUDO_NO_PASSWORD,
+        };
+        format!("{prefix} sh -c {}", shell_utils::quote(cmd))
+    }
+
+    fn password(&self) -> Option<&str> {
+        self.password.as_deref()
+    }
+}
+
+/// Run `cmd`, writing `password` to its stdin, and collect exit code, stdout and stderr
+fn run(
+    session: &mut Session,
+    cmd: &str,
+    password: Option<&str>,
+) -> RemoteResult<(u32, String, String)> {
+    let handle = ExecHandle::spawn(session, cmd, &ExecOptions::default())?;
+    if let Some(password) = password {
+        writeln!(handle.stdin(), "{password}")
+            .map_err(|err| RemoteError::new_ex(RemoteErrorType::IoError, err))?;
+    }
+    // stdout and stderr are drained together, so a command filling one of them can't stall
+    let (rc, stdout, stderr) = handle.wait_with_output()?;
+    trace!("Elevated command exited with code {}", rc);
+    Ok((rc, stdout, stderr))
+}
+
+/// Returns whether stderr of a failed command was written by sudo itself
+fn is_sudo_error(stderr: &str) -> bool {
+    stderr.lines().any(|line| line.starts_with("sudo:"))
+}
+
+/// Make a permission error out of the sudo stderr
+fn sudo_error(rc: u32, stderr: &str) -> RemoteError {
+    let msg = stderr
+        .lines()
+        .find(|line| line.contains("sudo"))
+        .map(|line| line.trim().to_string())
+        .unwrap_or_else(|| format!("sudo exited with code {rc}"));
+    error!("sudo refused to run command: {}", msg);
+    RemoteError::new_ex(RemoteErrorType::PexError, msg)
+}
+
+#[cfg(test)]
+mod test {
+
+    use pretty_assertions::assert_eq;
+
+    use super::*;
+
+    #[test]
+    fn should_wrap_command() {
+        let elevation = Elevation { password: None };
+        assert_eq!(
+            elevation.command("rm -f '/etc/a b'"),
+            r#"sudo -n -- sh -c 'rm -f '\''/etc/a b'\'''"#
+        );
+        let elevation = Elevation {
+            password: Some("secret".to_string()),
+        };
+        assert_eq!(
+            elevation.command("true"),
+            "sudo -k -S -p '' -- sh -c 'true'"
+        );
+    }
+
+    #[test]
+    fn should_tell_sudo_errors() {
+        assert!(is_sudo_error("sudo: a password is required\n"));
+        assert!(is_sudo_error(
+            "sudo: 1 incorrect password attempt\nsudo: no password was provided\n"
+        ));
+        assert!(!is_sudo_error("rm: can't remove '/etc/a': No such file\n"));
+        let err = sudo_error(1, "sudo: a password is required\n");
+        assert_eq!(err.kind, RemoteErrorType::PexError);
+        assert_eq!(err.msg.as_deref(), Some("sudo: a password is required"));
+        let err = sudo_error(127, "sh: sudo: not found\n");
+        assert_eq!(err.msg.as_deref(), Some("sh: sudo: not found"));
+        let err = sudo_error(1, "");
+        assert_eq!(err.msg.as_deref(), Some("sudo exited with code 1"));
+    }
+}
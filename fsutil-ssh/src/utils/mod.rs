@@ -5,3 +5,4 @@
 pub mod fmt;
 pub mod parser;
 pub mod path;
+pub mod shell;
@@ -0,0 +1,147 @@
+//! ## Shell
+//!
+//! POSIX shell quoting utilities
+
+use std::path::Path;
+
+/// Quote `s`, so that a POSIX shell interprets it as a single literal word.
+///
+/// The string is wrapped into single quotes; single quotes inside the string are
+/// closed, escaped and reopened (`'` becomes `'\''`)
+pub fn quote(s: &str) -> String {
+    let mut quoted = String::with_capacity(s.len() + 2);
+    quoted.push('\'');
+    for c in s.chars() {
+        if c == '\'' {
+            quoted.push_str(r"'\''");
+        } else {
+            quoted.push(c);
+        }
+    }
+    quoted.push('\'');
+    quoted
+}
+
+/// Quote an arbitrary byte string, so that a POSIX shell interprets it as a single literal word.
+///
+/// Valid UTF-8 sequences are quoted with [`quote`], while bytes which are not valid UTF-8
+/// are emitted through `printf` octal escapes (e.g. `"$(printf '\351')"`), since the command line
+/// sent to the server must be valid UTF-8.
+///
+/// NUL bytes can't be represented in a shell word and are dropped.
+pub fn quote_bytes(bytes: &[u8]) -> String {
+    let mut quoted = String::with_capacity(bytes.len() + 2);
+    for chunk in bytes.utf8_chunks() {
+        let valid = chunk.valid().replace('\0', "");
+        if !valid.is_empty() {
+            quoted.push_str(quote(&valid).as_str());
+        }
+        let invalid = chunk.invalid();
+        if !invalid.is_empty() {
+            quoted.push_str("\"$(printf '");
+            for byte in invalid {
+                quoted.push_str(format!("\\{byte:03o}").as_str());
+            }
+            quoted.push_str("')\"");
+        }
+    }
+    if quoted.is_empty() {
+        quote("")
+    } else {
+        quoted
+    }
+}
+
+/// Quote path, so that a POSIX shell interprets it as a single literal word
+#[cfg(target_family = "unix")]
+pub fn quote_path(p: &Path) -> String {
+    use std::os::unix::ffi::OsStrExt as _;
+
+    quote_bytes(p.as_os_str().as_bytes())
+}
+
+/// Quote path, so that a POSIX shell interprets it as a single literal word
+#[cfg(target_os = "windows")]
+pub fn quote_path(p: &Path) -> String {
+    quote(p.to_string_lossy().as_ref())
+}
+
+#[cfg(test)]
+mod test {
+
+    use pretty_assertions::assert_eq;
+
+    use super::*;
+
+    #[test]
+    fn should_quote_string() {
+        assert_eq!(quote("").as_str(), "''");
+        assert_eq!(quote("/tmp/a.txt").as_str(), "'/tmp/a.txt'");
+        assert_eq!(quote("it's").as_str(), r"'it'\''s'");
+        assert_eq!(quote("$(rm -rf /)").as_str(), "'$(rm -rf /)'");
+        assert_eq!(quote("a\"b`c\nd").as_str(), "'a\"b`c\nd'");
+    }
+
+    #[test]
+    fn should_quote_bytes() {
+        assert_eq!(quote_bytes(b"").as_str(), "''");
+        assert_eq!(quote_bytes(b"/tmp/a.txt").as_str(), "'/tmp/a.txt'");
+        assert_eq!(
+            quote_bytes(b"caf\xe9.txt").as_str(),
+            r#"'caf'"$(printf '\351')"'.txt'"#
+        );
+        assert_eq!(quote_bytes(b"\0").as_str(), "''");
+    }
+
+    #[test]
+    #[cfg(target_family = "unix")]
+    fn should_quote_path() {
+        assert_eq!(
+            quote_path(Path::new("/tmp/my dir/'a'.txt")).as_str(),
+            r"'/tmp/my dir/'\''a'\''.txt'"
+        );
+    }
+
+    #[cfg(target_family = "unix")]
+    mod roundtrip {
+
+        use std::ffi::OsStr;
+        use std::os::unix::ffi::OsStrExt as _;
+        use std::process::Command;
+
+        use proptest::prelude::*;
+
+        use super::*;
+
+        /// Let `sh` print the word produced by `quoted` and return its bytes
+        fn eval(quoted: &str) -> Vec<u8> {
+            let output = Command::new("sh")
+                .arg("-c")
+                .arg(format!("printf '%s' {quoted}"))
+                .output()
+                .expect("failed to run sh");
+            assert!(output.status.success());
+            output.stdout
+        }
+
+        proptest! {
+            #![proptest_config(ProptestConfig::with_cases(128))]
+
+            #[test]
+            fn should_roundtrip_strings(s in "[^\\x00]*") {
+                prop_assert_eq!(eval(&quote(&s)), s.into_bytes());
+            }
+
+            #[test]
+            fn should_roundtrip_byte_strings(bytes in proptest::collection::vec(1u8.., 0..64)) {
+                prop_assert_eq!(eval(&quote_bytes(&bytes)), bytes);
+            }
+
+            #[test]
+            fn should_roundtrip_paths(bytes in proptest::collection::vec(1u8.., 1..64)) {
+                let path = Path::new(OsStr::from_bytes(&bytes));
+                prop_assert_eq!(eval(&quote_path(path)), bytes);
+            }
+        }
+    }
+}